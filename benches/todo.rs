@@ -0,0 +1,94 @@
+use axum::{
+    body::Body,
+    http::{header, Method, Request},
+    Router,
+};
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use rust_web::{
+    config::Config,
+    repositories::{
+        label::Label,
+        todo::{fold_entities, TodoWithLabelFromRow},
+    },
+    test_support::{memory_app, LabelRepositoryForMemory, TodoRepositoryForMemory},
+};
+use tokio::runtime::Runtime;
+use tower::ServiceExt;
+
+// DB から返ってくる形と同じく、Todo ごとに付いているラベルの数だけ行を並べる
+fn rows(todo_count: i32, label_count: i32) -> Vec<TodoWithLabelFromRow> {
+    (1..=todo_count)
+        .flat_map(|id| {
+            let text = format!("todo {}", id);
+            if label_count == 0 {
+                return vec![TodoWithLabelFromRow::new(id, text, None)];
+            }
+            (1..=label_count)
+                .map(|label_id| {
+                    let label = Label::new(label_id, format!("label {}", label_id));
+                    TodoWithLabelFromRow::new(id, text.clone(), Some(label))
+                })
+                .collect()
+        })
+        .collect()
+}
+
+fn bench_fold_entities(c: &mut Criterion) {
+    let mut group = c.benchmark_group("fold_entities");
+    for todo_count in [10, 100, 1000] {
+        for label_count in [0, 3, 10] {
+            let rows = rows(todo_count, label_count);
+            group.throughput(Throughput::Elements(rows.len() as u64));
+            group.bench_with_input(
+                BenchmarkId::new(format!("{}_labels", label_count), todo_count),
+                &rows,
+                |b, rows| b.iter(|| fold_entities(rows.clone())),
+            );
+        }
+    }
+    group.finish();
+}
+
+fn build_app() -> Router {
+    memory_app(Config::default(), TodoRepositoryForMemory::new(), LabelRepositoryForMemory::new())
+}
+
+fn create_req() -> Request<Body> {
+    Request::builder()
+        .uri("/todos")
+        .method(Method::POST)
+        .header(header::CONTENT_TYPE, mime::APPLICATION_JSON.to_string())
+        .body(Body::from(r#"{"text": "bench", "labels": []}"#))
+        .unwrap()
+}
+
+fn bench_handlers(c: &mut Criterion) {
+    let rt = Runtime::new().unwrap();
+    let mut group = c.benchmark_group("handlers");
+    group.throughput(Throughput::Elements(1));
+
+    let app = build_app();
+    group.bench_function("create_todo", |b| {
+        b.to_async(&rt).iter(|| app.clone().oneshot(create_req()))
+    });
+
+    // 一覧はシリアライズのコストが件数に比例するので、件数を変えて測る
+    for todo_count in [10, 100, 1000] {
+        let app = build_app();
+        rt.block_on(async {
+            for _ in 0..todo_count {
+                app.clone().oneshot(create_req()).await.unwrap();
+            }
+        });
+        group.bench_with_input(BenchmarkId::new("all_todo", todo_count), &app, |b, app| {
+            b.to_async(&rt).iter(|| {
+                let req = Request::builder().uri("/todos").body(Body::empty()).unwrap();
+                app.clone().oneshot(req)
+            })
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, bench_fold_entities, bench_handlers);
+criterion_main!(benches);
@@ -0,0 +1,59 @@
+#![no_main]
+
+use axum::{
+    body::Body,
+    http::{header, Method, Request},
+};
+use libfuzzer_sys::fuzz_target;
+use rust_web::{
+    config::Config,
+    test_support::{memory_app, LabelRepositoryForMemory, TodoFixture, TodoRepositoryForMemory},
+};
+use std::sync::OnceLock;
+use tokio::runtime::Runtime;
+use tower::ServiceExt;
+
+fn runtime() -> &'static Runtime {
+    static RUNTIME: OnceLock<Runtime> = OnceLock::new();
+    RUNTIME.get_or_init(|| Runtime::new().unwrap())
+}
+
+// ValidatedJson を通るルート。入力の先頭 1 バイトで投げ先を選ぶ
+fn route(selector: u8) -> (Method, &'static str) {
+    match selector % 5 {
+        0 => (Method::POST, "/todos"),
+        1 => (Method::PATCH, "/todos/1"),
+        2 => (Method::PUT, "/todos/by-key/fuzz"),
+        3 => (Method::POST, "/labels"),
+        _ => (Method::POST, "/sync"),
+    }
+}
+
+// 残りのバイト列をそのまま JSON のボディとして送る。
+// 不正な入力は 4xx で弾かれるべきなので、5xx (パニックも catch_panic で 500 になる) はバグとして落とす
+fuzz_target!(|data: &[u8]| {
+    let (selector, body) = match data.split_first() {
+        Some(split) => split,
+        None => return,
+    };
+    let (method, path) = route(*selector);
+    runtime().block_on(async {
+        let todo_repo = TodoRepositoryForMemory::new();
+        TodoFixture::new().insert(&todo_repo).await;
+        let app = memory_app(Config::default(), todo_repo, LabelRepositoryForMemory::new());
+        let req = Request::builder()
+            .uri(path)
+            .method(method)
+            .header(header::CONTENT_TYPE, "application/json")
+            .body(Body::from(body.to_vec()))
+            .unwrap();
+        let res = app.oneshot(req).await.unwrap();
+        assert!(
+            !res.status().is_server_error(),
+            "{} {} responded {}",
+            route(*selector).0,
+            path,
+            res.status()
+        );
+    });
+});
@@ -0,0 +1,2 @@
+-- オフライン同期で「クライアントがどの時点の Todo を元に編集したか」を判定するための版番号
+ALTER TABLE todos ADD COLUMN version INTEGER NOT NULL DEFAULT 1;
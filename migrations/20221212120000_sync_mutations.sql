@@ -0,0 +1,8 @@
+-- クライアント側で採番した ID。オフライン作成された Todo をサーバー側の ID と対応付ける
+ALTER TABLE todos ADD COLUMN client_id UUID UNIQUE;
+
+-- 適用済みの同期ミューテーション。同じバッチが再送されても二重に適用しないために使う
+CREATE TABLE sync_mutations (
+    mutation_id UUID PRIMARY KEY,
+    applied_at  TIMESTAMPTZ NOT NULL DEFAULT now()
+);
@@ -0,0 +1,12 @@
+CREATE TABLE access_log (
+    id          BIGSERIAL PRIMARY KEY,
+    request_id  TEXT,
+    method      TEXT NOT NULL,
+    path        TEXT NOT NULL,
+    status      INTEGER NOT NULL,
+    latency_ms  BIGINT NOT NULL,
+    user_id     INTEGER,
+    created_at  TIMESTAMPTZ NOT NULL DEFAULT now()
+);
+
+CREATE INDEX access_log_created_at_idx ON access_log (created_at);
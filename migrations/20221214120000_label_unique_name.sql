@@ -0,0 +1,25 @@
+-- 既に同名のラベルがあれば、一番古いものに寄せてから消す。
+-- 寄せた先と同じ Todo に付いている関係は重複するので、先に 1 行だけ残して消しておく
+DELETE FROM todo_labels
+WHERE id IN (
+    SELECT id FROM (
+        SELECT tl.id, row_number() OVER (PARTITION BY tl.todo_id, keep.id ORDER BY tl.label_id = keep.id DESC, tl.id) rn
+        FROM todo_labels tl
+        JOIN labels dup ON dup.id = tl.label_id
+        JOIN (SELECT name, min(id) id FROM labels GROUP BY name) keep ON keep.name = dup.name
+    ) ranked
+    WHERE rn > 1
+);
+
+UPDATE todo_labels tl
+SET label_id = keep.id
+FROM labels dup
+JOIN (SELECT name, min(id) id FROM labels GROUP BY name) keep ON keep.name = dup.name
+WHERE tl.label_id = dup.id AND dup.id <> keep.id;
+
+DELETE FROM labels dup
+USING labels keep
+WHERE dup.name = keep.name AND dup.id > keep.id;
+
+-- 重複チェックをアプリ側の SELECT に頼ると同時作成で競合するので、DB 側で一意にする
+ALTER TABLE labels ADD CONSTRAINT labels_name_key UNIQUE (name);
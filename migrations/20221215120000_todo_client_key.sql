@@ -0,0 +1,2 @@
+-- 外部システム側のキー。連携先が サーバーの ID を覚えなくても PUT /todos/by-key/:client_key で同期できるようにする
+ALTER TABLE todos ADD COLUMN client_key TEXT UNIQUE;
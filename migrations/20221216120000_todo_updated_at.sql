@@ -0,0 +1,29 @@
+-- GET /todos の Last-Modified 用。行が更新されたら trigger で進める
+ALTER TABLE todos ADD COLUMN updated_at TIMESTAMPTZ NOT NULL DEFAULT clock_timestamp();
+
+CREATE FUNCTION touch_todo() RETURNS trigger AS $$
+BEGIN
+    NEW.updated_at = clock_timestamp();
+    RETURN NEW;
+END;
+$$ LANGUAGE plpgsql;
+
+CREATE TRIGGER todos_touch BEFORE UPDATE ON todos
+    FOR EACH ROW EXECUTE FUNCTION touch_todo();
+
+-- 削除は max(updated_at) に表れないので、最後に削除した時刻を 1 行だけ持っておく
+CREATE TABLE todo_deletions (
+    id BOOLEAN PRIMARY KEY DEFAULT true CHECK (id),
+    deleted_at TIMESTAMPTZ NOT NULL
+);
+
+CREATE FUNCTION record_todo_deletion() RETURNS trigger AS $$
+BEGIN
+    INSERT INTO todo_deletions (deleted_at) VALUES (clock_timestamp())
+    ON CONFLICT (id) DO UPDATE SET deleted_at = EXCLUDED.deleted_at;
+    RETURN NULL;
+END;
+$$ LANGUAGE plpgsql;
+
+CREATE TRIGGER todos_deleted AFTER DELETE ON todos
+    FOR EACH STATEMENT EXECUTE FUNCTION record_todo_deletion();
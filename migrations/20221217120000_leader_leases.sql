@@ -0,0 +1,6 @@
+-- 複数インスタンスのうち 1 つだけを leader にするためのリース。name ごとに 1 行
+CREATE TABLE leader_leases (
+    name TEXT PRIMARY KEY,
+    holder UUID NOT NULL,
+    expires_at TIMESTAMPTZ NOT NULL
+);
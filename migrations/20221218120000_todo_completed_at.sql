@@ -0,0 +1,26 @@
+-- 完了にした時刻。未完了に戻したら消す。どの書き込み経路でも揃うように trigger で持つ
+ALTER TABLE todos ADD COLUMN completed_at TIMESTAMPTZ;
+
+-- 既に完了しているものは、正確な時刻が分からないので最後に更新した時刻にしておく
+UPDATE todos SET completed_at = updated_at WHERE completed;
+
+CREATE FUNCTION track_todo_completion() RETURNS trigger AS $$
+BEGIN
+    IF NOT NEW.completed THEN
+        NEW.completed_at = NULL;
+    ELSIF TG_OP = 'INSERT' THEN
+        -- リストアなどで時刻を指定して入れた場合はそのまま使う
+        NEW.completed_at = COALESCE(NEW.completed_at, clock_timestamp());
+    ELSIF NOT OLD.completed THEN
+        NEW.completed_at = clock_timestamp();
+    ELSE
+        NEW.completed_at = OLD.completed_at;
+    END IF;
+    RETURN NEW;
+END;
+$$ LANGUAGE plpgsql;
+
+CREATE TRIGGER todos_track_completion BEFORE INSERT OR UPDATE ON todos
+    FOR EACH ROW EXECUTE FUNCTION track_todo_completion();
+
+CREATE INDEX todos_completed_at_idx ON todos (completed_at) WHERE completed_at IS NOT NULL;
@@ -0,0 +1,88 @@
+-- 一覧の総件数や集計のたびに COUNT(*) しなくて済むように、件数を trigger で持っておく。
+-- 書き込みと同じトランザクションで更新されるので、コミットされた件数と常に一致する
+
+-- 全体の件数。todo_deletions と同じく 1 行だけ
+CREATE TABLE todo_counters (
+    id BOOLEAN PRIMARY KEY DEFAULT true CHECK (id),
+    open BIGINT NOT NULL DEFAULT 0,
+    completed BIGINT NOT NULL DEFAULT 0
+);
+
+-- ラベルごとの件数。Todo が 1 件も付いたことがないラベルの行は無い。
+-- todo_labels と同じく、ラベルより先に関係が入っても良いように外部キーは遅延させる
+CREATE TABLE label_todo_counters (
+    label_id INTEGER PRIMARY KEY REFERENCES labels (id) ON DELETE CASCADE DEFERRABLE INITIALLY DEFERRED,
+    open BIGINT NOT NULL DEFAULT 0,
+    completed BIGINT NOT NULL DEFAULT 0
+);
+
+INSERT INTO todo_counters (open, completed)
+SELECT COUNT(*) FILTER (WHERE NOT completed), COUNT(*) FILTER (WHERE completed) FROM todos;
+
+INSERT INTO label_todo_counters (label_id, open, completed)
+SELECT tl.label_id, COUNT(*) FILTER (WHERE NOT t.completed), COUNT(*) FILTER (WHERE t.completed)
+FROM todo_labels tl
+    JOIN todos t ON t.id = tl.todo_id
+    JOIN labels l ON l.id = tl.label_id
+GROUP BY tl.label_id;
+
+-- 件数は、その時点で付いているラベルの分も増減する。
+-- 普通は Todo を作ってからラベルを付けるので、作成時点では付いておらず todo_labels 側の trigger が数える
+CREATE FUNCTION count_todos() RETURNS trigger AS $$
+BEGIN
+    IF TG_OP IN ('UPDATE', 'DELETE') THEN
+        UPDATE todo_counters
+        SET open = open - (NOT OLD.completed)::INTEGER, completed = completed - OLD.completed::INTEGER;
+        UPDATE label_todo_counters c
+        SET open = c.open - (NOT OLD.completed)::INTEGER, completed = c.completed - OLD.completed::INTEGER
+        FROM todo_labels tl
+        WHERE tl.todo_id = OLD.id AND tl.label_id = c.label_id;
+    END IF;
+    IF TG_OP IN ('INSERT', 'UPDATE') THEN
+        UPDATE todo_counters
+        SET open = open + (NOT NEW.completed)::INTEGER, completed = completed + NEW.completed::INTEGER;
+        INSERT INTO label_todo_counters AS c (label_id, open, completed)
+        SELECT tl.label_id, (NOT NEW.completed)::INTEGER, NEW.completed::INTEGER
+        FROM todo_labels tl
+        WHERE tl.todo_id = NEW.id
+        ON CONFLICT (label_id) DO UPDATE
+        SET open = c.open + EXCLUDED.open, completed = c.completed + EXCLUDED.completed;
+    END IF;
+    RETURN NULL;
+END;
+$$ LANGUAGE plpgsql;
+
+CREATE TRIGGER todos_count AFTER INSERT OR DELETE ON todos
+    FOR EACH ROW EXECUTE FUNCTION count_todos();
+
+CREATE TRIGGER todos_count_completion AFTER UPDATE OF completed ON todos
+    FOR EACH ROW WHEN (OLD.completed <> NEW.completed) EXECUTE FUNCTION count_todos();
+
+-- 関係が付け外しされたら、その Todo の完了状態でラベルの件数を増減する。
+-- Todo がまだ無い (または先に消えた) 場合は count_todos が数えるので何もしない
+CREATE FUNCTION count_todo_labels() RETURNS trigger AS $$
+DECLARE
+    done BOOLEAN;
+BEGIN
+    IF TG_OP = 'INSERT' THEN
+        SELECT completed INTO done FROM todos WHERE id = NEW.todo_id;
+        IF FOUND THEN
+            INSERT INTO label_todo_counters AS c (label_id, open, completed)
+            VALUES (NEW.label_id, (NOT done)::INTEGER, done::INTEGER)
+            ON CONFLICT (label_id) DO UPDATE
+            SET open = c.open + EXCLUDED.open, completed = c.completed + EXCLUDED.completed;
+        END IF;
+    ELSE
+        SELECT completed INTO done FROM todos WHERE id = OLD.todo_id;
+        IF FOUND THEN
+            UPDATE label_todo_counters
+            SET open = open - (NOT done)::INTEGER, completed = completed - done::INTEGER
+            WHERE label_id = OLD.label_id;
+        END IF;
+    END IF;
+    RETURN NULL;
+END;
+$$ LANGUAGE plpgsql;
+
+CREATE TRIGGER todo_labels_count AFTER INSERT OR DELETE ON todo_labels
+    FOR EACH ROW EXECUTE FUNCTION count_todo_labels();
@@ -0,0 +1,3 @@
+-- GET /todos/search 用の全文検索インデックス。列は増やさず式インデックスにする。
+-- 言語ごとの語幹処理はせず空白と記号で区切るだけなので、検索側も同じ 'simple' 設定で to_tsquery する
+CREATE INDEX todos_text_search_idx ON todos USING GIN (to_tsvector('simple', text));
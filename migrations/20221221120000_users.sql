@@ -0,0 +1,17 @@
+-- email はアプリ側で小文字にそろえてから入れる
+CREATE TABLE users (
+    id SERIAL PRIMARY KEY,
+    email TEXT NOT NULL UNIQUE,
+    password_hash TEXT NOT NULL,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT now()
+);
+
+-- ユーザーを入れる前からある行は持ち主なし (NULL) のまま残す
+ALTER TABLE todos ADD COLUMN owner_id INTEGER REFERENCES users (id) ON DELETE CASCADE;
+ALTER TABLE labels ADD COLUMN owner_id INTEGER REFERENCES users (id) ON DELETE CASCADE;
+
+CREATE INDEX todos_owner_id_idx ON todos (owner_id);
+
+-- ラベル名はユーザーごとに一意にする。NULL 同士は UNIQUE では重複扱いにならないので、持ち主なしは 0 として比べる
+ALTER TABLE labels DROP CONSTRAINT labels_name_key;
+CREATE UNIQUE INDEX labels_owner_name_key ON labels ((COALESCE(owner_id, 0)), name);
@@ -0,0 +1,3 @@
+-- 連携先のキーはユーザーごとに一意にする。ラベル名と同じく、持ち主なしは 0 として比べる
+ALTER TABLE todos DROP CONSTRAINT todos_client_key_key;
+CREATE UNIQUE INDEX todos_owner_client_key_key ON todos ((COALESCE(owner_id, 0)), client_key);
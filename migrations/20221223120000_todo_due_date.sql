@@ -0,0 +1,5 @@
+-- 期限。無いものもある
+ALTER TABLE todos ADD COLUMN due_date TIMESTAMPTZ;
+
+-- 期限切れの絞り込みと、期限順の一覧用
+CREATE INDEX todos_due_date_idx ON todos (due_date) WHERE due_date IS NOT NULL;
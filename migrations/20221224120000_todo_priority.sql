@@ -0,0 +1,2 @@
+-- 0: low, 1: medium, 2: high。大きいほど優先度が高いので、ORDER BY priority DESC で高い順に並ぶ
+ALTER TABLE todos ADD COLUMN priority SMALLINT NOT NULL DEFAULT 1 CHECK (priority BETWEEN 0 AND 2);
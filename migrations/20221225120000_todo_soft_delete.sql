@@ -0,0 +1,61 @@
+-- 削除は deleted_at を入れるだけにして、ゴミ箱から戻せるようにする。ラベルの関係も残しておく
+ALTER TABLE todos ADD COLUMN deleted_at TIMESTAMPTZ;
+
+-- ゴミ箱の一覧用
+CREATE INDEX todos_deleted_at_idx ON todos (deleted_at) WHERE deleted_at IS NOT NULL;
+
+-- 件数はゴミ箱の Todo を数えない。削除と復元は deleted_at の更新なので、完了状態の変化と同じく増減する
+CREATE OR REPLACE FUNCTION count_todos() RETURNS trigger AS $$
+BEGIN
+    IF TG_OP IN ('UPDATE', 'DELETE') AND OLD.deleted_at IS NULL THEN
+        UPDATE todo_counters
+        SET open = open - (NOT OLD.completed)::INTEGER, completed = completed - OLD.completed::INTEGER;
+        UPDATE label_todo_counters c
+        SET open = c.open - (NOT OLD.completed)::INTEGER, completed = c.completed - OLD.completed::INTEGER
+        FROM todo_labels tl
+        WHERE tl.todo_id = OLD.id AND tl.label_id = c.label_id;
+    END IF;
+    IF TG_OP IN ('INSERT', 'UPDATE') AND NEW.deleted_at IS NULL THEN
+        UPDATE todo_counters
+        SET open = open + (NOT NEW.completed)::INTEGER, completed = completed + NEW.completed::INTEGER;
+        INSERT INTO label_todo_counters AS c (label_id, open, completed)
+        SELECT tl.label_id, (NOT NEW.completed)::INTEGER, NEW.completed::INTEGER
+        FROM todo_labels tl
+        WHERE tl.todo_id = NEW.id
+        ON CONFLICT (label_id) DO UPDATE
+        SET open = c.open + EXCLUDED.open, completed = c.completed + EXCLUDED.completed;
+    END IF;
+    RETURN NULL;
+END;
+$$ LANGUAGE plpgsql;
+
+DROP TRIGGER todos_count_completion ON todos;
+
+CREATE TRIGGER todos_count_completion AFTER UPDATE OF completed, deleted_at ON todos
+    FOR EACH ROW WHEN (OLD.completed <> NEW.completed OR (OLD.deleted_at IS NULL) <> (NEW.deleted_at IS NULL))
+    EXECUTE FUNCTION count_todos();
+
+-- ゴミ箱の Todo に付け外ししても数えない
+CREATE OR REPLACE FUNCTION count_todo_labels() RETURNS trigger AS $$
+DECLARE
+    done BOOLEAN;
+BEGIN
+    IF TG_OP = 'INSERT' THEN
+        SELECT completed INTO done FROM todos WHERE id = NEW.todo_id AND deleted_at IS NULL;
+        IF FOUND THEN
+            INSERT INTO label_todo_counters AS c (label_id, open, completed)
+            VALUES (NEW.label_id, (NOT done)::INTEGER, done::INTEGER)
+            ON CONFLICT (label_id) DO UPDATE
+            SET open = c.open + EXCLUDED.open, completed = c.completed + EXCLUDED.completed;
+        END IF;
+    ELSE
+        SELECT completed INTO done FROM todos WHERE id = OLD.todo_id AND deleted_at IS NULL;
+        IF FOUND THEN
+            UPDATE label_todo_counters
+            SET open = open - (NOT done)::INTEGER, completed = completed - done::INTEGER
+            WHERE label_id = OLD.label_id;
+        END IF;
+    END IF;
+    RETURN NULL;
+END;
+$$ LANGUAGE plpgsql;
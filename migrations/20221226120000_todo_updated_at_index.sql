@@ -0,0 +1,2 @@
+-- GET /todos の Last-Modified は毎回 max(updated_at) を引くので、全件を読まずに済むようにする
+CREATE INDEX todos_updated_at_idx ON todos (updated_at);
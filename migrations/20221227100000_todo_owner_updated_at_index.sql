@@ -0,0 +1,2 @@
+-- Last-Modified はユーザーごとに max(updated_at) を引くので、持ち主で絞ってから索引の端を読む
+CREATE INDEX todos_owner_id_updated_at_idx ON todos (owner_id, updated_at);
@@ -0,0 +1,47 @@
+-- ユーザーごとの件数。認証しているときの /todos/stats や一覧の総件数も COUNT(*) せずに読めるようにする。
+-- 持ち主の無い Todo は数えない (全体の件数は todo_counters にある)
+CREATE TABLE todo_owner_counters (
+    owner_id INTEGER PRIMARY KEY REFERENCES users (id) ON DELETE CASCADE,
+    open BIGINT NOT NULL DEFAULT 0,
+    completed BIGINT NOT NULL DEFAULT 0
+);
+
+INSERT INTO todo_owner_counters (owner_id, open, completed)
+SELECT owner_id, COUNT(*) FILTER (WHERE NOT completed), COUNT(*) FILTER (WHERE completed)
+FROM todos
+WHERE owner_id IS NOT NULL AND deleted_at IS NULL
+GROUP BY owner_id;
+
+-- ゴミ箱の扱いは 20221225120000_todo_soft_delete と同じ。持ち主の行も一緒に増減する
+CREATE OR REPLACE FUNCTION count_todos() RETURNS trigger AS $$
+BEGIN
+    IF TG_OP IN ('UPDATE', 'DELETE') AND OLD.deleted_at IS NULL THEN
+        UPDATE todo_counters
+        SET open = open - (NOT OLD.completed)::INTEGER, completed = completed - OLD.completed::INTEGER;
+        UPDATE todo_owner_counters
+        SET open = open - (NOT OLD.completed)::INTEGER, completed = completed - OLD.completed::INTEGER
+        WHERE owner_id = OLD.owner_id;
+        UPDATE label_todo_counters c
+        SET open = c.open - (NOT OLD.completed)::INTEGER, completed = c.completed - OLD.completed::INTEGER
+        FROM todo_labels tl
+        WHERE tl.todo_id = OLD.id AND tl.label_id = c.label_id;
+    END IF;
+    IF TG_OP IN ('INSERT', 'UPDATE') AND NEW.deleted_at IS NULL THEN
+        UPDATE todo_counters
+        SET open = open + (NOT NEW.completed)::INTEGER, completed = completed + NEW.completed::INTEGER;
+        IF NEW.owner_id IS NOT NULL THEN
+            INSERT INTO todo_owner_counters AS c (owner_id, open, completed)
+            VALUES (NEW.owner_id, (NOT NEW.completed)::INTEGER, NEW.completed::INTEGER)
+            ON CONFLICT (owner_id) DO UPDATE
+            SET open = c.open + EXCLUDED.open, completed = c.completed + EXCLUDED.completed;
+        END IF;
+        INSERT INTO label_todo_counters AS c (label_id, open, completed)
+        SELECT tl.label_id, (NOT NEW.completed)::INTEGER, NEW.completed::INTEGER
+        FROM todo_labels tl
+        WHERE tl.todo_id = NEW.id
+        ON CONFLICT (label_id) DO UPDATE
+        SET open = c.open + EXCLUDED.open, completed = c.completed + EXCLUDED.completed;
+    END IF;
+    RETURN NULL;
+END;
+$$ LANGUAGE plpgsql;
@@ -0,0 +1,9 @@
+-- client_id とミューテーションの ID はクライアントが採番するので、ユーザーをまたいで一意とは限らない。
+-- 連携先のキーと同じく持ち主ごとに一意にし、持ち主なしは 0 として比べる
+ALTER TABLE todos DROP CONSTRAINT todos_client_id_key;
+CREATE UNIQUE INDEX todos_owner_client_id_key ON todos ((COALESCE(owner_id, 0)), client_id);
+
+-- 既存の行は持ち主なしとして残す
+ALTER TABLE sync_mutations ADD COLUMN owner_id INTEGER REFERENCES users (id) ON DELETE CASCADE;
+ALTER TABLE sync_mutations DROP CONSTRAINT sync_mutations_pkey;
+CREATE UNIQUE INDEX sync_mutations_owner_mutation_id_key ON sync_mutations ((COALESCE(owner_id, 0)), mutation_id);
@@ -0,0 +1,7 @@
+-- 同じラベルを同時に付けると、NOT EXISTS を見てから INSERT するまでの間に割り込まれて行が重複する。
+-- 重複は一番古い行だけ残して消し (件数は todo_labels の trigger が戻す)、以後は一意制約で防ぐ
+DELETE FROM todo_labels tl
+USING todo_labels keep
+WHERE keep.todo_id = tl.todo_id AND keep.label_id = tl.label_id AND keep.id < tl.id;
+
+ALTER TABLE todo_labels ADD CONSTRAINT todo_labels_todo_id_label_id_key UNIQUE (todo_id, label_id);
@@ -0,0 +1,13 @@
+-- ログインやパスワードの変更などの記録。GET /me/security-events で本人に見せる。
+-- 登録されていない email へのログインの失敗も残すので、user_id は無いことがある
+CREATE TABLE auth_events (
+    id BIGSERIAL PRIMARY KEY,
+    user_id INTEGER REFERENCES users (id) ON DELETE CASCADE,
+    email TEXT NOT NULL,
+    -- 0: login, 1: login_failed, 2: token_issued, 3: password_changed
+    kind SMALLINT NOT NULL,
+    user_agent TEXT,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT now()
+);
+
+CREATE INDEX auth_events_user_id_created_at_idx ON auth_events (user_id, created_at);
@@ -0,0 +1,3 @@
+-- ログインの失敗が続いた email へのログインを断るときに、email ごとの直近の記録を引く。
+-- kind に 4: account_unlocked (管理者によるロックの解除) を足す
+CREATE INDEX auth_events_email_created_at_idx ON auth_events (email, created_at);
@@ -0,0 +1,21 @@
+-- ログインごとの refresh トークンの系列。トークンは署名付きで配り、ここには jti だけを置く。
+-- 使ったトークンは used_at を付けて同じ系列に次のトークンを足す。
+-- 使用済みのトークンがまた来たら盗まれたとみなして、系列ごと revoked_at を付けて無効にする
+CREATE TABLE refresh_token_families (
+    id UUID PRIMARY KEY,
+    user_id INTEGER NOT NULL REFERENCES users (id) ON DELETE CASCADE,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+    revoked_at TIMESTAMPTZ
+);
+
+CREATE INDEX refresh_token_families_user_id_idx ON refresh_token_families (user_id);
+
+CREATE TABLE refresh_tokens (
+    id UUID PRIMARY KEY,
+    family_id UUID NOT NULL REFERENCES refresh_token_families (id) ON DELETE CASCADE,
+    expires_at TIMESTAMPTZ NOT NULL,
+    used_at TIMESTAMPTZ,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT now()
+);
+
+CREATE INDEX refresh_tokens_family_id_idx ON refresh_tokens (family_id);
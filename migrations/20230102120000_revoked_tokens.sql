@@ -0,0 +1,9 @@
+-- POST /auth/revoke で失効させたアクセストークンの jti。
+-- トークンの期限が過ぎれば署名の検証で弾けるので、expires_at を過ぎた行は消してよい
+CREATE TABLE revoked_tokens (
+    id UUID PRIMARY KEY,
+    expires_at TIMESTAMPTZ NOT NULL,
+    revoked_at TIMESTAMPTZ NOT NULL DEFAULT now()
+);
+
+CREATE INDEX revoked_tokens_expires_at_idx ON revoked_tokens (expires_at);
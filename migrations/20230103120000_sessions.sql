@@ -0,0 +1,4 @@
+-- refresh トークンの系列をログインのセッションとして GET /me/sessions で見せる。
+-- last_seen_at はログインと refresh トークンの引き換えのたびに更新する
+ALTER TABLE refresh_token_families ADD COLUMN user_agent TEXT;
+ALTER TABLE refresh_token_families ADD COLUMN last_seen_at TIMESTAMPTZ NOT NULL DEFAULT now();
@@ -0,0 +1,184 @@
+use anyhow::{anyhow, Context};
+use hyper::{body::Bytes, client::HttpConnector, header, Body, Client, Method, Request};
+use serde_json::{json, Value};
+use std::{
+    collections::BTreeMap,
+    env,
+    str::FromStr,
+    time::{Duration, Instant},
+};
+
+// 起動中のサーバーに Todo の CRUD を並行して投げ、操作ごとのレイテンシとエラー率を出す。
+// デプロイ前のキャパシティの見積もり用
+//   LOADGEN_URL=http://127.0.0.1:3000 LOADGEN_CONCURRENCY=16 LOADGEN_ITERATIONS=100 cargo run --release --bin loadgen
+#[derive(Debug, Clone)]
+struct Config {
+    base_url: String,
+    // 同時に走らせるワーカー数
+    concurrency: usize,
+    // ワーカーごとに create -> find -> update -> delete を繰り返す回数
+    iterations: usize,
+}
+
+fn from_env_or<T: FromStr>(key: &str, default: T) -> T {
+    env::var(key).ok().and_then(|value| value.parse().ok()).unwrap_or(default)
+}
+
+impl Config {
+    fn from_env() -> Self {
+        Self {
+            base_url: from_env_or("LOADGEN_URL", "http://127.0.0.1:3000".to_string())
+                .trim_end_matches('/')
+                .to_string(),
+            concurrency: from_env_or("LOADGEN_CONCURRENCY", 8),
+            iterations: from_env_or("LOADGEN_ITERATIONS", 100),
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+struct Stats {
+    latencies: Vec<Duration>,
+    errors: usize,
+}
+
+// 操作名 -> 計測結果
+type Report = BTreeMap<&'static str, Stats>;
+
+struct Worker {
+    client: Client<HttpConnector>,
+    config: Config,
+    report: Report,
+}
+
+impl Worker {
+    // 2xx 以外と通信エラーはエラーとして数える。レイテンシはどちらの場合も記録する
+    async fn send(&mut self, name: &'static str, method: Method, path: &str, body: Option<Value>) -> Option<Bytes> {
+        let mut req = Request::builder()
+            .method(method)
+            .uri(format!("{}{}", self.config.base_url, path));
+        let body = match body {
+            Some(body) => {
+                req = req.header(header::CONTENT_TYPE, mime::APPLICATION_JSON.to_string());
+                Body::from(body.to_string())
+            }
+            None => Body::empty(),
+        };
+        let req = req.body(body).unwrap();
+
+        let started = Instant::now();
+        let result = async {
+            let res = self.client.request(req).await?;
+            let status = res.status();
+            let bytes = hyper::body::to_bytes(res.into_body()).await?;
+            if !status.is_success() {
+                return Err(anyhow!("{} {}", status, String::from_utf8_lossy(&bytes)));
+            }
+            Ok(bytes)
+        }
+        .await;
+
+        let stats = self.report.entry(name).or_default();
+        stats.latencies.push(started.elapsed());
+        match result {
+            Ok(bytes) => Some(bytes),
+            Err(e) => {
+                stats.errors += 1;
+                tracing::debug!("{} failed: {:?}", name, e);
+                None
+            }
+        }
+    }
+
+    async fn run(mut self) -> Report {
+        for i in 0..self.config.iterations {
+            let created = self
+                .send("create", Method::POST, "/todos", Some(json!({"text": format!("loadgen {}", i), "labels": []})))
+                .await;
+            let id = match created.and_then(|bytes| serde_json::from_slice::<Value>(&bytes).ok()) {
+                Some(todo) => todo["id"].clone(),
+                None => continue,
+            };
+            let path = format!("/todos/{}", id);
+            self.send("find", Method::GET, &path, None).await;
+            self.send("update", Method::PATCH, &path, Some(json!({"completed": true}))).await;
+            self.send("delete", Method::DELETE, &path, None).await;
+        }
+        self.report
+    }
+}
+
+fn percentile(sorted: &[Duration], p: f64) -> Duration {
+    if sorted.is_empty() {
+        return Duration::ZERO;
+    }
+    let index = ((sorted.len() as f64 * p).ceil() as usize).clamp(1, sorted.len()) - 1;
+    sorted[index]
+}
+
+fn print_report(report: &mut Report, elapsed: Duration) {
+    println!(
+        "{:<8} {:>8} {:>8} {:>8} {:>10} {:>10} {:>10} {:>10}",
+        "op", "count", "errors", "err%", "p50(ms)", "p90(ms)", "p99(ms)", "max(ms)"
+    );
+    let mut total = 0;
+    for (name, stats) in report.iter_mut() {
+        stats.latencies.sort();
+        let count = stats.latencies.len();
+        total += count;
+        let ms = |d: Duration| d.as_secs_f64() * 1000.0;
+        println!(
+            "{:<8} {:>8} {:>8} {:>8.2} {:>10.2} {:>10.2} {:>10.2} {:>10.2}",
+            name,
+            count,
+            stats.errors,
+            stats.errors as f64 * 100.0 / count.max(1) as f64,
+            ms(percentile(&stats.latencies, 0.5)),
+            ms(percentile(&stats.latencies, 0.9)),
+            ms(percentile(&stats.latencies, 0.99)),
+            ms(stats.latencies.last().copied().unwrap_or_default()),
+        );
+    }
+    println!(
+        "{} requests in {:.2}s ({:.1} req/s)",
+        total,
+        elapsed.as_secs_f64(),
+        total as f64 / elapsed.as_secs_f64()
+    );
+}
+
+#[tokio::main]
+async fn main() -> anyhow::Result<()> {
+    tracing_subscriber::fmt::init();
+    let config = Config::from_env();
+    let client = Client::new();
+    // 始める前にサーバーが起動しているかだけ確認しておく
+    client
+        .get(config.base_url.parse().context("invalid LOADGEN_URL")?)
+        .await
+        .with_context(|| format!("cannot connect to [{}]", config.base_url))?;
+    println!(
+        "{} workers x {} iterations against {}",
+        config.concurrency, config.iterations, config.base_url
+    );
+
+    let started = Instant::now();
+    let workers = (0..config.concurrency).map(|_| {
+        let worker = Worker {
+            client: client.clone(),
+            config: config.clone(),
+            report: Report::new(),
+        };
+        tokio::spawn(worker.run())
+    });
+    let mut report = Report::new();
+    for handle in futures::future::join_all(workers).await {
+        for (name, stats) in handle? {
+            let merged = report.entry(name).or_default();
+            merged.latencies.extend(stats.latencies);
+            merged.errors += stats.errors;
+        }
+    }
+    print_report(&mut report, started.elapsed());
+    Ok(())
+}
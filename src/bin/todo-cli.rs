@@ -0,0 +1,142 @@
+use anyhow::{anyhow, bail, Context};
+use rust_web::{
+    client::Client,
+    handlers::dto::{LabelResponse, TodoResponse},
+    repositories::{
+        label::CreateLabel,
+        todo::{CreateTodo, UpdateTodo},
+    },
+};
+use serde::Serialize;
+use std::env;
+
+// rust_web::client を使った API のクライアント。API の使い方の見本も兼ねる
+//   cargo run --bin todo-cli -- list --label 2
+//   cargo run --bin todo-cli -- add "buy milk" --label 2
+//   cargo run --bin todo-cli -- done 1
+//   cargo run --bin todo-cli -- labels [add NAME]
+// 接続先は --url か TODO_API_URL、トークンは --token か TODO_API_TOKEN (Bearer で送る)
+const USAGE: &str = "usage: todo-cli [--url URL] [--token TOKEN] [--json] <list [--label ID] | add TEXT [--label ID]... | done ID | labels [add NAME]>";
+
+#[derive(Debug)]
+struct Options {
+    base_url: String,
+    token: Option<String>,
+    json: bool,
+    command: Vec<String>,
+}
+
+impl Options {
+    fn parse(mut args: impl Iterator<Item = String>) -> anyhow::Result<Self> {
+        let mut options = Options {
+            base_url: env::var("TODO_API_URL").unwrap_or_else(|_| "http://127.0.0.1:3000".to_string()),
+            token: env::var("TODO_API_TOKEN").ok().filter(|token| !token.is_empty()),
+            json: false,
+            command: vec![],
+        };
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "--url" => options.base_url = args.next().ok_or_else(|| anyhow!("--url needs a value"))?,
+                "--token" => options.token = Some(args.next().ok_or_else(|| anyhow!("--token needs a value"))?),
+                "--json" => options.json = true,
+                "-h" | "--help" => bail!(USAGE),
+                _ => options.command.push(arg),
+            }
+        }
+        Ok(options)
+    }
+}
+
+// --label ID を繰り返し指定できる。それ以外は位置引数として返す
+fn split_labels<'a>(args: &[&'a str]) -> anyhow::Result<(Vec<&'a str>, Vec<i32>)> {
+    let mut positional = vec![];
+    let mut labels = vec![];
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        if *arg == "--label" {
+            let id = args.next().ok_or_else(|| anyhow!("--label needs a value"))?;
+            labels.push(id.parse().with_context(|| format!("invalid label id [{}]", id))?);
+        } else {
+            positional.push(*arg);
+        }
+    }
+    Ok((positional, labels))
+}
+
+fn print_todos(todos: &[TodoResponse]) {
+    println!("{:>6}  {:<4}  {:<40}  labels", "id", "done", "text");
+    for todo in todos {
+        let labels: Vec<&str> = todo.labels.iter().map(|label| label.name.as_str()).collect();
+        println!(
+            "{:>6}  {:<4}  {:<40}  {}",
+            todo.id,
+            if todo.completed { "x" } else { "" },
+            todo.text,
+            labels.join(", ")
+        );
+    }
+}
+
+fn print_labels(labels: &[LabelResponse]) {
+    println!("{:>6}  name", "id");
+    for label in labels {
+        println!("{:>6}  {}", label.id, label.name);
+    }
+}
+
+// --json ならレスポンスの JSON をそのまま、それ以外は表にして出す
+fn output<T: Serialize + ?Sized>(json: bool, value: &T, table: impl FnOnce(&T)) -> anyhow::Result<()> {
+    if json {
+        println!("{}", serde_json::to_string_pretty(value)?);
+    } else {
+        table(value);
+    }
+    Ok(())
+}
+
+async fn run(client: &Client, options: &Options) -> anyhow::Result<()> {
+    let json = options.json;
+    let command: Vec<&str> = options.command.iter().map(String::as_str).collect();
+    match command.as_slice() {
+        ["list", rest @ ..] => {
+            let (_, labels) = split_labels(rest)?;
+            let mut todos = client.todos().list().await?;
+            // 一覧 API にラベルの絞り込みは無いので、ここで絞る
+            todos.retain(|todo| labels.iter().all(|id| todo.labels.iter().any(|label| label.id == *id)));
+            output(json, todos.as_slice(), print_todos)?;
+        }
+        ["add", rest @ ..] => {
+            let (text, labels) = split_labels(rest)?;
+            if text.is_empty() {
+                bail!(USAGE);
+            }
+            let todo = client.todos().create(CreateTodo::new(text.join(" "), labels)).await?;
+            output(json, &todo, |todo| print_todos(std::slice::from_ref(todo)))?;
+        }
+        ["done", id] => {
+            let id: i32 = id.parse().with_context(|| format!("invalid todo id [{}]", id))?;
+            let todo = client.todos().update(id, UpdateTodo::new(None, Some(true), None)).await?;
+            output(json, &todo, |todo| print_todos(std::slice::from_ref(todo)))?;
+        }
+        ["labels"] => {
+            let labels = client.labels().list().await?;
+            output(json, labels.as_slice(), print_labels)?;
+        }
+        ["labels", "add", name] => {
+            let label = client.labels().create(CreateLabel::new(name.to_string())).await?;
+            output(json, &label, |label| print_labels(std::slice::from_ref(label)))?;
+        }
+        _ => bail!(USAGE),
+    }
+    Ok(())
+}
+
+#[tokio::main]
+async fn main() -> anyhow::Result<()> {
+    let options = Options::parse(env::args().skip(1))?;
+    let mut client = Client::new(options.base_url.clone());
+    if let Some(token) = &options.token {
+        client = client.with_token(token.clone());
+    }
+    run(&client, &options).await
+}
@@ -0,0 +1,98 @@
+use axum::async_trait;
+use std::{
+    fmt,
+    path::{Component, Path, PathBuf},
+    sync::Arc,
+};
+
+// スナップショットなどの大きめのファイルの置き場所。キーは / 区切りの相対パス
+#[async_trait]
+pub trait BlobStore: fmt::Debug + Send + Sync + 'static {
+    async fn put(&self, key: &str, data: Vec<u8>) -> anyhow::Result<()>;
+    // 無ければ None
+    async fn get(&self, key: &str) -> anyhow::Result<Option<Vec<u8>>>;
+    // prefix 直下のキー (prefix は含まない) を名前順で返す
+    async fn list(&self, prefix: &str) -> anyhow::Result<Vec<String>>;
+}
+
+pub type SharedBlobStore = Arc<dyn BlobStore>;
+
+// ローカルのディレクトリ (ボリュームやネットワークドライブをマウントしたもの) に置く
+#[derive(Debug, Clone)]
+pub struct FsBlobStore {
+    root: PathBuf,
+}
+
+impl FsBlobStore {
+    pub fn new(root: impl Into<PathBuf>) -> Self {
+        Self { root: root.into() }
+    }
+
+    // .. や絶対パスで root の外を指せないようにする
+    fn path(&self, key: &str) -> anyhow::Result<PathBuf> {
+        let relative = Path::new(key);
+        if key.is_empty() || !relative.components().all(|component| matches!(component, Component::Normal(_))) {
+            anyhow::bail!("invalid blob key: [{}]", key);
+        }
+        Ok(self.root.join(relative))
+    }
+}
+
+#[async_trait]
+impl BlobStore for FsBlobStore {
+    async fn put(&self, key: &str, data: Vec<u8>) -> anyhow::Result<()> {
+        let path = self.path(key)?;
+        if let Some(parent) = path.parent() {
+            tokio::fs::create_dir_all(parent).await?;
+        }
+        // 書きかけのファイルを読ませないように、別名で書いてから rename する
+        let partial = path.with_extension("partial");
+        tokio::fs::write(&partial, data).await?;
+        tokio::fs::rename(&partial, &path).await?;
+        Ok(())
+    }
+
+    async fn get(&self, key: &str) -> anyhow::Result<Option<Vec<u8>>> {
+        match tokio::fs::read(self.path(key)?).await {
+            Ok(data) => Ok(Some(data)),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    async fn list(&self, prefix: &str) -> anyhow::Result<Vec<String>> {
+        let mut entries = match tokio::fs::read_dir(self.path(prefix)?).await {
+            Ok(entries) => entries,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(vec![]),
+            Err(e) => return Err(e.into()),
+        };
+        let mut names = vec![];
+        while let Some(entry) = entries.next_entry().await? {
+            names.push(entry.file_name().to_string_lossy().into_owned());
+        }
+        names.sort();
+        Ok(names)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[tokio::test]
+    async fn should_store_blobs_under_root() {
+        let root = std::env::temp_dir().join(format!("blob-{}", uuid::Uuid::new_v4()));
+        let store = FsBlobStore::new(&root);
+
+        assert_eq!(None, store.get("snapshots/a/labels.copy").await.unwrap());
+        assert!(store.list("snapshots").await.unwrap().is_empty());
+        store.put("snapshots/b/labels.copy", b"1\thome\n".to_vec()).await.unwrap();
+        store.put("snapshots/a/labels.copy", vec![]).await.unwrap();
+        assert_eq!(Some(b"1\thome\n".to_vec()), store.get("snapshots/b/labels.copy").await.unwrap());
+        assert_eq!(vec!["a", "b"], store.list("snapshots").await.unwrap());
+
+        assert!(store.put("../outside", vec![]).await.is_err());
+        assert!(store.get("/etc/passwd").await.is_err());
+        tokio::fs::remove_dir_all(root).await.unwrap();
+    }
+}
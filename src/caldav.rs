@@ -0,0 +1,184 @@
+use chrono::{DateTime, Utc};
+
+use crate::{feed::escape, repositories::todo::TodoEntity};
+
+pub const DAV_PREFIX: &str = "/dav";
+pub const COLLECTION_PATH: &str = "/dav/todos/";
+
+// CalDAV (RFC 4791) で扱う VTODO。クライアントの細かいプロパティ (期限や繰り返しなど) は保存できないので、
+// SUMMARY と STATUS だけを読み書きする
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct VTodo {
+    pub summary: String,
+    pub completed: bool,
+}
+
+pub fn href(todo: &TodoEntity) -> String {
+    format!("{}{}.ics", COLLECTION_PATH, todo.id)
+}
+
+pub fn calendar(todo: &TodoEntity, stamp: DateTime<Utc>) -> String {
+    let mut lines = vec![
+        "BEGIN:VCALENDAR".to_string(),
+        "VERSION:2.0".to_string(),
+        "PRODID:-//rust_web//todos//EN".to_string(),
+        "BEGIN:VTODO".to_string(),
+        format!("UID:todo-{}", todo.id),
+        format!("DTSTAMP:{}", stamp.format("%Y%m%dT%H%M%SZ")),
+        format!("SUMMARY:{}", escape_text(&todo.text)),
+        format!("STATUS:{}", if todo.completed { "COMPLETED" } else { "NEEDS-ACTION" }),
+        format!("SEQUENCE:{}", todo.version),
+    ];
+    if !todo.labels.is_empty() {
+        let names: Vec<String> = todo.labels.iter().map(|label| escape_text(&label.name)).collect();
+        lines.push(format!("CATEGORIES:{}", names.join(",")));
+    }
+    lines.push("END:VTODO".to_string());
+    lines.push("END:VCALENDAR".to_string());
+    lines.iter().map(|line| fold(line)).collect()
+}
+
+// 1 行は 75 オクテットまで。超える分は CRLF + 空白で折り返す (文字の途中では切らない)
+fn fold(line: &str) -> String {
+    let mut folded = String::with_capacity(line.len() + 2);
+    let mut width = 0;
+    for c in line.chars() {
+        if width + c.len_utf8() > 75 {
+            folded.push_str("\r\n ");
+            width = 1;
+        }
+        folded.push(c);
+        width += c.len_utf8();
+    }
+    folded.push_str("\r\n");
+    folded
+}
+
+fn escape_text(text: &str) -> String {
+    text.replace('\\', "\\\\")
+        .replace(';', "\\;")
+        .replace(',', "\\,")
+        .replace("\r\n", "\\n")
+        .replace('\n', "\\n")
+}
+
+fn unescape_text(text: &str) -> String {
+    let mut unescaped = String::with_capacity(text.len());
+    let mut chars = text.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            unescaped.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('n') | Some('N') => unescaped.push('\n'),
+            Some(c) => unescaped.push(c),
+            None => {}
+        }
+    }
+    unescaped
+}
+
+// PUT で受け取った iCalendar から最初の VTODO を読む。VTODO や SUMMARY が無ければ None
+pub fn parse_vtodo(body: &str) -> Option<VTodo> {
+    let unfolded = body.replace("\r\n ", "").replace("\r\n\t", "").replace("\n ", "").replace("\n\t", "");
+    let mut in_vtodo = false;
+    let mut summary = None;
+    let mut completed = false;
+    for line in unfolded.lines() {
+        let (name, value) = match line.split_once(':') {
+            Some(pair) => pair,
+            None => continue,
+        };
+        // SUMMARY;LANGUAGE=ja:... のようなパラメータは読み捨てる
+        let name = name.split(';').next().unwrap_or_default().to_ascii_uppercase();
+        match (name.as_str(), value) {
+            ("BEGIN", "VTODO") => in_vtodo = true,
+            ("END", "VTODO") => break,
+            _ if !in_vtodo => {}
+            ("SUMMARY", value) => summary = Some(unescape_text(value)),
+            ("STATUS", value) => completed = value.eq_ignore_ascii_case("COMPLETED"),
+            ("COMPLETED", _) => completed = true,
+            _ => {}
+        }
+    }
+    summary.map(|summary| VTodo { summary, completed })
+}
+
+// 207 Multi-Status の 1 リソース分。props は組み立て済みの XML 片
+pub fn response(href: &str, props: &[String]) -> String {
+    format!(
+        "<d:response><d:href>{}</d:href><d:propstat><d:prop>{}</d:prop><d:status>HTTP/1.1 200 OK</d:status></d:propstat></d:response>",
+        escape(href),
+        props.concat()
+    )
+}
+
+pub fn multistatus(responses: &[String]) -> String {
+    format!(
+        "<?xml version=\"1.0\" encoding=\"utf-8\"?>\n<d:multistatus xmlns:d=\"DAV:\" xmlns:c=\"urn:ietf:params:xml:ns:caldav\" xmlns:cs=\"http://calendarserver.org/ns/\">{}</d:multistatus>\n",
+        responses.concat()
+    )
+}
+
+pub fn prop(name: &str, value: &str) -> String {
+    format!("<{}>{}</{}>", name, escape(value), name)
+}
+
+// REPORT (calendar-multiget) の本文から href を拾う。名前空間の接頭辞はクライアントによって違う
+pub fn requested_hrefs(body: &str) -> Vec<String> {
+    let pattern = regex::Regex::new(r"<(?:[A-Za-z0-9_-]+:)?href>\s*([^<]*?)\s*</").unwrap();
+    pattern
+        .captures_iter(body)
+        .map(|captures| captures[1].replace("&amp;", "&"))
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::repositories::label::Label;
+
+    #[test]
+    fn should_round_trip_vtodo() {
+        let todo = TodoEntity {
+            completed: true,
+            labels: vec![Label::new(1, "home".to_string())],
+            version: 4,
+            ..TodoEntity::new(7, "milk, eggs; and\\more".to_string())
+        };
+        let ics = calendar(&todo, "2022-12-18T09:30:00Z".parse().unwrap());
+        assert!(ics.contains("SUMMARY:milk\\, eggs\\; and\\\\more\r\n"), "{}", ics);
+        assert!(ics.contains("DTSTAMP:20221218T093000Z\r\n"));
+        assert!(ics.contains("CATEGORIES:home\r\n"));
+        assert_eq!(
+            Some(VTodo { summary: "milk, eggs; and\\more".to_string(), completed: true }),
+            parse_vtodo(&ics)
+        );
+    }
+
+    #[test]
+    fn should_fold_and_unfold_long_lines() {
+        let todo = TodoEntity::new(1, "あ".repeat(40));
+        let ics = calendar(&todo, Utc::now());
+        assert!(ics.split("\r\n").all(|line| line.len() <= 75), "{}", ics);
+        assert_eq!("あ".repeat(40), parse_vtodo(&ics).unwrap().summary);
+    }
+
+    #[test]
+    fn should_read_client_vtodo() {
+        let body = "BEGIN:VCALENDAR\r\nBEGIN:VEVENT\r\nSUMMARY:not this\r\nEND:VEVENT\r\nBEGIN:VTODO\r\nUID:abc\r\nSUMMARY;LANGUAGE=en:buy\r\n  milk\r\nCOMPLETED:20221218T093000Z\r\nEND:VTODO\r\nEND:VCALENDAR\r\n";
+        assert_eq!(Some(VTodo { summary: "buy milk".to_string(), completed: true }), parse_vtodo(body));
+        assert_eq!(None, parse_vtodo("BEGIN:VCALENDAR\r\nEND:VCALENDAR\r\n"));
+    }
+
+    #[test]
+    fn should_find_requested_hrefs() {
+        let body = r#"<C:calendar-multiget xmlns:D="DAV:" xmlns:C="urn:ietf:params:xml:ns:caldav">
+            <D:prop><D:getetag/></D:prop>
+            <D:href>/dav/todos/1.ics</D:href>
+            <href>/dav/todos/2.ics</href>
+        </C:calendar-multiget>"#;
+        assert_eq!(vec!["/dav/todos/1.ics", "/dav/todos/2.ics"], requested_hrefs(body));
+    }
+}
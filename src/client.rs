@@ -0,0 +1,211 @@
+use hyper::{client::HttpConnector, header, Body, Method, Request, StatusCode};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use thiserror::Error;
+
+use crate::{
+    error_code::ErrorCode,
+    handlers::dto::{LabelDetailResponse, LabelResponse, TodoResponse},
+    repositories::{
+        label::{CreateLabel, UpdateLabel},
+        todo::{CreateTodo, UpdateTodo},
+    },
+};
+
+// この API を呼ぶ側の Rust のサービス向けのクライアント。
+// リクエストとレスポンスはサーバーと同じ型を使うので、API の形が変わればここもコンパイルエラーになる
+//   let client = Client::new("http://127.0.0.1:3000").with_token(token);
+//   let todo = client.todos().create(CreateTodo::new("buy milk".to_string(), vec![])).await?;
+#[derive(Debug, Clone)]
+pub struct Client {
+    http: hyper::Client<HttpConnector>,
+    base_url: String,
+    token: Option<String>,
+}
+
+#[derive(Debug, Error)]
+pub enum ClientError {
+    // サーバーが 2xx 以外を返した。code はエラーレスポンスの JSON に付いていたもの
+    #[error("{status}: {message}")]
+    Status {
+        status: StatusCode,
+        code: Option<ErrorCode>,
+        message: String,
+    },
+    #[error("invalid request: {0}")]
+    Request(#[from] hyper::http::Error),
+    #[error(transparent)]
+    Http(#[from] hyper::Error),
+    #[error("invalid response: {0}")]
+    Json(#[from] serde_json::Error),
+}
+
+#[derive(Debug, Default, Deserialize)]
+struct ErrorBody {
+    #[serde(default)]
+    error: String,
+    code: Option<ErrorCode>,
+}
+
+impl Client {
+    pub fn new(base_url: impl Into<String>) -> Self {
+        Self {
+            http: hyper::Client::new(),
+            base_url: base_url.into().trim_end_matches('/').to_string(),
+            token: None,
+        }
+    }
+
+    // Authorization: Bearer で送る
+    pub fn with_token(mut self, token: impl Into<String>) -> Self {
+        self.token = Some(token.into());
+        self
+    }
+
+    pub fn todos(&self) -> Todos<'_> {
+        Todos { client: self }
+    }
+
+    pub fn labels(&self) -> Labels<'_> {
+        Labels { client: self }
+    }
+
+    async fn send<B: Serialize, T: DeserializeOwned>(
+        &self,
+        method: Method,
+        path: &str,
+        body: Option<&B>,
+    ) -> Result<T, ClientError> {
+        let bytes = self.send_raw(method, path, body).await?;
+        Ok(serde_json::from_slice(&bytes)?)
+    }
+
+    async fn send_raw<B: Serialize>(
+        &self,
+        method: Method,
+        path: &str,
+        body: Option<&B>,
+    ) -> Result<hyper::body::Bytes, ClientError> {
+        let mut req = Request::builder()
+            .method(method)
+            .uri(format!("{}{}", self.base_url, path));
+        if let Some(token) = &self.token {
+            req = req.header(header::AUTHORIZATION, format!("Bearer {}", token));
+        }
+        let body = match body {
+            Some(body) => {
+                req = req.header(header::CONTENT_TYPE, mime::APPLICATION_JSON.to_string());
+                Body::from(serde_json::to_vec(body)?)
+            }
+            None => Body::empty(),
+        };
+        let res = self.http.request(req.body(body)?).await?;
+        let status = res.status();
+        let bytes = hyper::body::to_bytes(res.into_body()).await?;
+        if !status.is_success() {
+            let error: ErrorBody = serde_json::from_slice(&bytes).unwrap_or_default();
+            return Err(ClientError::Status {
+                status,
+                code: error.code,
+                message: error.error,
+            });
+        }
+        Ok(bytes)
+    }
+}
+
+pub struct Todos<'a> {
+    client: &'a Client,
+}
+
+impl Todos<'_> {
+    pub async fn list(&self) -> Result<Vec<TodoResponse>, ClientError> {
+        self.client.send::<(), _>(Method::GET, "/todos", None).await
+    }
+
+    pub async fn find(&self, id: i32) -> Result<TodoResponse, ClientError> {
+        self.client.send::<(), _>(Method::GET, &format!("/todos/{}", id), None).await
+    }
+
+    pub async fn create(&self, payload: CreateTodo) -> Result<TodoResponse, ClientError> {
+        self.client.send(Method::POST, "/todos", Some(&payload)).await
+    }
+
+    pub async fn update(&self, id: i32, payload: UpdateTodo) -> Result<TodoResponse, ClientError> {
+        self.client.send(Method::PATCH, &format!("/todos/{}", id), Some(&payload)).await
+    }
+
+    pub async fn delete(&self, id: i32) -> Result<(), ClientError> {
+        self.client.send_raw::<()>(Method::DELETE, &format!("/todos/{}", id), None).await?;
+        Ok(())
+    }
+}
+
+pub struct Labels<'a> {
+    client: &'a Client,
+}
+
+impl Labels<'_> {
+    pub async fn list(&self) -> Result<Vec<LabelResponse>, ClientError> {
+        self.client.send::<(), _>(Method::GET, "/labels", None).await
+    }
+
+    pub async fn find(&self, id: i32) -> Result<LabelDetailResponse, ClientError> {
+        self.client.send::<(), _>(Method::GET, &format!("/labels/{}", id), None).await
+    }
+
+    pub async fn create(&self, payload: CreateLabel) -> Result<LabelResponse, ClientError> {
+        self.client.send(Method::POST, "/labels", Some(&payload)).await
+    }
+
+    pub async fn update(&self, id: i32, payload: UpdateLabel) -> Result<LabelResponse, ClientError> {
+        self.client.send(Method::PATCH, &format!("/labels/{}", id), Some(&payload)).await
+    }
+
+    pub async fn delete(&self, id: i32) -> Result<(), ClientError> {
+        self.client.send_raw::<()>(Method::DELETE, &format!("/labels/{}", id), None).await?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{
+        config::Config,
+        test_support::{memory_app, LabelRepositoryForMemory, TodoRepositoryForMemory},
+    };
+    use std::net::TcpListener;
+
+    #[tokio::test]
+    async fn should_call_api_with_typed_payloads() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let app = memory_app(Config::default(), TodoRepositoryForMemory::new(), LabelRepositoryForMemory::new());
+        tokio::spawn(axum::Server::from_tcp(listener).unwrap().serve(app.into_make_service()));
+        let client = Client::new(format!("http://{}/", addr));
+
+        let label = client.labels().create(CreateLabel::new("home".to_string())).await.unwrap();
+        let todo = client
+            .todos()
+            .create(CreateTodo::new("buy milk".to_string(), vec![label.id]))
+            .await
+            .unwrap();
+        assert_eq!(vec![label.id], todo.labels.iter().map(|label| label.id).collect::<Vec<_>>());
+        let todo = client
+            .todos()
+            .update(todo.id, UpdateTodo::new(None, Some(true), None))
+            .await
+            .unwrap();
+        assert!(todo.completed);
+        assert_eq!(vec![todo.clone()], client.todos().list().await.unwrap());
+
+        client.todos().delete(todo.id).await.unwrap();
+        match client.todos().find(todo.id).await {
+            Err(ClientError::Status { status, code, .. }) => {
+                assert_eq!(StatusCode::NOT_FOUND, status);
+                assert_eq!(Some(ErrorCode::TodoNotFound), code);
+            }
+            other => panic!("unexpected result: {:?}", other),
+        }
+    }
+}
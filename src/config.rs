@@ -0,0 +1,655 @@
+use axum::http::HeaderValue;
+use figment::{
+    providers::{Env, Format, Serialized, Toml},
+    Figment,
+};
+use serde::{Deserialize, Deserializer, Serialize};
+use std::{collections::BTreeMap, env, fmt, net::SocketAddr, str::FromStr, sync::Arc, time::Duration};
+
+use crate::{
+    blob::{FsBlobStore, SharedBlobStore},
+    db::StartupRetry,
+    handlers::{
+        admin::{AdminConfig, SnapshotConfig, DEFAULT_RETENTION_DAYS},
+        auth::{AuthConfig, LoginLockout},
+        feed::FeedConfig,
+        pagination::PublicBaseUrl,
+        StrictJson,
+    },
+    metrics::Metrics,
+    middleware::{cache_control::CacheControl, load_shed::ConcurrencyLimits, timeout::RouteTimeouts},
+    moderation::{self, SharedContentFilter},
+    repositories::{
+        cache::{CacheConfig, QueryCache},
+        quota::Quota,
+        sync::ConflictPolicy,
+    },
+};
+
+const DEFAULT_CONFIG_FILE: &str = "config.toml";
+const MIN_JWT_SECRET_LENGTH: usize = 32;
+
+// APP_ENV で選ぶ実行環境。環境ごとのデフォルト値と、設定ファイルのどのテーブルを使うかを決める
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Profile {
+    #[default]
+    Dev,
+    Staging,
+    Prod,
+}
+
+impl FromStr for Profile {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_ascii_lowercase().as_str() {
+            "dev" | "development" => Ok(Profile::Dev),
+            "staging" | "stg" => Ok(Profile::Staging),
+            "prod" | "production" => Ok(Profile::Prod),
+            other => anyhow::bail!("unknown APP_ENV [{}] (expected dev, staging or prod)", other),
+        }
+    }
+}
+
+impl Profile {
+    // 未設定なら dev
+    pub fn from_env() -> anyhow::Result<Self> {
+        env::var("APP_ENV").map_or(Ok(Profile::Dev), |value| value.parse())
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Profile::Dev => "dev",
+            Profile::Staging => "staging",
+            Profile::Prod => "prod",
+        }
+    }
+
+    // 認証を切ると Todo とラベルが誰にでも見えるので、認証なしで動かしてよいのは dev (テストも含む) だけ
+    pub fn requires_auth(&self) -> bool {
+        *self != Profile::Dev
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LogFormat {
+    // 人が読む用
+    Pretty,
+    // ログ基盤に流す用。1 行 1 JSON
+    Json,
+}
+
+// ログに出さない値。中身は expose で取り出す
+#[derive(Clone, PartialEq, Eq, Serialize)]
+#[serde(transparent)]
+pub struct Secret(String);
+
+// 環境変数の値は数値としても解釈されるので、数字だけのトークンも文字列として受け取る
+impl<'de> Deserialize<'de> for Secret {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        #[derive(Deserialize)]
+        #[serde(untagged)]
+        enum Raw {
+            Text(String),
+            Number(serde_json::Number),
+        }
+        Ok(match Raw::deserialize(deserializer)? {
+            Raw::Text(value) => Secret(value),
+            Raw::Number(value) => Secret(value.to_string()),
+        })
+    }
+}
+
+impl Secret {
+    pub fn new(value: impl Into<String>) -> Self {
+        Self(value.into())
+    }
+
+    pub fn expose(&self) -> &str {
+        &self.0
+    }
+}
+
+impl fmt::Debug for Secret {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("***")
+    }
+}
+
+// DATABASE_URL のパスワードはログに出さない
+#[derive(Clone, PartialEq, Eq, Serialize, Deserialize, Default)]
+#[serde(transparent)]
+pub struct DatabaseUrl(String);
+
+impl DatabaseUrl {
+    pub fn new(url: impl Into<String>) -> Self {
+        Self(url.into())
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+}
+
+impl fmt::Debug for DatabaseUrl {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let (scheme, rest) = match self.0.split_once("://") {
+            Some(split) => split,
+            None => return f.write_str(&self.0),
+        };
+        match rest.rsplit_once('@') {
+            Some((userinfo, host)) => match userinfo.split_once(':') {
+                Some((user, _)) => write!(f, "{}://{}:***@{}", scheme, user, host),
+                None => f.write_str(&self.0),
+            },
+            None => f.write_str(&self.0),
+        }
+    }
+}
+
+// 環境変数ではカンマ区切りの文字列、設定ファイルでは配列で書ける
+fn comma_separated<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<String>, D::Error> {
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum List {
+        One(String),
+        Many(Vec<String>),
+    }
+    let items = match List::deserialize(deserializer)? {
+        List::One(items) => items.split(',').map(str::to_string).collect(),
+        List::Many(items) => items,
+    };
+    Ok(items
+        .into_iter()
+        .map(|item| item.trim().to_string())
+        .filter(|item| !item.is_empty())
+        .collect())
+}
+
+// 環境変数では "/todos=20,/admin/backup=1" のような文字列、設定ファイルではテーブルで書ける
+fn limits_by_route<'de, D: Deserializer<'de>>(deserializer: D) -> Result<BTreeMap<String, usize>, D::Error> {
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum Limits {
+        One(String),
+        Many(BTreeMap<String, usize>),
+    }
+    match Limits::deserialize(deserializer)? {
+        Limits::Many(limits) => Ok(limits),
+        Limits::One(limits) => limits
+            .split(',')
+            .map(str::trim)
+            .filter(|item| !item.is_empty())
+            .map(|item| {
+                let (route, limit) = item
+                    .rsplit_once('=')
+                    .ok_or_else(|| serde::de::Error::custom(format!("expected route=limit, got [{}]", item)))?;
+                let limit = limit
+                    .trim()
+                    .parse()
+                    .map_err(|_| serde::de::Error::custom(format!("invalid limit for [{}]", route)))?;
+                Ok((route.trim().to_string(), limit))
+            })
+            .collect(),
+    }
+}
+
+// 起動時に決まる設定。優先順位は 環境変数 > 設定ファイル ([APP_ENV のテーブル] > [default]) > プロファイルのデフォルト。
+// キーは環境変数名を小文字にしたもの (ADMIN_TOKEN なら admin_token)
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Config {
+    #[serde(skip)]
+    pub profile: Profile,
+    pub listen_addr: SocketAddr,
+    // 新しいバージョンを起動してから古いプロセスを止める入れ替えのために、SO_REUSEPORT で bind する
+    pub reuse_port: bool,
+    pub database_url: DatabaseUrl,
+    pub log_format: LogFormat,
+    // 空ならどのオリジンからのクロスオリジンリクエストも許可しない
+    #[serde(deserialize_with = "comma_separated")]
+    pub cors_allowed_origins: Vec<String>,
+    pub db_max_connections: u32,
+    // 起動時に開いておくコネクション数。0 ならウォームアップしない
+    pub db_pool_warmup_connections: u32,
+    pub db_startup_initial_backoff_ms: u64,
+    pub db_startup_max_backoff_ms: u64,
+    pub db_startup_max_wait_secs: u64,
+    pub db_health_check_interval_secs: u64,
+    // leader のリースの長さ。leader が落ちてから他のインスタンスが引き継ぐまでの最大の時間になる
+    pub leader_lease_secs: u64,
+    // leader が purge_interval_secs ごとに、POST /admin/maintenance/purge と同じ掃除をする。0 なら定期実行しない
+    pub purge_interval_secs: u64,
+    pub purge_retention_days: i32,
+    // 0 ならクエリ結果をキャッシュしない
+    pub query_cache_max_capacity: u64,
+    pub query_cache_ttl_secs: u64,
+    // ユーザーごとの上限 (ゴミ箱の Todo は数えない)。未設定なら無制限
+    pub quota_max_todos: Option<i64>,
+    pub quota_max_labels: Option<i64>,
+    pub cache_control_list_max_age_secs: u64,
+    // 同時に処理するリクエスト数の上限。0 なら制限しない
+    pub max_concurrent_requests: usize,
+    // ルートのパターン (/todos/:id など) ごとの上限
+    #[serde(deserialize_with = "limits_by_route")]
+    pub route_max_concurrent_requests: BTreeMap<String, usize>,
+    pub load_shed_retry_after_secs: u64,
+    // これより遅いリポジトリのクエリをログに出す。0 なら出さない
+    pub slow_query_threshold_ms: u64,
+    // リクエストのタイムアウト。読み込み (GET / HEAD) と書き込みと、インポート・エクスポートなどの重いルートとで分ける
+    pub request_timeout_read_ms: u64,
+    pub request_timeout_write_ms: u64,
+    pub request_timeout_long_secs: u64,
+    #[serde(deserialize_with = "comma_separated")]
+    pub request_timeout_long_routes: Vec<String>,
+    // 未設定なら管理用 API は無効
+    pub admin_token: Option<Secret>,
+    // 未設定なら /feeds/todos.atom は無効
+    pub feed_token: Option<Secret>,
+    // /todos と /labels の Bearer トークン (JWT, HS256) の署名鍵。
+    // 未設定なら認証せず、Todo とラベルをユーザーで分けない。dev 以外では必須
+    pub jwt_secret: Option<Secret>,
+    // アクセストークンは短くし、POST /auth/refresh で refresh トークンと引き換えに取り直させる
+    pub jwt_expiry_secs: u64,
+    pub refresh_token_expiry_secs: u64,
+    // login_lockout_secs のうちにこの回数ログインに失敗した email は、login_lockout_secs の間ロックする。0 ならロックしない
+    pub login_max_failures: u32,
+    pub login_lockout_secs: u64,
+    // スナップショットを書き出すディレクトリ。未設定ならスナップショットの API は無効
+    pub snapshot_dir: Option<String>,
+    // Link ヘッダに付けるベース URL。未設定なら相対 URL
+    pub public_base_url: Option<String>,
+    pub json_strict: bool,
+    pub sync_conflict_policy: ConflictPolicy,
+    #[serde(deserialize_with = "comma_separated")]
+    pub content_denylist: Vec<String>,
+    pub access_log_enabled: bool,
+    // /admin/query-plans を有効にする
+    pub query_plans_enabled: bool,
+    // /dav/ で Todo を CalDAV (VTODO) として公開する
+    pub caldav_enabled: bool,
+    // 未設定なら Sentry に送らない
+    pub sentry_dsn: Option<Secret>,
+    pub sentry_environment: Option<String>,
+}
+
+// テストや組み込み用。dev のデフォルトで、DB の URL は空
+impl Default for Config {
+    fn default() -> Self {
+        Self::defaults(Profile::Dev)
+    }
+}
+
+impl Config {
+    pub fn defaults(profile: Profile) -> Self {
+        let (log_format, cors_allowed_origins, db_max_connections) = match profile {
+            Profile::Dev => (LogFormat::Pretty, vec!["http://localhost:3001".to_string()], 5),
+            // フロントエンドのオリジンは環境ごとに違うので、CORS_ALLOWED_ORIGINS で明示させる
+            Profile::Staging => (LogFormat::Json, vec![], 10),
+            Profile::Prod => (LogFormat::Json, vec![], 20),
+        };
+        let startup_retry = StartupRetry::default();
+        let timeouts = RouteTimeouts::default();
+        Self {
+            profile,
+            listen_addr: SocketAddr::from(([127, 0, 0, 1], 3000)),
+            reuse_port: false,
+            database_url: DatabaseUrl::default(),
+            log_format,
+            cors_allowed_origins,
+            db_max_connections,
+            db_pool_warmup_connections: 0,
+            db_startup_initial_backoff_ms: startup_retry.initial_backoff.as_millis() as u64,
+            db_startup_max_backoff_ms: startup_retry.max_backoff.as_millis() as u64,
+            db_startup_max_wait_secs: startup_retry.max_duration.as_secs(),
+            db_health_check_interval_secs: 10,
+            leader_lease_secs: 15,
+            purge_interval_secs: 3600,
+            purge_retention_days: DEFAULT_RETENTION_DAYS,
+            query_cache_max_capacity: 0,
+            query_cache_ttl_secs: 30,
+            quota_max_todos: None,
+            quota_max_labels: None,
+            cache_control_list_max_age_secs: CacheControl::default().list_max_age,
+            max_concurrent_requests: 0,
+            route_max_concurrent_requests: BTreeMap::new(),
+            load_shed_retry_after_secs: ConcurrencyLimits::default().retry_after.as_secs(),
+            slow_query_threshold_ms: 500,
+            request_timeout_read_ms: timeouts.read.as_millis() as u64,
+            request_timeout_write_ms: timeouts.write.as_millis() as u64,
+            request_timeout_long_secs: timeouts.long.as_secs(),
+            request_timeout_long_routes: timeouts.long_routes.into_iter().collect(),
+            admin_token: None,
+            feed_token: None,
+            jwt_secret: None,
+            jwt_expiry_secs: 900,
+            refresh_token_expiry_secs: 30 * 24 * 3600,
+            login_max_failures: 5,
+            login_lockout_secs: 900,
+            snapshot_dir: None,
+            public_base_url: None,
+            json_strict: false,
+            sync_conflict_policy: ConflictPolicy::default(),
+            content_denylist: vec![],
+            access_log_enabled: false,
+            query_plans_enabled: profile != Profile::Prod,
+            caldav_enabled: false,
+            sentry_dsn: None,
+            sentry_environment: Some(profile.as_str().to_string()),
+        }
+    }
+
+    // APP_ENV でプロファイルを選び、APP_CONFIG (未設定なら config.toml) と環境変数を重ねる。
+    // 設定ファイルは無くてもよい
+    pub fn load() -> anyhow::Result<Self> {
+        let profile = Profile::from_env()?;
+        let path = env::var("APP_CONFIG").unwrap_or_else(|_| DEFAULT_CONFIG_FILE.to_string());
+        Self::extract(
+            profile,
+            Figment::from(Toml::file(path).nested()).merge(Env::raw().global()),
+        )
+    }
+
+    // sources は [default] や [prod] のテーブルを持つ。global のものはプロファイルに関係なく最優先
+    fn extract(profile: Profile, sources: Figment) -> anyhow::Result<Self> {
+        let mut config: Self = Figment::from(Serialized::defaults(Self::defaults(profile)))
+            .merge(sources)
+            .select(profile.as_str())
+            .extract()?;
+        config.profile = profile;
+        config.validate()?;
+        Ok(config)
+    }
+
+    fn validate(&self) -> anyhow::Result<()> {
+        if self.database_url.as_str().is_empty() {
+            anyhow::bail!("undefined [database_url] (set DATABASE_URL or database_url in the config file)");
+        }
+        if self.db_max_connections == 0 {
+            anyhow::bail!("[db_max_connections] must be greater than 0");
+        }
+        // プールの上限を超えてはウォームアップできない
+        if self.db_pool_warmup_connections > self.db_max_connections {
+            anyhow::bail!(
+                "[db_pool_warmup_connections] ({}) must not exceed [db_max_connections] ({})",
+                self.db_pool_warmup_connections,
+                self.db_max_connections
+            );
+        }
+        if self.db_health_check_interval_secs == 0 {
+            anyhow::bail!("[db_health_check_interval_secs] must be greater than 0");
+        }
+        if self.leader_lease_secs == 0 {
+            anyhow::bail!("[leader_lease_secs] must be greater than 0");
+        }
+        if self.purge_retention_days < 0 {
+            anyhow::bail!("[purge_retention_days] must not be negative");
+        }
+        if let Some((route, _)) = self.route_max_concurrent_requests.iter().find(|(_, limit)| **limit == 0) {
+            anyhow::bail!("[route_max_concurrent_requests] for [{}] must be greater than 0", route);
+        }
+        if self.request_timeout_read_ms == 0 || self.request_timeout_write_ms == 0 || self.request_timeout_long_secs == 0 {
+            anyhow::bail!("request timeouts must be greater than 0");
+        }
+        if self.jwt_expiry_secs == 0 || self.refresh_token_expiry_secs == 0 {
+            anyhow::bail!("[jwt_expiry_secs] and [refresh_token_expiry_secs] must be greater than 0");
+        }
+        if self.login_max_failures > 0 && self.login_lockout_secs == 0 {
+            anyhow::bail!("[login_lockout_secs] must be greater than 0");
+        }
+        // HS256 の鍵はハッシュの出力 (32 バイト) より短いと弱くなる
+        match self.jwt_secret.as_ref().filter(|secret| !secret.expose().is_empty()) {
+            Some(secret) if secret.expose().len() < MIN_JWT_SECRET_LENGTH => {
+                anyhow::bail!("[jwt_secret] must be at least {} bytes", MIN_JWT_SECRET_LENGTH);
+            }
+            None if self.profile.requires_auth() => {
+                anyhow::bail!("undefined [jwt_secret] (authentication is required when APP_ENV is {})", self.profile.as_str());
+            }
+            _ => {}
+        }
+        for origin in &self.cors_allowed_origins {
+            HeaderValue::from_str(origin).map_err(|_| anyhow::anyhow!("invalid CORS origin [{}]", origin))?;
+        }
+        Ok(())
+    }
+
+    pub fn cors_origins(&self) -> Vec<HeaderValue> {
+        self.cors_allowed_origins
+            .iter()
+            .filter_map(|origin| HeaderValue::from_str(origin).ok())
+            .collect()
+    }
+
+    pub fn startup_retry(&self) -> StartupRetry {
+        StartupRetry {
+            initial_backoff: Duration::from_millis(self.db_startup_initial_backoff_ms),
+            max_backoff: Duration::from_millis(self.db_startup_max_backoff_ms),
+            max_duration: Duration::from_secs(self.db_startup_max_wait_secs),
+        }
+    }
+
+    pub fn health_check_interval(&self) -> Duration {
+        Duration::from_secs(self.db_health_check_interval_secs)
+    }
+
+    pub fn leader_lease(&self) -> Duration {
+        Duration::from_secs(self.leader_lease_secs)
+    }
+
+    pub fn purge_interval(&self) -> Option<Duration> {
+        Some(Duration::from_secs(self.purge_interval_secs)).filter(|interval| !interval.is_zero())
+    }
+
+    pub fn query_cache(&self) -> QueryCache {
+        if self.query_cache_max_capacity == 0 {
+            return QueryCache::default();
+        }
+        QueryCache::new(CacheConfig {
+            max_capacity: self.query_cache_max_capacity,
+            ttl: Duration::from_secs(self.query_cache_ttl_secs),
+        })
+    }
+
+    pub fn quota(&self) -> Quota {
+        Quota {
+            max_todos: self.quota_max_todos,
+            max_labels: self.quota_max_labels,
+        }
+    }
+
+    pub fn cache_control(&self) -> CacheControl {
+        CacheControl {
+            list_max_age: self.cache_control_list_max_age_secs,
+        }
+    }
+
+    pub fn concurrency_limits(&self) -> ConcurrencyLimits {
+        ConcurrencyLimits {
+            global: Some(self.max_concurrent_requests).filter(|limit| *limit > 0),
+            routes: self.route_max_concurrent_requests.clone(),
+            retry_after: Duration::from_secs(self.load_shed_retry_after_secs),
+        }
+    }
+
+    pub fn metrics(&self) -> Metrics {
+        let threshold = Some(self.slow_query_threshold_ms).filter(|ms| *ms > 0).map(Duration::from_millis);
+        Metrics::new().with_slow_query_threshold(threshold)
+    }
+
+    pub fn request_timeouts(&self) -> RouteTimeouts {
+        RouteTimeouts {
+            read: Duration::from_millis(self.request_timeout_read_ms),
+            write: Duration::from_millis(self.request_timeout_write_ms),
+            long: Duration::from_secs(self.request_timeout_long_secs),
+            long_routes: self.request_timeout_long_routes.iter().cloned().collect(),
+        }
+    }
+
+    pub fn admin(&self) -> AdminConfig {
+        AdminConfig::new(self.admin_token.as_ref().map(|token| token.expose().to_string()))
+    }
+
+    pub fn feed(&self) -> FeedConfig {
+        FeedConfig::new(self.feed_token.as_ref().map(|token| token.expose().to_string()))
+    }
+
+    pub fn auth(&self) -> AuthConfig {
+        let auth = AuthConfig::new(
+            self.jwt_secret.as_ref().map(|secret| secret.expose().to_string()),
+            Duration::from_secs(self.jwt_expiry_secs),
+        )
+        .with_refresh_expiry(Duration::from_secs(self.refresh_token_expiry_secs))
+        .with_lockout(Some(self.login_max_failures).filter(|max| *max > 0).map(|max_failures| LoginLockout {
+            max_failures,
+            duration: Duration::from_secs(self.login_lockout_secs),
+        }));
+        if self.profile.requires_auth() {
+            auth.required()
+        } else {
+            auth
+        }
+    }
+
+    pub fn snapshots(&self) -> SnapshotConfig {
+        let store = self
+            .snapshot_dir
+            .as_ref()
+            .filter(|dir| !dir.is_empty())
+            .map(|dir| Arc::new(FsBlobStore::new(dir)) as SharedBlobStore);
+        SnapshotConfig::new(store)
+    }
+
+    pub fn public_base_url(&self) -> PublicBaseUrl {
+        PublicBaseUrl::new(self.public_base_url.clone())
+    }
+
+    pub fn strict_json(&self) -> StrictJson {
+        StrictJson(self.json_strict)
+    }
+
+    pub fn content_filter(&self) -> SharedContentFilter {
+        moderation::from_words(&self.content_denylist)
+    }
+
+    pub fn sentry_dsn(&self) -> Option<&str> {
+        self.sentry_dsn.as_ref().map(Secret::expose).filter(|dsn| !dsn.is_empty())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::collections::BTreeSet;
+
+    #[test]
+    fn should_pick_profile_defaults() {
+        assert_eq!(Ok(Profile::Prod), "production".parse::<Profile>().map_err(|e| e.to_string()));
+        assert!("qa".parse::<Profile>().is_err());
+
+        let dev = Config::defaults(Profile::Dev);
+        assert_eq!(LogFormat::Pretty, dev.log_format);
+        assert_eq!(vec!["http://localhost:3001".to_string()], dev.cors_allowed_origins);
+        let prod = Config::defaults(Profile::Prod);
+        assert_eq!(LogFormat::Json, prod.log_format);
+        assert!(prod.cors_allowed_origins.is_empty());
+        assert!(prod.db_max_connections > dev.db_max_connections);
+        // 実行計画の API は本番でだけ無効
+        assert!(dev.query_plans_enabled);
+        assert!(!prod.query_plans_enabled);
+    }
+
+    #[test]
+    fn should_redact_secrets() {
+        let config = Config {
+            database_url: DatabaseUrl::new("postgres://admin:hunter2@db:5432/todos"),
+            admin_token: Some(Secret::new("token")),
+            jwt_secret: Some(Secret::new("signing key")),
+            ..Config::default()
+        };
+        let logged = format!("{:?}", config);
+        assert!(logged.contains("postgres://admin:***@db:5432/todos"));
+        assert!(!logged.contains("hunter2"));
+        assert!(!logged.contains("token\""));
+        assert!(!logged.contains("signing key"));
+        assert_eq!("postgres://admin@db/todos", format!("{:?}", DatabaseUrl::new("postgres://admin@db/todos")));
+    }
+
+    const FILE: &str = r#"
+        [default]
+        database_url = "postgres://admin@db/todos"
+        db_max_connections = 8
+        content_denylist = ["spam"]
+
+        [prod]
+        db_max_connections = 30
+        query_cache_max_capacity = 100
+        jwt_secret = "0123456789abcdef0123456789abcdef"
+    "#;
+
+    #[test]
+    fn should_layer_file_and_env_over_profile_defaults() {
+        // 環境変数の値は数値などとして解釈されてから渡ってくる
+        let sources = || {
+            Figment::from(Toml::string(FILE).nested())
+                .merge(Serialized::global("cors_allowed_origins", "https://a.example.com, https://b.example.com"))
+                .merge(Serialized::global("admin_token", 12345))
+                .merge(Serialized::global("query_cache_max_capacity", 50))
+                .merge(Serialized::global("route_max_concurrent_requests", "/todos=20, /todos/:id=5"))
+                .merge(Serialized::global("request_timeout_long_routes", "/admin/backup, /sync"))
+        };
+
+        let prod = Config::extract(Profile::Prod, sources()).unwrap();
+        assert_eq!(Profile::Prod, prod.profile);
+        assert_eq!("postgres://admin@db/todos", prod.database_url.as_str());
+        assert_eq!(LogFormat::Json, prod.log_format);
+        assert_eq!(30, prod.db_max_connections);
+        // 環境変数は設定ファイルより優先する
+        assert_eq!(50, prod.query_cache_max_capacity);
+        assert_eq!(vec!["https://a.example.com", "https://b.example.com"], prod.cors_allowed_origins);
+        assert_eq!(Some("12345"), prod.admin_token.as_ref().map(Secret::expose));
+        assert_eq!(vec!["spam"], prod.content_denylist);
+        assert_eq!(
+            BTreeMap::from([("/todos".to_string(), 20), ("/todos/:id".to_string(), 5)]),
+            prod.route_max_concurrent_requests
+        );
+        assert_eq!(
+            BTreeSet::from(["/admin/backup".to_string(), "/sync".to_string()]),
+            prod.request_timeouts().long_routes
+        );
+
+        let dev = Config::extract(Profile::Dev, sources()).unwrap();
+        assert_eq!(8, dev.db_max_connections);
+        assert_eq!(LogFormat::Pretty, dev.log_format);
+    }
+
+    #[test]
+    fn should_reject_invalid_config() {
+        let sources = || Figment::from(Toml::string(FILE).nested());
+
+        let invalid = Serialized::global("db_max_connections", "many");
+        let error = Config::extract(Profile::Dev, sources().merge(invalid)).unwrap_err().to_string();
+        assert!(error.contains("db_max_connections"), "{}", error);
+
+        let too_many = Serialized::global("db_pool_warmup_connections", 10);
+        let error = Config::extract(Profile::Dev, sources().merge(too_many)).unwrap_err().to_string();
+        assert!(error.contains("must not exceed"), "{}", error);
+
+        let short_secret = Serialized::global("jwt_secret", "too short");
+        let error = Config::extract(Profile::Dev, sources().merge(short_secret)).unwrap_err().to_string();
+        assert!(error.contains("jwt_secret"), "{}", error);
+
+        let no_lockout = Serialized::global("login_lockout_secs", 0);
+        let error = Config::extract(Profile::Dev, sources().merge(no_lockout)).unwrap_err().to_string();
+        assert!(error.contains("login_lockout_secs"), "{}", error);
+
+        // dev 以外は認証を切れない
+        let error = Config::extract(Profile::Staging, sources()).unwrap_err().to_string();
+        assert!(error.contains("undefined [jwt_secret]"), "{}", error);
+        let empty_secret = Serialized::global("jwt_secret", "");
+        let error = Config::extract(Profile::Prod, sources().merge(empty_secret)).unwrap_err().to_string();
+        assert!(error.contains("undefined [jwt_secret]"), "{}", error);
+        assert!(!Config::extract(Profile::Dev, sources()).unwrap().auth().enabled());
+
+        assert!(Config::extract(Profile::Dev, Figment::new()).is_err());
+    }
+}
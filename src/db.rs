@@ -0,0 +1,298 @@
+use futures::future::try_join_all;
+use sqlx::{pool::PoolConnection, postgres::PgPoolOptions, Executor, PgPool, Postgres};
+use std::{
+    future::Future,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    time::{Duration, Instant},
+};
+use tokio::task::JoinHandle;
+
+// 起動時に DB に繋がらなくても落ちないよう、実際の接続は最初に使うときまで遅らせる。
+// 切れたコネクションは acquire 時の ping で捨てられ、次の acquire で張り直される。
+// min_connections 本はアイドルでも閉じずに残す (warm_up で開いた分を維持するため)
+pub fn connect_lazy(database_url: &str, max_connections: u32, min_connections: u32) -> anyhow::Result<PgPool> {
+    let pool = PgPoolOptions::new()
+        .acquire_timeout(Duration::from_secs(5))
+        .max_connections(max_connections)
+        .min_connections(min_connections.min(max_connections))
+        .test_before_acquire(true)
+        .connect_lazy(database_url)?;
+    Ok(pool)
+}
+
+// ヘルスチェックで分かった DB の状態。/health で返す
+#[derive(Debug, Clone)]
+pub struct DbHealth(Arc<AtomicBool>);
+
+// DB を使わない構成 (テストなど) では常に up 扱いにする
+impl Default for DbHealth {
+    fn default() -> Self {
+        Self::new(true)
+    }
+}
+
+impl DbHealth {
+    pub fn new(up: bool) -> Self {
+        Self(Arc::new(AtomicBool::new(up)))
+    }
+
+    pub fn is_up(&self) -> bool {
+        self.0.load(Ordering::SeqCst)
+    }
+
+    // 変更前の状態を返す
+    fn set(&self, up: bool) -> bool {
+        self.0.swap(up, Ordering::SeqCst)
+    }
+}
+
+// SELECT 1 が通るかで状態を更新する。状態が変わったときだけログを出す
+pub async fn check(pool: &PgPool, health: &DbHealth) -> bool {
+    let result = sqlx::query("SELECT 1").execute(pool).await;
+    let up = result.is_ok();
+    let was_up = health.set(up);
+    match result {
+        Ok(_) if !was_up => tracing::info!("database connection is back"),
+        Err(e) if was_up => tracing::error!("database is unreachable: {}", e),
+        _ => {}
+    }
+    up
+}
+
+// interval ごとに check する
+pub fn spawn_health_check(pool: PgPool, health: DbHealth, interval: Duration) -> JoinHandle<()> {
+    tokio::spawn(async move {
+        let mut ticker = tokio::time::interval(interval);
+        loop {
+            ticker.tick().await;
+            check(&pool, &health).await;
+        }
+    })
+}
+
+// 起動時に DB が使えるようになるまで待つときのリトライ設定
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct StartupRetry {
+    pub initial_backoff: Duration,
+    pub max_backoff: Duration,
+    // これを過ぎても繋がらなければ諦める
+    pub max_duration: Duration,
+}
+
+impl Default for StartupRetry {
+    fn default() -> Self {
+        Self {
+            initial_backoff: Duration::from_millis(500),
+            max_backoff: Duration::from_secs(10),
+            max_duration: Duration::from_secs(60),
+        }
+    }
+}
+
+// docker compose などで DB より先にアプリが起動しても、DB の準備ができるまで指数バックオフで待つ
+pub async fn wait_until_ready(pool: &PgPool, health: &DbHealth, retry: StartupRetry) -> anyhow::Result<()> {
+    let started = Instant::now();
+    let mut backoff = retry.initial_backoff;
+    for attempt in 1.. {
+        if check(pool, health).await {
+            tracing::info!("database is ready (attempt {})", attempt);
+            return Ok(());
+        }
+        let elapsed = started.elapsed();
+        if elapsed >= retry.max_duration {
+            anyhow::bail!(
+                "database is not ready after {:.1}s ({} attempts)",
+                elapsed.as_secs_f64(),
+                attempt
+            );
+        }
+        let wait = backoff.min(retry.max_duration - elapsed);
+        tracing::info!("waiting for database (attempt {}), retrying in {:?}", attempt, wait);
+        tokio::time::sleep(wait).await;
+        backoff = (backoff * 2).min(retry.max_backoff);
+    }
+    unreachable!()
+}
+
+// デプロイ直後の最初のリクエストが接続や prepare で遅くならないよう、
+// リクエストを受け付ける前にコネクションを開き、よく使うクエリを prepare しておく。
+// prepare した文はコネクションごとのキャッシュに残るので、同じ SQL 文字列のクエリはそのまま使い回される。
+// プールの上限を超える本数を渡すと、acquire が返ってこずにタイムアウトする
+pub async fn warm_up(pool: &PgPool, connections: u32, statements: &[&str]) -> anyhow::Result<()> {
+    // 同時に握っておかないと同じコネクションが使い回されるので、全部 acquire してから返す
+    let conns = try_join_all((0..connections).map(|_| async {
+        let mut conn = pool.acquire().await?;
+        for sql in statements {
+            conn.prepare(sql).await?;
+        }
+        anyhow::Ok(conn)
+    }))
+    .await?;
+    tracing::info!(
+        "warmed up {} connection(s) with {} statement(s)",
+        conns.len(),
+        statements.len()
+    );
+    Ok(())
+}
+
+// 複数インスタンスで動かしたときに、同じジョブを 1 つのインスタンスだけが実行するための Postgres の advisory lock。
+// セッション単位のロックなので、取ったコネクションを握っている間だけ有効。
+// インスタンスが落ちればコネクションごと解放されるので、ロックが残り続けることはない。
+// 使っているのは MaintenanceRepositoryForDb のジョブ (/admin/maintenance/* と、leader が定期実行する purge_expired) だけ
+#[derive(Debug, Clone)]
+pub struct DistributedLock {
+    pool: PgPool,
+}
+
+// 解放しないまま drop されたら (パニックやキャンセル)、ロックを持ったコネクションをプールに戻さずに閉じる
+struct HeldLock(Option<PoolConnection<Postgres>>);
+
+impl Drop for HeldLock {
+    fn drop(&mut self) {
+        if let Some(conn) = self.0.take() {
+            drop(conn.detach());
+        }
+    }
+}
+
+impl DistributedLock {
+    pub fn new(pool: PgPool) -> Self {
+        Self { pool }
+    }
+
+    // name のロックが取れたときだけ task を実行する。他のインスタンスが実行中なら待たずに None を返す
+    pub async fn run_exclusive<F, T>(&self, name: &str, task: F) -> anyhow::Result<Option<T>>
+    where
+        F: Future<Output = anyhow::Result<T>>,
+    {
+        let mut conn = self.pool.acquire().await?;
+        let locked: bool = sqlx::query_scalar("SELECT pg_try_advisory_lock(hashtext($1))")
+            .bind(name)
+            .fetch_one(&mut conn)
+            .await?;
+        if !locked {
+            tracing::info!("lock [{}] is held by another instance, skipping", name);
+            return Ok(None);
+        }
+        let mut held = HeldLock(Some(conn));
+        let result = task.await;
+        let mut conn = held.0.take().unwrap();
+        let unlocked = sqlx::query("SELECT pg_advisory_unlock(hashtext($1))")
+            .bind(name)
+            .execute(&mut conn)
+            .await;
+        if let Err(e) = unlocked {
+            // ロックが残ったコネクションを使い回さないよう閉じる
+            drop(conn.detach());
+            return Err(e.into());
+        }
+        result.map(Some)
+    }
+}
+
+#[cfg(test)]
+pub mod test_utils {
+    use std::{cell::Cell, future::Future, sync::Once};
+
+    thread_local! {
+        // None なら数えていない
+        static STATEMENTS: Cell<Option<usize>> = const { Cell::new(None) };
+    }
+
+    // sqlx はステートメントを 1 つ流すごとに sqlx::query の debug ログを出すので、それを数える
+    struct StatementCounter;
+
+    impl log::Log for StatementCounter {
+        fn enabled(&self, metadata: &log::Metadata) -> bool {
+            metadata.target() == "sqlx::query"
+        }
+
+        fn log(&self, record: &log::Record) {
+            if self.enabled(record.metadata()) {
+                STATEMENTS.with(|count| count.set(count.get().map(|n| n + 1)));
+            }
+        }
+
+        fn flush(&self) {}
+    }
+
+    // future の中で DB に流したステートメントの数 (BEGIN / COMMIT も含む) を返す。
+    // 数えるのはこのスレッドで流したものだけなので、current_thread のランタイム (#[tokio::test]) で使う
+    pub async fn count_statements<F: Future>(future: F) -> (F::Output, usize) {
+        static INIT: Once = Once::new();
+        INIT.call_once(|| {
+            log::set_boxed_logger(Box::new(StatementCounter)).expect("another logger is already set");
+            log::set_max_level(log::LevelFilter::Debug);
+        });
+        STATEMENTS.with(|count| count.set(Some(0)));
+        let output = future.await;
+        let count = STATEMENTS.with(|count| count.replace(None)).unwrap_or(0);
+        (output, count)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[tokio::test]
+    async fn lazy_pool_reports_unreachable_database() {
+        // 誰も listen していないポートでも、プールの作成自体は成功する
+        let pool = connect_lazy("postgres://admin@127.0.0.1:1/todos", 1, 0).unwrap();
+        let health = DbHealth::default();
+        let retry = StartupRetry {
+            max_duration: Duration::ZERO,
+            ..StartupRetry::default()
+        };
+        assert!(wait_until_ready(&pool, &health, retry).await.is_err());
+        assert!(!health.is_up());
+    }
+
+    #[cfg(feature = "database-test")]
+    #[tokio::test]
+    async fn warm_up_prepares_statements_on_every_connection() {
+        use crate::repositories::{label, todo};
+        use sqlx::Connection;
+
+        let database_url = std::env::var("DATABASE_URL").expect("undefined [DATABASE_URL]");
+        let pool = connect_lazy(&database_url, 2, 2).unwrap();
+        let statements = [todo::HOT_STATEMENTS, label::HOT_STATEMENTS].concat();
+        warm_up(&pool, 2, &statements).await.unwrap();
+
+        assert_eq!(pool.size(), 2);
+        // drop したコネクションはバックグラウンドでプールに戻るので、戻りきるまで待ってから取り出す
+        while pool.num_idle() < 2 {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        let mut conns = [pool.acquire().await.unwrap(), pool.acquire().await.unwrap()];
+        for conn in conns.iter_mut() {
+            assert!(conn.cached_statements_size() >= statements.len());
+        }
+    }
+
+    #[cfg(feature = "database-test")]
+    #[tokio::test]
+    async fn distributed_lock_runs_one_task_at_a_time() {
+        let database_url = std::env::var("DATABASE_URL").expect("undefined [DATABASE_URL]");
+        // 別インスタンスの代わりに、別のプールからロックを取る
+        let lock = DistributedLock::new(connect_lazy(&database_url, 1, 0).unwrap());
+        let other = DistributedLock::new(connect_lazy(&database_url, 1, 0).unwrap());
+        let name = "test:distributed_lock";
+
+        let result = lock
+            .run_exclusive(name, async { other.run_exclusive(name, async { Ok(()) }).await })
+            .await
+            .unwrap();
+        // 外側だけが実行され、内側は飛ばされる
+        assert_eq!(Some(None), result);
+
+        // 失敗しても解放されている
+        let failed = lock.run_exclusive(name, async { Err::<(), _>(anyhow::anyhow!("boom")) }).await;
+        assert!(failed.is_err());
+        assert_eq!(Some(1), other.run_exclusive(name, async { Ok(1) }).await.unwrap());
+    }
+}
@@ -0,0 +1,196 @@
+use axum::{
+    http::StatusCode,
+    response::{IntoResponse, Response},
+    Json,
+};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+
+// エラーレスポンスの code。クライアントはメッセージではなくこれを見て分岐する。
+// 一度返した値は変えない (意味が変わるときは新しい値を足す)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "SCREAMING_SNAKE_CASE")]
+pub enum ErrorCode {
+    // ステータスしか分からないエラーに付ける汎用の値
+    BadRequest,
+    Unauthorized,
+    NotFound,
+    MethodNotAllowed,
+    Conflict,
+    PayloadTooLarge,
+    UnprocessableEntity,
+    InternalError,
+    // リクエストの中身
+    InvalidJson,
+    ValidationFailed,
+    UnknownField,
+    UnsupportedMediaType,
+    InvalidInput,
+    ContentRejected,
+    // リソース
+    RouteNotFound,
+    TodoNotFound,
+    LabelNotFound,
+    LabelsNotFound,
+    LabelDuplicate,
+    PreconditionFailed,
+    QuotaExceeded,
+    // ユーザー
+    UserDuplicate,
+    InvalidCredentials,
+    AccountLocked,
+    // サーバーの状態
+    ServerBusy,
+    RequestTimeout,
+    DatabaseUnavailable,
+}
+
+impl ErrorCode {
+    // ドキュメントやクライアントの生成に使う一覧。値を足したらここにも足す
+    pub const ALL: &'static [ErrorCode] = &[
+        ErrorCode::BadRequest,
+        ErrorCode::Unauthorized,
+        ErrorCode::NotFound,
+        ErrorCode::MethodNotAllowed,
+        ErrorCode::Conflict,
+        ErrorCode::PayloadTooLarge,
+        ErrorCode::UnprocessableEntity,
+        ErrorCode::InternalError,
+        ErrorCode::InvalidJson,
+        ErrorCode::ValidationFailed,
+        ErrorCode::UnknownField,
+        ErrorCode::UnsupportedMediaType,
+        ErrorCode::InvalidInput,
+        ErrorCode::ContentRejected,
+        ErrorCode::RouteNotFound,
+        ErrorCode::TodoNotFound,
+        ErrorCode::LabelNotFound,
+        ErrorCode::LabelsNotFound,
+        ErrorCode::LabelDuplicate,
+        ErrorCode::PreconditionFailed,
+        ErrorCode::QuotaExceeded,
+        ErrorCode::UserDuplicate,
+        ErrorCode::InvalidCredentials,
+        ErrorCode::AccountLocked,
+        ErrorCode::ServerBusy,
+        ErrorCode::RequestTimeout,
+        ErrorCode::DatabaseUnavailable,
+    ];
+
+    pub fn status(self) -> StatusCode {
+        match self {
+            ErrorCode::BadRequest
+            | ErrorCode::InvalidJson
+            | ErrorCode::UnknownField
+            | ErrorCode::InvalidInput => StatusCode::BAD_REQUEST,
+            ErrorCode::Unauthorized | ErrorCode::InvalidCredentials => StatusCode::UNAUTHORIZED,
+            ErrorCode::NotFound | ErrorCode::RouteNotFound | ErrorCode::TodoNotFound | ErrorCode::LabelNotFound => {
+                StatusCode::NOT_FOUND
+            }
+            ErrorCode::MethodNotAllowed => StatusCode::METHOD_NOT_ALLOWED,
+            ErrorCode::Conflict | ErrorCode::LabelDuplicate | ErrorCode::UserDuplicate => StatusCode::CONFLICT,
+            ErrorCode::PreconditionFailed => StatusCode::PRECONDITION_FAILED,
+            ErrorCode::PayloadTooLarge => StatusCode::PAYLOAD_TOO_LARGE,
+            ErrorCode::UnsupportedMediaType => StatusCode::UNSUPPORTED_MEDIA_TYPE,
+            ErrorCode::UnprocessableEntity
+            | ErrorCode::ValidationFailed
+            | ErrorCode::ContentRejected
+            | ErrorCode::LabelsNotFound => StatusCode::UNPROCESSABLE_ENTITY,
+            ErrorCode::AccountLocked => StatusCode::LOCKED,
+            ErrorCode::QuotaExceeded => StatusCode::TOO_MANY_REQUESTS,
+            ErrorCode::InternalError => StatusCode::INTERNAL_SERVER_ERROR,
+            ErrorCode::ServerBusy | ErrorCode::DatabaseUnavailable => StatusCode::SERVICE_UNAVAILABLE,
+            ErrorCode::RequestTimeout => StatusCode::GATEWAY_TIMEOUT,
+        }
+    }
+
+    // ハンドラがステータスだけで返したエラーに付ける code
+    pub fn from_status(status: StatusCode) -> Self {
+        match status {
+            StatusCode::UNAUTHORIZED => ErrorCode::Unauthorized,
+            StatusCode::NOT_FOUND => ErrorCode::NotFound,
+            StatusCode::METHOD_NOT_ALLOWED => ErrorCode::MethodNotAllowed,
+            StatusCode::CONFLICT => ErrorCode::Conflict,
+            StatusCode::PRECONDITION_FAILED => ErrorCode::PreconditionFailed,
+            StatusCode::PAYLOAD_TOO_LARGE => ErrorCode::PayloadTooLarge,
+            StatusCode::UNSUPPORTED_MEDIA_TYPE => ErrorCode::UnsupportedMediaType,
+            StatusCode::UNPROCESSABLE_ENTITY => ErrorCode::UnprocessableEntity,
+            StatusCode::LOCKED => ErrorCode::AccountLocked,
+            StatusCode::TOO_MANY_REQUESTS => ErrorCode::QuotaExceeded,
+            StatusCode::SERVICE_UNAVAILABLE => ErrorCode::ServerBusy,
+            StatusCode::GATEWAY_TIMEOUT => ErrorCode::RequestTimeout,
+            status if status.is_client_error() => ErrorCode::BadRequest,
+            _ => ErrorCode::InternalError,
+        }
+    }
+
+    pub fn message(self) -> &'static str {
+        match self {
+            ErrorCode::BadRequest => "bad request",
+            ErrorCode::Unauthorized => "unauthorized",
+            ErrorCode::NotFound | ErrorCode::RouteNotFound => "not found",
+            ErrorCode::MethodNotAllowed => "method not allowed",
+            ErrorCode::Conflict => "conflict",
+            ErrorCode::PayloadTooLarge => "payload too large",
+            ErrorCode::UnprocessableEntity => "unprocessable entity",
+            ErrorCode::InternalError => "internal server error",
+            ErrorCode::InvalidJson => "invalid json",
+            ErrorCode::ValidationFailed => "validation failed",
+            ErrorCode::UnknownField => "unknown field",
+            ErrorCode::UnsupportedMediaType => "unsupported media type",
+            ErrorCode::InvalidInput => "invalid input",
+            ErrorCode::ContentRejected => "content rejected",
+            ErrorCode::TodoNotFound => "todo not found",
+            ErrorCode::LabelNotFound => "label not found",
+            ErrorCode::LabelsNotFound => "labels not found",
+            ErrorCode::LabelDuplicate => "label already exists",
+            ErrorCode::PreconditionFailed => "precondition failed",
+            ErrorCode::QuotaExceeded => "quota exceeded",
+            ErrorCode::UserDuplicate => "email already registered",
+            ErrorCode::InvalidCredentials => "invalid email or password",
+            ErrorCode::AccountLocked => "too many failed logins, retry later",
+            ErrorCode::ServerBusy => "server is busy, retry later",
+            ErrorCode::RequestTimeout => "request timed out",
+            ErrorCode::DatabaseUnavailable => "database unavailable",
+        }
+    }
+
+    // 既定のメッセージの代わりに、何が悪かったかを書いたメッセージを返す
+    pub fn with_message(self, message: impl Into<String>) -> Response {
+        (self.status(), Json(json!({"error": message.into(), "code": self}))).into_response()
+    }
+}
+
+impl IntoResponse for ErrorCode {
+    fn into_response(self) -> Response {
+        self.with_message(self.message())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::collections::HashSet;
+
+    #[test]
+    fn should_list_every_code_once() {
+        let codes: HashSet<_> = ErrorCode::ALL.iter().collect();
+        assert_eq!(ErrorCode::ALL.len(), codes.len());
+        for code in ErrorCode::ALL {
+            let json = serde_json::to_value(code).unwrap();
+            assert_eq!(*code, serde_json::from_value::<ErrorCode>(json).unwrap());
+        }
+        assert_eq!(json!("TODO_NOT_FOUND"), serde_json::to_value(ErrorCode::TodoNotFound).unwrap());
+        assert_eq!(json!("LABEL_DUPLICATE"), serde_json::to_value(ErrorCode::LabelDuplicate).unwrap());
+    }
+
+    #[test]
+    fn should_keep_status_of_generic_codes() {
+        for status in [StatusCode::NOT_FOUND, StatusCode::CONFLICT, StatusCode::GATEWAY_TIMEOUT] {
+            assert_eq!(status, ErrorCode::from_status(status).status());
+        }
+        // 一覧に無いステータスは 4xx / 5xx でまとめる
+        assert_eq!(ErrorCode::BadRequest, ErrorCode::from_status(StatusCode::IM_A_TEAPOT));
+        assert_eq!(ErrorCode::InternalError, ErrorCode::from_status(StatusCode::BAD_GATEWAY));
+    }
+}
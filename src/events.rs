@@ -0,0 +1,165 @@
+use axum::async_trait;
+use serde::Serialize;
+use std::{fmt, sync::Arc};
+use tokio::{
+    sync::broadcast::{self, error::RecvError},
+    task::JoinHandle,
+};
+
+use crate::repositories::{
+    label::Label,
+    todo::{LabelAssignment, TodoEntity},
+};
+
+// リポジトリがコミットした後に流すイベント。通知系の機能はこれを購読して作る
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum DomainEvent {
+    TodoCreated { todo: TodoEntity },
+    // ラベルの付け外しも含む
+    TodoUpdated { todo: TodoEntity },
+    TodoDeleted { id: i32 },
+    LabelCreated { label: Label },
+    LabelUpdated { label: Label },
+    LabelDeleted { id: i32 },
+    // まとめての付け外しは、Todo ごとの TodoUpdated ではなくこれを 1 つだけ流す
+    LabelAssigned { label_id: i32, action: LabelAssignment, todo_ids: Vec<i32> },
+}
+
+// 購読者の処理を待たずに返す。購読者がいなくても失敗しない
+pub trait EventBus: fmt::Debug + Send + Sync + 'static {
+    fn publish(&self, event: DomainEvent);
+}
+
+pub type SharedEventBus = Arc<dyn EventBus>;
+
+#[derive(Debug)]
+pub struct NoopEventBus;
+
+impl EventBus for NoopEventBus {
+    fn publish(&self, _event: DomainEvent) {}
+}
+
+pub fn noop() -> SharedEventBus {
+    Arc::new(NoopEventBus)
+}
+
+#[async_trait]
+pub trait EventSubscriber: Send + Sync + 'static {
+    fn name(&self) -> &'static str;
+    async fn handle(&self, event: &DomainEvent) -> anyhow::Result<()>;
+}
+
+const DEFAULT_CAPACITY: usize = 1024;
+
+// プロセス内の購読者に tokio の broadcast で配る。
+// 購読者が capacity 件より遅れたら、古いイベントは落としてログに残す
+#[derive(Debug, Clone)]
+pub struct BroadcastEventBus {
+    sender: broadcast::Sender<DomainEvent>,
+}
+
+impl Default for BroadcastEventBus {
+    fn default() -> Self {
+        Self::new(DEFAULT_CAPACITY)
+    }
+}
+
+impl BroadcastEventBus {
+    pub fn new(capacity: usize) -> Self {
+        let (sender, _) = broadcast::channel(capacity);
+        Self { sender }
+    }
+
+    pub fn subscribe(&self) -> broadcast::Receiver<DomainEvent> {
+        self.sender.subscribe()
+    }
+
+    // 起動時に登録する。購読者ごとに task を分けるので、遅い購読者が他を止めない
+    pub fn spawn_subscriber<S: EventSubscriber>(&self, subscriber: S) -> JoinHandle<()> {
+        let mut events = self.subscribe();
+        tokio::spawn(async move {
+            loop {
+                match events.recv().await {
+                    Ok(event) => {
+                        if let Err(e) = subscriber.handle(&event).await {
+                            tracing::warn!("[{}] failed to handle {:?}: {}", subscriber.name(), event, e);
+                        }
+                    }
+                    Err(RecvError::Lagged(missed)) => {
+                        tracing::warn!("[{}] fell behind and missed {} events", subscriber.name(), missed);
+                    }
+                    Err(RecvError::Closed) => break,
+                }
+            }
+        })
+    }
+}
+
+impl EventBus for BroadcastEventBus {
+    fn publish(&self, event: DomainEvent) {
+        // 購読者がいないときの Err は無視してよい
+        let _ = self.sender.send(event);
+    }
+}
+
+// 変更を JSON で audit ターゲットのログに残す
+pub struct AuditLogger;
+
+#[async_trait]
+impl EventSubscriber for AuditLogger {
+    fn name(&self) -> &'static str {
+        "audit"
+    }
+
+    async fn handle(&self, event: &DomainEvent) -> anyhow::Result<()> {
+        tracing::info!(target: "audit", "{}", serde_json::to_string(event)?);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::sync::Mutex;
+    use tokio::sync::mpsc;
+
+    struct Forward(Mutex<mpsc::UnboundedSender<DomainEvent>>);
+
+    #[async_trait]
+    impl EventSubscriber for Forward {
+        fn name(&self) -> &'static str {
+            "forward"
+        }
+
+        async fn handle(&self, event: &DomainEvent) -> anyhow::Result<()> {
+            self.0.lock().unwrap().send(event.clone())?;
+            Ok(())
+        }
+    }
+
+    #[tokio::test]
+    async fn should_deliver_events_to_every_subscriber() {
+        let bus = BroadcastEventBus::new(16);
+        // 購読者がいなくても publish できる
+        bus.publish(DomainEvent::TodoDeleted { id: 0 });
+
+        let (first_tx, mut first) = mpsc::unbounded_channel();
+        let (second_tx, mut second) = mpsc::unbounded_channel();
+        bus.spawn_subscriber(Forward(Mutex::new(first_tx)));
+        bus.spawn_subscriber(Forward(Mutex::new(second_tx)));
+        bus.publish(DomainEvent::TodoDeleted { id: 1 });
+        bus.publish(DomainEvent::LabelDeleted { id: 2 });
+
+        for events in [&mut first, &mut second] {
+            assert_eq!(Some(DomainEvent::TodoDeleted { id: 1 }), events.recv().await);
+            assert_eq!(Some(DomainEvent::LabelDeleted { id: 2 }), events.recv().await);
+        }
+    }
+
+    #[test]
+    fn should_tag_events_with_type() {
+        let json = serde_json::to_value(DomainEvent::TodoDeleted { id: 1 }).unwrap();
+        assert_eq!(serde_json::json!({"type": "todo_deleted", "id": 1}), json);
+    }
+}
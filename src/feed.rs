@@ -0,0 +1,118 @@
+use chrono::{DateTime, SecondsFormat, Utc};
+use std::time::UNIX_EPOCH;
+
+use crate::repositories::todo::RecentTodo;
+
+// Atom (RFC 4287) のフィード。Todo の本文やラベル名はユーザーの入力なので、必ず escape してから埋め込む
+pub struct AtomFeed<'a> {
+    pub title: &'a str,
+    // フィード自身の URL。id にも使うので、トークンのような秘密は含めない
+    pub self_url: &'a str,
+    // Todo へのリンクの前に付けるベース URL
+    pub base_url: &'a str,
+}
+
+impl AtomFeed<'_> {
+    pub fn render(&self, entries: &[RecentTodo]) -> String {
+        // 空のフィードでも updated は必須なので、そのときは 1970-01-01 にする
+        let updated = entries
+            .iter()
+            .map(|entry| entry.updated_at)
+            .max()
+            .unwrap_or_else(|| DateTime::from(UNIX_EPOCH));
+        let mut xml = String::new();
+        xml.push_str("<?xml version=\"1.0\" encoding=\"utf-8\"?>\n");
+        xml.push_str("<feed xmlns=\"http://www.w3.org/2005/Atom\">\n");
+        xml.push_str(&format!("  <id>{}</id>\n", escape(self.self_url)));
+        xml.push_str(&format!("  <title>{}</title>\n", escape(self.title)));
+        xml.push_str(&format!("  <updated>{}</updated>\n", timestamp(updated)));
+        xml.push_str(&format!("  <author><name>{}</name></author>\n", escape(self.title)));
+        xml.push_str(&format!("  <link rel=\"self\" href=\"{}\"/>\n", escape(self.self_url)));
+        for entry in entries {
+            xml.push_str(&self.entry(entry));
+        }
+        xml.push_str("</feed>\n");
+        xml
+    }
+
+    fn entry(&self, entry: &RecentTodo) -> String {
+        let todo = &entry.todo;
+        let url = format!("{}/todos/{}", self.base_url, todo.id);
+        let mut xml = String::from("  <entry>\n");
+        // 同じ Todo でも更新ごとに別のエントリとして読ませたいので、version を id に入れる
+        xml.push_str(&format!("    <id>{}#v{}</id>\n", escape(&url), todo.version));
+        xml.push_str(&format!("    <title>{}</title>\n", escape(&todo.text)));
+        xml.push_str(&format!("    <updated>{}</updated>\n", timestamp(entry.updated_at)));
+        xml.push_str(&format!("    <link href=\"{}\"/>\n", escape(&url)));
+        for label in &todo.labels {
+            xml.push_str(&format!("    <category term=\"{}\"/>\n", escape(&label.name)));
+        }
+        let status = if todo.completed { "completed" } else { "open" };
+        xml.push_str(&format!("    <summary>{}</summary>\n", status));
+        xml.push_str("  </entry>\n");
+        xml
+    }
+}
+
+fn timestamp(time: DateTime<Utc>) -> String {
+    time.to_rfc3339_opts(SecondsFormat::Secs, true)
+}
+
+// 要素の中身と属性値のどちらに入れても安全なように、引用符も escape する。
+// XML 1.0 で使えない制御文字は落とす
+pub(crate) fn escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&apos;"),
+            '\t' | '\n' | '\r' => escaped.push(c),
+            c if c.is_control() => {}
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::repositories::{label::Label, todo::TodoEntity};
+
+    #[test]
+    fn should_escape_markup() {
+        assert_eq!("a &lt;b&gt; &amp; &quot;c&quot; &apos;d&apos;", escape("a <b> & \"c\" 'd'"));
+        assert_eq!("bell", escape("bel\u{0007}l"));
+    }
+
+    #[test]
+    fn should_render_entries() {
+        let feed = AtomFeed {
+            title: "Todos",
+            self_url: "https://api.example.com/feeds/todos.atom",
+            base_url: "https://api.example.com",
+        };
+        let entry = RecentTodo {
+            todo: TodoEntity {
+                completed: true,
+                labels: vec![Label::new(1, "<home>".to_string())],
+                version: 2,
+                ..TodoEntity::new(7, "buy milk & eggs".to_string())
+            },
+            updated_at: "2022-12-18T09:30:00Z".parse().unwrap(),
+        };
+        let xml = feed.render(&[entry]);
+        assert!(xml.contains("<updated>2022-12-18T09:30:00Z</updated>"), "{}", xml);
+        assert!(xml.contains("<id>https://api.example.com/todos/7#v2</id>"));
+        assert!(xml.contains("<title>buy milk &amp; eggs</title>"));
+        assert!(xml.contains("<category term=\"&lt;home&gt;\"/>"));
+        assert!(xml.contains("<summary>completed</summary>"));
+
+        let empty = feed.render(&[]);
+        assert!(empty.contains("<updated>1970-01-01T00:00:00Z</updated>"), "{}", empty);
+        assert!(!empty.contains("<entry>"));
+    }
+}
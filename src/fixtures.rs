@@ -0,0 +1,124 @@
+use chrono::{DateTime, Utc};
+use serde_json::json;
+use crate::repositories::{
+    label::{CreateLabel, Label, LabelRepository},
+    todo::{CreateTodo, Priority, TodoEntity, TodoRepository, UpdateTodo},
+};
+
+// テストデータの組み立て。リポジトリ経由で登録するので、メモリ版でも DB 版でも使える
+//   let todo = TodoFixture::new().text("buy milk").with_labels(vec![label.id]).completed().insert(&repo).await;
+#[derive(Debug, Clone)]
+pub struct TodoFixture {
+    text: String,
+    labels: Vec<i32>,
+    completed: bool,
+    due_date: Option<DateTime<Utc>>,
+    priority: Priority,
+}
+
+impl Default for TodoFixture {
+    fn default() -> Self {
+        Self {
+            text: "fixture todo".to_string(),
+            labels: vec![],
+            completed: false,
+            due_date: None,
+            priority: Priority::default(),
+        }
+    }
+}
+
+impl TodoFixture {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn text(mut self, text: &str) -> Self {
+        self.text = text.to_string();
+        self
+    }
+
+    pub fn with_labels(mut self, labels: Vec<i32>) -> Self {
+        self.labels = labels;
+        self
+    }
+
+    // 作成 API では完了状態を指定できないので、insert 時は作成後に更新する (version は 2 になる)
+    pub fn completed(mut self) -> Self {
+        self.completed = true;
+        self
+    }
+
+    pub fn due(mut self, due_date: DateTime<Utc>) -> Self {
+        self.due_date = Some(due_date);
+        self
+    }
+
+    pub fn priority(mut self, priority: Priority) -> Self {
+        self.priority = priority;
+        self
+    }
+
+    pub async fn insert<T: TodoRepository>(self, repo: &T) -> TodoEntity {
+        let mut payload = CreateTodo::new(self.text, self.labels).with_priority(self.priority);
+        if let Some(due_date) = self.due_date {
+            payload = payload.with_due_date(due_date);
+        }
+        let todo = repo
+            .create(payload)
+            .await
+            .expect("cannot create todo fixture");
+        if !self.completed {
+            return todo;
+        }
+        repo.update(todo.id, UpdateTodo::new(None, Some(true), None), None)
+            .await
+            .expect("cannot complete todo fixture")
+    }
+
+    // POST /todos のリクエストボディ。completed は作成 API に無いので含めない
+    pub fn to_json(&self) -> String {
+        json!({
+            "text": self.text,
+            "labels": self.labels,
+            "due_date": self.due_date,
+            "priority": self.priority,
+        })
+        .to_string()
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct LabelFixture {
+    name: String,
+}
+
+impl Default for LabelFixture {
+    fn default() -> Self {
+        Self {
+            name: "fixture label".to_string(),
+        }
+    }
+}
+
+impl LabelFixture {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn name(mut self, name: &str) -> Self {
+        self.name = name.to_string();
+        self
+    }
+
+    pub async fn insert<T: LabelRepository>(self, repo: &T) -> Label {
+        repo.create(CreateLabel::new(self.name))
+            .await
+            .expect("cannot create label fixture")
+    }
+
+    // POST /labels のリクエストボディ
+    pub fn to_json(&self) -> String {
+        json!({ "name": self.name }).to_string()
+    }
+}
@@ -1,39 +1,307 @@
+pub mod admin;
+pub mod auth;
+pub mod caldav;
+pub mod dto;
+pub mod error;
+pub mod fallback;
+pub mod feed;
+pub mod health;
+pub mod import;
 pub mod label;
+pub mod pagination;
+pub mod sync;
 pub mod todo;
+pub mod user;
 
 use axum::{
     async_trait,
-    extract::{FromRequest, RequestParts},
-    http::StatusCode,
+    body::Bytes,
+    extract::{self, FromRef, FromRequest, FromRequestParts},
+    http::{header, request::Parts, HeaderMap, HeaderValue, Request, StatusCode},
+    response::{IntoResponse, Response},
     BoxError, Json,
 };
+use chrono::{DateTime, Utc};
 use serde::de::DeserializeOwned;
+use serde_json::json;
 use validator::Validate;
+use std::convert::Infallible;
+use crate::{
+    error_code::ErrorCode,
+    moderation::SharedContentFilter,
+    normalize::Normalize,
+    repositories::todo::{TodoEntity, TodoRepository},
+    services::todo::TodoService,
+    state::TodoRepo,
+};
 
 #[derive(Debug)]
 pub struct ValidatedJson<T>(T);
 
+// JSON_STRICT が有効なら、リクエストボディに知らないフィールドがあると 400 にする。
+// `compleded` のようなクライアント側のタイポを黙って無視しないためのオプトイン設定
+#[derive(Debug, Clone, Copy, Default)]
+pub struct StrictJson(pub bool);
+
 // trait 内のメソッドでは asycn を宣言できないので、 async-trait パッケージのマクロを用いる
 #[async_trait]
-impl<T, B> FromRequest<B> for ValidatedJson<T>
+impl<T, S, B> FromRequest<S, B> for ValidatedJson<T>
 where
     // Json::<T>::from_request(req) を実装するために必要なトレイト境界の宣言
-    T: DeserializeOwned + Validate,
-    B: http_body::Body + Send,
+    T: DeserializeOwned + Validate + Normalize,
+    StrictJson: FromRef<S>,
+    S: Send + Sync,
+    B: http_body::Body + Send + 'static,
     B::Data: Send,
     B::Error: Into<BoxError>,
 {
-    type Rejection = (StatusCode, String);
+    type Rejection = Response;
 
-    async fn from_request(req: &mut RequestParts<B>) -> Result<Self, Self::Rejection> {
-        let Json(value) = Json::<T>::from_request(req).await.map_err(|rejection| {
-            let message = format!("Json parse error: [{}]", rejection);
-            (StatusCode::BAD_REQUEST, message)
-        })?;
+    async fn from_request(req: Request<B>, state: &S) -> Result<Self, Self::Rejection> {
+        if let Some(rejection) = unsupported_media_type(req.headers()) {
+            return Err(rejection);
+        }
+        let StrictJson(strict) = StrictJson::from_ref(state);
+        let bytes = Bytes::from_request(req, state)
+            .await
+            .map_err(|rejection| rejection.into_response())?;
+
+        // serde は知らないフィールドを読み飛ばすので、読み飛ばされたパスを記録しておく
+        let mut unknown_fields = vec![];
+        let deserializer = &mut serde_json::Deserializer::from_slice(&bytes);
+        let mut value: T = serde_ignored::deserialize(deserializer, |path| unknown_fields.push(path.to_string()))
+            .map_err(|rejection| ErrorCode::InvalidJson.with_message(format!("Json parse error: [{}]", rejection)))?;
+        if strict && !unknown_fields.is_empty() {
+            return Err((
+                StatusCode::BAD_REQUEST,
+                Json(json!({
+                    "error": "unknown field",
+                    "code": ErrorCode::UnknownField,
+                    "fields": unknown_fields,
+                })),
+            )
+                .into_response());
+        }
+
+        value.normalize();
         value.validate().map_err(|rejection| {
             let message = format!("Validation error: [{}]", rejection).replace('\n', ", ");
-            (StatusCode::BAD_REQUEST, message)
+            ErrorCode::ValidationFailed.with_message(message)
         })?;
         Ok(ValidatedJson(value))
     }
 }
+
+// axum の Query と同じだが、読めないクエリ文字列は 400 ではなく 422 にする (axum 0.5 のときからの API の挙動)
+#[derive(Debug)]
+pub struct Query<T>(pub T);
+
+#[async_trait]
+impl<T, S> FromRequestParts<S> for Query<T>
+where
+    T: DeserializeOwned,
+    S: Send + Sync,
+{
+    type Rejection = (StatusCode, String);
+
+    async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
+        let extract::Query(value) = extract::Query::from_request_parts(parts, state)
+            .await
+            .map_err(|rejection| (StatusCode::UNPROCESSABLE_ENTITY, rejection.body_text()))?;
+        Ok(Query(value))
+    }
+}
+
+// application/json と、application/vnd.example+json のような +json の型を受け付ける
+pub(crate) fn is_json_content_type(content_type: &str) -> bool {
+    match content_type.parse::<mime::Mime>() {
+        Ok(mime) => {
+            mime.type_() == mime::APPLICATION
+                && (mime.subtype() == mime::JSON || mime.suffix() == Some(mime::JSON))
+        }
+        Err(_) => false,
+    }
+}
+
+// JSON 以外の Content-Type なら 415 のレスポンスを返す
+fn unsupported_media_type(headers: &HeaderMap) -> Option<Response> {
+    let content_type = headers
+        .get(header::CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok());
+    match content_type {
+        Some(content_type) if is_json_content_type(content_type) => None,
+        _ => Some((
+            StatusCode::UNSUPPORTED_MEDIA_TYPE,
+            Json(json!({
+                "error": "unsupported media type",
+                "code": ErrorCode::UnsupportedMediaType,
+                "expected": mime::APPLICATION_JSON.to_string(),
+                "received": content_type,
+            })),
+        )
+            .into_response()),
+    }
+}
+
+// 保存前にコンテンツフィルタを通す。弾かれたら 422 で理由を返す
+// リポジトリと禁止語のフィルタの State からリクエストごとに組み立てる
+#[async_trait]
+impl<T, S> FromRequestParts<S> for TodoService<T>
+where
+    T: TodoRepository,
+    TodoRepo<T>: FromRef<S>,
+    SharedContentFilter: FromRef<S>,
+    S: Send + Sync,
+{
+    type Rejection = Infallible;
+
+    async fn from_request_parts(_: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
+        let TodoRepo(repo) = TodoRepo::from_ref(state);
+        Ok(TodoService::new(repo, SharedContentFilter::from_ref(state)))
+    }
+}
+
+// Todo の ETag は version をそのまま使う
+pub fn etag(todo: &TodoEntity) -> HeaderValue {
+    HeaderValue::from_str(&format!("\"{}\"", todo.version)).unwrap()
+}
+
+// If-Match ヘッダで指定された version。ヘッダが無いか * なら None
+#[derive(Debug)]
+pub struct IfMatch(pub Option<i32>);
+
+#[async_trait]
+impl<S> FromRequestParts<S> for IfMatch
+where
+    S: Send + Sync,
+{
+    type Rejection = StatusCode;
+
+    async fn from_request_parts(parts: &mut Parts, _: &S) -> Result<Self, Self::Rejection> {
+        let value = match parts.headers.get(header::IF_MATCH) {
+            Some(value) => value.to_str().or(Err(StatusCode::PRECONDITION_FAILED))?.trim(),
+            None => return Ok(IfMatch(None)),
+        };
+        if value == "*" {
+            return Ok(IfMatch(None));
+        }
+        // 弱い ETag や複数指定は、今の ETag と強い比較で一致することがないので 412 にする
+        let version = value
+            .strip_prefix('"')
+            .and_then(|value| value.strip_suffix('"'))
+            .and_then(|version| version.parse().ok())
+            .ok_or(StatusCode::PRECONDITION_FAILED)?;
+        Ok(IfMatch(Some(version)))
+    }
+}
+
+// Last-Modified などで使う HTTP-date (IMF-fixdate)
+pub fn http_date(date: DateTime<Utc>) -> HeaderValue {
+    HeaderValue::from_str(&date.format("%a, %d %b %Y %H:%M:%S GMT").to_string()).unwrap()
+}
+
+// If-Modified-Since ヘッダの時刻。ヘッダが無いか読めない日付なら None (RFC 9110 に従い無視する)
+#[derive(Debug)]
+pub struct IfModifiedSince(pub Option<DateTime<Utc>>);
+
+#[async_trait]
+impl<S> FromRequestParts<S> for IfModifiedSince
+where
+    S: Send + Sync,
+{
+    type Rejection = StatusCode;
+
+    async fn from_request_parts(parts: &mut Parts, _: &S) -> Result<Self, Self::Rejection> {
+        let since = parts
+            .headers
+            .get(header::IF_MODIFIED_SINCE)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| DateTime::parse_from_rfc2822(value.trim()).ok())
+            .map(|date| date.with_timezone(&Utc));
+        Ok(IfModifiedSince(since))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{
+        fixtures::TodoFixture,
+        moderation::DenylistFilter,
+        repositories::todo::UpdateTodo,
+        test_support::{
+            AccessLogRepositoryForMemory, BackupRepositoryForMemory, LabelRepositoryForMemory,
+            MaintenanceRepositoryForMemory, TodoRepositoryForMemory, UserRepositoryForMemory,
+        },
+        AppBuilder,
+    };
+    use axum::{
+        body::Body,
+        http::Method,
+        routing::patch,
+        Router,
+    };
+    use tower::ServiceExt;
+
+    async fn strict_request(strict: bool, body: &str) -> Response {
+        let app = Router::new()
+            .route("/", patch(|ValidatedJson(_): ValidatedJson<UpdateTodo>| async { StatusCode::OK }))
+            .with_state(StrictJson(strict));
+        let req = Request::builder()
+            .uri("/")
+            .method(Method::PATCH)
+            .header(header::CONTENT_TYPE, mime::APPLICATION_JSON.to_string())
+            .body(Body::from(body.to_string()))
+            .unwrap();
+        app.oneshot(req).await.unwrap()
+    }
+
+    #[tokio::test]
+    async fn reject_unknown_fields_only_in_strict_mode() {
+        let body = r#"{"compleded": true}"#;
+        assert_eq!(StatusCode::OK, strict_request(false, body).await.status());
+
+        let res = strict_request(true, body).await;
+        assert_eq!(StatusCode::BAD_REQUEST, res.status());
+        let bytes = hyper::body::to_bytes(res.into_body()).await.unwrap();
+        let body: serde_json::Value = serde_json::from_slice(&bytes).unwrap();
+        assert_eq!(json!(["compleded"]), body["fields"]);
+
+        assert_eq!(StatusCode::OK, strict_request(true, r#"{"completed": true}"#).await.status());
+    }
+
+    #[tokio::test]
+    async fn reject_denied_content() {
+        let app = AppBuilder::new(
+            TodoRepositoryForMemory::new(),
+            LabelRepositoryForMemory::new(),
+            BackupRepositoryForMemory::new(),
+            MaintenanceRepositoryForMemory::new(),
+            AccessLogRepositoryForMemory::new(),
+            UserRepositoryForMemory::new(),
+        )
+        .with_content_filter(DenylistFilter::new(["spam"]))
+        .build();
+        let req = Request::builder()
+            .uri("/todos")
+            .method(Method::POST)
+            .header(header::CONTENT_TYPE, mime::APPLICATION_JSON.to_string())
+            .body(Body::from(TodoFixture::new().text("buy spam").to_json()))
+            .unwrap();
+        let res = app.oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::UNPROCESSABLE_ENTITY, res.status());
+        let bytes = hyper::body::to_bytes(res.into_body()).await.unwrap();
+        let body: serde_json::Value = serde_json::from_slice(&bytes).unwrap();
+        assert_eq!("content rejected", body["error"]);
+    }
+
+    #[test]
+    fn json_content_types() {
+        assert!(is_json_content_type("application/json"));
+        assert!(is_json_content_type("application/json; charset=utf-8"));
+        assert!(is_json_content_type("application/vnd.api+json"));
+        assert!(!is_json_content_type("text/plain"));
+        assert!(!is_json_content_type("application/x-www-form-urlencoded"));
+        assert!(!is_json_content_type("not a mime"));
+    }
+}
@@ -0,0 +1,289 @@
+use axum::{
+    async_trait,
+    body::StreamBody,
+    extract::{FromRef, FromRequestParts, Path, State},
+    http::{header, request::Parts, StatusCode},
+    response::IntoResponse,
+    Json,
+};
+use futures::TryStreamExt;
+use serde::Deserialize;
+use uuid::Uuid;
+use crate::{
+    blob::SharedBlobStore,
+    jobs::JobRegistry,
+    metrics::Metrics,
+    repositories::{
+        access_log::{AccessLogFilter, AccessLogRepository},
+        backup::{
+            list_snapshots, load_manifest, validate_backup, validate_schema_name, BackupRecord,
+            BackupRepository,
+        },
+        cache::QueryCache,
+        maintenance::MaintenanceRepository,
+        user::{AuthEventKind, NewAuthEvent, UserRepository},
+        RepositoryError,
+    },
+    state::{AccessLogRepo, BackupRepo, MaintenanceRepo, UserRepo},
+};
+use super::Query;
+
+pub const ADMIN_TOKEN_HEADER: &str = "x-admin-token";
+pub const DEFAULT_RETENTION_DAYS: i32 = 30;
+
+// ADMIN_TOKEN が未設定なら管理用 API は無効 (404) にしておく
+#[derive(Debug, Clone)]
+pub struct AdminConfig {
+    token: Option<String>,
+}
+
+impl AdminConfig {
+    pub fn new(token: Option<String>) -> Self {
+        Self {
+            token: token.filter(|token| !token.is_empty()),
+        }
+    }
+}
+
+// スナップショットの置き場所。未設定ならスナップショットの API は無効 (404)
+#[derive(Debug, Clone)]
+pub struct SnapshotConfig {
+    store: Option<SharedBlobStore>,
+}
+
+impl SnapshotConfig {
+    pub fn new(store: Option<SharedBlobStore>) -> Self {
+        Self { store }
+    }
+}
+
+// トークンの比較で早期リターンしないように、長さ以外は全バイトを比較する
+pub(crate) fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+// 管理用ハンドラの引数に置くと、X-Admin-Token ヘッダを検証する
+#[derive(Debug)]
+pub struct RequireAdmin;
+
+#[async_trait]
+impl<S> FromRequestParts<S> for RequireAdmin
+where
+    AdminConfig: FromRef<S>,
+    S: Send + Sync,
+{
+    type Rejection = StatusCode;
+
+    async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
+        let expected = AdminConfig::from_ref(state).token.ok_or(StatusCode::NOT_FOUND)?;
+        let provided = parts
+            .headers
+            .get(ADMIN_TOKEN_HEADER)
+            .ok_or(StatusCode::UNAUTHORIZED)?;
+        if constant_time_eq(provided.as_bytes(), expected.as_bytes()) {
+            Ok(RequireAdmin)
+        } else {
+            Err(StatusCode::UNAUTHORIZED)
+        }
+    }
+}
+
+pub async fn backup<T: BackupRepository>(
+    _: RequireAdmin,
+    State(BackupRepo(repo)): State<BackupRepo<T>>,
+) -> impl IntoResponse {
+    // 1 レコード 1 行の ndjson として流す
+    let body = repo.export().and_then(|record| async move {
+        let mut line = serde_json::to_vec(&record)?;
+        line.push(b'\n');
+        Ok(line)
+    });
+    (
+        [(header::CONTENT_TYPE, "application/x-ndjson")],
+        StreamBody::new(body),
+    )
+}
+
+pub async fn restore<T: BackupRepository>(
+    _: RequireAdmin,
+    State(BackupRepo(repo)): State<BackupRepo<T>>,
+    body: String,
+) -> Result<impl IntoResponse, (StatusCode, String)> {
+    let records = body
+        .lines()
+        .filter(|line| !line.trim().is_empty())
+        .enumerate()
+        .map(|(i, line)| {
+            serde_json::from_str::<BackupRecord>(line)
+                .map_err(|e| (StatusCode::BAD_REQUEST, format!("invalid record at line {}: [{}]", i + 1, e)))
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+    validate_backup(&records).map_err(|e| (StatusCode::BAD_REQUEST, e))?;
+
+    let summary = repo.restore(records).await.map_err(|e| {
+        match e.downcast_ref::<RepositoryError>() {
+            Some(RepositoryError::NotEmpty) => (StatusCode::CONFLICT, e.to_string()),
+            _ => (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()),
+        }
+    })?;
+    Ok((StatusCode::CREATED, Json(summary)))
+}
+
+// COPY で取るので時間がかかる。ジョブとして実行し、結果に manifest を載せる
+pub async fn create_snapshot<T: BackupRepository>(
+    _: RequireAdmin,
+    State(config): State<SnapshotConfig>,
+    State(BackupRepo(repo)): State<BackupRepo<T>>,
+    State(jobs): State<JobRegistry>,
+) -> Result<impl IntoResponse, StatusCode> {
+    let store = config.store.ok_or(StatusCode::NOT_FOUND)?;
+    let job = jobs.spawn("snapshot", async move { repo.snapshot(store).await });
+    Ok((StatusCode::ACCEPTED, Json(job)))
+}
+
+pub async fn all_snapshots(
+    _: RequireAdmin,
+    State(config): State<SnapshotConfig>,
+) -> Result<impl IntoResponse, StatusCode> {
+    let store = config.store.ok_or(StatusCode::NOT_FOUND)?;
+    let snapshots = list_snapshots(store.as_ref())
+        .await
+        .or(Err(StatusCode::INTERNAL_SERVER_ERROR))?;
+    Ok((StatusCode::OK, Json(snapshots)))
+}
+
+#[derive(Debug, Deserialize)]
+pub struct RestoreSnapshotQuery {
+    schema: String,
+}
+
+pub async fn restore_snapshot<T: BackupRepository>(
+    _: RequireAdmin,
+    Path(id): Path<String>,
+    Query(query): Query<RestoreSnapshotQuery>,
+    State(config): State<SnapshotConfig>,
+    State(BackupRepo(repo)): State<BackupRepo<T>>,
+    State(jobs): State<JobRegistry>,
+) -> Result<impl IntoResponse, (StatusCode, String)> {
+    let store = config.store.ok_or((StatusCode::NOT_FOUND, String::new()))?;
+    validate_schema_name(&query.schema).map_err(|e| (StatusCode::BAD_REQUEST, e))?;
+    // 無いスナップショットはジョブにせず、その場で 404 を返す
+    load_manifest(store.as_ref(), &id)
+        .await
+        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?
+        .ok_or((StatusCode::NOT_FOUND, format!("snapshot not found: [{}]", id)))?;
+    let job = jobs.spawn("restore_snapshot", async move {
+        repo.restore_snapshot(store, id, query.schema).await
+    });
+    Ok((StatusCode::ACCEPTED, Json(job)))
+}
+
+pub async fn rebuild_search_index<T: MaintenanceRepository>(
+    _: RequireAdmin,
+    State(MaintenanceRepo(repo)): State<MaintenanceRepo<T>>,
+    State(jobs): State<JobRegistry>,
+) -> impl IntoResponse {
+    let job = jobs.spawn("rebuild_search_index", async move { repo.rebuild_search_index().await });
+    (StatusCode::ACCEPTED, Json(job))
+}
+
+pub async fn refresh_stats<T: MaintenanceRepository>(
+    _: RequireAdmin,
+    State(MaintenanceRepo(repo)): State<MaintenanceRepo<T>>,
+    State(jobs): State<JobRegistry>,
+) -> impl IntoResponse {
+    let job = jobs.spawn("refresh_stats", async move { repo.refresh_stats().await });
+    (StatusCode::ACCEPTED, Json(job))
+}
+
+#[derive(Debug, Deserialize)]
+pub struct PurgeQuery {
+    retention_days: Option<i32>,
+}
+
+pub async fn purge_expired<T: MaintenanceRepository>(
+    _: RequireAdmin,
+    Query(query): Query<PurgeQuery>,
+    State(MaintenanceRepo(repo)): State<MaintenanceRepo<T>>,
+    State(jobs): State<JobRegistry>,
+) -> Result<impl IntoResponse, StatusCode> {
+    let retention_days = query.retention_days.unwrap_or(DEFAULT_RETENTION_DAYS);
+    if retention_days < 0 {
+        return Err(StatusCode::BAD_REQUEST);
+    }
+    let job = jobs.spawn("purge_expired", async move { repo.purge_expired(retention_days).await });
+    Ok((StatusCode::ACCEPTED, Json(job)))
+}
+
+// 代表的なクエリの実行計画。本番では無効 (設定の query_plans_enabled)
+pub async fn query_plans<T: MaintenanceRepository>(
+    _: RequireAdmin,
+    State(MaintenanceRepo(repo)): State<MaintenanceRepo<T>>,
+) -> Result<impl IntoResponse, StatusCode> {
+    let plans = repo
+        .explain_queries()
+        .await
+        .or(Err(StatusCode::INTERNAL_SERVER_ERROR))?;
+    Ok((StatusCode::OK, Json(plans)))
+}
+
+pub async fn all_jobs(
+    _: RequireAdmin,
+    State(jobs): State<JobRegistry>,
+) -> impl IntoResponse {
+    (StatusCode::OK, Json(jobs.all()))
+}
+
+pub async fn find_job(
+    _: RequireAdmin,
+    Path(id): Path<Uuid>,
+    State(jobs): State<JobRegistry>,
+) -> Result<impl IntoResponse, StatusCode> {
+    let job = jobs.find(id).ok_or(StatusCode::NOT_FOUND)?;
+    Ok((StatusCode::OK, Json(job)))
+}
+
+// キャッシュのヒット率などを返す。無効なら enabled: false
+pub async fn cache_stats(
+    _: RequireAdmin,
+    State(cache): State<QueryCache>,
+) -> impl IntoResponse {
+    (StatusCode::OK, Json(cache.stats()))
+}
+
+// Prometheus の text 形式で返す
+pub async fn metrics(_: RequireAdmin, State(metrics): State<Metrics>) -> impl IntoResponse {
+    (
+        [(header::CONTENT_TYPE, prometheus::TEXT_FORMAT)],
+        metrics.render(),
+    )
+}
+
+pub async fn access_log<T: AccessLogRepository>(
+    _: RequireAdmin,
+    Query(filter): Query<AccessLogFilter>,
+    State(AccessLogRepo(repo)): State<AccessLogRepo<T>>,
+) -> Result<impl IntoResponse, StatusCode> {
+    let entries = repo
+        .search(filter)
+        .await
+        .or(Err(StatusCode::INTERNAL_SERVER_ERROR))?;
+    Ok((StatusCode::OK, Json(entries)))
+}
+
+// ログインの失敗で掛かったロックを期限より前に解く。それまでの失敗は数えなくなる
+pub async fn unlock_user<T: UserRepository>(
+    _: RequireAdmin,
+    Path(id): Path<i32>,
+    State(UserRepo(repo)): State<UserRepo<T>>,
+) -> Result<impl IntoResponse, StatusCode> {
+    let user = repo.find(id).await.map_err(|e| match e.downcast_ref::<RepositoryError>() {
+        Some(RepositoryError::NotFound(_)) => StatusCode::NOT_FOUND,
+        _ => StatusCode::INTERNAL_SERVER_ERROR,
+    })?;
+    let event = NewAuthEvent::new(AuthEventKind::AccountUnlocked, user.email).user(Some(user.id));
+    repo.record_event(event)
+        .await
+        .or(Err(StatusCode::INTERNAL_SERVER_ERROR))?;
+    Ok(StatusCode::NO_CONTENT)
+}
@@ -0,0 +1,431 @@
+use axum::{
+    async_trait,
+    extract::{FromRef, FromRequestParts},
+    http::{header, request::Parts},
+    response::{IntoResponse, Response},
+};
+use chrono::{DateTime, Duration as ChronoDuration, Utc};
+use jsonwebtoken::{Algorithm, DecodingKey, EncodingKey, Header, Validation};
+use serde::{Deserialize, Serialize};
+use std::{fmt, sync::Arc, time::Duration};
+use uuid::Uuid;
+use crate::{
+    error_code::ErrorCode,
+    repositories::user::{FailedLogins, NewRefreshToken, UserRepository},
+};
+
+// refresh トークンの aud。アクセストークンと取り違えないようにする
+const REFRESH_AUDIENCE: &str = "refresh";
+const DEFAULT_REFRESH_EXPIRY: Duration = Duration::from_secs(30 * 24 * 3600);
+
+// POST /auth/revoke で失効させたアクセストークンを引く。AppBuilder::build でユーザーのリポジトリを渡す
+#[async_trait]
+pub trait RevokedTokens: Send + Sync + 'static {
+    async fn is_revoked(&self, token_id: Uuid, session_id: Option<Uuid>) -> anyhow::Result<bool>;
+}
+
+#[async_trait]
+impl<T: UserRepository> RevokedTokens for T {
+    async fn is_revoked(&self, token_id: Uuid, session_id: Option<Uuid>) -> anyhow::Result<bool> {
+        self.is_token_revoked(token_id, session_id).await
+    }
+}
+
+pub type SharedRevokedTokens = Arc<dyn RevokedTokens>;
+
+// JWT_SECRET が未設定なら認証しない。/todos と /labels は誰でも使え、Todo とラベルをユーザーで分けない
+#[derive(Clone)]
+pub struct AuthConfig {
+    secret: Option<String>,
+    expiry: Duration,
+    refresh_expiry: Duration,
+    // dev 以外のプロファイル。鍵が無くても認証なしには落とさず、全部のリクエストを断る
+    required: bool,
+    // None ならログインに何度失敗してもロックしない
+    lockout: Option<LoginLockout>,
+    // None なら失効を確かめない
+    revoked_tokens: Option<SharedRevokedTokens>,
+}
+
+// 鍵はログに出さない
+impl fmt::Debug for AuthConfig {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("AuthConfig")
+            .field("enabled", &self.enabled())
+            .field("expiry", &self.expiry)
+            .field("refresh_expiry", &self.refresh_expiry)
+            .field("required", &self.required)
+            .field("lockout", &self.lockout)
+            .finish_non_exhaustive()
+    }
+}
+
+// duration のうちに max_failures 回ログインに失敗した email は、最後の失敗から duration の間ログインを断る。
+// ロック中の試行は失敗に数えないので、期限が来れば解ける
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LoginLockout {
+    pub max_failures: u32,
+    pub duration: Duration,
+}
+
+impl LoginLockout {
+    // failures は duration のうちの失敗。ロックしないなら None
+    pub fn locked_until(&self, failures: &FailedLogins) -> Option<DateTime<Utc>> {
+        if failures.count < i64::from(self.max_failures) {
+            return None;
+        }
+        let duration = ChronoDuration::from_std(self.duration).ok()?;
+        failures.last_failed_at.map(|last_failed_at| last_failed_at + duration)
+    }
+}
+
+// sub はユーザーの id。JWT の仕様に合わせて文字列にする。jti は失効させるときに使う。
+// sid はログインのセッション (refresh トークンの系列) で、セッションを無効にしたらこのトークンも使えなくする。
+// aud が付いているのは別の用途のトークンなので、アクセストークンとしては受け付けない
+#[derive(Debug, Serialize, Deserialize)]
+struct Claims {
+    sub: String,
+    jti: Uuid,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    sid: Option<Uuid>,
+    iat: i64,
+    exp: i64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    aud: Option<String>,
+}
+
+// 署名と有効期限を検証したアクセストークン
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct VerifiedToken {
+    user_id: i32,
+    token_id: Uuid,
+    session_id: Option<Uuid>,
+    expires_at: DateTime<Utc>,
+}
+
+// POST /auth/revoke で失効させるもの
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Revocation {
+    // 期限が来るまで jti を覚えておく
+    Access { user_id: i32, token_id: Uuid, expires_at: DateTime<Utc> },
+    // 系列ごと無効にする
+    Refresh(NewRefreshToken),
+}
+
+// jti はトークンごと、fam はログインごとに振る
+#[derive(Debug, Serialize, Deserialize)]
+struct RefreshClaims {
+    sub: String,
+    jti: Uuid,
+    fam: Uuid,
+    aud: String,
+    iat: i64,
+    exp: i64,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct IssuedToken {
+    pub token: String,
+    pub expires_at: DateTime<Utc>,
+}
+
+// DB に登録するのは refresh だけ。token は返すだけで残さない
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct IssuedRefreshToken {
+    pub token: String,
+    pub refresh: NewRefreshToken,
+}
+
+// 認証しない
+impl Default for AuthConfig {
+    fn default() -> Self {
+        Self::new(None, Duration::from_secs(3600))
+    }
+}
+
+impl AuthConfig {
+    pub fn new(secret: Option<String>, expiry: Duration) -> Self {
+        Self {
+            secret: secret.filter(|secret| !secret.is_empty()),
+            expiry,
+            refresh_expiry: DEFAULT_REFRESH_EXPIRY,
+            required: false,
+            lockout: None,
+            revoked_tokens: None,
+        }
+    }
+
+    pub fn with_revoked_tokens(mut self, revoked_tokens: SharedRevokedTokens) -> Self {
+        self.revoked_tokens = Some(revoked_tokens);
+        self
+    }
+
+    pub fn with_refresh_expiry(mut self, refresh_expiry: Duration) -> Self {
+        self.refresh_expiry = refresh_expiry;
+        self
+    }
+
+    pub fn with_lockout(mut self, lockout: Option<LoginLockout>) -> Self {
+        self.lockout = lockout;
+        self
+    }
+
+    pub fn lockout(&self) -> Option<LoginLockout> {
+        self.lockout
+    }
+
+    pub fn required(mut self) -> Self {
+        self.required = true;
+        self
+    }
+
+    pub fn enabled(&self) -> bool {
+        self.secret.is_some()
+    }
+
+    // 認証が無効なら None
+    pub fn issue(&self, user_id: i32, session_id: Option<Uuid>) -> anyhow::Result<Option<IssuedToken>> {
+        let secret = match &self.secret {
+            Some(secret) => secret,
+            None => return Ok(None),
+        };
+        let now = Utc::now();
+        let expires_at = now + ChronoDuration::from_std(self.expiry)?;
+        let claims = Claims {
+            sub: user_id.to_string(),
+            jti: Uuid::new_v4(),
+            sid: session_id,
+            iat: now.timestamp(),
+            exp: expires_at.timestamp(),
+            aud: None,
+        };
+        let token = jsonwebtoken::encode(&Header::new(Algorithm::HS256), &claims, &EncodingKey::from_secret(secret.as_bytes()))?;
+        Ok(Some(IssuedToken { token, expires_at }))
+    }
+
+    // family_id の系列の次の refresh トークン。認証が無効なら None
+    pub fn issue_refresh(&self, user_id: i32, family_id: Uuid) -> anyhow::Result<Option<IssuedRefreshToken>> {
+        let secret = match &self.secret {
+            Some(secret) => secret,
+            None => return Ok(None),
+        };
+        let now = Utc::now();
+        let refresh = NewRefreshToken {
+            id: Uuid::new_v4(),
+            family_id,
+            user_id,
+            expires_at: now + ChronoDuration::from_std(self.refresh_expiry)?,
+        };
+        let claims = RefreshClaims {
+            sub: user_id.to_string(),
+            jti: refresh.id,
+            fam: family_id,
+            aud: REFRESH_AUDIENCE.to_string(),
+            iat: now.timestamp(),
+            exp: refresh.expires_at.timestamp(),
+        };
+        let token = jsonwebtoken::encode(&Header::new(Algorithm::HS256), &claims, &EncodingKey::from_secret(secret.as_bytes()))?;
+        Ok(Some(IssuedRefreshToken { token, refresh }))
+    }
+
+    // 署名と有効期限を検証して、トークンの jti と系列を返す。使用済みかどうかは DB で確かめる
+    pub fn verify_refresh(&self, token: &str) -> Option<NewRefreshToken> {
+        let secret = self.secret.as_ref()?;
+        let mut validation = Validation::new(Algorithm::HS256);
+        validation.set_audience(&[REFRESH_AUDIENCE]);
+        let claims = jsonwebtoken::decode::<RefreshClaims>(token, &DecodingKey::from_secret(secret.as_bytes()), &validation)
+            .ok()?
+            .claims;
+        Some(NewRefreshToken {
+            id: claims.jti,
+            family_id: claims.fam,
+            user_id: claims.sub.parse().ok()?,
+            expires_at: DateTime::from_timestamp(claims.exp, 0)?,
+        })
+    }
+
+    // 署名と有効期限を検証できたトークンだけ。期限の過ぎたものは失効させるまでもない
+    pub fn revocation(&self, token: &str) -> Option<Revocation> {
+        if let Some(refresh) = self.verify_refresh(token) {
+            return Some(Revocation::Refresh(refresh));
+        }
+        let verified = self.verify(token)?;
+        Some(Revocation::Access {
+            user_id: verified.user_id,
+            token_id: verified.token_id,
+            expires_at: verified.expires_at,
+        })
+    }
+
+    // 署名と有効期限を検証する。失効させたかどうかは見ない
+    fn verify(&self, token: &str) -> Option<VerifiedToken> {
+        let secret = self.secret.as_ref()?;
+        // アルゴリズムはトークンのヘッダに任せず HS256 に固定する
+        let claims = jsonwebtoken::decode::<Claims>(
+            token,
+            &DecodingKey::from_secret(secret.as_bytes()),
+            &Validation::new(Algorithm::HS256),
+        )
+        .ok()?
+        .claims;
+        if claims.aud.is_some() {
+            return None;
+        }
+        Some(VerifiedToken {
+            user_id: claims.sub.parse().ok()?,
+            token_id: claims.jti,
+            session_id: claims.sid,
+            expires_at: DateTime::from_timestamp(claims.exp, 0)?,
+        })
+    }
+}
+
+// /todos と /labels のハンドラの引数に置くと、Authorization: Bearer のトークンを検証する。
+// 認証が無効なら誰でも通し、ユーザーは None になる
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AuthenticatedUser {
+    id: Option<i32>,
+    session_id: Option<Uuid>,
+}
+
+impl AuthenticatedUser {
+    // リポジトリに渡す持ち主。None なら絞り込まない
+    pub fn id(&self) -> Option<i32> {
+        self.id
+    }
+
+    // トークンを発行したログインのセッション。ログイン以外で発行したトークンなら None
+    pub fn session_id(&self) -> Option<Uuid> {
+        self.session_id
+    }
+
+    // 他のユーザーの Todo やラベルは、無いものとして扱う
+    pub fn owns(&self, owner_id: Option<i32>) -> bool {
+        self.id.is_none_or(|id| owner_id == Some(id))
+    }
+}
+
+#[async_trait]
+impl<S> FromRequestParts<S> for AuthenticatedUser
+where
+    AuthConfig: FromRef<S>,
+    S: Send + Sync,
+{
+    type Rejection = Response;
+
+    async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
+        let config = AuthConfig::from_ref(state);
+        if !config.enabled() {
+            // Config::validate を通っていれば来ない。来たら誰の Todo も見せない
+            if config.required {
+                tracing::error!("authentication is required but [jwt_secret] is not set");
+                return Err(ErrorCode::InternalError.into_response());
+            }
+            return Ok(AuthenticatedUser {
+                id: None,
+                session_id: None,
+            });
+        }
+        let unauthorized = || ([(header::WWW_AUTHENTICATE, "Bearer")], ErrorCode::Unauthorized).into_response();
+        let verified = parts
+            .headers
+            .get(header::AUTHORIZATION)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.strip_prefix("Bearer "))
+            .and_then(|token| config.verify(token.trim()))
+            .ok_or_else(unauthorized)?;
+        if let Some(revoked_tokens) = &config.revoked_tokens {
+            // 確かめられないときは通さない
+            let revoked = revoked_tokens.is_revoked(verified.token_id, verified.session_id).await.map_err(|e| {
+                tracing::error!("failed to check token revocation: {:?}", e);
+                ErrorCode::InternalError.into_response()
+            })?;
+            if revoked {
+                return Err(unauthorized());
+            }
+        }
+        Ok(AuthenticatedUser {
+            id: Some(verified.user_id),
+            session_id: verified.session_id,
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use axum::{body::Body, http::{Request, StatusCode}, routing::get, Router};
+    use tower::ServiceExt;
+
+    const SECRET: &str = "0123456789abcdef0123456789abcdef";
+
+    #[test]
+    fn verify_issued_token() {
+        let config = AuthConfig::new(Some(SECRET.to_string()), Duration::from_secs(60));
+        let issued = config.issue(7, None).unwrap().unwrap();
+        assert!(issued.expires_at > Utc::now());
+        assert_eq!(Some(7), config.verify(&issued.token).map(|verified| verified.user_id));
+        let another = AuthConfig::new(Some("another secret, another secret!!".to_string()), Duration::from_secs(60));
+        assert_eq!(None, another.verify(&issued.token));
+        assert_eq!(None, config.verify("not a token"));
+
+        // 期限切れ (検証の猶予の 60 秒より前)
+        let expired = Claims {
+            sub: "7".to_string(),
+            jti: Uuid::new_v4(),
+            sid: None,
+            iat: Utc::now().timestamp() - 3600,
+            exp: Utc::now().timestamp() - 600,
+            aud: None,
+        };
+        let expired = jsonwebtoken::encode(&Header::default(), &expired, &EncodingKey::from_secret(SECRET.as_bytes())).unwrap();
+        assert_eq!(None, config.verify(&expired));
+
+        assert_eq!(None, AuthConfig::new(Some(String::new()), Duration::from_secs(60)).issue(7, None).unwrap());
+    }
+
+    #[test]
+    fn verify_issued_refresh_token() {
+        let config = AuthConfig::new(Some(SECRET.to_string()), Duration::from_secs(60));
+        let family_id = Uuid::new_v4();
+        let issued = config.issue_refresh(7, family_id).unwrap().unwrap();
+        assert_eq!((7, family_id), (issued.refresh.user_id, issued.refresh.family_id));
+        assert_eq!(Some(issued.refresh.id), config.verify_refresh(&issued.token).map(|refresh| refresh.id));
+        // アクセストークンと refresh トークンは取り違えない
+        assert_eq!(None, config.verify(&issued.token));
+        let access = config.issue(7, None).unwrap().unwrap();
+        assert_eq!(None, config.verify_refresh(&access.token));
+        assert_eq!(None, AuthConfig::default().issue_refresh(7, family_id).unwrap());
+    }
+
+    #[test]
+    fn lock_after_max_failures() {
+        let lockout = LoginLockout {
+            max_failures: 3,
+            duration: Duration::from_secs(600),
+        };
+        let now = Utc::now();
+        let failures = |count| FailedLogins {
+            count,
+            last_failed_at: Some(now).filter(|_| count > 0),
+        };
+        assert_eq!(None, lockout.locked_until(&failures(0)));
+        assert_eq!(None, lockout.locked_until(&failures(2)));
+        assert_eq!(Some(now + ChronoDuration::seconds(600)), lockout.locked_until(&failures(3)));
+        assert_eq!(Some(now + ChronoDuration::seconds(600)), lockout.locked_until(&failures(4)));
+    }
+
+    #[tokio::test]
+    async fn reject_everyone_when_required_secret_is_missing() {
+        let app = |config: AuthConfig| {
+            Router::new()
+                .route("/", get(|user: AuthenticatedUser| async move { format!("{:?}", user.id()) }))
+                .with_state(config)
+        };
+        let req = || Request::builder().uri("/").body(Body::empty()).unwrap();
+        let res = app(AuthConfig::default()).oneshot(req()).await.unwrap();
+        assert_eq!(StatusCode::OK, res.status());
+        let res = app(AuthConfig::default().required()).oneshot(req()).await.unwrap();
+        assert_eq!(StatusCode::INTERNAL_SERVER_ERROR, res.status());
+    }
+}
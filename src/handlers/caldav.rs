@@ -0,0 +1,236 @@
+use axum::{
+    body::Bytes,
+    extract::OriginalUri,
+    http::{header, header::HeaderName, HeaderMap, HeaderValue, Method, StatusCode},
+    response::{IntoResponse, Response},
+};
+use chrono::Utc;
+use validator::{Validate, ValidationErrors};
+use crate::{
+    caldav::{self, COLLECTION_PATH, DAV_PREFIX},
+    error_code::ErrorCode,
+    normalize::Normalize,
+    repositories::todo::{Include, TodoEntity, TodoFilter, TodoRepository, UpdateTodo, Upserted, UpsertTodo},
+    services::todo::TodoService,
+};
+use super::auth::AuthenticatedUser;
+use super::error::ApiError;
+use super::{etag, IfMatch};
+
+const ALLOW: &str = "OPTIONS, PROPFIND, REPORT, GET, PUT, DELETE";
+// クライアントが自分で名前を付けて PUT した Todo は、その名前を client_key にして upsert する
+const CLIENT_KEY_PREFIX: &str = "caldav:";
+
+// /dav/ がプリンシパル兼カレンダーホームで、その下の /dav/todos/ に全 Todo を 1 つのカレンダーとして置く
+enum Target {
+    Root,
+    Collection,
+    // /dav/todos/{name}.ics の name
+    Resource(String),
+}
+
+impl Target {
+    fn parse(path: &str) -> Option<Self> {
+        let rest = path.strip_prefix(DAV_PREFIX)?;
+        match rest.trim_end_matches('/') {
+            "" => Some(Target::Root),
+            "/todos" => Some(Target::Collection),
+            rest => rest
+                .strip_prefix("/todos/")
+                .and_then(|name| name.strip_suffix(".ics"))
+                .filter(|name| !name.is_empty() && !name.contains('/'))
+                .map(|name| Target::Resource(name.to_string())),
+        }
+    }
+}
+
+// axum の MethodFilter には PROPFIND や REPORT が無いので、メソッドはここで振り分ける。
+// カレンダーに見えるのは認証したユーザーの Todo だけ
+pub async fn dav<T: TodoRepository>(
+    user: AuthenticatedUser,
+    method: Method,
+    OriginalUri(uri): OriginalUri,
+    headers: HeaderMap,
+    IfMatch(expected_version): IfMatch,
+    service: TodoService<T>,
+    body: Bytes,
+) -> Result<Response, Response> {
+    let repo = service.repo();
+    let target = Target::parse(uri.path()).ok_or_else(|| ErrorCode::RouteNotFound.into_response())?;
+    // PROPFIND の Depth の既定値は infinity だが、1 段下までしか返さない
+    let shallow = headers.get("depth").is_some_and(|depth| depth == "0");
+    match (method.as_str(), target) {
+        ("OPTIONS", _) => Ok((
+            StatusCode::OK,
+            [(HeaderName::from_static("dav"), "1, calendar-access"), (header::ALLOW, ALLOW)],
+        )
+            .into_response()),
+        ("PROPFIND", Target::Root) => {
+            let mut responses = vec![root_response()];
+            if !shallow {
+                responses.push(collection_response(repo, user).await?);
+            }
+            Ok(multistatus(&responses))
+        }
+        ("PROPFIND", Target::Collection) => {
+            let mut responses = vec![collection_response(repo, user).await?];
+            if !shallow {
+                // href と etag しか返さないので、ラベルは読まない
+                let filter = TodoFilter { owner_id: user.id(), ..TodoFilter::default() };
+                let todos = repo.all(filter, Include::NOTHING).await.map_err(|_| ErrorCode::InternalError.into_response())?;
+                responses.extend(todos.iter().map(|todo| caldav::response(&caldav::href(todo), &resource_props(todo))));
+            }
+            Ok(multistatus(&responses))
+        }
+        ("PROPFIND", Target::Resource(name)) => {
+            let todo = find(repo, &name, user).await?;
+            Ok(multistatus(&[caldav::response(&caldav::href(&todo), &resource_props(&todo))]))
+        }
+        ("REPORT", Target::Collection) => report(repo, user, &body).await,
+        ("GET", Target::Resource(name)) => {
+            let todo = find(repo, &name, user).await?;
+            Ok((
+                [
+                    (header::CONTENT_TYPE, HeaderValue::from_static("text/calendar; charset=utf-8")),
+                    (header::ETAG, etag(&todo)),
+                ],
+                caldav::calendar(&todo, Utc::now()),
+            )
+                .into_response())
+        }
+        ("PUT", Target::Resource(name)) => put(repo, &service, user, name, expected_version, &body).await,
+        ("DELETE", Target::Resource(name)) => {
+            let todo = find(repo, &name, user).await?;
+            repo.delete(todo.id, expected_version)
+                .await
+                .map_err(|e| ApiError::from(e).not_found(ErrorCode::TodoNotFound).into_response())?;
+            Ok(StatusCode::NO_CONTENT.into_response())
+        }
+        _ => Ok((StatusCode::METHOD_NOT_ALLOWED, [(header::ALLOW, ALLOW)]).into_response()),
+    }
+}
+
+// 他のユーザーの Todo は無いものとして扱う
+async fn find<T: TodoRepository>(repo: &T, name: &str, user: AuthenticatedUser) -> Result<TodoEntity, Response> {
+    let id = name.parse::<i32>().map_err(|_| ErrorCode::TodoNotFound.into_response())?;
+    repo.find(id)
+        .await
+        .ok()
+        .filter(|todo| user.owns(todo.owner_id))
+        .ok_or_else(|| ErrorCode::TodoNotFound.into_response())
+}
+
+// calendar-multiget なら指定された href だけ、calendar-query なら (フィルタは見ずに) 全件返す
+async fn report<T: TodoRepository>(repo: &T, user: AuthenticatedUser, body: &Bytes) -> Result<Response, Response> {
+    let hrefs = caldav::requested_hrefs(&String::from_utf8_lossy(body));
+    let filter = TodoFilter { owner_id: user.id(), ..TodoFilter::default() };
+    let todos = repo.all(filter, Include::default()).await.map_err(|_| ErrorCode::InternalError.into_response())?;
+    let now = Utc::now();
+    let responses: Vec<String> = todos
+        .iter()
+        .map(|todo| (caldav::href(todo), todo))
+        .filter(|(href, _)| hrefs.is_empty() || hrefs.contains(href))
+        .map(|(href, todo)| {
+            let props = [
+                caldav::prop("d:getetag", &format!("\"{}\"", todo.version)),
+                caldav::prop("c:calendar-data", &caldav::calendar(todo, now)),
+            ];
+            caldav::response(&href, &props)
+        })
+        .collect();
+    Ok(multistatus(&responses))
+}
+
+async fn put<T: TodoRepository>(
+    repo: &T,
+    service: &TodoService<T>,
+    user: AuthenticatedUser,
+    name: String,
+    expected_version: Option<i32>,
+    body: &Bytes,
+) -> Result<Response, Response> {
+    let vtodo = caldav::parse_vtodo(&String::from_utf8_lossy(body))
+        .ok_or_else(|| ErrorCode::InvalidInput.with_message("a VTODO with a SUMMARY is required"))?;
+    if let Ok(todo) = find(repo, &name, user).await {
+        let mut payload = UpdateTodo::new(Some(vtodo.summary), Some(vtodo.completed), None);
+        payload.normalize();
+        payload.validate().map_err(validation_failed)?;
+        let todo = service
+            .update(todo.id, payload, expected_version)
+            .await
+            .map_err(|e| ApiError::from(e).not_found(ErrorCode::TodoNotFound).into_response())?;
+        return Ok((StatusCode::NO_CONTENT, [(header::ETAG, etag(&todo))]).into_response());
+    }
+
+    let mut payload = UpsertTodo::new(vtodo.summary, vtodo.completed, vec![]);
+    if let Some(owner_id) = user.id() {
+        payload = payload.with_owner(owner_id);
+    }
+    payload.normalize();
+    payload.validate().map_err(validation_failed)?;
+    let upserted = service
+        .upsert_by_key(format!("{}{}", CLIENT_KEY_PREFIX, name), payload, expected_version)
+        .await
+        .map_err(|e| ApiError::from(e).into_response())?;
+    Ok(match upserted {
+        // 以降の一覧には {id}.ics として出るので、その場所を返しておく
+        Upserted::Created(todo) => (
+            StatusCode::CREATED,
+            [(header::ETAG, etag(&todo)), (header::LOCATION, HeaderValue::from_str(&caldav::href(&todo)).unwrap())],
+        )
+            .into_response(),
+        Upserted::Updated(todo) => (StatusCode::NO_CONTENT, [(header::ETAG, etag(&todo))]).into_response(),
+    })
+}
+
+fn validation_failed(e: ValidationErrors) -> Response {
+    let message = format!("Validation error: [{}]", e).replace('\n', ", ");
+    ErrorCode::ValidationFailed.with_message(message)
+}
+
+fn root_response() -> String {
+    let home = format!("<d:href>{}/</d:href>", DAV_PREFIX);
+    caldav::response(
+        &format!("{}/", DAV_PREFIX),
+        &[
+            "<d:resourcetype><d:collection/></d:resourcetype>".to_string(),
+            format!("<d:current-user-principal>{}</d:current-user-principal>", home),
+            format!("<c:calendar-home-set>{}</c:calendar-home-set>", home),
+        ],
+    )
+}
+
+// getctag はコレクションのどこかが変わると変わる値。クライアントはこれが同じなら中身を取り直さない
+async fn collection_response<T: TodoRepository>(repo: &T, user: AuthenticatedUser) -> Result<String, Response> {
+    let last_modified = repo
+        .last_modified(user.id())
+        .await
+        .map_err(|_| ErrorCode::InternalError.into_response())?;
+    let ctag = last_modified.map_or(0, |time| time.timestamp_millis());
+    Ok(caldav::response(
+        COLLECTION_PATH,
+        &[
+            "<d:resourcetype><d:collection/><c:calendar/></d:resourcetype>".to_string(),
+            caldav::prop("d:displayname", "Todos"),
+            "<c:supported-calendar-component-set><c:comp name=\"VTODO\"/></c:supported-calendar-component-set>".to_string(),
+            caldav::prop("cs:getctag", &ctag.to_string()),
+        ],
+    ))
+}
+
+fn resource_props(todo: &TodoEntity) -> Vec<String> {
+    vec![
+        caldav::prop("d:getetag", &format!("\"{}\"", todo.version)),
+        caldav::prop("d:getcontenttype", "text/calendar; charset=utf-8; component=VTODO"),
+        "<d:resourcetype/>".to_string(),
+    ]
+}
+
+fn multistatus(responses: &[String]) -> Response {
+    (
+        StatusCode::MULTI_STATUS,
+        [(header::CONTENT_TYPE, "application/xml; charset=utf-8")],
+        caldav::multistatus(responses),
+    )
+        .into_response()
+}
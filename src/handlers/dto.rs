@@ -0,0 +1,303 @@
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use uuid::Uuid;
+use crate::repositories::{
+    label::{Label, LabelDetail},
+    sync::{Resolution, SyncConflict, SyncIdMapping, SyncResult},
+    todo::{LabelAssignment, LabelTodoCounts, Priority, TodoCounts, TodoEntity, TodosByLabel},
+    user::{AuthEvent, AuthEventKind, Session, User},
+};
+
+// レスポンスで返す JSON の形。リポジトリの型はそのまま返さずにここで詰め替えるので、
+// テーブルにカラムを足したり名前を変えたりしても、ここを変えない限り API の形は変わらない
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
+pub struct LabelResponse {
+    pub id: i32,
+    pub name: String,
+}
+
+impl From<Label> for LabelResponse {
+    fn from(label: Label) -> Self {
+        Self {
+            id: label.id,
+            name: label.name,
+        }
+    }
+}
+
+// GET /labels/:id の結果。todo_count はゴミ箱の Todo を数えない
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
+pub struct LabelDetailResponse {
+    pub id: i32,
+    pub name: String,
+    pub todo_count: i64,
+}
+
+impl From<LabelDetail> for LabelDetailResponse {
+    fn from(label: LabelDetail) -> Self {
+        Self {
+            id: label.id,
+            name: label.name,
+            todo_count: label.todo_count,
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
+pub struct TodoResponse {
+    pub id: i32,
+    pub text: String,
+    pub completed: bool,
+    pub labels: Vec<LabelResponse>,
+    pub version: i32,
+    pub completed_at: Option<DateTime<Utc>>,
+    pub due_date: Option<DateTime<Utc>>,
+    pub priority: Priority,
+    // ゴミ箱の一覧でだけ付く
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub deleted_at: Option<DateTime<Utc>>,
+}
+
+impl From<TodoEntity> for TodoResponse {
+    fn from(todo: TodoEntity) -> Self {
+        Self {
+            id: todo.id,
+            text: todo.text,
+            completed: todo.completed,
+            labels: todo.labels.into_iter().map(LabelResponse::from).collect(),
+            version: todo.version,
+            completed_at: todo.completed_at,
+            due_date: todo.due_date,
+            priority: todo.priority,
+            deleted_at: todo.deleted_at,
+        }
+    }
+}
+
+// password_hash は返さない
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
+pub struct UserResponse {
+    pub id: i32,
+    pub email: String,
+    pub created_at: DateTime<Utc>,
+}
+
+impl From<User> for UserResponse {
+    fn from(user: User) -> Self {
+        Self {
+            id: user.id,
+            email: user.email,
+            created_at: user.created_at,
+        }
+    }
+}
+
+// GET /me/security-events の 1 件。email は本人のものなので返さない
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
+pub struct AuthEventResponse {
+    pub id: i64,
+    pub kind: AuthEventKind,
+    pub user_agent: Option<String>,
+    pub created_at: DateTime<Utc>,
+}
+
+impl From<AuthEvent> for AuthEventResponse {
+    fn from(event: AuthEvent) -> Self {
+        Self {
+            id: event.id,
+            kind: event.kind,
+            user_agent: event.user_agent,
+            created_at: event.created_at,
+        }
+    }
+}
+
+// GET /me/sessions の 1 件。current はこのリクエストのトークンを発行したセッション
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
+pub struct SessionResponse {
+    pub id: Uuid,
+    pub user_agent: Option<String>,
+    pub created_at: DateTime<Utc>,
+    pub last_seen_at: DateTime<Utc>,
+    pub current: bool,
+}
+
+impl SessionResponse {
+    pub fn new(session: Session, current: Option<Uuid>) -> Self {
+        Self {
+            current: current == Some(session.id),
+            id: session.id,
+            user_agent: session.user_agent,
+            created_at: session.created_at,
+            last_seen_at: session.last_seen_at,
+        }
+    }
+}
+
+// ログインと POST /auth/refresh の結果。認証が無効 (JWT_SECRET が未設定) ならトークンは付かない
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
+pub struct LoginResponse {
+    #[serde(flatten)]
+    pub user: UserResponse,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub token: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub expires_at: Option<DateTime<Utc>>,
+    // POST /auth/refresh で新しいトークンと引き換える。引き換えるたびに変わる
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub refresh_token: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub refresh_expires_at: Option<DateTime<Utc>>,
+}
+
+pub fn todos(todos: Vec<TodoEntity>) -> Vec<TodoResponse> {
+    todos.into_iter().map(TodoResponse::from).collect()
+}
+
+pub fn labels(labels: Vec<Label>) -> Vec<LabelResponse> {
+    labels.into_iter().map(LabelResponse::from).collect()
+}
+
+// ラベル名 -> そのラベルが付いた Todo
+pub type TodosByLabelResponse = BTreeMap<String, Vec<TodoResponse>>;
+
+pub fn todos_by_label(groups: TodosByLabel) -> TodosByLabelResponse {
+    groups.into_iter().map(|(name, group)| (name, todos(group))).collect()
+}
+
+// POST /labels/:id/assign の結果。todo_ids は実際に付け外しした Todo だけ
+// GET /me/feed。トークン付きなので、フィードリーダーにそのまま登録できる
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
+pub struct FeedUrlResponse {
+    pub url: String,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
+pub struct LabelAssignmentResponse {
+    pub label_id: i32,
+    pub action: LabelAssignment,
+    pub todo_ids: Vec<i32>,
+}
+
+// DELETE /todos の結果。deleted は実際にゴミ箱に入れた Todo だけ
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
+pub struct BulkDeleteResponse {
+    pub deleted: Vec<i32>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
+pub struct LabelCountsResponse {
+    pub id: i32,
+    pub name: String,
+    pub open: i64,
+    pub completed: i64,
+}
+
+impl From<LabelTodoCounts> for LabelCountsResponse {
+    fn from(counts: LabelTodoCounts) -> Self {
+        Self {
+            id: counts.label_id,
+            name: counts.name,
+            open: counts.open,
+            completed: counts.completed,
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
+pub struct TodoCountsResponse {
+    pub open: i64,
+    pub completed: i64,
+    pub labels: Vec<LabelCountsResponse>,
+}
+
+impl From<TodoCounts> for TodoCountsResponse {
+    fn from(counts: TodoCounts) -> Self {
+        Self {
+            open: counts.open,
+            completed: counts.completed,
+            labels: counts.labels.into_iter().map(LabelCountsResponse::from).collect(),
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
+pub struct SyncConflictResponse {
+    pub id: i32,
+    pub base_version: i32,
+    pub resolution: Resolution,
+    pub fields: Vec<String>,
+    pub server: TodoResponse,
+}
+
+impl From<SyncConflict> for SyncConflictResponse {
+    fn from(conflict: SyncConflict) -> Self {
+        Self {
+            id: conflict.id,
+            base_version: conflict.base_version,
+            resolution: conflict.resolution,
+            fields: conflict.fields,
+            server: conflict.server.into(),
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
+pub struct SyncResultResponse {
+    pub applied: Vec<TodoResponse>,
+    pub conflicts: Vec<SyncConflictResponse>,
+    pub deleted: Vec<i32>,
+    pub id_map: Vec<SyncIdMapping>,
+    pub skipped: Vec<Uuid>,
+    pub missing: Vec<i32>,
+}
+
+impl From<SyncResult> for SyncResultResponse {
+    fn from(result: SyncResult) -> Self {
+        Self {
+            applied: todos(result.applied),
+            conflicts: result.conflicts.into_iter().map(SyncConflictResponse::from).collect(),
+            deleted: result.deleted,
+            id_map: result.id_map,
+            skipped: result.skipped,
+            missing: result.missing,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn should_keep_todo_json_shape() {
+        let todo = TodoEntity {
+            id: 1,
+            text: "buy milk".to_string(),
+            completed: false,
+            labels: vec![Label { id: 2, name: "home".to_string() }],
+            version: 3,
+            completed_at: None,
+            owner_id: None,
+            due_date: None,
+            priority: Priority::Medium,
+            deleted_at: None,
+        };
+        assert_eq!(
+            json!({
+                "id": 1,
+                "text": "buy milk",
+                "completed": false,
+                "labels": [{"id": 2, "name": "home"}],
+                "version": 3,
+                "completed_at": null,
+                "due_date": null,
+                "priority": "medium",
+            }),
+            serde_json::to_value(TodoResponse::from(todo)).unwrap()
+        );
+    }
+}
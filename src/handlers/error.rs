@@ -0,0 +1,165 @@
+use axum::{
+    http::{header, StatusCode},
+    response::{IntoResponse, Response},
+    Json,
+};
+use serde_json::json;
+use crate::{
+    error_code::ErrorCode,
+    middleware::error_report,
+    moderation::Rejected,
+    repositories::RepositoryError,
+    services::ServiceError,
+};
+
+// ハンドラのエラー。リポジトリやサービスのエラーから ? で作り、{ "error": ..., "code": ... } の JSON で返す。
+// 無いものは 404、重複は 409、検証の失敗 (ValidatedJson、存在しないラベル、モデレーション) は 422、想定外のものは 500。
+// ログインの失敗が続いたアカウントは 423。JSON として読めないボディは 400 のまま
+#[derive(Debug)]
+pub enum ApiError {
+    // code は not_found や duplicate でリソースに合わせたものに変える
+    NotFound(ErrorCode),
+    Duplicate(ErrorCode),
+    PreconditionFailed,
+    LabelsNotFound(Vec<i32>),
+    Rejected(Rejected),
+    InvalidInput(&'static str),
+    QuotaExceeded { resource: String, limit: i64 },
+    // 何秒後にログインできるようになるか
+    AccountLocked { retry_after: u64 },
+    // 上のどれでもないもの。ステータスは code から決まる
+    Code(ErrorCode),
+    // 中身はクライアントに見せず、エラー報告にだけ送る
+    Unexpected(anyhow::Error),
+}
+
+impl ApiError {
+    pub fn not_found(self, code: ErrorCode) -> Self {
+        match self {
+            ApiError::NotFound(_) => ApiError::NotFound(code),
+            e => e,
+        }
+    }
+
+    pub fn duplicate(self, code: ErrorCode) -> Self {
+        match self {
+            ApiError::Duplicate(_) => ApiError::Duplicate(code),
+            e => e,
+        }
+    }
+}
+
+impl From<anyhow::Error> for ApiError {
+    fn from(e: anyhow::Error) -> Self {
+        match e.downcast_ref::<RepositoryError>() {
+            Some(RepositoryError::NotFound(_)) => ApiError::NotFound(ErrorCode::NotFound),
+            Some(RepositoryError::Duplicate(_)) => ApiError::Duplicate(ErrorCode::Conflict),
+            Some(RepositoryError::NotEmpty) => ApiError::Code(ErrorCode::Conflict),
+            Some(RepositoryError::PreconditionFailed) => ApiError::PreconditionFailed,
+            Some(RepositoryError::LabelsNotFound(ids)) => ApiError::LabelsNotFound(ids.clone()),
+            Some(RepositoryError::QuotaExceeded { resource, limit }) => ApiError::QuotaExceeded {
+                resource: resource.clone(),
+                limit: *limit,
+            },
+            None | Some(RepositoryError::Unexpected(_)) => ApiError::Unexpected(e),
+        }
+    }
+}
+
+impl From<ServiceError> for ApiError {
+    fn from(e: ServiceError) -> Self {
+        match e {
+            ServiceError::Rejected(rejected) => ApiError::Rejected(rejected),
+            ServiceError::InvalidInput(message) => ApiError::InvalidInput(message),
+            ServiceError::Repository(e) => e.into(),
+        }
+    }
+}
+
+impl From<ErrorCode> for ApiError {
+    fn from(code: ErrorCode) -> Self {
+        ApiError::Code(code)
+    }
+}
+
+// クエリの検証などでステータスだけを返すもの
+impl From<StatusCode> for ApiError {
+    fn from(status: StatusCode) -> Self {
+        ApiError::Code(ErrorCode::from_status(status))
+    }
+}
+
+impl IntoResponse for ApiError {
+    fn into_response(self) -> Response {
+        match self {
+            ApiError::NotFound(code) | ApiError::Duplicate(code) | ApiError::Code(code) => code.into_response(),
+            ApiError::PreconditionFailed => ErrorCode::PreconditionFailed.into_response(),
+            ApiError::LabelsNotFound(ids) => (
+                StatusCode::UNPROCESSABLE_ENTITY,
+                Json(json!({
+                    "error": "labels not found",
+                    "code": ErrorCode::LabelsNotFound,
+                    "label_ids": ids,
+                })),
+            )
+                .into_response(),
+            ApiError::Rejected(rejected) => (
+                StatusCode::UNPROCESSABLE_ENTITY,
+                Json(json!({
+                    "error": "content rejected",
+                    "code": ErrorCode::ContentRejected,
+                    "reason": rejected.reason,
+                })),
+            )
+                .into_response(),
+            ApiError::InvalidInput(message) => ErrorCode::InvalidInput.with_message(message),
+            // どの上限に引っかかったかも返す
+            ApiError::QuotaExceeded { resource, limit } => (
+                StatusCode::TOO_MANY_REQUESTS,
+                Json(json!({
+                    "error": "quota exceeded",
+                    "code": ErrorCode::QuotaExceeded,
+                    "resource": resource,
+                    "limit": limit,
+                })),
+            )
+                .into_response(),
+            ApiError::AccountLocked { retry_after } => {
+                ([(header::RETRY_AFTER, retry_after.to_string())], ErrorCode::AccountLocked).into_response()
+            }
+            ApiError::Unexpected(e) => {
+                tracing::error!("unexpected error: {:?}", e);
+                error_report::capture_unexpected(&e);
+                ErrorCode::InternalError.into_response()
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    async fn body(e: ApiError) -> (StatusCode, serde_json::Value) {
+        let res = e.into_response();
+        let status = res.status();
+        let bytes = hyper::body::to_bytes(res.into_body()).await.unwrap();
+        (status, serde_json::from_slice(&bytes).unwrap())
+    }
+
+    #[tokio::test]
+    async fn map_repository_errors() {
+        let (status, json) = body(ApiError::from(anyhow::Error::from(RepositoryError::NotFound(1))).not_found(ErrorCode::TodoNotFound)).await;
+        assert_eq!((StatusCode::NOT_FOUND, json!("TODO_NOT_FOUND")), (status, json["code"].clone()));
+        let (status, json) = body(ApiError::from(anyhow::Error::from(RepositoryError::Duplicate(1)))).await;
+        assert_eq!((StatusCode::CONFLICT, json!("CONFLICT")), (status, json["code"].clone()));
+        let (status, json) = body(ApiError::from(anyhow::Error::from(RepositoryError::LabelsNotFound(vec![3])))).await;
+        assert_eq!((StatusCode::UNPROCESSABLE_ENTITY, json!([3])), (status, json["label_ids"].clone()));
+        let res = ApiError::AccountLocked { retry_after: 30 }.into_response();
+        assert_eq!((StatusCode::LOCKED, "30"), (res.status(), res.headers()[header::RETRY_AFTER].to_str().unwrap()));
+        // 想定外のエラーの中身は返さない
+        let (status, json) = body(ApiError::from(anyhow::anyhow!("connection reset"))).await;
+        assert_eq!(StatusCode::INTERNAL_SERVER_ERROR, status);
+        assert_eq!(json!({"error": "internal server error", "code": "INTERNAL_ERROR"}), json);
+    }
+}
@@ -0,0 +1,22 @@
+use axum::{
+    http::{Request, StatusCode},
+    response::IntoResponse,
+    Json,
+};
+use serde_json::json;
+use crate::{error_code::ErrorCode, middleware::request_id::request_id};
+
+// ルーティングされているパスの先頭部分。存在しないパスへのリクエストにヒントとして返す
+const API_PREFIXES: [&str; 4] = ["/todos", "/labels", "/sync", "/admin"];
+
+pub async fn not_found<B>(req: Request<B>) -> impl IntoResponse {
+    (
+        StatusCode::NOT_FOUND,
+        Json(json!({
+            "error": "not found",
+            "code": ErrorCode::RouteNotFound,
+            "request_id": request_id(&req),
+            "hint": format!("available API prefixes: {}", API_PREFIXES.join(", ")),
+        })),
+    )
+}
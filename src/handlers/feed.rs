@@ -0,0 +1,139 @@
+use axum::{
+    extract::State,
+    http::header,
+    response::{IntoResponse, Response},
+    Json,
+};
+use jsonwebtoken::{Algorithm, DecodingKey, EncodingKey, Header, Validation};
+use serde::{Deserialize, Serialize};
+use crate::{
+    error_code::ErrorCode,
+    feed::AtomFeed,
+    repositories::todo::TodoRepository,
+    state::TodoRepo,
+};
+use super::{
+    admin::constant_time_eq,
+    auth::{AuthConfig, AuthenticatedUser},
+    dto::FeedUrlResponse,
+    pagination::PublicBaseUrl,
+    Query,
+};
+
+pub const FEED_PATH: &str = "/feeds/todos.atom";
+// フィードリーダーが見るのは最近のものだけなので、件数は固定にする
+pub const FEED_LIMIT: i64 = 50;
+
+// ユーザーごとのトークンの aud。ログインのトークンと取り違えないようにする
+const FEED_AUDIENCE: &str = "feed";
+
+// FEED_TOKEN が未設定ならフィードは無効 (404)。
+// フィードリーダーはヘッダを付けられないことが多いので、トークンはクエリで受け取る。
+// 認証が有効なら FEED_TOKEN そのものでは読めず、GET /me/feed で配るユーザーごとのトークンを使う
+#[derive(Debug, Clone)]
+pub struct FeedConfig {
+    token: Option<String>,
+}
+
+// リーダーに登録した URL を使い続けられるよう、期限は付けない
+#[derive(Debug, Serialize, Deserialize)]
+struct FeedClaims {
+    sub: String,
+    aud: String,
+}
+
+impl FeedConfig {
+    pub fn new(token: Option<String>) -> Self {
+        Self {
+            token: token.filter(|token| !token.is_empty()),
+        }
+    }
+
+    // FEED_TOKEN で署名した、user_id のフィードだけを読めるトークン。フィードが無効なら None
+    pub fn issue(&self, user_id: i32) -> anyhow::Result<Option<String>> {
+        let secret = match &self.token {
+            Some(secret) => secret,
+            None => return Ok(None),
+        };
+        let claims = FeedClaims {
+            sub: user_id.to_string(),
+            aud: FEED_AUDIENCE.to_string(),
+        };
+        let token = jsonwebtoken::encode(&Header::new(Algorithm::HS256), &claims, &EncodingKey::from_secret(secret.as_bytes()))?;
+        Ok(Some(token))
+    }
+
+    // 署名を検証して、ユーザーの id を返す
+    fn verify(&self, token: &str) -> Option<i32> {
+        let secret = self.token.as_ref()?;
+        let mut validation = Validation::new(Algorithm::HS256);
+        validation.validate_exp = false;
+        validation.required_spec_claims.clear();
+        validation.set_audience(&[FEED_AUDIENCE]);
+        let claims = jsonwebtoken::decode::<FeedClaims>(token, &DecodingKey::from_secret(secret.as_bytes()), &validation)
+            .ok()?
+            .claims;
+        claims.sub.parse().ok()
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct FeedQuery {
+    token: Option<String>,
+    label: Option<i32>,
+}
+
+pub async fn todos_feed<T: TodoRepository>(
+    Query(query): Query<FeedQuery>,
+    State(config): State<FeedConfig>,
+    State(auth): State<AuthConfig>,
+    State(TodoRepo(repo)): State<TodoRepo<T>>,
+    State(base_url): State<PublicBaseUrl>,
+) -> Result<Response, ErrorCode> {
+    let expected = config.token.as_ref().ok_or(ErrorCode::RouteNotFound)?;
+    let provided = query.token.ok_or(ErrorCode::Unauthorized)?;
+    // 認証が有効なら、トークンの持ち主の Todo だけを出す
+    let owner_id = if auth.enabled() {
+        Some(config.verify(&provided).ok_or(ErrorCode::Unauthorized)?)
+    } else if constant_time_eq(provided.as_bytes(), expected.as_bytes()) {
+        None
+    } else {
+        return Err(ErrorCode::Unauthorized);
+    };
+
+    let entries = repo
+        .recently_updated(FEED_LIMIT, query.label, owner_id)
+        .await
+        .or(Err(ErrorCode::InternalError))?;
+    // 購読 URL はトークン付きだが、フィードの id や self には入れない
+    let self_url = match query.label {
+        Some(label) => format!("{}?label={}", base_url.join(FEED_PATH), label),
+        None => base_url.join(FEED_PATH),
+    };
+    let feed = AtomFeed {
+        title: "Todos",
+        self_url: &self_url,
+        base_url: &base_url.join(""),
+    };
+    Ok((
+        [(header::CONTENT_TYPE, "application/atom+xml; charset=utf-8")],
+        feed.render(&entries),
+    )
+        .into_response())
+}
+
+// ログインしているユーザーのフィードの購読 URL。認証が無効なら FEED_TOKEN をそのまま使うので 404
+pub async fn my_feed(
+    user: AuthenticatedUser,
+    State(config): State<FeedConfig>,
+    State(base_url): State<PublicBaseUrl>,
+) -> Result<impl IntoResponse, ErrorCode> {
+    let user_id = user.id().ok_or(ErrorCode::RouteNotFound)?;
+    let token = config
+        .issue(user_id)
+        .or(Err(ErrorCode::InternalError))?
+        .ok_or(ErrorCode::RouteNotFound)?;
+    Ok(Json(FeedUrlResponse {
+        url: format!("{}?token={}", base_url.join(FEED_PATH), token),
+    }))
+}
@@ -0,0 +1,30 @@
+use axum::{
+    extract::State,
+    http::StatusCode,
+    response::IntoResponse,
+    Json,
+};
+use serde_json::json;
+use crate::{db::DbHealth, error_code::ErrorCode, leader::Leadership};
+
+// ロードバランサやオーケストレータ向け。DB に繋がらないあいだは 503 を返す。
+// leader かどうかは情報として返すだけで、ステータスには影響しない
+pub async fn health(
+    State(db): State<DbHealth>,
+    State(leadership): State<Leadership>,
+) -> impl IntoResponse {
+    let leader = leadership.is_leader();
+    if db.is_up() {
+        (StatusCode::OK, Json(json!({"status": "ok", "database": "up", "leader": leader})))
+    } else {
+        (
+            StatusCode::SERVICE_UNAVAILABLE,
+            Json(json!({
+                "status": "unavailable",
+                "code": ErrorCode::DatabaseUnavailable,
+                "database": "down",
+                "leader": leader,
+            })),
+        )
+    }
+}
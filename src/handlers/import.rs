@@ -0,0 +1,81 @@
+use axum::{
+    body::Bytes,
+    extract::{Path, State},
+    http::{header, StatusCode},
+    response::{IntoResponse, Response},
+    Json,
+};
+use uuid::Uuid;
+use crate::{
+    error_code::ErrorCode,
+    import::{ImportPlan, TodoistExport, TrelloExport},
+    jobs::JobRegistry,
+    repositories::{label::LabelRepository, todo::TodoRepository},
+    services::{import::ImportService, todo::TodoService},
+    state::LabelRepo,
+};
+use super::auth::AuthenticatedUser;
+
+const JOB_KIND_PREFIX: &str = "import_";
+
+// エクスポートはサービス側のフィールドを大量に含むので、ValidatedJson (JSON_STRICT) は通さずに読む
+fn invalid_json(e: serde_json::Error) -> Response {
+    ErrorCode::InvalidJson.with_message(format!("Json parse error: [{}]", e))
+}
+
+// 件数が多いと時間がかかるので、ジョブとして受け付けて 202 を返す。結果は /import/jobs/:id で見る
+fn start<T: TodoRepository, L: LabelRepository>(
+    source: &str,
+    plan: ImportPlan,
+    user: AuthenticatedUser,
+    service: ImportService<T, L>,
+    jobs: JobRegistry,
+) -> Response {
+    let service = match user.id() {
+        Some(owner_id) => service.with_owner(owner_id),
+        None => service,
+    };
+    let kind = format!("{}{}", JOB_KIND_PREFIX, source);
+    let job = jobs.spawn_for(user.id(), &kind, async move { service.run(plan).await });
+    (
+        StatusCode::ACCEPTED,
+        [(header::LOCATION, format!("/import/jobs/{}", job.id))],
+        Json(job),
+    )
+        .into_response()
+}
+
+pub async fn import_todoist<T: TodoRepository, L: LabelRepository>(
+    user: AuthenticatedUser,
+    todos: TodoService<T>,
+    State(LabelRepo(labels)): State<LabelRepo<L>>,
+    State(jobs): State<JobRegistry>,
+    body: Bytes,
+) -> Result<Response, Response> {
+    let export: TodoistExport = serde_json::from_slice(&body).map_err(invalid_json)?;
+    Ok(start("todoist", export.into(), user, ImportService::new(todos, labels), jobs))
+}
+
+pub async fn import_trello<T: TodoRepository, L: LabelRepository>(
+    user: AuthenticatedUser,
+    todos: TodoService<T>,
+    State(LabelRepo(labels)): State<LabelRepo<L>>,
+    State(jobs): State<JobRegistry>,
+    body: Bytes,
+) -> Result<Response, Response> {
+    let export: TrelloExport = serde_json::from_slice(&body).map_err(invalid_json)?;
+    Ok(start("trello", export.into(), user, ImportService::new(todos, labels), jobs))
+}
+
+// 管理用のジョブと、他のユーザーが始めたジョブは見せない
+pub async fn find_import_job(
+    user: AuthenticatedUser,
+    Path(id): Path<Uuid>,
+    State(jobs): State<JobRegistry>,
+) -> Result<impl IntoResponse, ErrorCode> {
+    let job = jobs
+        .find(id)
+        .filter(|job| job.kind.starts_with(JOB_KIND_PREFIX) && user.owns(job.owner_id))
+        .ok_or(ErrorCode::NotFound)?;
+    Ok(Json(job))
+}
@@ -1,61 +1,103 @@
 use axum::{
-    extract::{Extension, Path},
+    extract::{Path, State},
     response::IntoResponse,
     http::StatusCode,
     Json,
 };
-use std::sync::Arc;
-use crate::repositories::label::{
-    LabelRepository,
-    CreateLabel,
+use crate::{
+    error_code::ErrorCode,
+    repositories::{
+        label::{CreateLabel, LabelRepository, UpdateLabel},
+        todo::{AssignLabel, TodoRepository},
+    },
+    state::{LabelRepo, TodoRepo},
 };
+use super::auth::AuthenticatedUser;
+use super::dto::{self, LabelAssignmentResponse, LabelDetailResponse, LabelResponse};
+use super::error::ApiError;
 use super::ValidatedJson;
 
 pub async fn create_label<T: LabelRepository>(
+    user: AuthenticatedUser,
+    State(LabelRepo(repo)): State<LabelRepo<T>>,
     ValidatedJson(payload): ValidatedJson<CreateLabel>,
-    Extension(repo): Extension<Arc<T>>,
-) -> Result<impl IntoResponse, StatusCode> {
-    let todo = repo
+) -> Result<impl IntoResponse, ApiError> {
+    let payload = match user.id() {
+        Some(owner_id) => payload.with_owner(owner_id),
+        None => payload,
+    };
+    let label = repo
         .create(payload)
         .await
-        .or(Err(StatusCode::NOT_FOUND))?;
+        .map_err(|e| ApiError::from(e).duplicate(ErrorCode::LabelDuplicate))?;
 
-    Ok((StatusCode::CREATED, Json(todo)))
+    Ok((StatusCode::CREATED, Json(LabelResponse::from(label))))
 }
 
-// pub async fn find_todo<T: LabelRepository>(
-//     Path(id): Path<i32>,
-//     Extension(repo): Extension<Arc<T>>,
-// ) -> Result<impl IntoResponse, StatusCode> {
-//     let todo = repo.find(id).await.or(Err(StatusCode::NOT_FOUND))?;
-//     Ok((StatusCode::OK, Json(todo)))
-// }
+// 使っている Todo の数も返すので、フロントエンドは消す前に確認を出せる
+pub async fn find_label<T: LabelRepository>(
+    user: AuthenticatedUser,
+    Path(id): Path<i32>,
+    State(LabelRepo(repo)): State<LabelRepo<T>>,
+) -> Result<impl IntoResponse, ApiError> {
+    let label = repo
+        .find(id, user.id())
+        .await
+        .map_err(|e| ApiError::from(e).not_found(ErrorCode::LabelNotFound))?;
+    Ok((StatusCode::OK, Json(LabelDetailResponse::from(label))))
+}
 
 pub async fn all_label<T: LabelRepository>(
-    Extension(repo): Extension<Arc<T>>,
-) -> Result<impl IntoResponse, StatusCode> {
-    let todos = repo.all().await.unwrap();
-    Ok((StatusCode::OK, Json(todos)))
+    user: AuthenticatedUser,
+    State(LabelRepo(repo)): State<LabelRepo<T>>,
+) -> Result<impl IntoResponse, ApiError> {
+    let todos = repo.all(user.id()).await?;
+    Ok((StatusCode::OK, Json(dto::labels(todos))))
 }
 
-// pub async fn update_todo<T: TodoRepository>(
-//     Path(id): Path<i32>,
-//     ValidatedJson(payload): ValidatedJson<UpdateTodo>,
-//     Extension(repo): Extension<Arc<T>>,
-// ) -> Result<impl IntoResponse, StatusCode> {
-//     let todo = repo
-//         .update(id, payload)
-//         .await
-//         .or(Err(StatusCode::NOT_FOUND))?;
-//     Ok((StatusCode::CREATED, Json(todo)))
-// }
+pub async fn update_label<T: LabelRepository>(
+    user: AuthenticatedUser,
+    Path(id): Path<i32>,
+    State(LabelRepo(repo)): State<LabelRepo<T>>,
+    ValidatedJson(payload): ValidatedJson<UpdateLabel>,
+) -> Result<impl IntoResponse, ApiError> {
+    let label = repo
+        .update(id, payload, user.id())
+        .await
+        .map_err(|e| ApiError::from(e).not_found(ErrorCode::LabelNotFound).duplicate(ErrorCode::LabelDuplicate))?;
+    Ok((StatusCode::OK, Json(LabelResponse::from(label))))
+}
 
 pub async fn delete_label<T: LabelRepository>(
+    user: AuthenticatedUser,
     Path(id): Path<i32>,
-    Extension(repo): Extension<Arc<T>>,
-) -> impl IntoResponse {
-    repo.delete(id)
+    State(LabelRepo(repo)): State<LabelRepo<T>>,
+) -> Result<impl IntoResponse, ApiError> {
+    repo.delete(id, user.id())
         .await
-        .map(|_| StatusCode::NO_CONTENT)
-        .unwrap_or(StatusCode::INTERNAL_SERVER_ERROR)
-}
\ No newline at end of file
+        .map_err(|e| ApiError::from(e).not_found(ErrorCode::LabelNotFound))?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+// ラベルの付け外しは Todo 側のリポジトリで行う。他のユーザーの Todo の id は無視する
+pub async fn assign_label<T: TodoRepository>(
+    user: AuthenticatedUser,
+    Path(id): Path<i32>,
+    State(TodoRepo(repo)): State<TodoRepo<T>>,
+    ValidatedJson(payload): ValidatedJson<AssignLabel>,
+) -> Result<impl IntoResponse, ApiError> {
+    let payload = match user.id() {
+        Some(owner_id) => payload.with_owner(owner_id),
+        None => payload,
+    };
+    let action = payload.action();
+    let todo_ids = repo.assign_label(id, payload).await?;
+    Ok((
+        StatusCode::OK,
+        Json(LabelAssignmentResponse {
+            label_id: id,
+            action,
+            todo_ids,
+        }),
+    ))
+}
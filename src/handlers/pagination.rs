@@ -0,0 +1,127 @@
+use axum::http::{HeaderValue, StatusCode, Uri};
+
+pub const DEFAULT_PER_PAGE: i64 = 20;
+pub const MAX_PER_PAGE: i64 = 100;
+
+// Link ヘッダの URL の前に付ける、外から見たときのベース URL (例: https://api.example.com)。
+// 未設定ならパスだけの相対 URL にする
+#[derive(Debug, Clone, Default)]
+pub struct PublicBaseUrl(Option<String>);
+
+impl PublicBaseUrl {
+    pub fn new(url: Option<String>) -> Self {
+        PublicBaseUrl(
+            url.map(|url| url.trim_end_matches('/').to_string())
+                .filter(|url| !url.is_empty()),
+        )
+    }
+
+    // path は / 始まり。未設定ならそのまま返す
+    pub fn join(&self, path: &str) -> String {
+        format!("{}{}", self.0.as_deref().unwrap_or(""), path)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Page {
+    // 1 始まり
+    pub number: i64,
+    pub per_page: i64,
+}
+
+impl Page {
+    // page も per_page も無ければページングしない。0 以下や上限超えは 400
+    pub fn from_query(page: Option<i64>, per_page: Option<i64>) -> Result<Option<Self>, StatusCode> {
+        if page.is_none() && per_page.is_none() {
+            return Ok(None);
+        }
+        let number = page.unwrap_or(1);
+        let per_page = per_page.unwrap_or(DEFAULT_PER_PAGE);
+        if number < 1 || !(1..=MAX_PER_PAGE).contains(&per_page) {
+            return Err(StatusCode::BAD_REQUEST);
+        }
+        Ok(Some(Page { number, per_page }))
+    }
+
+    pub fn offset(&self) -> i64 {
+        (self.number - 1) * self.per_page
+    }
+
+    // 0 件でも 1 ページ目はある
+    pub fn last(&self, total: i64) -> i64 {
+        ((total + self.per_page - 1) / self.per_page).max(1)
+    }
+}
+
+// RFC 8288 の Link ヘッダ。page 以外のクエリパラメータはそのまま引き継ぐ
+pub fn link_header(base_url: &PublicBaseUrl, uri: &Uri, page: Page, total: i64) -> HeaderValue {
+    let last = page.last(total);
+    let mut rels = vec![("first", 1)];
+    if page.number > 1 {
+        rels.push(("prev", (page.number - 1).min(last)));
+    }
+    if page.number < last {
+        rels.push(("next", page.number + 1));
+    }
+    rels.push(("last", last));
+
+    let params: Vec<(String, String)> = form_urlencoded::parse(uri.query().unwrap_or("").as_bytes())
+        .into_owned()
+        .filter(|(key, _)| key != "page" && key != "per_page")
+        .collect();
+    let links = rels
+        .into_iter()
+        .map(|(rel, number)| {
+            let query = form_urlencoded::Serializer::new(String::new())
+                .extend_pairs(&params)
+                .append_pair("page", &number.to_string())
+                .append_pair("per_page", &page.per_page.to_string())
+                .finish();
+            format!(
+                "<{}{}?{}>; rel=\"{}\"",
+                base_url.0.as_deref().unwrap_or(""),
+                uri.path(),
+                query,
+                rel
+            )
+        })
+        .collect::<Vec<_>>();
+    HeaderValue::from_str(&links.join(", ")).unwrap()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn should_link_neighbour_pages() {
+        let uri: Uri = "/todos?page=2&per_page=10&sort=desc".parse().unwrap();
+        let page = Page::from_query(Some(2), Some(10)).unwrap().unwrap();
+        let base_url = PublicBaseUrl::new(Some("https://api.example.com/".to_string()));
+        assert_eq!(
+            link_header(&base_url, &uri, page, 35),
+            "<https://api.example.com/todos?sort=desc&page=1&per_page=10>; rel=\"first\", \
+             <https://api.example.com/todos?sort=desc&page=1&per_page=10>; rel=\"prev\", \
+             <https://api.example.com/todos?sort=desc&page=3&per_page=10>; rel=\"next\", \
+             <https://api.example.com/todos?sort=desc&page=4&per_page=10>; rel=\"last\""
+        );
+
+        // 範囲外のページでも prev は最後のページを指す
+        let uri: Uri = "/todos?page=9".parse().unwrap();
+        let page = Page::from_query(Some(9), None).unwrap().unwrap();
+        assert_eq!(
+            link_header(&PublicBaseUrl::default(), &uri, page, 0),
+            "</todos?page=1&per_page=20>; rel=\"first\", \
+             </todos?page=1&per_page=20>; rel=\"prev\", \
+             </todos?page=1&per_page=20>; rel=\"last\""
+        );
+    }
+
+    #[test]
+    fn should_validate_page_query() {
+        assert_eq!(Page::from_query(None, None), Ok(None));
+        assert_eq!(Page::from_query(None, Some(5)), Ok(Some(Page { number: 1, per_page: 5 })));
+        assert_eq!(Page::from_query(Some(0), None), Err(StatusCode::BAD_REQUEST));
+        assert_eq!(Page::from_query(Some(1), Some(MAX_PER_PAGE + 1)), Err(StatusCode::BAD_REQUEST));
+    }
+}
@@ -0,0 +1,31 @@
+use axum::{
+    extract::State,
+    http::StatusCode,
+    response::IntoResponse,
+    Json,
+};
+use crate::{
+    repositories::{
+        sync::{ConflictPolicy, SyncRequest},
+        todo::TodoRepository,
+    },
+    services::todo::TodoService,
+};
+use super::auth::AuthenticatedUser;
+use super::dto::SyncResultResponse;
+use super::error::ApiError;
+use super::ValidatedJson;
+
+pub async fn sync_todos<T: TodoRepository>(
+    user: AuthenticatedUser,
+    State(policy): State<ConflictPolicy>,
+    service: TodoService<T>,
+    ValidatedJson(payload): ValidatedJson<SyncRequest>,
+) -> Result<impl IntoResponse, ApiError> {
+    let payload = match user.id() {
+        Some(owner_id) => payload.with_owner(owner_id),
+        None => payload,
+    };
+    let result = service.sync(payload, policy).await?;
+    Ok((StatusCode::OK, Json(SyncResultResponse::from(result))))
+}
@@ -1,62 +1,393 @@
 use axum::{
-    extract::{Extension, Path},
-    http::StatusCode,
-    response::IntoResponse,
+    body::StreamBody,
+    extract::{OriginalUri, Path, State},
+    http::{header, header::HeaderName, HeaderValue, StatusCode},
+    response::{IntoResponse, Response},
     Json,
 };
-use std::sync::Arc;
-use crate::repositories::todo::{
-    CreateTodo,
-    TodoRepository,
-    UpdateTodo,
+use chrono::{DateTime, Utc};
+use futures::{stream, TryStreamExt};
+use serde::Deserialize;
+use std::convert::Infallible;
+use crate::{
+    markdown,
+    repositories::todo::{
+        BulkCreateTodo,
+        BulkDeleteTodo,
+        CreateTodo,
+        Include,
+        TodoFilter,
+        TodoRepository,
+        UpdateTodo,
+        Upserted,
+        UpsertTodo,
+    },
+    error_code::ErrorCode,
+    services::todo::TodoService,
+    state::TodoRepo,
 };
-use super::ValidatedJson;
+use super::auth::AuthenticatedUser;
+use super::dto::{self, BulkDeleteResponse, TodoCountsResponse, TodoResponse};
+use super::pagination::{link_header, Page, PublicBaseUrl, DEFAULT_PER_PAGE, MAX_PER_PAGE};
+use super::error::ApiError;
+use super::{etag, http_date, IfMatch, IfModifiedSince, Query, ValidatedJson};
 
-pub async fn create_todo<T: TodoRepository>(
-    ValidatedJson(payload): ValidatedJson<CreateTodo>,
-    Extension(repo): Extension<Arc<T>>,
-) -> Result<impl IntoResponse, StatusCode> {
+pub const TOTAL_COUNT_HEADER: &str = "x-total-count";
+
+// 他のユーザーの Todo は無いものとして 404 にする。認証が無効なら読みに行かない
+async fn check_owner<T: TodoRepository>(repo: &T, id: i32, user: AuthenticatedUser) -> Result<(), ApiError> {
+    if user.id().is_none() {
+        return Ok(());
+    }
     let todo = repo
-        .create(payload)
+        .find(id)
         .await
-        .or(Err(StatusCode::NOT_FOUND))?;
+        .map_err(|e| ApiError::from(e).not_found(ErrorCode::TodoNotFound))?;
+    if user.owns(todo.owner_id) {
+        Ok(())
+    } else {
+        Err(ApiError::NotFound(ErrorCode::TodoNotFound))
+    }
+}
+
+pub async fn create_todo<T: TodoRepository>(
+    user: AuthenticatedUser,
+    service: TodoService<T>,
+    ValidatedJson(payload): ValidatedJson<CreateTodo>,
+) -> Result<impl IntoResponse, ApiError> {
+    let payload = match user.id() {
+        Some(owner_id) => payload.with_owner(owner_id),
+        None => payload,
+    };
+    let todo = service.create(payload).await?;
 
-    Ok((StatusCode::CREATED, Json(todo)))
+    Ok((StatusCode::CREATED, Json(TodoResponse::from(todo))))
+}
+
+// 全部作るか、1 件も作らないか
+pub async fn bulk_create_todos<T: TodoRepository>(
+    user: AuthenticatedUser,
+    service: TodoService<T>,
+    ValidatedJson(payload): ValidatedJson<BulkCreateTodo>,
+) -> Result<impl IntoResponse, ApiError> {
+    let payload = match user.id() {
+        Some(owner_id) => payload.with_owner(owner_id),
+        None => payload,
+    };
+    let todos = service.bulk_create(payload).await?;
+
+    Ok((StatusCode::CREATED, Json(dto::todos(todos))))
 }
 
 pub async fn find_todo<T: TodoRepository>(
+    user: AuthenticatedUser,
     Path(id): Path<i32>,
-    Extension(repo): Extension<Arc<T>>,
-) -> Result<impl IntoResponse, StatusCode> {
-    let todo = repo.find(id).await.or(Err(StatusCode::NOT_FOUND))?;
-    Ok((StatusCode::OK, Json(todo)))
+    State(TodoRepo(repo)): State<TodoRepo<T>>,
+) -> Result<impl IntoResponse, ApiError> {
+    let todo = repo
+        .find(id)
+        .await
+        .map_err(|e| ApiError::from(e).not_found(ErrorCode::TodoNotFound))?;
+    if !user.owns(todo.owner_id) {
+        return Err(ApiError::NotFound(ErrorCode::TodoNotFound));
+    }
+    Ok((StatusCode::OK, [(header::ETAG, etag(&todo))], Json(TodoResponse::from(todo))))
+}
+
+#[derive(Debug, Deserialize)]
+pub struct AllQuery {
+    // ndjson を指定すると 1 件 1 行で流す。件数が多いときのエクスポート用
+    format: Option<String>,
+    // どちらかを指定するとページングし、Link ヘッダを付ける
+    page: Option<i64>,
+    per_page: Option<i64>,
+    // カンマ区切りで一緒に返すもの。省略すると labels。
+    // include= のように空にするとラベルを読まず、labels は空配列になる
+    include: Option<String>,
+}
+
+fn parse_include(include: Option<&str>) -> Result<Include, StatusCode> {
+    let include = match include {
+        Some(include) => include,
+        None => return Ok(Include::default()),
+    };
+    let mut parsed = Include::NOTHING;
+    for name in include.split(',').filter(|name| !name.is_empty()) {
+        match name {
+            "labels" => parsed.labels = true,
+            _ => return Err(StatusCode::BAD_REQUEST),
+        }
+    }
+    Ok(parsed)
 }
 
 pub async fn all_todo<T: TodoRepository>(
-    Extension(repo): Extension<Arc<T>>,
-) -> Result<impl IntoResponse, StatusCode> {
-    let todos = repo.all().await.unwrap();
-    Ok((StatusCode::OK, Json(todos)))
+    user: AuthenticatedUser,
+    Query(query): Query<AllQuery>,
+    Query(mut filter): Query<TodoFilter>,
+    OriginalUri(uri): OriginalUri,
+    IfModifiedSince(since): IfModifiedSince,
+    State(TodoRepo(repo)): State<TodoRepo<T>>,
+    State(base_url): State<PublicBaseUrl>,
+) -> Result<Response, ApiError> {
+    filter.owner_id = user.id();
+    let ndjson = match query.format.as_deref() {
+        None | Some("json") => false,
+        Some("ndjson") => true,
+        Some(_) => return Err(ErrorCode::BadRequest.into()),
+    };
+    let page = Page::from_query(query.page, query.per_page)?;
+    let include = parse_include(query.include.as_deref())?;
+    // ndjson は全件のエクスポート用なのでページングしない
+    if ndjson && page.is_some() {
+        return Err(ErrorCode::BadRequest.into());
+    }
+    // 一覧より先に読むので、間に書き込みがあっても Last-Modified が古い側にずれるだけ (次のポーリングで取り直される)
+    let last_modified = repo.last_modified(filter.owner_id).await?;
+    // HTTP-date は秒単位なので秒で比べる
+    if let (Some(since), Some(last_modified)) = (since, last_modified) {
+        if last_modified.timestamp() <= since.timestamp() {
+            return Ok((StatusCode::NOT_MODIFIED, [(header::LAST_MODIFIED, http_date(last_modified))]).into_response());
+        }
+    }
+
+    let mut res = if let Some(page) = page {
+        let (todos, total) = repo
+            .page(filter, page.per_page, page.offset(), include)
+            .await?;
+        let headers = [
+            (header::LINK, link_header(&base_url, &uri, page, total)),
+            (HeaderName::from_static(TOTAL_COUNT_HEADER), HeaderValue::from(total)),
+        ];
+        (StatusCode::OK, headers, Json(dto::todos(todos))).into_response()
+    } else if ndjson {
+        let body = repo.stream_all(filter, include).and_then(|todo| async move {
+            let mut line = serde_json::to_vec(&TodoResponse::from(todo))?;
+            line.push(b'\n');
+            Ok(line)
+        });
+        ([(header::CONTENT_TYPE, "application/x-ndjson")], StreamBody::new(body)).into_response()
+    } else {
+        let todos = repo.all(filter, include).await?;
+        (StatusCode::OK, Json(dto::todos(todos))).into_response()
+    };
+    if let Some(last_modified) = last_modified {
+        res.headers_mut().insert(header::LAST_MODIFIED, http_date(last_modified));
+    }
+    Ok(res)
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ExportQuery {
+    format: Option<String>,
+}
+
+pub async fn export_todos<T: TodoRepository>(
+    user: AuthenticatedUser,
+    Query(query): Query<ExportQuery>,
+    State(TodoRepo(repo)): State<TodoRepo<T>>,
+) -> Result<Response, ApiError> {
+    // 今のところ markdown だけ。形式を足すときはここで分岐する
+    match query.format.as_deref() {
+        None | Some("markdown") => {}
+        Some(_) => return Err(ErrorCode::BadRequest.into()),
+    }
+    // ラベルごとにまとめるので全件読んでから、節ごとに chunk にして流す
+    let filter = TodoFilter {
+        owner_id: user.id(),
+        ..TodoFilter::default()
+    };
+    let todos = repo.all(filter, Include::default()).await?;
+    let sections = markdown::checklist_sections(&todos)
+        .into_iter()
+        .map(Ok::<_, Infallible>);
+    Ok((
+        [(header::CONTENT_TYPE, "text/markdown; charset=utf-8")],
+        StreamBody::new(stream::iter(sections)),
+    )
+        .into_response())
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ByLabelQuery {
+    include_completed: Option<bool>,
+}
+
+pub async fn todos_by_label<T: TodoRepository>(
+    user: AuthenticatedUser,
+    Query(query): Query<ByLabelQuery>,
+    State(TodoRepo(repo)): State<TodoRepo<T>>,
+) -> Result<impl IntoResponse, ApiError> {
+    // デフォルトは未完了の Todo だけ
+    let groups = repo
+        .by_label(query.include_completed.unwrap_or(false), user.id())
+        .await?;
+    Ok((StatusCode::OK, Json(dto::todos_by_label(groups))))
+}
+
+#[derive(Debug, Deserialize)]
+pub struct CompletedQuery {
+    // RFC 3339。since 以降 until より前に完了したもの
+    since: Option<DateTime<Utc>>,
+    until: Option<DateTime<Utc>>,
+    limit: Option<i64>,
+}
+
+pub async fn completed_todos<T: TodoRepository>(
+    user: AuthenticatedUser,
+    Query(query): Query<CompletedQuery>,
+    State(TodoRepo(repo)): State<TodoRepo<T>>,
+) -> Result<impl IntoResponse, ApiError> {
+    let limit = query.limit.unwrap_or(DEFAULT_PER_PAGE);
+    if !(1..=MAX_PER_PAGE).contains(&limit) {
+        return Err(ErrorCode::BadRequest.into());
+    }
+    if let (Some(since), Some(until)) = (query.since, query.until) {
+        if since > until {
+            return Err(ErrorCode::BadRequest.into());
+        }
+    }
+    let todos = repo
+        .completed_between(query.since, query.until, limit, user.id())
+        .await?;
+    Ok((StatusCode::OK, Json(dto::todos(todos))))
+}
+
+#[derive(Debug, Deserialize)]
+pub struct SearchQuery {
+    q: String,
+    limit: Option<i64>,
+}
+
+pub async fn search_todos<T: TodoRepository>(
+    user: AuthenticatedUser,
+    Query(query): Query<SearchQuery>,
+    State(TodoRepo(repo)): State<TodoRepo<T>>,
+) -> Result<impl IntoResponse, ApiError> {
+    let limit = query.limit.unwrap_or(DEFAULT_PER_PAGE);
+    if !(1..=MAX_PER_PAGE).contains(&limit) {
+        return Err(ErrorCode::BadRequest.into());
+    }
+    let q = query.q.trim();
+    if q.is_empty() {
+        return Err(ErrorCode::BadRequest.into());
+    }
+    let todos = repo
+        .search(q.to_string(), limit, user.id())
+        .await?;
+    Ok((StatusCode::OK, Json(dto::todos(todos))))
+}
+
+pub async fn todo_stats<T: TodoRepository>(
+    user: AuthenticatedUser,
+    State(TodoRepo(repo)): State<TodoRepo<T>>,
+) -> Result<impl IntoResponse, ApiError> {
+    let counts = repo.counts(user.id()).await?;
+    Ok((StatusCode::OK, Json(TodoCountsResponse::from(counts))))
 }
 
 pub async fn update_todo<T: TodoRepository>(
+    user: AuthenticatedUser,
     Path(id): Path<i32>,
+    IfMatch(expected_version): IfMatch,
+    State(TodoRepo(repo)): State<TodoRepo<T>>,
+    service: TodoService<T>,
     ValidatedJson(payload): ValidatedJson<UpdateTodo>,
-    Extension(repo): Extension<Arc<T>>,
-) -> Result<impl IntoResponse, StatusCode> {
-    let todo = repo
-        .update(id, payload)
+) -> Result<impl IntoResponse, ApiError> {
+    check_owner(&*repo, id, user).await?;
+    let todo = service
+        .update(id, payload, expected_version)
         .await
-        .or(Err(StatusCode::NOT_FOUND))?;
-    Ok((StatusCode::CREATED, Json(todo)))
+        .map_err(|e| ApiError::from(e).not_found(ErrorCode::TodoNotFound))?;
+    Ok((StatusCode::CREATED, [(header::ETAG, etag(&todo))], Json(TodoResponse::from(todo))))
+}
+
+pub async fn upsert_todo_by_key<T: TodoRepository>(
+    user: AuthenticatedUser,
+    Path(client_key): Path<String>,
+    IfMatch(expected_version): IfMatch,
+    service: TodoService<T>,
+    ValidatedJson(payload): ValidatedJson<UpsertTodo>,
+) -> Result<impl IntoResponse, ApiError> {
+    let payload = match user.id() {
+        Some(owner_id) => payload.with_owner(owner_id),
+        None => payload,
+    };
+    let upserted = service
+        .upsert_by_key(client_key, payload, expected_version)
+        .await?;
+    let (status, todo) = match upserted {
+        Upserted::Created(todo) => (StatusCode::CREATED, todo),
+        Upserted::Updated(todo) => (StatusCode::OK, todo),
+    };
+    Ok((status, [(header::ETAG, etag(&todo))], Json(TodoResponse::from(todo))))
+}
+
+pub async fn attach_label<T: TodoRepository>(
+    user: AuthenticatedUser,
+    Path((id, label_id)): Path<(i32, i32)>,
+    State(TodoRepo(repo)): State<TodoRepo<T>>,
+) -> Result<impl IntoResponse, ApiError> {
+    check_owner(&*repo, id, user).await?;
+    // Todo とラベルのどちらが無くても 404
+    let todo = repo.attach_label(id, label_id).await?;
+    Ok((StatusCode::OK, [(header::ETAG, etag(&todo))], Json(TodoResponse::from(todo))))
+}
+
+pub async fn detach_label<T: TodoRepository>(
+    user: AuthenticatedUser,
+    Path((id, label_id)): Path<(i32, i32)>,
+    State(TodoRepo(repo)): State<TodoRepo<T>>,
+) -> Result<impl IntoResponse, ApiError> {
+    check_owner(&*repo, id, user).await?;
+    let todo = repo.detach_label(id, label_id).await?;
+    Ok((StatusCode::OK, [(header::ETAG, etag(&todo))], Json(TodoResponse::from(todo))))
 }
 
 pub async fn delete_todo<T: TodoRepository>(
+    user: AuthenticatedUser,
     Path(id): Path<i32>,
-    Extension(repo): Extension<Arc<T>>,
-) -> impl IntoResponse {
-    repo.delete(id)
+    IfMatch(expected_version): IfMatch,
+    State(TodoRepo(repo)): State<TodoRepo<T>>,
+) -> Result<impl IntoResponse, ApiError> {
+    check_owner(&*repo, id, user).await?;
+    repo.delete(id, expected_version)
         .await
-        .map(|_| StatusCode::NO_CONTENT)
-        .unwrap_or(StatusCode::NOT_FOUND)
-}
\ No newline at end of file
+        .map_err(|e| ApiError::from(e).not_found(ErrorCode::TodoNotFound))?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+pub async fn trash_todos<T: TodoRepository>(
+    user: AuthenticatedUser,
+    State(TodoRepo(repo)): State<TodoRepo<T>>,
+) -> Result<impl IntoResponse, ApiError> {
+    let todos = repo.trash(user.id()).await?;
+    Ok((StatusCode::OK, Json(dto::todos(todos))))
+}
+
+// ゴミ箱に無い Todo と、他のユーザーの Todo は 404
+pub async fn restore_todo<T: TodoRepository>(
+    user: AuthenticatedUser,
+    Path(id): Path<i32>,
+    State(TodoRepo(repo)): State<TodoRepo<T>>,
+) -> Result<impl IntoResponse, ApiError> {
+    let todo = repo
+        .restore(id, user.id())
+        .await
+        .map_err(|e| ApiError::from(e).not_found(ErrorCode::TodoNotFound))?;
+    Ok((StatusCode::OK, [(header::ETAG, etag(&todo))], Json(TodoResponse::from(todo))))
+}
+
+// 無い Todo と他のユーザーの Todo は飛ばし、エラーにはしない
+pub async fn bulk_delete_todos<T: TodoRepository>(
+    user: AuthenticatedUser,
+    State(TodoRepo(repo)): State<TodoRepo<T>>,
+    ValidatedJson(payload): ValidatedJson<BulkDeleteTodo>,
+) -> Result<impl IntoResponse, ApiError> {
+    let payload = match user.id() {
+        Some(owner_id) => payload.with_owner(owner_id),
+        None => payload,
+    };
+    let deleted = repo.bulk_delete(payload).await?;
+    Ok((StatusCode::OK, Json(BulkDeleteResponse { deleted })))
+}
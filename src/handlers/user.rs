@@ -0,0 +1,296 @@
+use axum::{
+    extract::{Path, State},
+    http::{header, HeaderMap, StatusCode},
+    response::IntoResponse,
+    Json,
+};
+use crate::{
+    error_code::ErrorCode,
+    password,
+    repositories::{
+        user::{
+            AuthEventKind, ChangePassword, LoginUser, NewAuthEvent, RefreshRotation, RefreshToken, RegisterUser,
+            RevokeToken, User, UserRepository,
+        },
+        RepositoryError,
+    },
+    state::UserRepo,
+};
+use chrono::{Duration as ChronoDuration, Utc};
+use uuid::Uuid;
+use super::auth::{AuthConfig, AuthenticatedUser, IssuedRefreshToken, IssuedToken, Revocation};
+use super::dto::{AuthEventResponse, LoginResponse, SessionResponse, UserResponse};
+use super::error::ApiError;
+use super::ValidatedJson;
+
+// GET /me/security-events で返す件数
+const SECURITY_EVENTS_LIMIT: i64 = 100;
+// 記録する User-Agent の長さ。ヘッダをそのまま溜め込まない
+const USER_AGENT_MAX_CHARS: usize = 256;
+
+fn user_agent(headers: &HeaderMap) -> Option<String> {
+    headers
+        .get(header::USER_AGENT)
+        .and_then(|value| value.to_str().ok())
+        .map(|value| value.chars().take(USER_AGENT_MAX_CHARS).collect())
+}
+
+fn login_response(user: User, issued: Option<IssuedToken>, refresh: Option<IssuedRefreshToken>) -> LoginResponse {
+    LoginResponse {
+        user: UserResponse::from(user),
+        token: issued.as_ref().map(|issued| issued.token.clone()),
+        expires_at: issued.map(|issued| issued.expires_at),
+        refresh_token: refresh.as_ref().map(|refresh| refresh.token.clone()),
+        refresh_expires_at: refresh.map(|refresh| refresh.refresh.expires_at),
+    }
+}
+
+pub async fn register_user<T: UserRepository>(
+    State(UserRepo(repo)): State<UserRepo<T>>,
+    ValidatedJson(payload): ValidatedJson<RegisterUser>,
+) -> Result<impl IntoResponse, ErrorCode> {
+    let password = payload.password().to_string();
+    // argon2 はわざと重くしてあるので、非同期のワーカーを塞がないよう別スレッドで計算する
+    let password_hash = tokio::task::spawn_blocking(move || password::hash(&password))
+        .await
+        .or(Err(ErrorCode::InternalError))?
+        .or(Err(ErrorCode::InternalError))?;
+    let user = repo
+        .create(payload.email().to_string(), password_hash)
+        .await
+        .map_err(|e| match e.downcast_ref::<RepositoryError>() {
+            Some(RepositoryError::Duplicate(_)) => ErrorCode::UserDuplicate,
+            _ => ErrorCode::InternalError,
+        })?;
+    Ok((StatusCode::CREATED, Json(UserResponse::from(user))))
+}
+
+// email とパスワードのどちらが違っても同じ 401 を返す。
+// 通ったら /todos と /labels の Authorization: Bearer に使うトークンを返す。
+// 成功も失敗も auth_events に残す。失敗が続いた email は、パスワードを確かめずに 423 で断る
+pub async fn login_user<T: UserRepository>(
+    State(UserRepo(repo)): State<UserRepo<T>>,
+    State(auth): State<AuthConfig>,
+    headers: HeaderMap,
+    ValidatedJson(payload): ValidatedJson<LoginUser>,
+) -> Result<impl IntoResponse, ApiError> {
+    let user_agent = user_agent(&headers);
+    if let Some(lockout) = auth.lockout() {
+        let now = Utc::now();
+        let since = now - ChronoDuration::from_std(lockout.duration).map_err(anyhow::Error::from)?;
+        let failures = repo.failed_logins(payload.email(), since).await?;
+        if let Some(locked_until) = lockout.locked_until(&failures).filter(|locked_until| *locked_until > now) {
+            // 切り上げて、Retry-After の秒数が過ぎれば必ずログインできるようにする
+            let retry_after = (locked_until - now).num_seconds() + 1;
+            return Err(ApiError::AccountLocked { retry_after: retry_after as u64 });
+        }
+    }
+    let user = repo
+        .find_by_email(payload.email())
+        .await
+        .or(Err(ErrorCode::InternalError))?;
+    let password = payload.password().to_string();
+    let password_hash = user.as_ref().map(|user| user.password_hash.clone());
+    let verified = tokio::task::spawn_blocking(move || match password_hash {
+        Some(password_hash) => password::verify(&password, &password_hash),
+        None => password::verify_absent(&password),
+    })
+    .await
+    .or(Err(ErrorCode::InternalError))?;
+    let user = match user {
+        Some(user) if verified => user,
+        user => {
+            let event = NewAuthEvent::new(AuthEventKind::LoginFailed, payload.email().to_string())
+                .user(user.map(|user| user.id))
+                .user_agent(user_agent);
+            repo.record_event(event).await.or(Err(ErrorCode::InternalError))?;
+            return Err(ErrorCode::InvalidCredentials.into());
+        }
+    };
+    // ログインごとに refresh トークンの系列 (セッション) を作る
+    let session_id = Uuid::new_v4();
+    let refresh = auth
+        .issue_refresh(user.id, session_id)
+        .or(Err(ErrorCode::InternalError))?;
+    if let Some(refresh) = &refresh {
+        repo.create_refresh_family(refresh.refresh, user_agent.clone())
+            .await
+            .or(Err(ErrorCode::InternalError))?;
+    }
+    let issued = auth
+        .issue(user.id, Some(session_id))
+        .or(Err(ErrorCode::InternalError))?;
+    let mut kinds = vec![AuthEventKind::Login];
+    if issued.is_some() {
+        kinds.push(AuthEventKind::TokenIssued);
+    }
+    for kind in kinds {
+        let event = NewAuthEvent::new(kind, user.email.clone())
+            .user(Some(user.id))
+            .user_agent(user_agent.clone());
+        repo.record_event(event).await.or(Err(ErrorCode::InternalError))?;
+    }
+    Ok((StatusCode::OK, Json(login_response(user, issued, refresh))))
+}
+
+// refresh トークンを使用済みにして、新しいアクセストークンと refresh トークンを返す。
+// 使用済みの refresh トークンが来たら盗まれたとみなし、そのログインの系列ごと無効にして 401。
+// 認証が無効なら 404
+pub async fn refresh_token<T: UserRepository>(
+    State(UserRepo(repo)): State<UserRepo<T>>,
+    State(auth): State<AuthConfig>,
+    headers: HeaderMap,
+    ValidatedJson(payload): ValidatedJson<RefreshToken>,
+) -> Result<impl IntoResponse, ApiError> {
+    if !auth.enabled() {
+        return Err(ErrorCode::RouteNotFound.into());
+    }
+    let current = auth
+        .verify_refresh(payload.refresh_token())
+        .ok_or(ErrorCode::Unauthorized)?;
+    let next = auth
+        .issue_refresh(current.user_id, current.family_id)?
+        .ok_or(ErrorCode::InternalError)?;
+    let kind = match repo.rotate_refresh_token(current.id, next.refresh).await? {
+        RefreshRotation::Rotated => AuthEventKind::TokenIssued,
+        RefreshRotation::Reused => AuthEventKind::RefreshTokenReused,
+        RefreshRotation::Invalid => return Err(ErrorCode::Unauthorized.into()),
+    };
+    let user = repo.find(current.user_id).await?;
+    let event = NewAuthEvent::new(kind, user.email.clone())
+        .user(Some(user.id))
+        .user_agent(user_agent(&headers));
+    repo.record_event(event).await?;
+    if kind == AuthEventKind::RefreshTokenReused {
+        tracing::warn!("refresh token reused, revoked the family [{}]", current.family_id);
+        return Err(ErrorCode::Unauthorized.into());
+    }
+    let issued = auth.issue(user.id, Some(current.family_id))?;
+    Ok((StatusCode::OK, Json(login_response(user, issued, Some(next)))))
+}
+
+// トークンを持っている人なら誰でも失効させられる。アクセストークンは jti を、refresh トークンはその系列を無効にする。
+// 知らないトークンや期限の切れたトークンでも、失効させたのと同じく 204 を返す (RFC 7009)。認証が無効なら 404
+pub async fn revoke_token<T: UserRepository>(
+    State(UserRepo(repo)): State<UserRepo<T>>,
+    State(auth): State<AuthConfig>,
+    headers: HeaderMap,
+    ValidatedJson(payload): ValidatedJson<RevokeToken>,
+) -> Result<impl IntoResponse, ApiError> {
+    if !auth.enabled() {
+        return Err(ErrorCode::RouteNotFound.into());
+    }
+    let user_id = match auth.revocation(payload.token()) {
+        Some(Revocation::Access { user_id, token_id, expires_at }) => {
+            repo.revoke_token(token_id, expires_at).await?;
+            user_id
+        }
+        Some(Revocation::Refresh(refresh)) => {
+            repo.revoke_refresh_family(refresh.family_id, refresh.user_id).await?;
+            refresh.user_id
+        }
+        None => return Ok(StatusCode::NO_CONTENT),
+    };
+    let user = repo.find(user_id).await?;
+    let event = NewAuthEvent::new(AuthEventKind::TokenRevoked, user.email)
+        .user(Some(user.id))
+        .user_agent(user_agent(&headers));
+    repo.record_event(event).await?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+// 今のパスワードが違えばログインと同じ 401。認証が無効なら本人が分からないので 404
+pub async fn change_password<T: UserRepository>(
+    user: AuthenticatedUser,
+    State(UserRepo(repo)): State<UserRepo<T>>,
+    headers: HeaderMap,
+    ValidatedJson(payload): ValidatedJson<ChangePassword>,
+) -> Result<impl IntoResponse, ErrorCode> {
+    let user_id = user.id().ok_or(ErrorCode::RouteNotFound)?;
+    let user = repo.find(user_id).await.or(Err(ErrorCode::InternalError))?;
+    let current_password = payload.current_password().to_string();
+    let new_password = payload.new_password().to_string();
+    let password_hash = user.password_hash.clone();
+    let new_hash = tokio::task::spawn_blocking(move || {
+        if !password::verify(&current_password, &password_hash) {
+            return Ok(None);
+        }
+        password::hash(&new_password).map(Some)
+    })
+    .await
+    .or(Err(ErrorCode::InternalError))?
+    .or(Err(ErrorCode::InternalError))?
+    .ok_or(ErrorCode::InvalidCredentials)?;
+    repo.update_password(user.id, new_hash)
+        .await
+        .or(Err(ErrorCode::InternalError))?;
+    let event = NewAuthEvent::new(AuthEventKind::PasswordChanged, user.email)
+        .user(Some(user.id))
+        .user_agent(user_agent(&headers));
+    repo.record_event(event).await.or(Err(ErrorCode::InternalError))?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+// 新しい順。認証が無効なら 404
+pub async fn security_events<T: UserRepository>(
+    user: AuthenticatedUser,
+    State(UserRepo(repo)): State<UserRepo<T>>,
+) -> Result<impl IntoResponse, ErrorCode> {
+    let user_id = user.id().ok_or(ErrorCode::RouteNotFound)?;
+    let events = repo
+        .events(user_id, SECURITY_EVENTS_LIMIT)
+        .await
+        .or(Err(ErrorCode::InternalError))?;
+    Ok(Json(events.into_iter().map(AuthEventResponse::from).collect::<Vec<_>>()))
+}
+
+// まだ refresh トークンを引き換えられるログイン。認証が無効なら 404
+pub async fn sessions<T: UserRepository>(
+    user: AuthenticatedUser,
+    State(UserRepo(repo)): State<UserRepo<T>>,
+) -> Result<impl IntoResponse, ApiError> {
+    let user_id = user.id().ok_or(ErrorCode::RouteNotFound)?;
+    let sessions = repo.sessions(user_id).await?;
+    Ok(Json(
+        sessions
+            .into_iter()
+            .map(|session| SessionResponse::new(session, user.session_id()))
+            .collect::<Vec<_>>(),
+    ))
+}
+
+// そのセッションの refresh トークンもアクセストークンも使えなくする。
+// 他のユーザーのものや無効にしたものは 404
+pub async fn revoke_session<T: UserRepository>(
+    user: AuthenticatedUser,
+    Path(id): Path<Uuid>,
+    State(UserRepo(repo)): State<UserRepo<T>>,
+    headers: HeaderMap,
+) -> Result<impl IntoResponse, ApiError> {
+    let user_id = user.id().ok_or(ErrorCode::RouteNotFound)?;
+    if !repo.revoke_refresh_family(id, user_id).await? {
+        return Err(ErrorCode::NotFound.into());
+    }
+    let user = repo.find(user_id).await?;
+    let event = NewAuthEvent::new(AuthEventKind::TokenRevoked, user.email)
+        .user(Some(user.id))
+        .user_agent(user_agent(&headers));
+    repo.record_event(event).await?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+// このリクエストのセッションも含めて、全部のセッションを無効にする
+pub async fn revoke_all_sessions<T: UserRepository>(
+    user: AuthenticatedUser,
+    State(UserRepo(repo)): State<UserRepo<T>>,
+    headers: HeaderMap,
+) -> Result<impl IntoResponse, ApiError> {
+    let user_id = user.id().ok_or(ErrorCode::RouteNotFound)?;
+    repo.revoke_all_refresh_families(user_id).await?;
+    let user = repo.find(user_id).await?;
+    let event = NewAuthEvent::new(AuthEventKind::TokenRevoked, user.email)
+        .user(Some(user.id))
+        .user_agent(user_agent(&headers));
+    repo.record_event(event).await?;
+    Ok(StatusCode::NO_CONTENT)
+}
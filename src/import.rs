@@ -0,0 +1,290 @@
+use serde::{Deserialize, Serialize};
+use std::{collections::HashMap, fmt};
+
+// 他サービスのエクスポートを、この API の Todo とラベルに置き換えたもの。
+// プロジェクトやリストはこのアプリに無いので、同じ名前のラベルとして付ける
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ImportPlan {
+    // 出てきた順で重複なし
+    pub labels: Vec<String>,
+    pub todos: Vec<ImportedTodo>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ImportedTodo {
+    pub text: String,
+    pub completed: bool,
+    pub labels: Vec<String>,
+}
+
+impl ImportPlan {
+    fn push(&mut self, text: &str, completed: bool, labels: Vec<String>) {
+        let mut unique: Vec<String> = vec![];
+        for label in labels.into_iter().filter(|label| !label.trim().is_empty()) {
+            if !unique.contains(&label) {
+                unique.push(label);
+            }
+        }
+        for label in &unique {
+            if !self.labels.contains(label) {
+                self.labels.push(label.clone());
+            }
+        }
+        self.todos.push(ImportedTodo {
+            text: text.to_string(),
+            completed,
+            labels: unique,
+        });
+    }
+}
+
+// API のバージョンによって ID が数値だったり文字列だったりするので、どちらも受ける
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Deserialize)]
+#[serde(untagged)]
+pub enum ExternalId {
+    Number(i64),
+    Text(String),
+}
+
+impl fmt::Display for ExternalId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ExternalId::Number(id) => write!(f, "{}", id),
+            ExternalId::Text(id) => f.write_str(id),
+        }
+    }
+}
+
+// 古い API は 0 / 1 で返していた
+#[derive(Debug, Clone, Copy, Deserialize)]
+#[serde(untagged)]
+enum Flag {
+    Bool(bool),
+    Number(i64),
+}
+
+impl Default for Flag {
+    fn default() -> Self {
+        Flag::Bool(false)
+    }
+}
+
+impl Flag {
+    fn is_set(self) -> bool {
+        match self {
+            Flag::Bool(flag) => flag,
+            Flag::Number(flag) => flag != 0,
+        }
+    }
+}
+
+// Todoist の Sync API (resource_types=["all"]) の結果。使うところ以外は読み捨てる
+#[derive(Debug, Deserialize)]
+pub struct TodoistExport {
+    #[serde(default)]
+    projects: Vec<TodoistProject>,
+    items: Vec<TodoistItem>,
+}
+
+#[derive(Debug, Deserialize)]
+struct TodoistProject {
+    id: ExternalId,
+    name: String,
+    // 受信箱はどの Todo にも付くので、ラベルにしない
+    #[serde(default)]
+    inbox_project: bool,
+}
+
+#[derive(Debug, Deserialize)]
+struct TodoistItem {
+    content: String,
+    #[serde(default)]
+    checked: Flag,
+    #[serde(default)]
+    is_deleted: Flag,
+    project_id: Option<ExternalId>,
+    // v9 からはラベル名
+    #[serde(default)]
+    labels: Vec<String>,
+}
+
+impl From<TodoistExport> for ImportPlan {
+    fn from(export: TodoistExport) -> Self {
+        let projects: HashMap<ExternalId, &TodoistProject> =
+            export.projects.iter().map(|project| (project.id.clone(), project)).collect();
+        let mut plan = ImportPlan::default();
+        for item in export.items.into_iter().filter(|item| !item.is_deleted.is_set()) {
+            let project = item
+                .project_id
+                .as_ref()
+                .and_then(|id| projects.get(id))
+                .filter(|project| !project.inbox_project)
+                .map(|project| project.name.clone());
+            let labels = project.into_iter().chain(item.labels).collect();
+            plan.push(&item.content, item.checked.is_set(), labels);
+        }
+        plan
+    }
+}
+
+// Trello のボードの「JSON でエクスポート」の結果。アーカイブ済みのカードとリストは取り込まない
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TrelloExport {
+    #[serde(default)]
+    lists: Vec<TrelloList>,
+    cards: Vec<TrelloCard>,
+    #[serde(default)]
+    labels: Vec<TrelloLabel>,
+}
+
+#[derive(Debug, Deserialize)]
+struct TrelloList {
+    id: String,
+    name: String,
+    #[serde(default)]
+    closed: bool,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct TrelloCard {
+    name: String,
+    #[serde(default)]
+    closed: bool,
+    id_list: Option<String>,
+    #[serde(default)]
+    id_labels: Vec<String>,
+    // 期限の「完了」のチェック。Trello のカードに完了の状態はこれしか無い
+    #[serde(default)]
+    due_complete: bool,
+}
+
+#[derive(Debug, Deserialize)]
+struct TrelloLabel {
+    id: String,
+    #[serde(default)]
+    name: String,
+    // 名前の無いラベルは色で呼ぶ
+    color: Option<String>,
+}
+
+impl From<TrelloExport> for ImportPlan {
+    fn from(export: TrelloExport) -> Self {
+        let lists: HashMap<&str, &TrelloList> = export.lists.iter().map(|list| (list.id.as_str(), list)).collect();
+        let labels: HashMap<&str, String> = export
+            .labels
+            .iter()
+            .map(|label| {
+                let name = if label.name.trim().is_empty() {
+                    label.color.clone().unwrap_or_default()
+                } else {
+                    label.name.clone()
+                };
+                (label.id.as_str(), name)
+            })
+            .collect();
+        let mut plan = ImportPlan::default();
+        for card in export.cards.iter().filter(|card| !card.closed) {
+            let list = card.id_list.as_deref().and_then(|id| lists.get(id));
+            if list.is_some_and(|list| list.closed) {
+                continue;
+            }
+            let card_labels = card.id_labels.iter().filter_map(|id| labels.get(id.as_str()).cloned());
+            let labels = list.map(|list| list.name.clone()).into_iter().chain(card_labels).collect();
+            plan.push(&card.name, card.due_complete, labels);
+        }
+        plan
+    }
+}
+
+// 取り込めなかったものとその理由
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ImportSkip {
+    pub kind: String,
+    pub name: String,
+    pub reason: String,
+}
+
+// ジョブの結果として返す
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ImportReport {
+    pub labels_created: usize,
+    // 同じ名前のラベルが既にあったもの
+    pub labels_reused: usize,
+    pub todos_created: usize,
+    pub skipped: Vec<ImportSkip>,
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn should_map_todoist_projects_to_labels() {
+        let export: TodoistExport = serde_json::from_value(json!({
+            "projects": [
+                {"id": "1", "name": "Inbox", "inbox_project": true},
+                {"id": "2", "name": "Home"},
+            ],
+            "items": [
+                {"id": "10", "content": "buy milk", "checked": true, "project_id": "2", "labels": ["errand", "Home"]},
+                {"id": "11", "content": "read mail", "checked": 0, "project_id": "1"},
+                {"id": "12", "content": "gone", "checked": false, "project_id": "2", "is_deleted": 1},
+            ],
+            "labels": [{"id": "20", "name": "errand"}],
+        }))
+        .unwrap();
+        assert_eq!(
+            ImportPlan {
+                labels: vec!["Home".to_string(), "errand".to_string()],
+                todos: vec![
+                    ImportedTodo {
+                        text: "buy milk".to_string(),
+                        completed: true,
+                        labels: vec!["Home".to_string(), "errand".to_string()],
+                    },
+                    ImportedTodo {
+                        text: "read mail".to_string(),
+                        completed: false,
+                        labels: vec![],
+                    },
+                ],
+            },
+            ImportPlan::from(export)
+        );
+    }
+
+    #[test]
+    fn should_map_trello_lists_and_labels() {
+        let export: TrelloExport = serde_json::from_value(json!({
+            "name": "Board",
+            "lists": [
+                {"id": "l1", "name": "Doing", "closed": false},
+                {"id": "l2", "name": "Old", "closed": true},
+            ],
+            "labels": [
+                {"id": "a", "name": "", "color": "red"},
+                {"id": "b", "name": "bug", "color": "blue"},
+            ],
+            "cards": [
+                {"id": "c1", "name": "fix login", "closed": false, "idList": "l1", "idLabels": ["a", "b"], "dueComplete": true},
+                {"id": "c2", "name": "archived", "closed": true, "idList": "l1", "idLabels": []},
+                {"id": "c3", "name": "in old list", "closed": false, "idList": "l2", "idLabels": []},
+            ],
+        }))
+        .unwrap();
+        assert_eq!(
+            ImportPlan {
+                labels: vec!["Doing".to_string(), "red".to_string(), "bug".to_string()],
+                todos: vec![ImportedTodo {
+                    text: "fix login".to_string(),
+                    completed: true,
+                    labels: vec!["Doing".to_string(), "red".to_string(), "bug".to_string()],
+                }],
+            },
+            ImportPlan::from(export)
+        );
+    }
+}
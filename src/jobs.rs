@@ -0,0 +1,144 @@
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::VecDeque,
+    future::Future,
+    sync::{Arc, RwLock},
+};
+use uuid::Uuid;
+
+// 保持しておくジョブ数の上限。古い完了済みジョブから捨てる
+const MAX_JOBS: usize = 100;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum JobStatus {
+    Running,
+    Succeeded,
+    Failed,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Job {
+    pub id: Uuid,
+    pub kind: String,
+    pub status: JobStatus,
+    pub result: Option<serde_json::Value>,
+    pub error: Option<String>,
+    // ジョブを始めたユーザー。レスポンスには載せない
+    #[serde(skip)]
+    pub owner_id: Option<i32>,
+}
+
+// 管理用の重い処理をバックグラウンドで実行し、その状態をポーリングできるようにする
+#[derive(Debug, Clone, Default)]
+pub struct JobRegistry {
+    jobs: Arc<RwLock<VecDeque<Job>>>,
+}
+
+impl JobRegistry {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn spawn<F, T>(&self, kind: &str, task: F) -> Job
+    where
+        F: Future<Output = anyhow::Result<T>> + Send + 'static,
+        T: Serialize,
+    {
+        self.spawn_for(None, kind, task)
+    }
+
+    // ユーザーが始めたジョブ。owner_id を見て、本人にだけ結果を見せる
+    pub fn spawn_for<F, T>(&self, owner_id: Option<i32>, kind: &str, task: F) -> Job
+    where
+        F: Future<Output = anyhow::Result<T>> + Send + 'static,
+        T: Serialize,
+    {
+        let job = Job {
+            id: Uuid::new_v4(),
+            kind: kind.to_string(),
+            status: JobStatus::Running,
+            result: None,
+            error: None,
+            owner_id,
+        };
+        {
+            let mut jobs = self.jobs.write().unwrap();
+            if jobs.len() >= MAX_JOBS {
+                if let Some(index) = jobs.iter().position(|job| job.status != JobStatus::Running) {
+                    jobs.remove(index);
+                }
+            }
+            jobs.push_back(job.clone());
+        }
+
+        let registry = self.clone();
+        let id = job.id;
+        tokio::spawn(async move {
+            let outcome = task.await;
+            if let Err(e) = &outcome {
+                tracing::error!("job {} failed: {:?}", id, e);
+            }
+            registry.finish(id, outcome.and_then(|result| Ok(serde_json::to_value(result)?)));
+        });
+        job
+    }
+
+    fn finish(&self, id: Uuid, outcome: anyhow::Result<serde_json::Value>) {
+        let mut jobs = self.jobs.write().unwrap();
+        if let Some(job) = jobs.iter_mut().find(|job| job.id == id) {
+            match outcome {
+                Ok(result) => {
+                    job.status = JobStatus::Succeeded;
+                    job.result = Some(result);
+                }
+                Err(e) => {
+                    job.status = JobStatus::Failed;
+                    job.error = Some(e.to_string());
+                }
+            }
+        }
+    }
+
+    pub fn find(&self, id: Uuid) -> Option<Job> {
+        self.jobs.read().unwrap().iter().find(|job| job.id == id).cloned()
+    }
+
+    pub fn all(&self) -> Vec<Job> {
+        self.jobs.read().unwrap().iter().cloned().collect()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::time::Duration;
+
+    async fn wait_for(registry: &JobRegistry, id: Uuid) -> Job {
+        for _ in 0..100 {
+            let job = registry.find(id).unwrap();
+            if job.status != JobStatus::Running {
+                return job;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        panic!("job {} did not finish", id);
+    }
+
+    #[tokio::test]
+    async fn job_scenario() {
+        let registry = JobRegistry::new();
+
+        let job = registry.spawn("succeed", async { Ok(1) });
+        let job = wait_for(&registry, job.id).await;
+        assert_eq!(job.status, JobStatus::Succeeded);
+        assert_eq!(job.result, Some(serde_json::json!(1)));
+
+        let job = registry.spawn("fail", async { Err::<(), _>(anyhow::anyhow!("boom")) });
+        let job = wait_for(&registry, job.id).await;
+        assert_eq!(job.status, JobStatus::Failed);
+        assert_eq!(job.error, Some("boom".to_string()));
+
+        assert_eq!(registry.all().len(), 2);
+    }
+}
@@ -0,0 +1,191 @@
+use sqlx::PgPool;
+use std::{
+    future::Future,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+use tokio::task::JoinHandle;
+use uuid::Uuid;
+
+// 自分が leader かどうか。選出のタスクと、leader だけが動かす処理とで共有する
+#[derive(Debug, Clone)]
+pub struct Leadership(Arc<AtomicBool>);
+
+// 選出しない構成 (1 インスタンスだけで動かすときやテスト) では常に leader 扱いにする
+impl Default for Leadership {
+    fn default() -> Self {
+        Self::new(true)
+    }
+}
+
+impl Leadership {
+    pub fn new(leader: bool) -> Self {
+        Self(Arc::new(AtomicBool::new(leader)))
+    }
+
+    pub fn is_leader(&self) -> bool {
+        self.0.load(Ordering::SeqCst)
+    }
+
+    // 変更前の状態を返す
+    fn set(&self, leader: bool) -> bool {
+        self.0.swap(leader, Ordering::SeqCst)
+    }
+}
+
+// leader_leases の行をリースとして取り合う。leader は期限が切れる前に延長し続け、
+// leader が落ちて延長が止まれば、期限切れの後に他のインスタンスが取り直す
+#[derive(Debug, Clone)]
+pub struct LeaderElection {
+    pool: PgPool,
+    name: String,
+    // インスタンスごとに起動時に決める
+    holder: Uuid,
+    lease: Duration,
+}
+
+impl LeaderElection {
+    pub fn new(pool: PgPool, name: &str, lease: Duration) -> Self {
+        Self {
+            pool,
+            name: name.to_string(),
+            holder: Uuid::new_v4(),
+            lease,
+        }
+    }
+
+    // 空いているか期限切れならリースを取り、自分が持っていれば延長する。持てたら true
+    pub async fn try_acquire(&self) -> anyhow::Result<bool> {
+        let held = sqlx::query(
+            r#"
+            INSERT INTO leader_leases (name, holder, expires_at)
+            VALUES ($1, $2, now() + make_interval(secs => $3))
+            ON CONFLICT (name) DO UPDATE
+            SET holder = EXCLUDED.holder, expires_at = EXCLUDED.expires_at
+            WHERE leader_leases.holder = EXCLUDED.holder OR leader_leases.expires_at < now()
+            RETURNING holder
+            "#,
+        )
+        .bind(&self.name)
+        .bind(self.holder)
+        .bind(self.lease.as_secs_f64())
+        .fetch_optional(&self.pool)
+        .await?;
+        Ok(held.is_some())
+    }
+
+    // 期限切れを待たずに他のインスタンスへ譲る。停止するときに呼ぶ
+    pub async fn resign(&self) -> anyhow::Result<()> {
+        sqlx::query("DELETE FROM leader_leases WHERE name = $1 AND holder = $2")
+            .bind(&self.name)
+            .bind(self.holder)
+            .execute(&self.pool)
+            .await?;
+        Ok(())
+    }
+
+    // リースの 1/3 ごとに取得か延長を試す。
+    // DB に繋がらないあいだはリースを持っている確証が無いので、leader を降りる
+    pub fn spawn(self, leadership: Leadership) -> JoinHandle<()> {
+        let interval = self.lease / 3;
+        tokio::spawn(async move {
+            let mut ticker = tokio::time::interval(interval);
+            loop {
+                ticker.tick().await;
+                let leader = match self.try_acquire().await {
+                    Ok(leader) => leader,
+                    Err(e) => {
+                        tracing::warn!("failed to renew the [{}] lease: {}", self.name, e);
+                        false
+                    }
+                };
+                match (leadership.set(leader), leader) {
+                    (false, true) => tracing::info!("became the [{}] leader", self.name),
+                    (true, false) => tracing::info!("lost the [{}] leadership", self.name),
+                    _ => {}
+                }
+            }
+        })
+    }
+}
+
+// interval ごとに task を動かす。leader でないあいだは飛ばし、leader になっているインスタンスに任せる。
+// 失敗してもログに出すだけで、次の回にまた試す
+pub fn spawn_periodic<F, Fut>(leadership: Leadership, name: &'static str, interval: Duration, task: F) -> JoinHandle<()>
+where
+    F: Fn() -> Fut + Send + 'static,
+    Fut: Future<Output = anyhow::Result<()>> + Send,
+{
+    tokio::spawn(async move {
+        let mut ticker = tokio::time::interval(interval);
+        // 起動直後はまだ選出が済んでいないので、最初の 1 回は待つだけにする
+        ticker.tick().await;
+        loop {
+            ticker.tick().await;
+            if !leadership.is_leader() {
+                continue;
+            }
+            if let Err(e) = task().await {
+                tracing::warn!("scheduled job [{}] failed: {}", name, e);
+            }
+        }
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use dotenv::dotenv;
+    use std::{env, sync::atomic::AtomicUsize};
+
+    #[tokio::test]
+    async fn periodic_task_runs_only_on_leader() {
+        let leadership = Leadership::new(false);
+        let runs = Arc::new(AtomicUsize::new(0));
+        let counter = runs.clone();
+        let task = spawn_periodic(leadership.clone(), "test", Duration::from_millis(10), move || {
+            let counter = counter.clone();
+            async move {
+                counter.fetch_add(1, Ordering::SeqCst);
+                Ok(())
+            }
+        });
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        assert_eq!(0, runs.load(Ordering::SeqCst));
+
+        leadership.set(true);
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        assert!(runs.load(Ordering::SeqCst) > 0);
+        task.abort();
+    }
+
+    #[cfg(feature = "database-test")]
+    #[tokio::test]
+    async fn lease_fails_over_when_leader_stops_renewing() {
+        dotenv().ok();
+        let database_url = env::var("DATABASE_URL").expect("undefined [DATABASE_URL]");
+        let pool = PgPool::connect(&database_url)
+            .await
+            .unwrap_or_else(|_| panic!("cannot connect database: [{}]", database_url));
+        let name = format!("test:{}", Uuid::new_v4());
+        let first = LeaderElection::new(pool.clone(), &name, Duration::from_millis(300));
+        let second = LeaderElection::new(pool.clone(), &name, Duration::from_millis(300));
+
+        assert!(first.try_acquire().await.unwrap());
+        assert!(!second.try_acquire().await.unwrap());
+        // 延長できるのは持っているインスタンスだけ
+        assert!(first.try_acquire().await.unwrap());
+
+        // first が延長しなくなれば、期限切れの後に second が取る
+        tokio::time::sleep(Duration::from_millis(400)).await;
+        assert!(second.try_acquire().await.unwrap());
+        assert!(!first.try_acquire().await.unwrap());
+
+        second.resign().await.unwrap();
+        assert!(first.try_acquire().await.unwrap());
+        first.resign().await.unwrap();
+    }
+}
@@ -0,0 +1,3097 @@
+#[cfg(any(test, feature = "test-support"))]
+pub mod fixtures;
+pub mod blob;
+pub mod caldav;
+pub mod client;
+pub mod config;
+pub mod db;
+pub mod error_code;
+pub mod events;
+pub mod feed;
+pub mod handlers;
+pub mod import;
+pub mod jobs;
+pub mod leader;
+pub mod markdown;
+pub mod metrics;
+pub mod middleware;
+pub mod moderation;
+pub mod normalize;
+pub mod password;
+pub mod patch;
+pub mod repositories;
+pub mod server;
+pub mod services;
+pub mod smoke;
+pub mod state;
+pub mod systemd;
+#[cfg(any(test, feature = "test-support"))]
+pub mod test_support;
+
+use axum::{
+    body::Body,
+    extract::DefaultBodyLimit,
+    http::Request,
+    response::IntoResponse,
+    routing::{any, delete, get, post, put, Route},
+    Router,
+};
+use crate::blob::SharedBlobStore;
+use crate::config::Config;
+use crate::db::DbHealth;
+use crate::jobs::JobRegistry;
+use crate::leader::Leadership;
+use crate::metrics::Metrics;
+use crate::moderation::{ContentFilter, SharedContentFilter};
+use crate::state::{AccessLogRepo, AppState, BackupRepo, LabelRepo, MaintenanceRepo, TodoRepo, UserRepo};
+use crate::repositories::{
+    access_log::AccessLogRepository,
+    backup::BackupRepository,
+    cache::QueryCache,
+    label::LabelRepository,
+    maintenance::MaintenanceRepository,
+    todo::TodoRepository,
+    user::UserRepository,
+};
+use handlers::{
+    admin::{
+        access_log, all_jobs, all_snapshots, backup, cache_stats, create_snapshot, find_job,
+        metrics, purge_expired, query_plans, rebuild_search_index, refresh_stats, restore,
+        restore_snapshot, unlock_user, SnapshotConfig,
+    },
+    caldav::dav,
+    fallback::not_found,
+    feed::{my_feed, todos_feed, FEED_PATH},
+    health::health,
+    import::{find_import_job, import_todoist, import_trello},
+    label::{all_label, assign_label, create_label, delete_label, find_label, update_label},
+    sync::sync_todos,
+    todo::{
+        all_todo, attach_label, bulk_create_todos, bulk_delete_todos, completed_todos, create_todo, delete_todo,
+        detach_label, export_todos, find_todo, restore_todo, search_todos, todo_stats, todos_by_label,
+        trash_todos, update_todo, upsert_todo_by_key, TOTAL_COUNT_HEADER,
+    },
+    user::{
+        change_password, login_user, refresh_token, register_user, revoke_all_sessions, revoke_session,
+        revoke_token, security_events, sessions,
+    },
+};
+use hyper::header::{HeaderName, AUTHORIZATION, CONTENT_TYPE, ETAG, IF_MATCH, IF_MODIFIED_SINCE, LINK};
+use std::{convert::Infallible, sync::Arc};
+use tower::{Layer, Service};
+use tower_http::cors::{Any, CorsLayer, AllowOrigin};
+
+type RouterLayer = Box<dyn FnOnce(Router) -> Router + Send>;
+
+// アプリの組み立て。create_app を fork しなくても、独自のルートや layer (認証やログなど) を足せるようにする
+pub struct AppBuilder<Todo, Label, Backup, Maintenance, AccessLog, User> {
+    todo_repository: Todo,
+    label_repository: Label,
+    backup_repository: Backup,
+    maintenance_repository: Maintenance,
+    access_log_repository: AccessLog,
+    user_repository: User,
+    config: Config,
+    // None なら設定の禁止語から作る
+    content_filter: Option<SharedContentFilter>,
+    // None なら設定の snapshot_dir から作る
+    snapshot_store: Option<SharedBlobStore>,
+    db_health: DbHealth,
+    leadership: Leadership,
+    query_cache: QueryCache,
+    metrics: Metrics,
+    routes: Router<AppState<Todo, Label, Backup, Maintenance, AccessLog, User>>,
+    layers: Vec<RouterLayer>,
+}
+
+impl<
+    Todo: TodoRepository,
+    Label: LabelRepository,
+    Backup: BackupRepository,
+    Maintenance: MaintenanceRepository,
+    AccessLog: AccessLogRepository,
+    User: UserRepository,
+> AppBuilder<Todo, Label, Backup, Maintenance, AccessLog, User> {
+    pub fn new(
+        todo_repository: Todo,
+        label_repository: Label,
+        backup_repository: Backup,
+        maintenance_repository: Maintenance,
+        access_log_repository: AccessLog,
+        user_repository: User,
+    ) -> Self {
+        Self {
+            todo_repository,
+            label_repository,
+            backup_repository,
+            maintenance_repository,
+            access_log_repository,
+            user_repository,
+            config: Config::default(),
+            content_filter: None,
+            snapshot_store: None,
+            db_health: DbHealth::default(),
+            leadership: Leadership::default(),
+            query_cache: QueryCache::default(),
+            metrics: Metrics::default(),
+            routes: Router::new(),
+            layers: vec![],
+        }
+    }
+
+    // 追加したルートからもリポジトリや設定を State で参照できる
+    pub fn with_routes(mut self, routes: Router<AppState<Todo, Label, Backup, Maintenance, AccessLog, User>>) -> Self {
+        self.routes = self.routes.merge(routes);
+        self
+    }
+
+    // 後から追加した layer ほど外側になる。
+    // request id やアクセスログの layer より内側に置くので、認証で弾いたリクエストもログに残る。
+    // State を渡した後の Router に付けるので、リポジトリは参照できない
+    pub fn with_layer<L>(mut self, layer: L) -> Self
+    where
+        L: Layer<Route<Body>> + Clone + Send + 'static,
+        L::Service: Service<Request<Body>> + Clone + Send + 'static,
+        <L::Service as Service<Request<Body>>>::Response: IntoResponse + 'static,
+        <L::Service as Service<Request<Body>>>::Error: Into<Infallible> + 'static,
+        <L::Service as Service<Request<Body>>>::Future: Send + 'static,
+    {
+        self.layers.push(Box::new(move |router: Router| router.layer(layer)));
+        self
+    }
+
+    // CORS や管理用 API などの設定。デフォルトは dev プロファイルのデフォルト値
+    pub fn with_config(mut self, config: Config) -> Self {
+        self.config = config;
+        self
+    }
+
+    // 設定の禁止語から作るフィルタの代わりに、独自のモデレーション方針を使う
+    pub fn with_content_filter<F: ContentFilter>(mut self, filter: F) -> Self {
+        self.content_filter = Some(Arc::new(filter));
+        self
+    }
+
+    // スナップショットをローカルのディレクトリ以外 (オブジェクトストレージなど) に置く
+    pub fn with_snapshot_store(mut self, store: SharedBlobStore) -> Self {
+        self.snapshot_store = Some(store);
+        self
+    }
+
+    // /health で返す DB の状態。ヘルスチェックのタスクと共有する
+    pub fn with_db_health(mut self, db_health: DbHealth) -> Self {
+        self.db_health = db_health;
+        self
+    }
+
+    // /health で返す leader かどうか。選出のタスクと共有する
+    pub fn with_leadership(mut self, leadership: Leadership) -> Self {
+        self.leadership = leadership;
+        self
+    }
+
+    // /admin/cache で統計を返すキャッシュ。リポジトリに渡したものと同じものを渡す
+    pub fn with_query_cache(mut self, query_cache: QueryCache) -> Self {
+        self.query_cache = query_cache;
+        self
+    }
+
+    // /admin/metrics で返すメトリクス。クエリの時間も載せるなら、リポジトリに渡したものと同じものを渡す
+    pub fn with_metrics(mut self, metrics: Metrics) -> Self {
+        self.metrics = metrics;
+        self
+    }
+
+    pub fn build(self) -> Router {
+        let config = self.config;
+        let content_filter = self.content_filter.unwrap_or_else(|| config.content_filter());
+        let snapshots = match self.snapshot_store {
+            Some(store) => SnapshotConfig::new(Some(store)),
+            None => config.snapshots(),
+        };
+        let router = Router::new()
+            .route("/", get(root))
+            .route("/health", get(health))
+            .route("/users/register", post(register_user))
+            .route("/users/login", post(login_user))
+            .route("/auth/refresh", post(refresh_token))
+            .route("/auth/revoke", post(revoke_token))
+            .route(
+                "/todos",
+                post(create_todo)
+                    .get(all_todo)
+                    .delete(bulk_delete_todos),
+            )
+            .route("/todos/bulk", post(bulk_create_todos))
+            .route("/todos/by-label", get(todos_by_label))
+            .route("/todos/completed", get(completed_todos))
+            .route("/todos/stats", get(todo_stats))
+            .route("/todos/search", get(search_todos))
+            .route("/todos/export", get(export_todos))
+            .route("/todos/trash", get(trash_todos))
+            .route("/todos/by-key/:client_key", put(upsert_todo_by_key))
+            .route(
+                "/todos/:id",
+                get(find_todo)
+                    .delete(delete_todo)
+                    .patch(update_todo)
+            )
+            .route("/todos/:id/restore", post(restore_todo))
+            .route(
+                "/todos/:id/labels/:label_id",
+                post(attach_label).delete(detach_label),
+            )
+            .route(
+                "/labels",
+                post(create_label).get(all_label)
+            )
+            .route(
+                "/labels/:id",
+                get(find_label)
+                    .delete(delete_label)
+                    .patch(update_label)
+            )
+            .route("/labels/:id/assign", post(assign_label))
+            .route("/sync", post(sync_todos))
+            .route(FEED_PATH, get(todos_feed))
+            .route("/me/feed", get(my_feed))
+            .route("/me/password", post(change_password))
+            .route("/me/security-events", get(security_events))
+            .route("/me/sessions", get(sessions))
+            .route("/me/sessions/revoke-all", post(revoke_all_sessions))
+            .route("/me/sessions/:id", delete(revoke_session))
+            // エクスポートやバックアップは丸ごと送られてくるので、axum の既定のボディの上限 (2MB) を外す
+            .route("/import/todoist", post(import_todoist).layer(DefaultBodyLimit::disable()))
+            .route("/import/trello", post(import_trello).layer(DefaultBodyLimit::disable()))
+            .route("/import/jobs/:id", get(find_import_job))
+            .route("/admin/backup", get(backup))
+            .route("/admin/restore", post(restore).layer(DefaultBodyLimit::disable()))
+            .route("/admin/snapshots", post(create_snapshot).get(all_snapshots))
+            .route("/admin/snapshots/:id/restore", post(restore_snapshot))
+            .route(
+                "/admin/maintenance/search-index",
+                post(rebuild_search_index),
+            )
+            .route("/admin/maintenance/stats", post(refresh_stats))
+            .route("/admin/maintenance/purge", post(purge_expired))
+            .route("/admin/jobs", get(all_jobs))
+            .route("/admin/jobs/:id", get(find_job))
+            .route("/admin/cache", get(cache_stats))
+            .route("/admin/access-log", get(access_log))
+            .route("/admin/users/:id/unlock", post(unlock_user))
+            .route("/admin/metrics", get(metrics));
+        // ANALYZE で実際にクエリを流すので、本番では生やさない
+        let router = if config.query_plans_enabled {
+            router.route("/admin/query-plans", get(query_plans))
+        } else {
+            router
+        };
+        let router = if config.caldav_enabled {
+            router
+                .route("/dav", any(dav))
+                // ワイルドカードは空のパスに当たらないので、/dav/ は別に登録する
+                .route("/dav/", any(dav))
+                .route("/dav/*path", any(dav))
+        } else {
+            router
+        };
+        let router = router.merge(self.routes);
+        let users = Arc::new(self.user_repository);
+        let state = AppState {
+            todos: TodoRepo(Arc::new(self.todo_repository)),
+            labels: LabelRepo(Arc::new(self.label_repository)),
+            backups: BackupRepo(Arc::new(self.backup_repository)),
+            maintenance: MaintenanceRepo(Arc::new(self.maintenance_repository)),
+            access_log: AccessLogRepo(Arc::new(self.access_log_repository.clone())),
+            users: UserRepo(users.clone()),
+            jobs: JobRegistry::new(),
+            conflict_policy: config.sync_conflict_policy,
+            admin: config.admin(),
+            auth: config.auth().with_revoked_tokens(users),
+            feed: config.feed(),
+            snapshots,
+            strict_json: config.strict_json(),
+            public_base_url: config.public_base_url(),
+            content_filter,
+            db_health: self.db_health,
+            leadership: self.leadership,
+            query_cache: self.query_cache,
+            metrics: self.metrics.clone(),
+        };
+        let router: Router = router.fallback(not_found).with_state(state);
+        let router = self.layers.into_iter().fold(router, |router, layer| layer(router));
+        // 弾いた 503 にもアクセスログや no-store が付くよう、他のミドルウェアより内側に置く
+        // 時間切れで handler を打ち切る。待っているあいだも同時実行数に数えるよう、load_shed より内側に置く
+        let router = middleware::timeout::layer(router, config.request_timeouts());
+        let router = middleware::load_shed::layer(router, config.concurrency_limits());
+        // パニックの 500 もアクセスログとエラー報告に載るよう、一番内側に置く
+        let router = middleware::catch_panic::layer(router);
+        // ステータスだけで返したエラーにも code 付きの JSON を付ける。メトリクスやアクセスログはステータスしか見ないので順番は問わない
+        let router = middleware::error_body::layer(router);
+        // 弾いた 503、時間切れの 504、パニックの 500 もルートごとに数える
+        let router = middleware::metrics::layer(router, self.metrics);
+        // パニックの 500 にも no-store を付ける
+        let router = middleware::cache_control::layer(router, config.cache_control());
+        // アクセスログとエラー報告は request id を参照するので、request id の layer より内側に置く
+        let router = if config.access_log_enabled {
+            middleware::access_log::layer(router, self.access_log_repository)
+        } else {
+            router
+        };
+        let router = if config.sentry_dsn().is_some() {
+            middleware::error_report::layer(router)
+        } else {
+            router
+        };
+        middleware::request_id::layer(router)
+            .layer(
+                CorsLayer::new()
+                    .allow_origin(AllowOrigin::list(config.cors_origins()))
+                    .allow_methods(Any)
+                    .allow_headers(vec![AUTHORIZATION, CONTENT_TYPE, IF_MATCH, IF_MODIFIED_SINCE])
+                    .expose_headers(vec![ETAG, LINK, HeaderName::from_static(TOTAL_COUNT_HEADER)])
+            )
+    }
+}
+
+pub fn create_app<
+    Todo: TodoRepository,
+    Label: LabelRepository,
+    Backup: BackupRepository,
+    Maintenance: MaintenanceRepository,
+    AccessLog: AccessLogRepository,
+    User: UserRepository,
+>(
+    config: Config,
+    todo_repository: Todo,
+    label_repository: Label,
+    backup_repository: Backup,
+    maintenance_repository: Maintenance,
+    access_log_repository: AccessLog,
+    user_repository: User,
+) -> Router {
+    AppBuilder::new(
+        todo_repository,
+        label_repository,
+        backup_repository,
+        maintenance_repository,
+        access_log_repository,
+        user_repository,
+    )
+    .with_config(config)
+    .build()
+}
+
+async fn root() -> &'static str {
+    "hello world"
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::fixtures::{LabelFixture, TodoFixture};
+    use crate::repositories::todo::{
+        test_utils::{MockTodoRepository, TodoRepositoryForMemory},
+        CreateTodo,
+        Include,
+        Priority,
+        TodoEntity,
+        TodoFilter,
+        UpdateTodo,
+    };
+    use crate::repositories::sync::Resolution;
+    use crate::handlers::dto::{AuthEventResponse, FeedUrlResponse, SessionResponse, SyncResultResponse, TodoResponse};
+    use crate::repositories::label::{
+        test_utils::{LabelRepositoryForMemory, MockLabelRepository},
+        Label,
+    };
+    use crate::repositories::backup::{
+        test_utils::BackupRepositoryForMemory, BackupRecord, RestoreSummary, SnapshotManifest,
+    };
+    use crate::repositories::maintenance::test_utils::MaintenanceRepositoryForMemory;
+    use crate::repositories::access_log::{test_utils::AccessLogRepositoryForMemory, AccessLogEntry};
+    use crate::repositories::user::{test_utils::UserRepositoryForMemory, AuthEventKind};
+    use crate::config::Secret;
+    use crate::jobs::{Job, JobStatus};
+    use crate::import::ImportReport;
+    use crate::handlers::admin::ADMIN_TOKEN_HEADER;
+    use crate::repositories::{
+        cache::{CacheConfig, CacheStats},
+        quota::Quota,
+        RepositoryError,
+    };
+    use mockall::predicate::eq;
+    use std::time::Duration;
+    use axum::response::Response;
+    use axum::{
+        body::Body,
+        extract::State,
+        http::{header, Method, Request, StatusCode},
+    };
+    use tower::ServiceExt;
+
+    fn build_todo_req_with_json(path: &str, method: Method, json_body: String) -> Request<Body> {
+        Request::builder()
+            .uri(path)
+            .method(method)
+            .header(header::CONTENT_TYPE, mime::APPLICATION_JSON.to_string())
+            .body(Body::from(json_body))
+            .unwrap()
+    }
+
+    fn build_todo_req_with_empty(method: Method, path: &str) -> Request<Body> {
+        Request::builder()
+            .uri(path)
+            .method(method)
+            .body(Body::empty())
+            .unwrap()
+    }
+
+    async fn res_to_todo(res: Response) -> TodoResponse {
+        let bytes = hyper::body::to_bytes(res.into_body()).await.unwrap();
+        let body: String = String::from_utf8(bytes.to_vec()).unwrap();
+        let todo: TodoResponse = serde_json::from_str(&body)
+            .unwrap_or_else(|_| panic!("cannot convert Todo instance. body: {}", body));
+        todo
+    }
+
+    async fn res_to_error_code(res: Response) -> String {
+        let bytes = hyper::body::to_bytes(res.into_body()).await.unwrap();
+        let body: serde_json::Value = serde_json::from_slice(&bytes).unwrap();
+        body["code"].as_str().unwrap_or_default().to_string()
+    }
+
+    #[tokio::test]
+    async fn should_return_hello_world() {
+        let todo_repo = TodoRepositoryForMemory::new();
+        let label_repo = LabelRepositoryForMemory::new();
+        let backup_repo = BackupRepositoryForMemory::new();
+        let maintenance_repo = MaintenanceRepositoryForMemory::new();
+        let access_log_repo = AccessLogRepositoryForMemory::new();
+        let req = Request::builder().uri("/").body(Body::empty()).unwrap();
+        let router = create_app(
+            Config::default(),
+            todo_repo,
+            label_repo,
+            backup_repo,
+            maintenance_repo,
+            access_log_repo,
+            UserRepositoryForMemory::new(),
+        );
+        let res = router.oneshot(req).await.unwrap();
+        let bytes = hyper::body::to_bytes(res.into_body()).await.unwrap();
+        let body = String::from_utf8(bytes.to_vec()).unwrap();
+        assert_eq!(body, "hello world");
+    }
+
+    #[tokio::test]
+    async fn should_created_todo() {
+        let expected = TodoEntity::new(1, "should_return_created_todo".to_string());
+
+        let todo_repo = TodoRepositoryForMemory::new();
+        let label_repo = LabelRepositoryForMemory::new();
+        let backup_repo = BackupRepositoryForMemory::new();
+        let maintenance_repo = MaintenanceRepositoryForMemory::new();
+        let access_log_repo = AccessLogRepositoryForMemory::new();
+        let req = build_todo_req_with_json(
+            "/todos",
+            Method::POST,
+            TodoFixture::new().text("should_return_created_todo").to_json(),
+        );
+        let res = create_app(
+                Config::default(),
+                todo_repo,
+                label_repo,
+                backup_repo,
+                maintenance_repo,
+                access_log_repo,
+                UserRepositoryForMemory::new(),
+            )
+            .oneshot(req)
+            .await
+            .expect("failed create todo");
+
+        let todo = res_to_todo(res).await;
+        assert_eq!(TodoResponse::from(expected), todo);
+    }
+
+    #[tokio::test]
+    async fn should_find_todo() {
+        let expected = TodoEntity::new(1, "should_find_todo".to_string());
+
+        let todo_repo = TodoRepositoryForMemory::new();
+        let label_repo = LabelRepositoryForMemory::new();
+        let backup_repo = BackupRepositoryForMemory::new();
+        let maintenance_repo = MaintenanceRepositoryForMemory::new();
+        let access_log_repo = AccessLogRepositoryForMemory::new();
+        TodoFixture::new().text("should_find_todo").insert(&todo_repo).await;
+        let req = build_todo_req_with_empty(Method::GET, "/todos/1");
+        let res = create_app(
+            Config::default(),
+            todo_repo,
+            label_repo,
+            backup_repo,
+            maintenance_repo,
+            access_log_repo,
+            UserRepositoryForMemory::new(),
+        ).oneshot(req).await.unwrap();
+        let todo = res_to_todo(res).await;
+        assert_eq!(TodoResponse::from(expected), todo);
+    }
+
+    #[tokio::test]
+    async fn should_get_all_todos() {
+        let expected = TodoEntity::new(1, "should_get_all_todos".to_string());
+
+        let todo_repo = TodoRepositoryForMemory::new();
+        let label_repo = LabelRepositoryForMemory::new();
+        let backup_repo = BackupRepositoryForMemory::new();
+        let maintenance_repo = MaintenanceRepositoryForMemory::new();
+        let access_log_repo = AccessLogRepositoryForMemory::new();
+        TodoFixture::new().text("should_get_all_todos").insert(&todo_repo).await;
+        let req = build_todo_req_with_empty(Method::GET, "/todos");
+        let res = create_app(
+            Config::default(),
+            todo_repo,
+            label_repo,
+            backup_repo,
+            maintenance_repo,
+            access_log_repo,
+            UserRepositoryForMemory::new(),
+        ).oneshot(req).await.unwrap();
+        let bytes = hyper::body::to_bytes(res.into_body()).await.unwrap();
+        let body = String::from_utf8(bytes.to_vec()).unwrap();
+        let todo: Vec<TodoResponse> = serde_json::from_str(&body)
+            .unwrap_or_else(|_| panic!("cannot convert Todo instance. body: {:?}", body));
+        assert_eq!(vec![TodoResponse::from(expected)], todo);
+    }
+
+    #[tokio::test]
+    async fn should_stream_todos_as_ndjson() {
+        let todo_repo = TodoRepositoryForMemory::new();
+        let first = TodoFixture::new().text("first").insert(&todo_repo).await;
+        let second = TodoFixture::new().text("second").insert(&todo_repo).await;
+        let app = create_app(
+            Config::default(),
+            todo_repo,
+            LabelRepositoryForMemory::new(),
+            BackupRepositoryForMemory::new(),
+            MaintenanceRepositoryForMemory::new(),
+            AccessLogRepositoryForMemory::new(),
+            UserRepositoryForMemory::new(),
+        );
+
+        let res = app.clone().oneshot(build_todo_req_with_empty(Method::GET, "/todos?format=ndjson")).await.unwrap();
+        assert_eq!(StatusCode::OK, res.status());
+        assert_eq!("application/x-ndjson", res.headers()[header::CONTENT_TYPE]);
+        let bytes = hyper::body::to_bytes(res.into_body()).await.unwrap();
+        let mut todos = String::from_utf8(bytes.to_vec())
+            .unwrap()
+            .lines()
+            .map(|line| serde_json::from_str::<TodoResponse>(line).unwrap())
+            .collect::<Vec<_>>();
+        todos.sort_by_key(|todo| todo.id);
+        assert_eq!(vec![TodoResponse::from(first), TodoResponse::from(second)], todos);
+
+        let res = app.oneshot(build_todo_req_with_empty(Method::GET, "/todos?format=xml")).await.unwrap();
+        assert_eq!(StatusCode::BAD_REQUEST, res.status());
+    }
+
+    #[tokio::test]
+    async fn should_paginate_todos_with_link_header() {
+        let todo_repo = TodoRepositoryForMemory::new();
+        for i in 0..5 {
+            TodoFixture::new().text(&format!("todo {}", i)).insert(&todo_repo).await;
+        }
+        let app = create_app(
+            Config::default(),
+            todo_repo,
+            LabelRepositoryForMemory::new(),
+            BackupRepositoryForMemory::new(),
+            MaintenanceRepositoryForMemory::new(),
+            AccessLogRepositoryForMemory::new(),
+            UserRepositoryForMemory::new(),
+        );
+
+        let res = app.clone().oneshot(build_todo_req_with_empty(Method::GET, "/todos?page=2&per_page=2")).await.unwrap();
+        assert_eq!(StatusCode::OK, res.status());
+        assert_eq!("5", res.headers()[TOTAL_COUNT_HEADER]);
+        assert_eq!(
+            "</todos?page=1&per_page=2>; rel=\"first\", </todos?page=1&per_page=2>; rel=\"prev\", \
+             </todos?page=3&per_page=2>; rel=\"next\", </todos?page=3&per_page=2>; rel=\"last\"",
+            res.headers()[header::LINK]
+        );
+        let bytes = hyper::body::to_bytes(res.into_body()).await.unwrap();
+        let todos: Vec<TodoResponse> = serde_json::from_slice(&bytes).unwrap();
+        assert_eq!(vec![3, 2], todos.iter().map(|todo| todo.id).collect::<Vec<_>>());
+
+        // ページングしなければ今まで通り全件で、Link ヘッダも付かない
+        let res = app.clone().oneshot(build_todo_req_with_empty(Method::GET, "/todos")).await.unwrap();
+        assert!(res.headers().get(header::LINK).is_none());
+        let res = app.oneshot(build_todo_req_with_empty(Method::GET, "/todos?page=0")).await.unwrap();
+        assert_eq!(StatusCode::BAD_REQUEST, res.status());
+    }
+
+    #[tokio::test]
+    async fn should_answer_not_modified_todos() {
+        let todo_repo = TodoRepositoryForMemory::new();
+        let app = create_app(
+            Config::default(),
+            todo_repo.clone(),
+            LabelRepositoryForMemory::new(),
+            BackupRepositoryForMemory::new(),
+            MaintenanceRepositoryForMemory::new(),
+            AccessLogRepositoryForMemory::new(),
+            UserRepositoryForMemory::new(),
+        );
+        // まだ一度も書き込まれていなければ Last-Modified は付かない
+        let res = app.clone().oneshot(build_todo_req_with_empty(Method::GET, "/todos")).await.unwrap();
+        assert!(res.headers().get(header::LAST_MODIFIED).is_none());
+
+        TodoFixture::new().insert(&todo_repo).await;
+        let res = app.clone().oneshot(build_todo_req_with_empty(Method::GET, "/todos")).await.unwrap();
+        assert_eq!(StatusCode::OK, res.status());
+        let last_modified = res.headers().get(header::LAST_MODIFIED).unwrap().clone();
+
+        let conditional = |since: &str| {
+            Request::builder()
+                .uri("/todos")
+                .header(header::IF_MODIFIED_SINCE, since)
+                .body(Body::empty())
+                .unwrap()
+        };
+        let res = app.clone().oneshot(conditional(last_modified.to_str().unwrap())).await.unwrap();
+        assert_eq!(StatusCode::NOT_MODIFIED, res.status());
+        assert_eq!(Some(&last_modified), res.headers().get(header::LAST_MODIFIED));
+        let bytes = hyper::body::to_bytes(res.into_body()).await.unwrap();
+        assert!(bytes.is_empty());
+
+        let res = app.clone().oneshot(conditional("Sat, 01 Jan 2000 00:00:00 GMT")).await.unwrap();
+        assert_eq!(StatusCode::OK, res.status());
+        // 読めない日付は無視する
+        let res = app.oneshot(conditional("yesterday")).await.unwrap();
+        assert_eq!(StatusCode::OK, res.status());
+    }
+
+    #[tokio::test]
+    async fn should_update_todo() {
+        let expected = TodoEntity {
+            version: 2,
+            ..TodoEntity::new(1, "should_update_todo".to_string())
+        };
+
+        let todo_repo = TodoRepositoryForMemory::new();
+        let label_repo = LabelRepositoryForMemory::new();
+        let backup_repo = BackupRepositoryForMemory::new();
+        let maintenance_repo = MaintenanceRepositoryForMemory::new();
+        let access_log_repo = AccessLogRepositoryForMemory::new();
+        TodoFixture::new().text("before_update_todo").insert(&todo_repo).await;
+        let req = build_todo_req_with_json(
+            "/todos/1",
+            Method::PATCH,
+            r#"{
+                "text": "should_update_todo",
+                "completed": false
+            }"#.to_string(),
+        );
+        let res = create_app(
+            Config::default(),
+            todo_repo,
+            label_repo,
+            backup_repo,
+            maintenance_repo,
+            access_log_repo,
+            UserRepositoryForMemory::new(),
+        ).oneshot(req).await.unwrap();
+        let todo = res_to_todo(res).await;
+        assert_eq!(TodoResponse::from(expected), todo);
+    }
+
+    #[tokio::test]
+    async fn should_tell_null_from_absent_in_patch() {
+        let todo_repo = TodoRepositoryForMemory::new();
+        TodoFixture::new().text("patch").with_labels(vec![1, 2]).insert(&todo_repo).await;
+        let app = create_app(
+            Config::default(),
+            todo_repo,
+            LabelRepositoryForMemory::new(),
+            BackupRepositoryForMemory::new(),
+            MaintenanceRepositoryForMemory::new(),
+            AccessLogRepositoryForMemory::new(),
+            UserRepositoryForMemory::new(),
+        );
+        let patch = |body: &str| build_todo_req_with_json("/todos/1", Method::PATCH, body.to_string());
+
+        // labels を省略したら付いたまま
+        let todo = res_to_todo(app.clone().oneshot(patch(r#"{"completed": true}"#)).await.unwrap()).await;
+        assert_eq!(vec![1, 2], todo.labels.iter().map(|label| label.id).collect::<Vec<_>>());
+        // null なら全て外す
+        let todo = res_to_todo(app.clone().oneshot(patch(r#"{"labels": null}"#)).await.unwrap()).await;
+        assert!(todo.labels.is_empty());
+        assert!(todo.completed);
+
+        // text と completed は消せない
+        for body in [r#"{"text": null}"#, r#"{"completed": null}"#] {
+            let res = app.clone().oneshot(patch(body)).await.unwrap();
+            assert_eq!(StatusCode::UNPROCESSABLE_ENTITY, res.status());
+            assert_eq!("VALIDATION_FAILED", res_to_error_code(res).await);
+        }
+    }
+
+    #[tokio::test]
+    async fn should_delete_todo() {
+        let todo_repo = TodoRepositoryForMemory::new();
+        let label_repo = LabelRepositoryForMemory::new();
+        let backup_repo = BackupRepositoryForMemory::new();
+        let maintenance_repo = MaintenanceRepositoryForMemory::new();
+        let access_log_repo = AccessLogRepositoryForMemory::new();
+        TodoFixture::new().text("should_delete_todo").insert(&todo_repo).await;
+        let req = build_todo_req_with_empty(Method::DELETE, "/todos/1");
+        let res = create_app(
+            Config::default(),
+            todo_repo,
+            label_repo,
+            backup_repo,
+            maintenance_repo,
+            access_log_repo,
+            UserRepositoryForMemory::new(),
+        ).oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::NO_CONTENT, res.status());
+    }
+
+    #[tokio::test]
+    async fn should_skip_labels_unless_included() {
+        let todo_repo = TodoRepositoryForMemory::new();
+        let label_repo = LabelRepositoryForMemory::new();
+        let label = LabelFixture::new().insert(&label_repo).await;
+        TodoFixture::new().with_labels(vec![label.id]).insert(&todo_repo).await;
+        let app = create_app(
+            Config::default(),
+            todo_repo,
+            label_repo,
+            BackupRepositoryForMemory::new(),
+            MaintenanceRepositoryForMemory::new(),
+            AccessLogRepositoryForMemory::new(),
+            UserRepositoryForMemory::new(),
+        );
+
+        for (path, labels) in [("/todos", 1), ("/todos?include=labels", 1), ("/todos?include=", 0), ("/todos?page=1&include=", 0)] {
+            let req = build_todo_req_with_empty(Method::GET, path);
+            let res = app.clone().oneshot(req).await.unwrap();
+            assert_eq!(StatusCode::OK, res.status(), "{}", path);
+            let bytes = hyper::body::to_bytes(res.into_body()).await.unwrap();
+            let todos: Vec<TodoResponse> = serde_json::from_slice(&bytes).unwrap();
+            assert_eq!(labels, todos[0].labels.len(), "{}", path);
+        }
+
+        let req = build_todo_req_with_empty(Method::GET, "/todos?include=comments");
+        let res = app.oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::BAD_REQUEST, res.status());
+    }
+
+    #[tokio::test]
+    async fn should_filter_todos() {
+        let todo_repo = TodoRepositoryForMemory::new();
+        let label_repo = LabelRepositoryForMemory::new();
+        let label = LabelFixture::new().insert(&label_repo).await;
+        let open = TodoFixture::new().with_labels(vec![label.id]).insert(&todo_repo).await;
+        let done = TodoFixture::new().with_labels(vec![label.id]).completed().insert(&todo_repo).await;
+        let other = TodoFixture::new().insert(&todo_repo).await;
+        let app = create_app(
+            Config::default(),
+            todo_repo,
+            label_repo,
+            BackupRepositoryForMemory::new(),
+            MaintenanceRepositoryForMemory::new(),
+            AccessLogRepositoryForMemory::new(),
+            UserRepositoryForMemory::new(),
+        );
+
+        let label_id = label.id;
+        for (path, expected) in [
+            ("/todos?completed=false".to_string(), vec![other.id, open.id]),
+            (format!("/todos?label_id={}", label_id), vec![done.id, open.id]),
+            (format!("/todos?completed=true&label_id={}", label_id), vec![done.id]),
+            (format!("/todos?completed=false&label_id={}&page=1", label_id), vec![open.id]),
+        ] {
+            let req = build_todo_req_with_empty(Method::GET, &path);
+            let res = app.clone().oneshot(req).await.unwrap();
+            assert_eq!(StatusCode::OK, res.status(), "{}", path);
+            let bytes = hyper::body::to_bytes(res.into_body()).await.unwrap();
+            let todos: Vec<TodoResponse> = serde_json::from_slice(&bytes).unwrap();
+            let mut ids: Vec<i32> = todos.iter().map(|todo| todo.id).collect();
+            ids.sort_by_key(|id| std::cmp::Reverse(*id));
+            assert_eq!(expected, ids, "{}", path);
+        }
+
+        // bool にならない値は Query の extractor が弾く
+        let req = build_todo_req_with_empty(Method::GET, "/todos?completed=maybe");
+        let res = app.oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::UNPROCESSABLE_ENTITY, res.status());
+    }
+
+    #[tokio::test]
+    async fn should_filter_overdue_todos_and_sort_by_due_date() {
+        let todo_repo = TodoRepositoryForMemory::new();
+        let now = chrono::Utc::now();
+        let overdue = TodoFixture::new().due(now - chrono::Duration::days(1)).insert(&todo_repo).await;
+        let upcoming = TodoFixture::new().due(now + chrono::Duration::days(1)).insert(&todo_repo).await;
+        let undated = TodoFixture::new().insert(&todo_repo).await;
+        // 完了したものは期限を過ぎていても期限切れにしない
+        let done = TodoFixture::new().due(now - chrono::Duration::days(2)).completed().insert(&todo_repo).await;
+        let app = create_app(
+            Config::default(),
+            todo_repo,
+            LabelRepositoryForMemory::new(),
+            BackupRepositoryForMemory::new(),
+            MaintenanceRepositoryForMemory::new(),
+            AccessLogRepositoryForMemory::new(),
+            UserRepositoryForMemory::new(),
+        );
+
+        for (path, expected) in [
+            ("/todos?overdue=true", vec![overdue.id]),
+            ("/todos?overdue=false", vec![done.id, undated.id, upcoming.id]),
+            ("/todos?sort=due_date", vec![done.id, overdue.id, upcoming.id, undated.id]),
+            ("/todos?sort=due_date&page=1&per_page=2", vec![done.id, overdue.id]),
+        ] {
+            let req = build_todo_req_with_empty(Method::GET, path);
+            let res = app.clone().oneshot(req).await.unwrap();
+            assert_eq!(StatusCode::OK, res.status(), "{}", path);
+            let bytes = hyper::body::to_bytes(res.into_body()).await.unwrap();
+            let todos: Vec<TodoResponse> = serde_json::from_slice(&bytes).unwrap();
+            let ids: Vec<i32> = todos.iter().map(|todo| todo.id).collect();
+            assert_eq!(expected, ids, "{}", path);
+        }
+
+        let req = build_todo_req_with_empty(Method::GET, "/todos?sort=urgency");
+        let res = app.clone().oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::UNPROCESSABLE_ENTITY, res.status());
+
+        // null で期限を消す
+        let req = build_todo_req_with_json(
+            &format!("/todos/{}", overdue.id),
+            Method::PATCH,
+            r#"{"due_date": null}"#.to_string(),
+        );
+        let res = app.oneshot(req).await.unwrap();
+        let todo: TodoResponse = res_to_todo(res).await;
+        assert_eq!(None, todo.due_date);
+    }
+
+    #[tokio::test]
+    async fn should_sort_todos_by_priority() {
+        let todo_repo = TodoRepositoryForMemory::new();
+        let low = TodoFixture::new().priority(Priority::Low).insert(&todo_repo).await;
+        let high = TodoFixture::new().priority(Priority::High).insert(&todo_repo).await;
+        let medium = TodoFixture::new().insert(&todo_repo).await;
+        let app = create_app(
+            Config::default(),
+            todo_repo,
+            LabelRepositoryForMemory::new(),
+            BackupRepositoryForMemory::new(),
+            MaintenanceRepositoryForMemory::new(),
+            AccessLogRepositoryForMemory::new(),
+            UserRepositoryForMemory::new(),
+        );
+
+        let req = build_todo_req_with_empty(Method::GET, "/todos?sort=priority");
+        let res = app.clone().oneshot(req).await.unwrap();
+        let bytes = hyper::body::to_bytes(res.into_body()).await.unwrap();
+        let todos: Vec<TodoResponse> = serde_json::from_slice(&bytes).unwrap();
+        let ids: Vec<i32> = todos.iter().map(|todo| todo.id).collect();
+        assert_eq!(vec![high.id, medium.id, low.id], ids);
+
+        let patch = |body: &str| build_todo_req_with_json(&format!("/todos/{}", low.id), Method::PATCH, body.to_string());
+        let res = app.clone().oneshot(patch(r#"{"priority": "high"}"#)).await.unwrap();
+        assert_eq!(Priority::High, res_to_todo(res).await.priority);
+        // 決まった値以外と null は受け付けない
+        let res = app.clone().oneshot(patch(r#"{"priority": "urgent"}"#)).await.unwrap();
+        assert_eq!(StatusCode::BAD_REQUEST, res.status());
+        let res = app.oneshot(patch(r#"{"priority": null}"#)).await.unwrap();
+        assert_eq!(StatusCode::UNPROCESSABLE_ENTITY, res.status());
+    }
+
+    #[tokio::test]
+    async fn should_trash_and_restore_todos() {
+        let todo_repo = TodoRepositoryForMemory::new();
+        let kept = TodoFixture::new().insert(&todo_repo).await;
+        let trashed = TodoFixture::new().insert(&todo_repo).await;
+        let app = create_app(
+            Config::default(),
+            todo_repo,
+            LabelRepositoryForMemory::new(),
+            BackupRepositoryForMemory::new(),
+            MaintenanceRepositoryForMemory::new(),
+            AccessLogRepositoryForMemory::new(),
+            UserRepositoryForMemory::new(),
+        );
+        let ids = |bytes: &[u8]| -> Vec<i32> {
+            let todos: Vec<TodoResponse> = serde_json::from_slice(bytes).unwrap();
+            todos.iter().map(|todo| todo.id).collect()
+        };
+
+        let req = build_todo_req_with_empty(Method::DELETE, &format!("/todos/{}", trashed.id));
+        let res = app.clone().oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::NO_CONTENT, res.status());
+
+        // 一覧と find からは消え、ゴミ箱に入る
+        let req = build_todo_req_with_empty(Method::GET, "/todos");
+        let res = app.clone().oneshot(req).await.unwrap();
+        assert_eq!(vec![kept.id], ids(&hyper::body::to_bytes(res.into_body()).await.unwrap()));
+        let req = build_todo_req_with_empty(Method::GET, &format!("/todos/{}", trashed.id));
+        let res = app.clone().oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::NOT_FOUND, res.status());
+        let req = build_todo_req_with_empty(Method::GET, "/todos/trash");
+        let res = app.clone().oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::OK, res.status());
+        let bytes = hyper::body::to_bytes(res.into_body()).await.unwrap();
+        assert_eq!(vec![trashed.id], ids(&bytes));
+        let todos: Vec<TodoResponse> = serde_json::from_slice(&bytes).unwrap();
+        assert!(todos[0].deleted_at.is_some());
+
+        let req = build_todo_req_with_empty(Method::POST, &format!("/todos/{}/restore", trashed.id));
+        let res = app.clone().oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::OK, res.status());
+        let todo = res_to_todo(res).await;
+        assert_eq!((trashed.id, None), (todo.id, todo.deleted_at));
+
+        let req = build_todo_req_with_empty(Method::GET, "/todos");
+        let res = app.clone().oneshot(req).await.unwrap();
+        assert_eq!(vec![trashed.id, kept.id], ids(&hyper::body::to_bytes(res.into_body()).await.unwrap()));
+        // ゴミ箱に無い Todo は戻せない
+        let req = build_todo_req_with_empty(Method::POST, &format!("/todos/{}/restore", kept.id));
+        let res = app.oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::NOT_FOUND, res.status());
+    }
+
+    #[tokio::test]
+    async fn should_bulk_create_and_delete_todos() {
+        let todo_repo = TodoRepositoryForMemory::new();
+        let app = create_app(
+            Config::default(),
+            todo_repo.clone(),
+            LabelRepositoryForMemory::new(),
+            BackupRepositoryForMemory::new(),
+            MaintenanceRepositoryForMemory::new(),
+            AccessLogRepositoryForMemory::new(),
+            UserRepositoryForMemory::new(),
+        );
+
+        let req = build_todo_req_with_json(
+            "/todos/bulk",
+            Method::POST,
+            r#"[{"text": "first", "labels": []}, {"text": "second", "labels": [], "priority": "high"}]"#.to_string(),
+        );
+        let res = app.clone().oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::CREATED, res.status());
+        let bytes = hyper::body::to_bytes(res.into_body()).await.unwrap();
+        let todos: Vec<TodoResponse> = serde_json::from_slice(&bytes).unwrap();
+        let created: Vec<(&str, Priority)> = todos.iter().map(|todo| (todo.text.as_str(), todo.priority)).collect();
+        assert_eq!(vec![("first", Priority::Medium), ("second", Priority::High)], created);
+
+        // 1 件でも検証に通らなければ何も作らない。空の配列も受け付けない
+        for body in [r#"[{"text": "ok", "labels": []}, {"text": "", "labels": []}]"#, "[]"] {
+            let req = build_todo_req_with_json("/todos/bulk", Method::POST, body.to_string());
+            let res = app.clone().oneshot(req).await.unwrap();
+            assert_eq!(StatusCode::UNPROCESSABLE_ENTITY, res.status(), "{}", body);
+        }
+        assert_eq!(2, todo_repo.all(TodoFilter::default(), Include::default()).await.unwrap().len());
+
+        let body = format!(r#"{{"ids": [{}, {}]}}"#, todos[0].id, todos[0].id + 100);
+        let req = build_todo_req_with_json("/todos", Method::DELETE, body);
+        let res = app.oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::OK, res.status());
+        let bytes = hyper::body::to_bytes(res.into_body()).await.unwrap();
+        let deleted: serde_json::Value = serde_json::from_slice(&bytes).unwrap();
+        assert_eq!(serde_json::json!({"deleted": [todos[0].id]}), deleted);
+        let remaining = todo_repo.all(TodoFilter::default(), Include::default()).await.unwrap();
+        assert_eq!(vec![todos[1].id], remaining.iter().map(|todo| todo.id).collect::<Vec<_>>());
+    }
+
+    #[tokio::test]
+    async fn should_route_todos_by_label() {
+        let todo_repo = TodoRepositoryForMemory::new();
+        let label_repo = LabelRepositoryForMemory::new();
+        let backup_repo = BackupRepositoryForMemory::new();
+        let maintenance_repo = MaintenanceRepositoryForMemory::new();
+        let access_log_repo = AccessLogRepositoryForMemory::new();
+        // /todos/:id ではなく /todos/by-label にルーティングされること
+        let req = build_todo_req_with_empty(Method::GET, "/todos/by-label?include_completed=true");
+        let res = create_app(
+            Config::default(),
+            todo_repo,
+            label_repo,
+            backup_repo,
+            maintenance_repo,
+            access_log_repo,
+            UserRepositoryForMemory::new(),
+        ).oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::OK, res.status());
+        let bytes = hyper::body::to_bytes(res.into_body()).await.unwrap();
+        assert_eq!("{}", String::from_utf8(bytes.to_vec()).unwrap());
+    }
+
+    #[tokio::test]
+    async fn should_list_completed_todos() {
+        let todo_repo = TodoRepositoryForMemory::new();
+        let done = TodoFixture::new().text("done item").completed().insert(&todo_repo).await;
+        TodoFixture::new().text("open item").insert(&todo_repo).await;
+        let app = create_app(
+            Config::default(),
+            todo_repo.clone(),
+            LabelRepositoryForMemory::new(),
+            BackupRepositoryForMemory::new(),
+            MaintenanceRepositoryForMemory::new(),
+            AccessLogRepositoryForMemory::new(),
+            UserRepositoryForMemory::new(),
+        );
+
+        let req = build_todo_req_with_empty(Method::GET, "/todos/completed");
+        let res = app.clone().oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::OK, res.status());
+        let bytes = hyper::body::to_bytes(res.into_body()).await.unwrap();
+        let todos: Vec<TodoResponse> = serde_json::from_slice(&bytes).unwrap();
+        assert_eq!(vec![TodoResponse::from(done.clone())], todos);
+        assert!(todos[0].completed_at.is_some());
+
+        // until は含まない
+        let completed_at = done.completed_at.unwrap().to_rfc3339_opts(chrono::SecondsFormat::Micros, true);
+        let req = build_todo_req_with_empty(Method::GET, &format!("/todos/completed?until={}", completed_at));
+        let res = app.clone().oneshot(req).await.unwrap();
+        let bytes = hyper::body::to_bytes(res.into_body()).await.unwrap();
+        assert_eq!("[]", String::from_utf8(bytes.to_vec()).unwrap());
+
+        // 未完了に戻すと消える
+        todo_repo
+            .update(done.id, UpdateTodo::new(None, Some(false), None), None)
+            .await
+            .unwrap();
+        let req = build_todo_req_with_empty(Method::GET, "/todos/completed");
+        let res = app.clone().oneshot(req).await.unwrap();
+        let bytes = hyper::body::to_bytes(res.into_body()).await.unwrap();
+        assert_eq!("[]", String::from_utf8(bytes.to_vec()).unwrap());
+
+        let req = build_todo_req_with_empty(Method::GET, "/todos/completed?limit=0");
+        let res = app.oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::BAD_REQUEST, res.status());
+    }
+
+    #[tokio::test]
+    async fn should_search_todos() {
+        let todo_repo = TodoRepositoryForMemory::new();
+        let older = TodoFixture::new().text("Buy milk").insert(&todo_repo).await;
+        let newer = TodoFixture::new().text("milk the cow").insert(&todo_repo).await;
+        TodoFixture::new().text("walk the dog").insert(&todo_repo).await;
+        let app = create_app(
+            Config::default(),
+            todo_repo,
+            LabelRepositoryForMemory::new(),
+            BackupRepositoryForMemory::new(),
+            MaintenanceRepositoryForMemory::new(),
+            AccessLogRepositoryForMemory::new(),
+            UserRepositoryForMemory::new(),
+        );
+
+        // /todos/:id ではなく /todos/search にルーティングされること
+        let req = build_todo_req_with_empty(Method::GET, "/todos/search?q=MILK");
+        let res = app.clone().oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::OK, res.status());
+        let bytes = hyper::body::to_bytes(res.into_body()).await.unwrap();
+        let todos: Vec<TodoResponse> = serde_json::from_slice(&bytes).unwrap();
+        assert_eq!(vec![TodoResponse::from(newer), TodoResponse::from(older)], todos);
+
+        for path in ["/todos/search?q=%20", "/todos/search?q=milk&limit=0"] {
+            let req = build_todo_req_with_empty(Method::GET, path);
+            let res = app.clone().oneshot(req).await.unwrap();
+            assert_eq!(StatusCode::BAD_REQUEST, res.status(), "{}", path);
+        }
+    }
+
+    #[tokio::test]
+    async fn should_register_and_login_user() {
+        let app = create_app(
+            Config::default(),
+            TodoRepositoryForMemory::new(),
+            LabelRepositoryForMemory::new(),
+            BackupRepositoryForMemory::new(),
+            MaintenanceRepositoryForMemory::new(),
+            AccessLogRepositoryForMemory::new(),
+            UserRepositoryForMemory::new(),
+        );
+
+        let body = r#"{"email": " Alice@Example.com ", "password": "correct horse"}"#.to_string();
+        let res = app.clone().oneshot(build_todo_req_with_json("/users/register", Method::POST, body)).await.unwrap();
+        assert_eq!(StatusCode::CREATED, res.status());
+        let bytes = hyper::body::to_bytes(res.into_body()).await.unwrap();
+        let user: serde_json::Value = serde_json::from_slice(&bytes).unwrap();
+        assert_eq!("alice@example.com", user["email"]);
+        assert!(user.get("password_hash").is_none());
+
+        // email は大文字小文字を区別しない
+        let body = r#"{"email": "ALICE@example.com", "password": "another horse"}"#.to_string();
+        let res = app.clone().oneshot(build_todo_req_with_json("/users/register", Method::POST, body)).await.unwrap();
+        assert_eq!(StatusCode::CONFLICT, res.status());
+        assert_eq!("USER_DUPLICATE", res_to_error_code(res).await);
+
+        for body in [r#"{"email": "bob", "password": "correct horse"}"#, r#"{"email": "bob@example.com", "password": "short"}"#] {
+            let res = app.clone().oneshot(build_todo_req_with_json("/users/register", Method::POST, body.to_string())).await.unwrap();
+            assert_eq!(StatusCode::UNPROCESSABLE_ENTITY, res.status(), "{}", body);
+        }
+
+        let body = r#"{"email": "alice@example.com", "password": "correct horse"}"#.to_string();
+        let res = app.clone().oneshot(build_todo_req_with_json("/users/login", Method::POST, body)).await.unwrap();
+        assert_eq!(StatusCode::OK, res.status());
+        let bytes = hyper::body::to_bytes(res.into_body()).await.unwrap();
+        let logged_in: serde_json::Value = serde_json::from_slice(&bytes).unwrap();
+        assert_eq!(user["id"], logged_in["id"]);
+        // JWT_SECRET が未設定ならトークンは出さない
+        assert!(logged_in.get("token").is_none());
+
+        // パスワード違いと未登録は区別しない
+        for body in [
+            r#"{"email": "alice@example.com", "password": "wrong horse"}"#,
+            r#"{"email": "nobody@example.com", "password": "correct horse"}"#,
+        ] {
+            let res = app.clone().oneshot(build_todo_req_with_json("/users/login", Method::POST, body.to_string())).await.unwrap();
+            assert_eq!(StatusCode::UNAUTHORIZED, res.status(), "{}", body);
+            assert_eq!("INVALID_CREDENTIALS", res_to_error_code(res).await);
+        }
+    }
+
+    #[tokio::test]
+    async fn should_record_security_events() {
+        let app = create_app(
+            Config {
+                jwt_secret: Some(Secret::new("0123456789abcdef0123456789abcdef")),
+                ..Config::default()
+            },
+            TodoRepositoryForMemory::new(),
+            LabelRepositoryForMemory::new(),
+            BackupRepositoryForMemory::new(),
+            MaintenanceRepositoryForMemory::new(),
+            AccessLogRepositoryForMemory::new(),
+            UserRepositoryForMemory::new(),
+        );
+        let login = |password: &str| {
+            let mut req = build_todo_req_with_json(
+                "/users/login",
+                Method::POST,
+                format!(r#"{{"email": "alice@example.com", "password": "{}"}}"#, password),
+            );
+            req.headers_mut().insert(header::USER_AGENT, "curl/7.88".parse().unwrap());
+            req
+        };
+        let body = r#"{"email": "alice@example.com", "password": "correct horse"}"#;
+        let res = app.clone().oneshot(build_todo_req_with_json("/users/register", Method::POST, body.to_string())).await.unwrap();
+        assert_eq!(StatusCode::CREATED, res.status());
+        let res = app.clone().oneshot(login("wrong horse")).await.unwrap();
+        assert_eq!(StatusCode::UNAUTHORIZED, res.status());
+        let res = app.clone().oneshot(login("correct horse")).await.unwrap();
+        let bytes = hyper::body::to_bytes(res.into_body()).await.unwrap();
+        let logged_in: serde_json::Value = serde_json::from_slice(&bytes).unwrap();
+        let token = logged_in["token"].as_str().unwrap().to_string();
+        let authorized = |mut req: Request<Body>| {
+            req.headers_mut()
+                .insert(header::AUTHORIZATION, format!("Bearer {}", token).parse().unwrap());
+            req
+        };
+
+        // 今のパスワードが違えば変えない
+        let change = |current: &str| {
+            authorized(build_todo_req_with_json(
+                "/me/password",
+                Method::POST,
+                format!(r#"{{"current_password": "{}", "new_password": "battery staple"}}"#, current),
+            ))
+        };
+        let res = app.clone().oneshot(change("wrong horse")).await.unwrap();
+        assert_eq!(StatusCode::UNAUTHORIZED, res.status());
+        let res = app.clone().oneshot(change("correct horse")).await.unwrap();
+        assert_eq!(StatusCode::NO_CONTENT, res.status());
+        let res = app.clone().oneshot(login("correct horse")).await.unwrap();
+        assert_eq!(StatusCode::UNAUTHORIZED, res.status());
+
+        let res = app
+            .clone()
+            .oneshot(authorized(build_todo_req_with_empty(Method::GET, "/me/security-events")))
+            .await
+            .unwrap();
+        assert_eq!(StatusCode::OK, res.status());
+        let bytes = hyper::body::to_bytes(res.into_body()).await.unwrap();
+        let events: Vec<AuthEventResponse> = serde_json::from_slice(&bytes).unwrap();
+        assert_eq!(
+            vec![
+                AuthEventKind::LoginFailed,
+                AuthEventKind::PasswordChanged,
+                AuthEventKind::TokenIssued,
+                AuthEventKind::Login,
+                AuthEventKind::LoginFailed,
+            ],
+            events.iter().map(|event| event.kind).collect::<Vec<_>>()
+        );
+        assert_eq!(Some("curl/7.88"), events[0].user_agent.as_deref());
+
+        // 認証が無効なら本人が分からない
+        let res = create_app(
+            Config::default(),
+            TodoRepositoryForMemory::new(),
+            LabelRepositoryForMemory::new(),
+            BackupRepositoryForMemory::new(),
+            MaintenanceRepositoryForMemory::new(),
+            AccessLogRepositoryForMemory::new(),
+            UserRepositoryForMemory::new(),
+        )
+        .oneshot(build_todo_req_with_empty(Method::GET, "/me/security-events"))
+        .await
+        .unwrap();
+        assert_eq!(StatusCode::NOT_FOUND, res.status());
+    }
+
+    #[tokio::test]
+    async fn should_lock_out_after_failed_logins() {
+        let build = |login_lockout_secs| {
+            create_app(
+                Config {
+                    jwt_secret: Some(Secret::new("0123456789abcdef0123456789abcdef")),
+                    admin_token: Some(Secret::new(TEST_ADMIN_TOKEN)),
+                    login_max_failures: 3,
+                    login_lockout_secs,
+                    ..Config::default()
+                },
+                TodoRepositoryForMemory::new(),
+                LabelRepositoryForMemory::new(),
+                BackupRepositoryForMemory::new(),
+                MaintenanceRepositoryForMemory::new(),
+                AccessLogRepositoryForMemory::new(),
+                UserRepositoryForMemory::new(),
+            )
+        };
+        let login = |email: &str, password: &str| {
+            build_todo_req_with_json(
+                "/users/login",
+                Method::POST,
+                format!(r#"{{"email": "{}", "password": "{}"}}"#, email, password),
+            )
+        };
+        let unlock = |id: i32| {
+            Request::builder()
+                .uri(format!("/admin/users/{}/unlock", id))
+                .method(Method::POST)
+                .header(ADMIN_TOKEN_HEADER, TEST_ADMIN_TOKEN)
+                .body(Body::empty())
+                .unwrap()
+        };
+        let app = build(900);
+        let body = r#"{"email": "alice@example.com", "password": "correct horse"}"#;
+        let res = app.clone().oneshot(build_todo_req_with_json("/users/register", Method::POST, body.to_string())).await.unwrap();
+        assert_eq!(StatusCode::CREATED, res.status());
+
+        // 登録されていない email も同じように数える
+        for email in ["alice@example.com", "nobody@example.com"] {
+            for _ in 0..3 {
+                let res = app.clone().oneshot(login(email, "wrong horse")).await.unwrap();
+                assert_eq!(StatusCode::UNAUTHORIZED, res.status());
+            }
+            let res = app.clone().oneshot(login(email, "correct horse")).await.unwrap();
+            assert_eq!(StatusCode::LOCKED, res.status(), "{}", email);
+            let retry_after: u64 = res.headers()[header::RETRY_AFTER].to_str().unwrap().parse().unwrap();
+            assert!((1..=901).contains(&retry_after), "{}", retry_after);
+            assert_eq!("ACCOUNT_LOCKED", res_to_error_code(res).await);
+        }
+
+        let res = app.clone().oneshot(unlock(999)).await.unwrap();
+        assert_eq!(StatusCode::NOT_FOUND, res.status());
+        let res = app.clone().oneshot(unlock(1)).await.unwrap();
+        assert_eq!(StatusCode::NO_CONTENT, res.status());
+        let res = app.clone().oneshot(login("alice@example.com", "correct horse")).await.unwrap();
+        assert_eq!(StatusCode::OK, res.status());
+
+        // 期限が過ぎれば解ける
+        let app = build(1);
+        let res = app.clone().oneshot(build_todo_req_with_json("/users/register", Method::POST, body.to_string())).await.unwrap();
+        assert_eq!(StatusCode::CREATED, res.status());
+        for _ in 0..3 {
+            app.clone().oneshot(login("alice@example.com", "wrong horse")).await.unwrap();
+        }
+        let res = app.clone().oneshot(login("alice@example.com", "correct horse")).await.unwrap();
+        assert_eq!(StatusCode::LOCKED, res.status());
+        tokio::time::sleep(std::time::Duration::from_millis(1100)).await;
+        let res = app.clone().oneshot(login("alice@example.com", "correct horse")).await.unwrap();
+        assert_eq!(StatusCode::OK, res.status());
+    }
+
+    #[tokio::test]
+    async fn should_rotate_refresh_tokens() {
+        let app = create_app(
+            Config {
+                jwt_secret: Some(Secret::new("0123456789abcdef0123456789abcdef")),
+                ..Config::default()
+            },
+            TodoRepositoryForMemory::new(),
+            LabelRepositoryForMemory::new(),
+            BackupRepositoryForMemory::new(),
+            MaintenanceRepositoryForMemory::new(),
+            AccessLogRepositoryForMemory::new(),
+            UserRepositoryForMemory::new(),
+        );
+        let tokens = |res: Response| async move {
+            let bytes = hyper::body::to_bytes(res.into_body()).await.unwrap();
+            let body: serde_json::Value = serde_json::from_slice(&bytes).unwrap();
+            (body["token"].as_str().unwrap().to_string(), body["refresh_token"].as_str().unwrap().to_string())
+        };
+        let refresh = |refresh_token: &str| {
+            build_todo_req_with_json(
+                "/auth/refresh",
+                Method::POST,
+                format!(r#"{{"refresh_token": "{}"}}"#, refresh_token),
+            )
+        };
+        let authorized = |path: &str, token: &str| {
+            let mut req = build_todo_req_with_empty(Method::GET, path);
+            req.headers_mut()
+                .insert(header::AUTHORIZATION, format!("Bearer {}", token).parse().unwrap());
+            req
+        };
+        let body = r#"{"email": "alice@example.com", "password": "correct horse"}"#;
+        let res = app.clone().oneshot(build_todo_req_with_json("/users/register", Method::POST, body.to_string())).await.unwrap();
+        assert_eq!(StatusCode::CREATED, res.status());
+        let res = app.clone().oneshot(build_todo_req_with_json("/users/login", Method::POST, body.to_string())).await.unwrap();
+        let (access, first) = tokens(res).await;
+
+        let res = app.clone().oneshot(refresh(&first)).await.unwrap();
+        assert_eq!(StatusCode::OK, res.status());
+        let (rotated_access, second) = tokens(res).await;
+        assert_ne!(first, second);
+        let res = app.clone().oneshot(authorized("/todos", &rotated_access)).await.unwrap();
+        assert_eq!(StatusCode::OK, res.status());
+        let res = app.clone().oneshot(refresh(&second)).await.unwrap();
+        assert_eq!(StatusCode::OK, res.status());
+        let (_, third) = tokens(res).await;
+
+        // アクセストークンや壊れたトークンとは引き換えない
+        for token in [access.as_str(), "not a token"] {
+            let res = app.clone().oneshot(refresh(token)).await.unwrap();
+            assert_eq!(StatusCode::UNAUTHORIZED, res.status());
+        }
+        // refresh トークンはアクセストークンとして使えない
+        let res = app.clone().oneshot(authorized("/todos", &third)).await.unwrap();
+        assert_eq!(StatusCode::UNAUTHORIZED, res.status());
+
+        // 使用済みのトークンがまた来たら、その系列の最新のトークンも使えなくなる
+        let res = app.clone().oneshot(refresh(&first)).await.unwrap();
+        assert_eq!(StatusCode::UNAUTHORIZED, res.status());
+        let res = app.clone().oneshot(refresh(&third)).await.unwrap();
+        assert_eq!(StatusCode::UNAUTHORIZED, res.status());
+        // 系列のセッションごと無効になるので、そこから出たアクセストークンも使えない
+        let res = app.clone().oneshot(authorized("/todos", &rotated_access)).await.unwrap();
+        assert_eq!(StatusCode::UNAUTHORIZED, res.status());
+
+        // ログインし直せば新しい系列になる
+        let res = app.clone().oneshot(build_todo_req_with_json("/users/login", Method::POST, body.to_string())).await.unwrap();
+        let (fresh_access, fresh) = tokens(res).await;
+        let res = app.clone().oneshot(refresh(&fresh)).await.unwrap();
+        assert_eq!(StatusCode::OK, res.status());
+        let res = app.clone().oneshot(authorized("/me/security-events", &fresh_access)).await.unwrap();
+        let bytes = hyper::body::to_bytes(res.into_body()).await.unwrap();
+        let events: Vec<AuthEventResponse> = serde_json::from_slice(&bytes).unwrap();
+        assert!(events.iter().any(|event| event.kind == AuthEventKind::RefreshTokenReused));
+
+        // 認証が無効なら無い
+        let res = create_app(
+            Config::default(),
+            TodoRepositoryForMemory::new(),
+            LabelRepositoryForMemory::new(),
+            BackupRepositoryForMemory::new(),
+            MaintenanceRepositoryForMemory::new(),
+            AccessLogRepositoryForMemory::new(),
+            UserRepositoryForMemory::new(),
+        )
+        .oneshot(refresh(&fresh))
+        .await
+        .unwrap();
+        assert_eq!(StatusCode::NOT_FOUND, res.status());
+    }
+
+    #[tokio::test]
+    async fn should_revoke_tokens() {
+        let app = create_app(
+            Config {
+                jwt_secret: Some(Secret::new("0123456789abcdef0123456789abcdef")),
+                ..Config::default()
+            },
+            TodoRepositoryForMemory::new(),
+            LabelRepositoryForMemory::new(),
+            BackupRepositoryForMemory::new(),
+            MaintenanceRepositoryForMemory::new(),
+            AccessLogRepositoryForMemory::new(),
+            UserRepositoryForMemory::new(),
+        );
+        let login = || async {
+            let body = r#"{"email": "alice@example.com", "password": "correct horse"}"#;
+            let res = app.clone().oneshot(build_todo_req_with_json("/users/login", Method::POST, body.to_string())).await.unwrap();
+            let bytes = hyper::body::to_bytes(res.into_body()).await.unwrap();
+            let body: serde_json::Value = serde_json::from_slice(&bytes).unwrap();
+            (body["token"].as_str().unwrap().to_string(), body["refresh_token"].as_str().unwrap().to_string())
+        };
+        let revoke = |token: &str| {
+            build_todo_req_with_json("/auth/revoke", Method::POST, format!(r#"{{"token": "{}"}}"#, token))
+        };
+        let todos = |token: &str| {
+            let mut req = build_todo_req_with_empty(Method::GET, "/todos");
+            req.headers_mut()
+                .insert(header::AUTHORIZATION, format!("Bearer {}", token).parse().unwrap());
+            req
+        };
+        let body = r#"{"email": "alice@example.com", "password": "correct horse"}"#;
+        let res = app.clone().oneshot(build_todo_req_with_json("/users/register", Method::POST, body.to_string())).await.unwrap();
+        assert_eq!(StatusCode::CREATED, res.status());
+        let (access, refresh) = login().await;
+        let (other_access, _) = login().await;
+
+        // 失効させたアクセストークンだけが使えなくなる
+        let res = app.clone().oneshot(revoke(&access)).await.unwrap();
+        assert_eq!(StatusCode::NO_CONTENT, res.status());
+        let res = app.clone().oneshot(todos(&access)).await.unwrap();
+        assert_eq!(StatusCode::UNAUTHORIZED, res.status());
+        let res = app.clone().oneshot(todos(&other_access)).await.unwrap();
+        assert_eq!(StatusCode::OK, res.status());
+
+        // refresh トークンは引き換えられなくなる
+        let res = app.clone().oneshot(revoke(&refresh)).await.unwrap();
+        assert_eq!(StatusCode::NO_CONTENT, res.status());
+        let req = build_todo_req_with_json("/auth/refresh", Method::POST, format!(r#"{{"refresh_token": "{}"}}"#, refresh));
+        assert_eq!(StatusCode::UNAUTHORIZED, app.clone().oneshot(req).await.unwrap().status());
+
+        // 知らないトークンでもエラーにしない
+        let res = app.clone().oneshot(revoke("not a token")).await.unwrap();
+        assert_eq!(StatusCode::NO_CONTENT, res.status());
+    }
+
+    #[tokio::test]
+    async fn should_list_and_revoke_sessions() {
+        let app = create_app(
+            Config {
+                jwt_secret: Some(Secret::new("0123456789abcdef0123456789abcdef")),
+                ..Config::default()
+            },
+            TodoRepositoryForMemory::new(),
+            LabelRepositoryForMemory::new(),
+            BackupRepositoryForMemory::new(),
+            MaintenanceRepositoryForMemory::new(),
+            AccessLogRepositoryForMemory::new(),
+            UserRepositoryForMemory::new(),
+        );
+        let login = |email: &'static str, user_agent: &'static str| {
+            let app = app.clone();
+            async move {
+                let body = format!(r#"{{"email": "{}", "password": "correct horse"}}"#, email);
+                let mut req = build_todo_req_with_json("/users/login", Method::POST, body);
+                req.headers_mut().insert(header::USER_AGENT, user_agent.parse().unwrap());
+                let res = app.oneshot(req).await.unwrap();
+                let bytes = hyper::body::to_bytes(res.into_body()).await.unwrap();
+                let body: serde_json::Value = serde_json::from_slice(&bytes).unwrap();
+                body["token"].as_str().unwrap().to_string()
+            }
+        };
+        let authorized = |method: Method, path: &str, token: &str| {
+            let mut req = build_todo_req_with_empty(method, path);
+            req.headers_mut()
+                .insert(header::AUTHORIZATION, format!("Bearer {}", token).parse().unwrap());
+            req
+        };
+        let sessions = |token: String| {
+            let app = app.clone();
+            async move {
+                let res = app.oneshot(authorized(Method::GET, "/me/sessions", &token)).await.unwrap();
+                assert_eq!(StatusCode::OK, res.status());
+                let bytes = hyper::body::to_bytes(res.into_body()).await.unwrap();
+                serde_json::from_slice::<Vec<SessionResponse>>(&bytes).unwrap()
+            }
+        };
+        for email in ["alice@example.com", "bob@example.com"] {
+            let body = format!(r#"{{"email": "{}", "password": "correct horse"}}"#, email);
+            let res = app.clone().oneshot(build_todo_req_with_json("/users/register", Method::POST, body)).await.unwrap();
+            assert_eq!(StatusCode::CREATED, res.status());
+        }
+        let phone = login("alice@example.com", "phone").await;
+        let laptop = login("alice@example.com", "laptop").await;
+        let bob = login("bob@example.com", "bob's phone").await;
+
+        let listed = sessions(laptop.clone()).await;
+        assert_eq!(2, listed.len());
+        let current = listed.iter().find(|session| session.current).unwrap();
+        assert_eq!(Some("laptop"), current.user_agent.as_deref());
+        let other = listed.iter().find(|session| !session.current).unwrap().id;
+
+        // 他のユーザーのセッションは消せない
+        let path = format!("/me/sessions/{}", other);
+        let res = app.clone().oneshot(authorized(Method::DELETE, &path, &bob)).await.unwrap();
+        assert_eq!(StatusCode::NOT_FOUND, res.status());
+        let res = app.clone().oneshot(authorized(Method::DELETE, &path, &laptop)).await.unwrap();
+        assert_eq!(StatusCode::NO_CONTENT, res.status());
+        let res = app.clone().oneshot(authorized(Method::DELETE, &path, &laptop)).await.unwrap();
+        assert_eq!(StatusCode::NOT_FOUND, res.status());
+        // 消したセッションのアクセストークンはすぐに使えなくなる
+        let res = app.clone().oneshot(authorized(Method::GET, "/todos", &phone)).await.unwrap();
+        assert_eq!(StatusCode::UNAUTHORIZED, res.status());
+        assert_eq!(1, sessions(laptop.clone()).await.len());
+
+        let res = app.clone().oneshot(authorized(Method::POST, "/me/sessions/revoke-all", &laptop)).await.unwrap();
+        assert_eq!(StatusCode::NO_CONTENT, res.status());
+        let res = app.clone().oneshot(authorized(Method::GET, "/todos", &laptop)).await.unwrap();
+        assert_eq!(StatusCode::UNAUTHORIZED, res.status());
+        assert_eq!(1, sessions(bob).await.len());
+    }
+
+    #[tokio::test]
+    async fn should_authenticate_todos_and_labels() {
+        let config = Config {
+            jwt_secret: Some(Secret::new("0123456789abcdef0123456789abcdef")),
+            ..Config::default()
+        };
+        let app = create_app(
+            config,
+            TodoRepositoryForMemory::new(),
+            LabelRepositoryForMemory::new(),
+            BackupRepositoryForMemory::new(),
+            MaintenanceRepositoryForMemory::new(),
+            AccessLogRepositoryForMemory::new(),
+            UserRepositoryForMemory::new(),
+        );
+        let mut tokens = vec![];
+        for email in ["alice@example.com", "bob@example.com"] {
+            let body = format!(r#"{{"email": "{}", "password": "correct horse"}}"#, email);
+            let res = app.clone().oneshot(build_todo_req_with_json("/users/register", Method::POST, body.clone())).await.unwrap();
+            assert_eq!(StatusCode::CREATED, res.status());
+            let res = app.clone().oneshot(build_todo_req_with_json("/users/login", Method::POST, body)).await.unwrap();
+            assert_eq!(StatusCode::OK, res.status());
+            let bytes = hyper::body::to_bytes(res.into_body()).await.unwrap();
+            let logged_in: serde_json::Value = serde_json::from_slice(&bytes).unwrap();
+            assert!(logged_in["expires_at"].is_string());
+            tokens.push(logged_in["token"].as_str().unwrap().to_string());
+        }
+        let authorized = |mut req: Request<Body>, token: &str| {
+            req.headers_mut()
+                .insert(header::AUTHORIZATION, format!("Bearer {}", token).parse().unwrap());
+            req
+        };
+
+        // トークンが無い、壊れている
+        let res = app.clone().oneshot(build_todo_req_with_empty(Method::GET, "/todos")).await.unwrap();
+        assert_eq!(StatusCode::UNAUTHORIZED, res.status());
+        assert_eq!("Bearer", res.headers()[header::WWW_AUTHENTICATE]);
+        assert_eq!("UNAUTHORIZED", res_to_error_code(res).await);
+        let req = authorized(build_todo_req_with_empty(Method::GET, "/labels"), "not a token");
+        assert_eq!(StatusCode::UNAUTHORIZED, app.clone().oneshot(req).await.unwrap().status());
+
+        let req = authorized(
+            build_todo_req_with_json("/todos", Method::POST, TodoFixture::new().text("alice's todo").to_json()),
+            &tokens[0],
+        );
+        let res = app.clone().oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::CREATED, res.status());
+        let todo = res_to_todo(res).await;
+        let req = authorized(
+            build_todo_req_with_json("/labels", Method::POST, LabelFixture::new().name("alice's label").to_json()),
+            &tokens[0],
+        );
+        assert_eq!(StatusCode::CREATED, app.clone().oneshot(req).await.unwrap().status());
+
+        // 持ち主には見える
+        let req = authorized(build_todo_req_with_empty(Method::GET, "/todos"), &tokens[0]);
+        let res = app.clone().oneshot(req).await.unwrap();
+        let bytes = hyper::body::to_bytes(res.into_body()).await.unwrap();
+        let todos: Vec<TodoResponse> = serde_json::from_slice(&bytes).unwrap();
+        assert_eq!(vec![todo.id], todos.iter().map(|todo| todo.id).collect::<Vec<_>>());
+
+        // 他のユーザーには無いものとして扱う
+        let req = authorized(build_todo_req_with_empty(Method::GET, "/todos"), &tokens[1]);
+        let res = app.clone().oneshot(req).await.unwrap();
+        let bytes = hyper::body::to_bytes(res.into_body()).await.unwrap();
+        assert_eq!(b"[]", &bytes[..]);
+        let req = authorized(build_todo_req_with_empty(Method::GET, "/labels"), &tokens[1]);
+        let res = app.clone().oneshot(req).await.unwrap();
+        let bytes = hyper::body::to_bytes(res.into_body()).await.unwrap();
+        assert_eq!(b"[]", &bytes[..]);
+        let path = format!("/todos/{}", todo.id);
+        let req = authorized(build_todo_req_with_empty(Method::GET, &path), &tokens[1]);
+        assert_eq!(StatusCode::NOT_FOUND, app.clone().oneshot(req).await.unwrap().status());
+        let req = authorized(
+            build_todo_req_with_json(&path, Method::PATCH, r#"{"completed": true}"#.to_string()),
+            &tokens[1],
+        );
+        assert_eq!(StatusCode::NOT_FOUND, app.clone().oneshot(req).await.unwrap().status());
+        let req = authorized(build_todo_req_with_empty(Method::DELETE, &path), &tokens[1]);
+        assert_eq!(StatusCode::NOT_FOUND, app.clone().oneshot(req).await.unwrap().status());
+
+        let req = authorized(build_todo_req_with_empty(Method::DELETE, &path), &tokens[0]);
+        assert_eq!(StatusCode::NO_CONTENT, app.clone().oneshot(req).await.unwrap().status());
+    }
+
+    #[tokio::test]
+    async fn should_scope_sync_import_and_caldav_to_user() {
+        let app = AppBuilder::new(
+            TodoRepositoryForMemory::new(),
+            LabelRepositoryForMemory::new(),
+            BackupRepositoryForMemory::new(),
+            MaintenanceRepositoryForMemory::new(),
+            AccessLogRepositoryForMemory::new(),
+            UserRepositoryForMemory::new(),
+        )
+        .with_config(Config {
+            jwt_secret: Some(Secret::new("0123456789abcdef0123456789abcdef")),
+            caldav_enabled: true,
+            ..Config::default()
+        })
+        .build();
+        let mut tokens = vec![];
+        for email in ["alice@example.com", "bob@example.com"] {
+            let body = format!(r#"{{"email": "{}", "password": "correct horse"}}"#, email);
+            let res = app.clone().oneshot(build_todo_req_with_json("/users/register", Method::POST, body.clone())).await.unwrap();
+            assert_eq!(StatusCode::CREATED, res.status());
+            let res = app.clone().oneshot(build_todo_req_with_json("/users/login", Method::POST, body)).await.unwrap();
+            let bytes = hyper::body::to_bytes(res.into_body()).await.unwrap();
+            let logged_in: serde_json::Value = serde_json::from_slice(&bytes).unwrap();
+            tokens.push(logged_in["token"].as_str().unwrap().to_string());
+        }
+        let authorized = |mut req: Request<Body>, token: &str| {
+            req.headers_mut()
+                .insert(header::AUTHORIZATION, format!("Bearer {}", token).parse().unwrap());
+            req
+        };
+        let sync = |body: &str, token: &str| {
+            authorized(build_todo_req_with_json("/sync", Method::POST, body.to_string()), token)
+        };
+        let sync_result = |res: Response| async move {
+            let bytes = hyper::body::to_bytes(res.into_body()).await.unwrap();
+            serde_json::from_slice::<SyncResultResponse>(&bytes).unwrap()
+        };
+
+        let create = r#"{
+            "mutations": [{
+                "type": "create",
+                "mutation_id": "7c9e6679-7425-40de-944b-e07fc1f90ae7",
+                "client_id": "16fd2706-8baf-433b-82eb-8c7fada847da",
+                "text": "alice's todo",
+                "labels": []
+            }]
+        }"#;
+        let req = build_todo_req_with_json("/sync", Method::POST, create.to_string());
+        assert_eq!(StatusCode::UNAUTHORIZED, app.clone().oneshot(req).await.unwrap().status());
+        let res = app.clone().oneshot(sync(create, &tokens[0])).await.unwrap();
+        assert_eq!(StatusCode::OK, res.status());
+        let id = sync_result(res).await.id_map[0].id;
+        let path = format!("/todos/{}", id);
+        let req = authorized(build_todo_req_with_empty(Method::GET, &path), &tokens[1]);
+        assert_eq!(StatusCode::NOT_FOUND, app.clone().oneshot(req).await.unwrap().status());
+
+        // 他のユーザーの Todo への更新と削除は、消された Todo と同じ扱いで何もしない
+        let update = format!(
+            r#"{{"mutations": [{{"type": "update", "mutation_id": "a0e1c1c4-3f1e-4b8e-9c44-5b7f0e2d9a01", "id": {}, "base_version": 1, "completed": true}}]}}"#,
+            id
+        );
+        let res = app.clone().oneshot(sync(&update, &tokens[1])).await.unwrap();
+        assert_eq!(vec![id], sync_result(res).await.missing);
+        let delete = format!(
+            r#"{{"mutations": [{{"type": "delete", "mutation_id": "a0e1c1c4-3f1e-4b8e-9c44-5b7f0e2d9a02", "id": {}, "base_version": 1}}]}}"#,
+            id
+        );
+        let res = app.clone().oneshot(sync(&delete, &tokens[1])).await.unwrap();
+        assert!(sync_result(res).await.applied.is_empty());
+        let req = authorized(build_todo_req_with_empty(Method::GET, &path), &tokens[0]);
+        let res = app.clone().oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::OK, res.status());
+        let todo = res_to_todo(res).await;
+        assert_eq!((1, false), (todo.version, todo.completed));
+
+        // CalDAV のカレンダーも持ち主の Todo だけ
+        let ics = format!("/dav/todos/{}.ics", id);
+        let req = build_todo_req_with_empty(Method::GET, &ics);
+        assert_eq!(StatusCode::UNAUTHORIZED, app.clone().oneshot(req).await.unwrap().status());
+        let req = authorized(build_todo_req_with_empty(Method::GET, &ics), &tokens[1]);
+        assert_eq!(StatusCode::NOT_FOUND, app.clone().oneshot(req).await.unwrap().status());
+        let req = authorized(build_todo_req_with_empty(Method::DELETE, &ics), &tokens[1]);
+        assert_eq!(StatusCode::NOT_FOUND, app.clone().oneshot(req).await.unwrap().status());
+        let req = authorized(build_todo_req_with_empty(Method::GET, &ics), &tokens[0]);
+        assert_eq!(StatusCode::OK, app.clone().oneshot(req).await.unwrap().status());
+
+        // 取り込みのジョブは始めたユーザーにしか見えない
+        let board = r#"{"cards": [{"id": "c1", "name": "bob's card", "closed": false, "idLabels": []}]}"#;
+        let req = build_todo_req_with_json("/import/trello", Method::POST, board.to_string());
+        assert_eq!(StatusCode::UNAUTHORIZED, app.clone().oneshot(req).await.unwrap().status());
+        let req = authorized(build_todo_req_with_json("/import/trello", Method::POST, board.to_string()), &tokens[1]);
+        let res = app.clone().oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::ACCEPTED, res.status());
+        let location = res.headers()[header::LOCATION].to_str().unwrap().to_string();
+        let req = authorized(build_todo_req_with_empty(Method::GET, &location), &tokens[0]);
+        assert_eq!(StatusCode::NOT_FOUND, app.clone().oneshot(req).await.unwrap().status());
+        let mut status = JobStatus::Running;
+        for _ in 0..100 {
+            let req = authorized(build_todo_req_with_empty(Method::GET, &location), &tokens[1]);
+            let res = app.clone().oneshot(req).await.unwrap();
+            assert_eq!(StatusCode::OK, res.status());
+            let bytes = hyper::body::to_bytes(res.into_body()).await.unwrap();
+            status = serde_json::from_slice::<Job>(&bytes).unwrap().status;
+            if status != JobStatus::Running {
+                break;
+            }
+            tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+        }
+        assert_eq!(JobStatus::Succeeded, status);
+        let req = authorized(build_todo_req_with_empty(Method::GET, "/todos"), &tokens[1]);
+        let res = app.clone().oneshot(req).await.unwrap();
+        let bytes = hyper::body::to_bytes(res.into_body()).await.unwrap();
+        let todos: Vec<TodoResponse> = serde_json::from_slice(&bytes).unwrap();
+        assert_eq!(vec!["bob's card"], todos.iter().map(|todo| todo.text.as_str()).collect::<Vec<_>>());
+    }
+
+    #[tokio::test]
+    async fn should_count_todos() {
+        let todo_repo = TodoRepositoryForMemory::new();
+        let label_repo = LabelRepositoryForMemory::new();
+        let label = LabelFixture::new().insert(&label_repo).await;
+        TodoFixture::new().with_labels(vec![label.id]).completed().insert(&todo_repo).await;
+        TodoFixture::new().with_labels(vec![label.id]).insert(&todo_repo).await;
+        TodoFixture::new().insert(&todo_repo).await;
+        let app = create_app(
+            Config::default(),
+            todo_repo,
+            label_repo,
+            BackupRepositoryForMemory::new(),
+            MaintenanceRepositoryForMemory::new(),
+            AccessLogRepositoryForMemory::new(),
+            UserRepositoryForMemory::new(),
+        );
+
+        let req = build_todo_req_with_empty(Method::GET, "/todos/stats");
+        let res = app.oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::OK, res.status());
+        let bytes = hyper::body::to_bytes(res.into_body()).await.unwrap();
+        let counts: serde_json::Value = serde_json::from_slice(&bytes).unwrap();
+        assert_eq!(
+            serde_json::json!({
+                "open": 2,
+                "completed": 1,
+                "labels": [{"id": label.id, "name": "", "open": 1, "completed": 1}],
+            }),
+            counts
+        );
+    }
+
+    #[tokio::test]
+    async fn should_export_todos_as_markdown() {
+        let todo_repo = TodoRepositoryForMemory::new();
+        TodoFixture::new().text("done item").completed().insert(&todo_repo).await;
+        TodoFixture::new().text("open item").insert(&todo_repo).await;
+        let app = create_app(
+            Config::default(),
+            todo_repo,
+            LabelRepositoryForMemory::new(),
+            BackupRepositoryForMemory::new(),
+            MaintenanceRepositoryForMemory::new(),
+            AccessLogRepositoryForMemory::new(),
+            UserRepositoryForMemory::new(),
+        );
+
+        let req = build_todo_req_with_empty(Method::GET, "/todos/export?format=markdown");
+        let res = app.clone().oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::OK, res.status());
+        assert_eq!("text/markdown; charset=utf-8", res.headers()[header::CONTENT_TYPE]);
+        let bytes = hyper::body::to_bytes(res.into_body()).await.unwrap();
+        assert_eq!(
+            "# Todos\n\n## No label\n\n- [x] done item\n- [ ] open item\n",
+            String::from_utf8(bytes.to_vec()).unwrap()
+        );
+
+        let req = build_todo_req_with_empty(Method::GET, "/todos/export?format=pdf");
+        let res = app.oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::BAD_REQUEST, res.status());
+    }
+
+    #[tokio::test]
+    async fn should_attach_and_detach_label() {
+        let todo_repo = TodoRepositoryForMemory::new();
+        let label_repo = LabelRepositoryForMemory::new();
+        let backup_repo = BackupRepositoryForMemory::new();
+        let maintenance_repo = MaintenanceRepositoryForMemory::new();
+        let access_log_repo = AccessLogRepositoryForMemory::new();
+        let todo = TodoFixture::new().text("should_attach_and_detach_label").insert(&todo_repo).await;
+        let label = LabelFixture::new().insert(&label_repo).await;
+        let app = create_app(
+            Config::default(),
+            todo_repo,
+            label_repo,
+            backup_repo,
+            maintenance_repo,
+            access_log_repo,
+            UserRepositoryForMemory::new(),
+        );
+        let path = format!("/todos/{}/labels/{}", todo.id, label.id);
+
+        let req = build_todo_req_with_empty(Method::POST, &path);
+        let res = app.clone().oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::OK, res.status());
+        let todo = res_to_todo(res).await;
+        assert_eq!(vec![label.id], todo.labels.iter().map(|label| label.id).collect::<Vec<_>>());
+
+        let req = build_todo_req_with_empty(Method::DELETE, &path);
+        let todo = res_to_todo(app.clone().oneshot(req).await.unwrap()).await;
+        assert!(todo.labels.is_empty());
+
+        let req = build_todo_req_with_empty(Method::POST, &format!("/todos/404/labels/{}", label.id));
+        let res = app.oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::NOT_FOUND, res.status());
+    }
+
+    #[tokio::test]
+    async fn should_assign_label_to_many_todos() {
+        let todo_repo = TodoRepositoryForMemory::new();
+        let label_repo = LabelRepositoryForMemory::new();
+        let first = TodoFixture::new().insert(&todo_repo).await;
+        let second = TodoFixture::new().insert(&todo_repo).await;
+        let label = LabelFixture::new().insert(&label_repo).await;
+        todo_repo.attach_label(second.id, label.id).await.unwrap();
+        let app = create_app(
+            Config::default(),
+            todo_repo.clone(),
+            label_repo,
+            BackupRepositoryForMemory::new(),
+            MaintenanceRepositoryForMemory::new(),
+            AccessLogRepositoryForMemory::new(),
+            UserRepositoryForMemory::new(),
+        );
+        let path = format!("/labels/{}/assign", label.id);
+
+        // 既に付いているものと無い Todo は結果に含まれない
+        let body = format!(r#"{{"todo_ids": [{}, {}, 404], "action": "attach"}}"#, first.id, second.id);
+        let res = app.clone().oneshot(build_todo_req_with_json(&path, Method::POST, body)).await.unwrap();
+        assert_eq!(StatusCode::OK, res.status());
+        let bytes = hyper::body::to_bytes(res.into_body()).await.unwrap();
+        let assigned: serde_json::Value = serde_json::from_slice(&bytes).unwrap();
+        assert_eq!(
+            serde_json::json!({"label_id": label.id, "action": "attach", "todo_ids": [first.id]}),
+            assigned
+        );
+        assert_eq!(vec![label.id], todo_repo.find(first.id).await.unwrap().labels.iter().map(|label| label.id).collect::<Vec<_>>());
+
+        let body = format!(r#"{{"todo_ids": [{}, {}], "action": "detach"}}"#, first.id, second.id);
+        let res = app.clone().oneshot(build_todo_req_with_json(&path, Method::POST, body)).await.unwrap();
+        assert_eq!(StatusCode::OK, res.status());
+        assert!(todo_repo.find(second.id).await.unwrap().labels.is_empty());
+
+        let body = r#"{"todo_ids": [], "action": "attach"}"#.to_string();
+        let res = app.oneshot(build_todo_req_with_json(&path, Method::POST, body)).await.unwrap();
+        assert_eq!(StatusCode::UNPROCESSABLE_ENTITY, res.status());
+    }
+
+    #[tokio::test]
+    async fn should_rename_label() {
+        let label_repo = LabelRepositoryForMemory::new();
+        let label = LabelFixture::new().name("home").insert(&label_repo).await;
+        LabelFixture::new().name("work").insert(&label_repo).await;
+        let app = create_app(
+            Config::default(),
+            TodoRepositoryForMemory::new(),
+            label_repo,
+            BackupRepositoryForMemory::new(),
+            MaintenanceRepositoryForMemory::new(),
+            AccessLogRepositoryForMemory::new(),
+            UserRepositoryForMemory::new(),
+        );
+        let path = format!("/labels/{}", label.id);
+
+        let req = build_todo_req_with_json(&path, Method::PATCH, r#"{"name": "  house "}"#.to_string());
+        let res = app.clone().oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::OK, res.status());
+        let bytes = hyper::body::to_bytes(res.into_body()).await.unwrap();
+        let renamed: serde_json::Value = serde_json::from_slice(&bytes).unwrap();
+        assert_eq!(serde_json::json!({"id": label.id, "name": "house"}), renamed);
+
+        // 他のラベルと同じ名前にはできない
+        let req = build_todo_req_with_json(&path, Method::PATCH, r#"{"name": "work"}"#.to_string());
+        let res = app.clone().oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::CONFLICT, res.status());
+        assert_eq!("LABEL_DUPLICATE", res_to_error_code(res).await);
+
+        let req = build_todo_req_with_json(&path, Method::PATCH, r#"{"name": ""}"#.to_string());
+        let res = app.clone().oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::UNPROCESSABLE_ENTITY, res.status());
+
+        let req = build_todo_req_with_json("/labels/404", Method::PATCH, r#"{"name": "missing"}"#.to_string());
+        let res = app.oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::NOT_FOUND, res.status());
+        assert_eq!("LABEL_NOT_FOUND", res_to_error_code(res).await);
+    }
+
+    #[tokio::test]
+    async fn should_find_label_with_todo_count() {
+        let todo_repo = TodoRepositoryForMemory::new();
+        let label_repo = LabelRepositoryForMemory::new().with_todos(todo_repo.clone());
+        let label = LabelFixture::new().name("home").insert(&label_repo).await;
+        TodoFixture::new().with_labels(vec![label.id]).insert(&todo_repo).await;
+        TodoFixture::new().with_labels(vec![label.id]).completed().insert(&todo_repo).await;
+        let trashed = TodoFixture::new().with_labels(vec![label.id]).insert(&todo_repo).await;
+        todo_repo.delete(trashed.id, None).await.unwrap();
+        let app = create_app(
+            Config::default(),
+            todo_repo,
+            label_repo,
+            BackupRepositoryForMemory::new(),
+            MaintenanceRepositoryForMemory::new(),
+            AccessLogRepositoryForMemory::new(),
+            UserRepositoryForMemory::new(),
+        );
+
+        // 完了したものは数え、ゴミ箱のものは数えない
+        let req = build_todo_req_with_empty(Method::GET, &format!("/labels/{}", label.id));
+        let res = app.clone().oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::OK, res.status());
+        let bytes = hyper::body::to_bytes(res.into_body()).await.unwrap();
+        let found: serde_json::Value = serde_json::from_slice(&bytes).unwrap();
+        assert_eq!(serde_json::json!({"id": label.id, "name": "home", "todo_count": 2}), found);
+
+        let req = build_todo_req_with_empty(Method::GET, "/labels/404");
+        let res = app.oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::NOT_FOUND, res.status());
+        assert_eq!("LABEL_NOT_FOUND", res_to_error_code(res).await);
+    }
+
+    #[tokio::test]
+    async fn should_delete_label_and_detach_todos() {
+        let todo_repo = TodoRepositoryForMemory::new();
+        let label_repo = LabelRepositoryForMemory::new().with_todos(todo_repo.clone());
+        let label = LabelFixture::new().name("home").insert(&label_repo).await;
+        let todo = TodoFixture::new().with_labels(vec![label.id]).insert(&todo_repo).await;
+        let app = create_app(
+            Config::default(),
+            todo_repo.clone(),
+            label_repo,
+            BackupRepositoryForMemory::new(),
+            MaintenanceRepositoryForMemory::new(),
+            AccessLogRepositoryForMemory::new(),
+            UserRepositoryForMemory::new(),
+        );
+        let path = format!("/labels/{}", label.id);
+
+        let req = build_todo_req_with_empty(Method::DELETE, &path);
+        let res = app.clone().oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::NO_CONTENT, res.status());
+        assert!(todo_repo.find(todo.id).await.unwrap().labels.is_empty());
+
+        // 無いラベルは 404
+        let req = build_todo_req_with_empty(Method::DELETE, &path);
+        let res = app.oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::NOT_FOUND, res.status());
+        assert_eq!("LABEL_NOT_FOUND", res_to_error_code(res).await);
+    }
+
+    #[tokio::test]
+    async fn should_upsert_todo_by_key() {
+        let todo_repo = TodoRepositoryForMemory::new();
+        let label_repo = LabelRepositoryForMemory::new();
+        let backup_repo = BackupRepositoryForMemory::new();
+        let maintenance_repo = MaintenanceRepositoryForMemory::new();
+        let access_log_repo = AccessLogRepositoryForMemory::new();
+        let app = create_app(
+            Config::default(),
+            todo_repo,
+            label_repo,
+            backup_repo,
+            maintenance_repo,
+            access_log_repo,
+            UserRepositoryForMemory::new(),
+        );
+
+        let req = build_todo_req_with_json(
+            "/todos/by-key/issue-42",
+            Method::PUT,
+            r#"{"text": "imported"}"#.to_string(),
+        );
+        let res = app.clone().oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::CREATED, res.status());
+        let created = res_to_todo(res).await;
+
+        let req = build_todo_req_with_json(
+            "/todos/by-key/issue-42",
+            Method::PUT,
+            r#"{"text": "imported", "completed": true}"#.to_string(),
+        );
+        let res = app.oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::OK, res.status());
+        let updated = res_to_todo(res).await;
+        assert_eq!(created.id, updated.id);
+        assert!(updated.completed);
+    }
+
+    #[tokio::test]
+    async fn should_reject_stale_if_match() {
+        let todo_repo = TodoRepositoryForMemory::new();
+        let label_repo = LabelRepositoryForMemory::new();
+        let backup_repo = BackupRepositoryForMemory::new();
+        let maintenance_repo = MaintenanceRepositoryForMemory::new();
+        let access_log_repo = AccessLogRepositoryForMemory::new();
+        TodoFixture::new().text("should_reject_stale_if_match").insert(&todo_repo).await;
+        let app = create_app(
+            Config::default(),
+            todo_repo,
+            label_repo,
+            backup_repo,
+            maintenance_repo,
+            access_log_repo,
+            UserRepositoryForMemory::new(),
+        );
+
+        let req = build_todo_req_with_empty(Method::GET, "/todos/1");
+        let res = app.clone().oneshot(req).await.unwrap();
+        let etag = res.headers()[header::ETAG].clone();
+        assert_eq!("\"1\"", etag);
+
+        let mut req = build_todo_req_with_json(
+            "/todos/1",
+            Method::PATCH,
+            r#"{"completed": true}"#.to_string(),
+        );
+        req.headers_mut().insert(header::IF_MATCH, etag.clone());
+        let res = app.clone().oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::CREATED, res.status());
+        assert_eq!("\"2\"", res.headers()[header::ETAG]);
+
+        // 同じ ETag での 2 回目の変更は古い version に対するものなので弾かれる
+        let mut req = build_todo_req_with_empty(Method::DELETE, "/todos/1");
+        req.headers_mut().insert(header::IF_MATCH, etag);
+        let res = app.clone().oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::PRECONDITION_FAILED, res.status());
+
+        let req = build_todo_req_with_empty(Method::DELETE, "/todos/1");
+        let res = app.oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::NO_CONTENT, res.status());
+    }
+
+    #[tokio::test]
+    async fn should_return_json_not_found() {
+        let todo_repo = TodoRepositoryForMemory::new();
+        let label_repo = LabelRepositoryForMemory::new();
+        let backup_repo = BackupRepositoryForMemory::new();
+        let maintenance_repo = MaintenanceRepositoryForMemory::new();
+        let access_log_repo = AccessLogRepositoryForMemory::new();
+        let req = Request::builder()
+            .uri("/unknown")
+            .header("x-request-id", "should_return_json_not_found")
+            .body(Body::empty())
+            .unwrap();
+        let res = create_app(
+            Config::default(),
+            todo_repo,
+            label_repo,
+            backup_repo,
+            maintenance_repo,
+            access_log_repo,
+            UserRepositoryForMemory::new(),
+        ).oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::NOT_FOUND, res.status());
+        let bytes = hyper::body::to_bytes(res.into_body()).await.unwrap();
+        let body: serde_json::Value = serde_json::from_slice(&bytes).unwrap();
+        assert_eq!("not found", body["error"]);
+        assert_eq!("should_return_json_not_found", body["request_id"]);
+    }
+
+    #[tokio::test]
+    async fn should_reject_non_json_content_type() {
+        let todo_repo = TodoRepositoryForMemory::new();
+        let label_repo = LabelRepositoryForMemory::new();
+        let backup_repo = BackupRepositoryForMemory::new();
+        let maintenance_repo = MaintenanceRepositoryForMemory::new();
+        let access_log_repo = AccessLogRepositoryForMemory::new();
+        let req = Request::builder()
+            .uri("/todos")
+            .method(Method::POST)
+            .header(header::CONTENT_TYPE, "text/plain")
+            .body(Body::from(TodoFixture::new().text("plain").to_json()))
+            .unwrap();
+        let res = create_app(
+            Config::default(),
+            todo_repo,
+            label_repo,
+            backup_repo,
+            maintenance_repo,
+            access_log_repo,
+            UserRepositoryForMemory::new(),
+        ).oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::UNSUPPORTED_MEDIA_TYPE, res.status());
+        let bytes = hyper::body::to_bytes(res.into_body()).await.unwrap();
+        let body: serde_json::Value = serde_json::from_slice(&bytes).unwrap();
+        assert_eq!("text/plain", body["received"]);
+    }
+
+    #[tokio::test]
+    async fn should_normalize_todo_text() {
+        let todo_repo = TodoRepositoryForMemory::new();
+        let label_repo = LabelRepositoryForMemory::new();
+        let backup_repo = BackupRepositoryForMemory::new();
+        let maintenance_repo = MaintenanceRepositoryForMemory::new();
+        let access_log_repo = AccessLogRepositoryForMemory::new();
+        let app = create_app(
+            Config::default(),
+            todo_repo,
+            label_repo,
+            backup_repo,
+            maintenance_repo,
+            access_log_repo,
+            UserRepositoryForMemory::new(),
+        );
+
+        let req = build_todo_req_with_json(
+            "/todos",
+            Method::POST,
+            TodoFixture::new().text("  buy \t milk\u{0007}  ").to_json(),
+        );
+        let todo = res_to_todo(app.clone().oneshot(req).await.unwrap()).await;
+        assert_eq!("buy milk", todo.text);
+
+        // 空白だけのテキストは正規化すると空になるので弾かれる
+        let req = build_todo_req_with_json(
+            "/todos",
+            Method::POST,
+            TodoFixture::new().text("   ").to_json(),
+        );
+        let res = app.oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::UNPROCESSABLE_ENTITY, res.status());
+    }
+
+    #[tokio::test]
+    async fn should_sync_todo_with_conflict() {
+        let todo_repo = TodoRepositoryForMemory::new();
+        let label_repo = LabelRepositoryForMemory::new();
+        let backup_repo = BackupRepositoryForMemory::new();
+        let maintenance_repo = MaintenanceRepositoryForMemory::new();
+        let access_log_repo = AccessLogRepositoryForMemory::new();
+        TodoFixture::new().text("should_sync_todo").insert(&todo_repo).await;
+        // サーバー側で version を 2 に進めておく
+        todo_repo.update(1, UpdateTodo::new(
+            Some("server_updated_todo".to_string()),
+            None,
+            None,
+        ), None).await.expect("cannot update todo");
+
+        let req = build_todo_req_with_json(
+            "/sync",
+            Method::POST,
+            r#"{
+                "policy": "merge",
+                "mutations": [{
+                    "type": "update",
+                    "mutation_id": "5d0f3f0e-6c1b-4b59-9f3b-7b0b8d1c2e33",
+                    "id": 1,
+                    "base_version": 1,
+                    "completed": true,
+                    "base": { "text": "should_sync_todo", "completed": false, "labels": [] }
+                }]
+            }"#.to_string(),
+        );
+        let res = create_app(
+            Config::default(),
+            todo_repo,
+            label_repo,
+            backup_repo,
+            maintenance_repo,
+            access_log_repo,
+            UserRepositoryForMemory::new(),
+        ).oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::OK, res.status());
+        let bytes = hyper::body::to_bytes(res.into_body()).await.unwrap();
+        let body = String::from_utf8(bytes.to_vec()).unwrap();
+        let result: SyncResultResponse = serde_json::from_str(&body)
+            .unwrap_or_else(|_| panic!("cannot convert SyncResult instance. body: {:?}", body));
+        assert!(result.applied.is_empty());
+        let conflict = result.conflicts.first().unwrap();
+        assert_eq!(Resolution::Merged, conflict.resolution);
+        assert_eq!("server_updated_todo", conflict.server.text);
+        assert!(conflict.server.completed);
+        assert_eq!(3, conflict.server.version);
+    }
+
+    #[tokio::test]
+    async fn should_sync_batch_idempotently() {
+        let todo_repo = TodoRepositoryForMemory::new();
+        let label_repo = LabelRepositoryForMemory::new();
+        let backup_repo = BackupRepositoryForMemory::new();
+        let maintenance_repo = MaintenanceRepositoryForMemory::new();
+        let access_log_repo = AccessLogRepositoryForMemory::new();
+        let body = r#"{
+            "mutations": [{
+                "type": "create",
+                "mutation_id": "7c9e6679-7425-40de-944b-e07fc1f90ae7",
+                "client_id": "16fd2706-8baf-433b-82eb-8c7fada847da",
+                "text": "should_sync_batch",
+                "labels": []
+            }]
+        }"#;
+        let app = create_app(
+            Config::default(),
+            todo_repo.clone(),
+            label_repo,
+            backup_repo,
+            maintenance_repo,
+            access_log_repo,
+            UserRepositoryForMemory::new(),
+        );
+
+        for _ in 0..2 {
+            let req = build_todo_req_with_json("/sync", Method::POST, body.to_string());
+            let res = app.clone().oneshot(req).await.unwrap();
+            assert_eq!(StatusCode::OK, res.status());
+            let bytes = hyper::body::to_bytes(res.into_body()).await.unwrap();
+            let result: SyncResultResponse = serde_json::from_slice(&bytes).unwrap();
+            assert_eq!(1, result.id_map.first().unwrap().id);
+        }
+        assert_eq!(1, todo_repo.all(TodoFilter::default(), Include::default()).await.unwrap().len());
+    }
+
+    #[tokio::test]
+    async fn should_not_delete_stale_todo_on_sync() {
+        let todo_repo = TodoRepositoryForMemory::new();
+        let todo = TodoFixture::new().text("should_not_delete_stale_todo").insert(&todo_repo).await;
+        todo_repo.update(todo.id, UpdateTodo::new(Some("edited on server".to_string()), None, None), None).await.unwrap();
+        let app = create_app(
+            Config::default(),
+            todo_repo.clone(),
+            LabelRepositoryForMemory::new(),
+            BackupRepositoryForMemory::new(),
+            MaintenanceRepositoryForMemory::new(),
+            AccessLogRepositoryForMemory::new(),
+            UserRepositoryForMemory::new(),
+        );
+
+        // 古い version での削除と、サーバー側に無い Todo の更新
+        let body = format!(
+            r#"{{
+                "mutations": [
+                    {{ "type": "delete", "mutation_id": "0e7c3a52-56d4-4a8e-a4f6-2b2f0f9c1d01", "id": {}, "base_version": {} }},
+                    {{ "type": "update", "mutation_id": "0e7c3a52-56d4-4a8e-a4f6-2b2f0f9c1d02", "id": 404, "base_version": 1, "completed": true }}
+                ]
+            }}"#,
+            todo.id, todo.version
+        );
+        let res = app.oneshot(build_todo_req_with_json("/sync", Method::POST, body)).await.unwrap();
+        assert_eq!(StatusCode::OK, res.status());
+        let bytes = hyper::body::to_bytes(res.into_body()).await.unwrap();
+        let result: SyncResultResponse = serde_json::from_slice(&bytes).unwrap();
+        assert!(result.deleted.is_empty());
+        assert_eq!(Resolution::ServerWins, result.conflicts[0].resolution);
+        assert_eq!(vec![404], result.missing);
+        assert!(todo_repo.find(todo.id).await.is_ok());
+    }
+
+    const TEST_ADMIN_TOKEN: &str = "test-admin-token";
+
+    fn admin_config() -> Config {
+        Config {
+            admin_token: Some(Secret::new(TEST_ADMIN_TOKEN)),
+            ..Config::default()
+        }
+    }
+
+    #[tokio::test]
+    async fn should_reject_backup_without_admin_token() {
+        let todo_repo = TodoRepositoryForMemory::new();
+        let label_repo = LabelRepositoryForMemory::new();
+        let backup_repo = BackupRepositoryForMemory::new();
+        let maintenance_repo = MaintenanceRepositoryForMemory::new();
+        let access_log_repo = AccessLogRepositoryForMemory::new();
+        let req = build_todo_req_with_empty(Method::GET, "/admin/backup");
+        let res = create_app(
+            admin_config(),
+            todo_repo,
+            label_repo,
+            backup_repo,
+            maintenance_repo,
+            access_log_repo,
+            UserRepositoryForMemory::new(),
+        ).oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::UNAUTHORIZED, res.status());
+    }
+
+    #[tokio::test]
+    async fn should_restore_and_backup() {
+        let todo_repo = TodoRepositoryForMemory::new();
+        let label_repo = LabelRepositoryForMemory::new();
+        let backup_repo = BackupRepositoryForMemory::new();
+        let maintenance_repo = MaintenanceRepositoryForMemory::new();
+        let access_log_repo = AccessLogRepositoryForMemory::new();
+        let app = create_app(
+            admin_config(),
+            todo_repo,
+            label_repo,
+            backup_repo,
+            maintenance_repo,
+            access_log_repo,
+            UserRepositoryForMemory::new(),
+        );
+        let dump = [
+            r#"{"kind":"header","format":"rust-webapp-backup","version":1}"#,
+            r#"{"kind":"label","id":1,"name":"label 1"}"#,
+            r#"{"kind":"todo","id":1,"text":"todo 1","completed":false,"version":1,"client_id":null,"client_key":null,"completed_at":null,"due_date":null,"priority":"medium","deleted_at":null}"#,
+            r#"{"kind":"todo_label","todo_id":1,"label_id":1}"#,
+        ];
+
+        let req = Request::builder()
+            .uri("/admin/restore")
+            .method(Method::POST)
+            .header(ADMIN_TOKEN_HEADER, TEST_ADMIN_TOKEN)
+            .body(Body::from(dump.join("\n")))
+            .unwrap();
+        let res = app.clone().oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::CREATED, res.status());
+        let bytes = hyper::body::to_bytes(res.into_body()).await.unwrap();
+        let summary: RestoreSummary = serde_json::from_slice(&bytes).unwrap();
+        assert_eq!(1, summary.todos);
+
+        // ボディの大きさでは弾かない。空でない DB には戻せないので 409
+        let req = Request::builder()
+            .uri("/admin/restore")
+            .method(Method::POST)
+            .header(ADMIN_TOKEN_HEADER, TEST_ADMIN_TOKEN)
+            .body(Body::from(dump.join("\n") + &"\n".repeat(3 * 1024 * 1024)))
+            .unwrap();
+        let res = app.clone().oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::CONFLICT, res.status());
+
+        let req = Request::builder()
+            .uri("/admin/backup")
+            .header(ADMIN_TOKEN_HEADER, TEST_ADMIN_TOKEN)
+            .body(Body::empty())
+            .unwrap();
+        let res = app.oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::OK, res.status());
+        let bytes = hyper::body::to_bytes(res.into_body()).await.unwrap();
+        let body = String::from_utf8(bytes.to_vec()).unwrap();
+        assert_eq!(dump.join("\n") + "\n", body);
+    }
+
+    #[tokio::test]
+    async fn should_run_maintenance_job() {
+        let todo_repo = TodoRepositoryForMemory::new();
+        let label_repo = LabelRepositoryForMemory::new();
+        let backup_repo = BackupRepositoryForMemory::new();
+        let maintenance_repo = MaintenanceRepositoryForMemory::new();
+        let access_log_repo = AccessLogRepositoryForMemory::new();
+        let app = create_app(
+            admin_config(),
+            todo_repo,
+            label_repo,
+            backup_repo,
+            maintenance_repo,
+            access_log_repo,
+            UserRepositoryForMemory::new(),
+        );
+
+        let req = Request::builder()
+            .uri("/admin/maintenance/purge?retention_days=7")
+            .method(Method::POST)
+            .header(ADMIN_TOKEN_HEADER, TEST_ADMIN_TOKEN)
+            .body(Body::empty())
+            .unwrap();
+        let res = app.clone().oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::ACCEPTED, res.status());
+        let bytes = hyper::body::to_bytes(res.into_body()).await.unwrap();
+        let job: Job = serde_json::from_slice(&bytes).unwrap();
+
+        // 完了するまでポーリングする
+        let mut status = job.status;
+        for _ in 0..100 {
+            let req = Request::builder()
+                .uri(format!("/admin/jobs/{}", job.id))
+                .header(ADMIN_TOKEN_HEADER, TEST_ADMIN_TOKEN)
+                .body(Body::empty())
+                .unwrap();
+            let res = app.clone().oneshot(req).await.unwrap();
+            assert_eq!(StatusCode::OK, res.status());
+            let bytes = hyper::body::to_bytes(res.into_body()).await.unwrap();
+            status = serde_json::from_slice::<Job>(&bytes).unwrap().status;
+            if status != JobStatus::Running {
+                break;
+            }
+            tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+        }
+        assert_eq!(JobStatus::Succeeded, status);
+    }
+
+    #[tokio::test]
+    async fn should_import_trello_board_as_job() {
+        let todo_repo = TodoRepositoryForMemory::new();
+        let app = create_app(
+            Config::default(),
+            todo_repo.clone(),
+            LabelRepositoryForMemory::new(),
+            BackupRepositoryForMemory::new(),
+            MaintenanceRepositoryForMemory::new(),
+            AccessLogRepositoryForMemory::new(),
+            UserRepositoryForMemory::new(),
+        );
+        let board = r#"{
+            "lists": [{"id": "l1", "name": "Doing", "closed": false}],
+            "cards": [
+                {"id": "c1", "name": "fix login", "closed": false, "idList": "l1", "idLabels": [], "dueComplete": true},
+                {"id": "c2", "name": "", "closed": false, "idList": "l1", "idLabels": []}
+            ]
+        }"#;
+        let req = build_todo_req_with_json("/import/trello", Method::POST, "{".to_string());
+        let res = app.clone().oneshot(req).await.unwrap();
+        assert_eq!("INVALID_JSON", res_to_error_code(res).await);
+
+        let req = build_todo_req_with_json("/import/trello", Method::POST, board.to_string());
+        let res = app.clone().oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::ACCEPTED, res.status());
+        let location = res.headers()[header::LOCATION].to_str().unwrap().to_string();
+
+        let mut job: Option<Job> = None;
+        for _ in 0..100 {
+            let res = app.clone().oneshot(build_todo_req_with_empty(Method::GET, &location)).await.unwrap();
+            assert_eq!(StatusCode::OK, res.status());
+            let bytes = hyper::body::to_bytes(res.into_body()).await.unwrap();
+            let polled: Job = serde_json::from_slice(&bytes).unwrap();
+            if polled.status != JobStatus::Running {
+                job = Some(polled);
+                break;
+            }
+            tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+        }
+        let job = job.expect("import did not finish");
+        assert_eq!(JobStatus::Succeeded, job.status);
+        let report: ImportReport = serde_json::from_value(job.result.unwrap()).unwrap();
+        assert_eq!((1, 1), (report.labels_created, report.todos_created));
+        assert_eq!(1, report.skipped.len());
+
+        let todos = todo_repo.all(TodoFilter::default(), Include::default()).await.unwrap();
+        assert_eq!(vec![("fix login".to_string(), true)], todos.into_iter().map(|todo| (todo.text, todo.completed)).collect::<Vec<_>>());
+    }
+
+    #[tokio::test]
+    async fn should_snapshot_and_restore_into_schema() {
+        async fn admin_json(app: &Router, method: Method, uri: &str) -> (StatusCode, serde_json::Value) {
+            let req = Request::builder()
+                .uri(uri)
+                .method(method)
+                .header(ADMIN_TOKEN_HEADER, TEST_ADMIN_TOKEN)
+                .body(Body::empty())
+                .unwrap();
+            let res = app.clone().oneshot(req).await.unwrap();
+            let status = res.status();
+            let bytes = hyper::body::to_bytes(res.into_body()).await.unwrap();
+            (status, serde_json::from_slice(&bytes).unwrap_or_default())
+        }
+        async fn wait_for(app: &Router, job: serde_json::Value) -> Job {
+            for _ in 0..100 {
+                let (_, polled) = admin_json(app, Method::GET, &format!("/admin/jobs/{}", job["id"].as_str().unwrap())).await;
+                let polled: Job = serde_json::from_value(polled).unwrap();
+                if polled.status != JobStatus::Running {
+                    return polled;
+                }
+                tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+            }
+            panic!("job did not finish");
+        }
+
+        // 置き場所が無ければ無効
+        let app = create_app(
+            admin_config(),
+            TodoRepositoryForMemory::new(),
+            LabelRepositoryForMemory::new(),
+            BackupRepositoryForMemory::new(),
+            MaintenanceRepositoryForMemory::new(),
+            AccessLogRepositoryForMemory::new(),
+            UserRepositoryForMemory::new(),
+        );
+        assert_eq!(StatusCode::NOT_FOUND, admin_json(&app, Method::POST, "/admin/snapshots").await.0);
+
+        let backup_repo = BackupRepositoryForMemory::new();
+        backup_repo
+            .restore(vec![BackupRecord::header(), BackupRecord::Label(Label::new(1, "home".to_string()))])
+            .await
+            .unwrap();
+        let root = std::env::temp_dir().join(format!("snapshots-{}", uuid::Uuid::new_v4()));
+        let app = AppBuilder::new(
+            TodoRepositoryForMemory::new(),
+            LabelRepositoryForMemory::new(),
+            backup_repo.clone(),
+            MaintenanceRepositoryForMemory::new(),
+            AccessLogRepositoryForMemory::new(),
+            UserRepositoryForMemory::new(),
+        )
+        .with_config(admin_config())
+        .with_snapshot_store(Arc::new(blob::FsBlobStore::new(&root)))
+        .build();
+
+        let (status, job) = admin_json(&app, Method::POST, "/admin/snapshots").await;
+        assert_eq!(StatusCode::ACCEPTED, status);
+        let job = wait_for(&app, job).await;
+        assert_eq!(JobStatus::Succeeded, job.status);
+        let manifest: SnapshotManifest = serde_json::from_value(job.result.unwrap()).unwrap();
+        assert_eq!(1, manifest.tables[0].rows);
+
+        let (status, snapshots) = admin_json(&app, Method::GET, "/admin/snapshots").await;
+        assert_eq!(StatusCode::OK, status);
+        assert_eq!(vec![manifest.clone()], serde_json::from_value::<Vec<SnapshotManifest>>(snapshots).unwrap());
+
+        let restore = format!("/admin/snapshots/{}/restore", manifest.id);
+        assert_eq!(StatusCode::BAD_REQUEST, admin_json(&app, Method::POST, &format!("{}?schema=public", restore)).await.0);
+        let missing = "/admin/snapshots/missing/restore?schema=before_migration";
+        assert_eq!(StatusCode::NOT_FOUND, admin_json(&app, Method::POST, missing).await.0);
+        let (status, job) = admin_json(&app, Method::POST, &format!("{}?schema=before_migration", restore)).await;
+        assert_eq!(StatusCode::ACCEPTED, status);
+        assert_eq!(JobStatus::Succeeded, wait_for(&app, job).await.status);
+        assert_eq!(
+            Some(vec![BackupRecord::Label(Label::new(1, "home".to_string()))]),
+            backup_repo.restored("before_migration")
+        );
+
+        // 同じ schema には二度復元しない
+        let (_, job) = admin_json(&app, Method::POST, &format!("{}?schema=before_migration", restore)).await;
+        assert_eq!(JobStatus::Failed, wait_for(&app, job).await.status);
+        tokio::fs::remove_dir_all(root).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn should_report_cache_stats() {
+        let cache = QueryCache::new(CacheConfig { max_capacity: 10, ttl: Duration::from_secs(60) });
+        cache.labels(async { Ok(vec![]) }).await.unwrap();
+        cache.labels(async { Ok(vec![]) }).await.unwrap();
+        let app = AppBuilder::new(
+            TodoRepositoryForMemory::new(),
+            LabelRepositoryForMemory::new(),
+            BackupRepositoryForMemory::new(),
+            MaintenanceRepositoryForMemory::new(),
+            AccessLogRepositoryForMemory::new(),
+            UserRepositoryForMemory::new(),
+        )
+        .with_query_cache(cache)
+        .with_config(admin_config())
+        .build();
+
+        let req = Request::builder()
+            .uri("/admin/cache")
+            .header(ADMIN_TOKEN_HEADER, TEST_ADMIN_TOKEN)
+            .body(Body::empty())
+            .unwrap();
+        let res = app.oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::OK, res.status());
+        let bytes = hyper::body::to_bytes(res.into_body()).await.unwrap();
+        let stats: CacheStats = serde_json::from_slice(&bytes).unwrap();
+        assert!(stats.enabled);
+        assert_eq!((stats.hits, stats.misses), (1, 1));
+        assert_eq!(stats.hit_rate, 0.5);
+    }
+
+    #[tokio::test]
+    async fn should_serve_atom_feed_with_feed_token() {
+        let todo_repo = TodoRepositoryForMemory::new();
+        todo_repo.create(CreateTodo::new("milk & <eggs>".to_string(), vec![1])).await.unwrap();
+        todo_repo.create(CreateTodo::new("unlabeled".to_string(), vec![])).await.unwrap();
+        let build = |feed_token: Option<&str>| {
+            AppBuilder::new(
+                todo_repo.clone(),
+                LabelRepositoryForMemory::new(),
+                BackupRepositoryForMemory::new(),
+                MaintenanceRepositoryForMemory::new(),
+                AccessLogRepositoryForMemory::new(),
+                UserRepositoryForMemory::new(),
+            )
+            .with_config(Config {
+                feed_token: feed_token.map(Secret::new),
+                public_base_url: Some("https://api.example.com".to_string()),
+                ..Config::default()
+            })
+            .build()
+        };
+        let get = |uri: &str| Request::builder().uri(uri).body(Body::empty()).unwrap();
+
+        // トークンが未設定ならフィード自体が無い
+        let res = build(None).oneshot(get("/feeds/todos.atom?token=secret")).await.unwrap();
+        assert_eq!(StatusCode::NOT_FOUND, res.status());
+        for uri in ["/feeds/todos.atom", "/feeds/todos.atom?token=wrong"] {
+            let res = build(Some("secret")).oneshot(get(uri)).await.unwrap();
+            assert_eq!(StatusCode::UNAUTHORIZED, res.status());
+        }
+
+        let res = build(Some("secret"))
+            .oneshot(get("/feeds/todos.atom?token=secret&label=1"))
+            .await
+            .unwrap();
+        assert_eq!(StatusCode::OK, res.status());
+        assert_eq!("application/atom+xml; charset=utf-8", res.headers()[header::CONTENT_TYPE]);
+        let bytes = hyper::body::to_bytes(res.into_body()).await.unwrap();
+        let xml = String::from_utf8(bytes.to_vec()).unwrap();
+        assert!(xml.contains("<title>milk &amp; &lt;eggs&gt;</title>"), "{}", xml);
+        assert!(xml.contains("<link rel=\"self\" href=\"https://api.example.com/feeds/todos.atom?label=1\"/>"));
+        assert!(!xml.contains("unlabeled"));
+        assert!(!xml.contains("secret"));
+    }
+
+    #[tokio::test]
+    async fn should_scope_atom_feed_to_user() {
+        const SECRET: &str = "0123456789abcdef0123456789abcdef";
+        let todo_repo = TodoRepositoryForMemory::new();
+        todo_repo.create(CreateTodo::new("alice todo".to_string(), vec![]).with_owner(1)).await.unwrap();
+        todo_repo.create(CreateTodo::new("bob todo".to_string(), vec![]).with_owner(2)).await.unwrap();
+        let app = AppBuilder::new(
+            todo_repo,
+            LabelRepositoryForMemory::new(),
+            BackupRepositoryForMemory::new(),
+            MaintenanceRepositoryForMemory::new(),
+            AccessLogRepositoryForMemory::new(),
+            UserRepositoryForMemory::new(),
+        )
+        .with_config(Config {
+            feed_token: Some(Secret::new("secret")),
+            jwt_secret: Some(Secret::new(SECRET)),
+            public_base_url: Some("https://api.example.com".to_string()),
+            ..Config::default()
+        })
+        .build();
+        let get = |uri: &str| Request::builder().uri(uri).body(Body::empty()).unwrap();
+
+        // 認証が有効なら、FEED_TOKEN だけでは全員の Todo は読めない
+        let res = app.clone().oneshot(get("/feeds/todos.atom?token=secret")).await.unwrap();
+        assert_eq!(StatusCode::UNAUTHORIZED, res.status());
+        assert_eq!(StatusCode::UNAUTHORIZED, app.clone().oneshot(get("/me/feed")).await.unwrap().status());
+
+        let login = handlers::auth::AuthConfig::new(Some(SECRET.to_string()), Duration::from_secs(60))
+            .issue(2, None)
+            .unwrap()
+            .unwrap();
+        let mut req = get("/me/feed");
+        req.headers_mut()
+            .insert(header::AUTHORIZATION, format!("Bearer {}", login.token).parse().unwrap());
+        let res = app.clone().oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::OK, res.status());
+        let bytes = hyper::body::to_bytes(res.into_body()).await.unwrap();
+        let feed: FeedUrlResponse = serde_json::from_slice(&bytes).unwrap();
+        let uri = feed.url.strip_prefix("https://api.example.com").unwrap();
+        assert!(uri.starts_with("/feeds/todos.atom?token="), "{}", uri);
+
+        // bob のフィードに alice の Todo は出てこない
+        let res = app.clone().oneshot(get(uri)).await.unwrap();
+        assert_eq!(StatusCode::OK, res.status());
+        let bytes = hyper::body::to_bytes(res.into_body()).await.unwrap();
+        let xml = String::from_utf8(bytes.to_vec()).unwrap();
+        assert!(xml.contains("<title>bob todo</title>"), "{}", xml);
+        assert!(!xml.contains("alice"), "{}", xml);
+
+        // ログインのトークンはフィードには使えない
+        let uri = format!("/feeds/todos.atom?token={}", login.token);
+        assert_eq!(StatusCode::UNAUTHORIZED, app.oneshot(get(&uri)).await.unwrap().status());
+    }
+
+    #[tokio::test]
+    async fn should_sync_todos_over_caldav() {
+        let build = |enabled| {
+            AppBuilder::new(
+                TodoRepositoryForMemory::new(),
+                LabelRepositoryForMemory::new(),
+                BackupRepositoryForMemory::new(),
+                MaintenanceRepositoryForMemory::new(),
+                AccessLogRepositoryForMemory::new(),
+                UserRepositoryForMemory::new(),
+            )
+            .with_config(Config { caldav_enabled: enabled, ..Config::default() })
+            .build()
+        };
+        let dav = |method: &str, uri: &str, body: &str| {
+            Request::builder()
+                .method(method)
+                .uri(uri)
+                .header("depth", "1")
+                .body(Body::from(body.to_string()))
+                .unwrap()
+        };
+        let body_of = |res: Response| async move {
+            String::from_utf8(hyper::body::to_bytes(res.into_body()).await.unwrap().to_vec()).unwrap()
+        };
+        let res = build(false).oneshot(dav("PROPFIND", "/dav/todos/", "")).await.unwrap();
+        assert_eq!(StatusCode::NOT_FOUND, res.status());
+
+        let app = build(true);
+        let res = app.clone().oneshot(dav("PROPFIND", "/dav/", "")).await.unwrap();
+        assert_eq!(StatusCode::MULTI_STATUS, res.status());
+        assert!(body_of(res).await.contains("<c:calendar-home-set><d:href>/dav/</d:href></c:calendar-home-set>"));
+        let vtodo = "BEGIN:VCALENDAR\r\nBEGIN:VTODO\r\nUID:abc\r\nSUMMARY:buy milk\r\nEND:VTODO\r\nEND:VCALENDAR\r\n";
+        let res = app.clone().oneshot(dav("PUT", "/dav/todos/abc.ics", vtodo)).await.unwrap();
+        assert_eq!(StatusCode::CREATED, res.status());
+        let href = res.headers()[header::LOCATION].to_str().unwrap().to_string();
+
+        let res = app.clone().oneshot(dav("PROPFIND", "/dav/todos/", "")).await.unwrap();
+        assert_eq!(StatusCode::MULTI_STATUS, res.status());
+        let xml = body_of(res).await;
+        assert!(xml.contains("<c:comp name=\"VTODO\"/>"), "{}", xml);
+        assert!(xml.contains(&format!("<d:href>{}</d:href>", href)));
+
+        let completed = vtodo.replace("SUMMARY:buy milk", "SUMMARY:buy milk\r\nSTATUS:COMPLETED");
+        let mut req = dav("PUT", &href, &completed);
+        req.headers_mut().insert(IF_MATCH, "\"1\"".parse().unwrap());
+        let res = app.clone().oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::NO_CONTENT, res.status());
+
+        let multiget = format!("<c:calendar-multiget xmlns:d=\"DAV:\"><d:href>{}</d:href></c:calendar-multiget>", href);
+        let res = app.clone().oneshot(dav("REPORT", "/dav/todos/", &multiget)).await.unwrap();
+        let xml = body_of(res).await;
+        assert!(xml.contains("SUMMARY:buy milk"), "{}", xml);
+        assert!(xml.contains("STATUS:COMPLETED"));
+
+        let res = app.clone().oneshot(dav("DELETE", &href, "")).await.unwrap();
+        assert_eq!(StatusCode::NO_CONTENT, res.status());
+        let res = app.oneshot(dav("GET", &href, "")).await.unwrap();
+        assert_eq!(StatusCode::NOT_FOUND, res.status());
+    }
+
+    #[tokio::test]
+    async fn should_serve_query_plans_only_when_enabled() {
+        for (enabled, expected) in [(true, StatusCode::OK), (false, StatusCode::NOT_FOUND)] {
+            let app = AppBuilder::new(
+                TodoRepositoryForMemory::new(),
+                LabelRepositoryForMemory::new(),
+                BackupRepositoryForMemory::new(),
+                MaintenanceRepositoryForMemory::new(),
+                AccessLogRepositoryForMemory::new(),
+                UserRepositoryForMemory::new(),
+            )
+            .with_config(Config { query_plans_enabled: enabled, ..admin_config() })
+            .build();
+            let req = Request::builder()
+                .uri("/admin/query-plans")
+                .header(ADMIN_TOKEN_HEADER, TEST_ADMIN_TOKEN)
+                .body(Body::empty())
+                .unwrap();
+            let res = app.oneshot(req).await.unwrap();
+            assert_eq!(expected, res.status());
+        }
+    }
+
+    #[tokio::test]
+    async fn should_export_metrics_by_route() {
+        let app = AppBuilder::new(
+            TodoRepositoryForMemory::new(),
+            LabelRepositoryForMemory::new(),
+            BackupRepositoryForMemory::new(),
+            MaintenanceRepositoryForMemory::new(),
+            AccessLogRepositoryForMemory::new(),
+            UserRepositoryForMemory::new(),
+        )
+        .with_config(admin_config())
+        .build();
+        for id in [1, 2] {
+            let req = build_todo_req_with_empty(Method::GET, &format!("/todos/{}", id));
+            app.clone().oneshot(req).await.unwrap();
+        }
+        let req = build_todo_req_with_empty(Method::GET, "/nowhere");
+        app.clone().oneshot(req).await.unwrap();
+
+        let req = Request::builder()
+            .uri("/admin/metrics")
+            .header(ADMIN_TOKEN_HEADER, TEST_ADMIN_TOKEN)
+            .body(Body::empty())
+            .unwrap();
+        let res = app.oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::OK, res.status());
+        let bytes = hyper::body::to_bytes(res.into_body()).await.unwrap();
+        let text = String::from_utf8(bytes.to_vec()).unwrap();
+        // ID ごとではなくルートのパターンでまとめる
+        assert!(
+            text.contains(r#"http_requests_total{method="GET",route="/todos/:id",status="4xx"} 2"#),
+            "{}",
+            text
+        );
+        assert!(text.contains(r#"http_requests_total{method="GET",route="unmatched",status="4xx"} 1"#));
+    }
+
+    #[tokio::test]
+    async fn should_record_access_log() {
+        let todo_repo = TodoRepositoryForMemory::new();
+        let label_repo = LabelRepositoryForMemory::new();
+        let backup_repo = BackupRepositoryForMemory::new();
+        let maintenance_repo = MaintenanceRepositoryForMemory::new();
+        let access_log_repo = AccessLogRepositoryForMemory::new();
+        let app = create_app(
+            Config { access_log_enabled: true, ..admin_config() },
+            todo_repo,
+            label_repo,
+            backup_repo,
+            maintenance_repo,
+            access_log_repo,
+            UserRepositoryForMemory::new(),
+        );
+
+        let req = Request::builder()
+            .uri("/todos/404")
+            .header("x-request-id", "should_record_access_log")
+            .body(Body::empty())
+            .unwrap();
+        let res = app.clone().oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::NOT_FOUND, res.status());
+        assert_eq!("should_record_access_log", res.headers()["x-request-id"]);
+
+        // バッチで書き込まれるまで待つ
+        let mut entries = vec![];
+        for _ in 0..40 {
+            tokio::time::sleep(std::time::Duration::from_millis(50)).await;
+            let req = Request::builder()
+                .uri("/admin/access-log?request_id=should_record_access_log")
+                .header(ADMIN_TOKEN_HEADER, TEST_ADMIN_TOKEN)
+                .body(Body::empty())
+                .unwrap();
+            let res = app.clone().oneshot(req).await.unwrap();
+            let bytes = hyper::body::to_bytes(res.into_body()).await.unwrap();
+            entries = serde_json::from_slice::<Vec<AccessLogEntry>>(&bytes).unwrap();
+            if !entries.is_empty() {
+                break;
+            }
+        }
+        let entry = entries.first().expect("access log was not recorded");
+        assert_eq!("GET", entry.method);
+        assert_eq!("/todos/404", entry.path);
+        assert_eq!(404, entry.status);
+    }
+
+    #[tokio::test]
+    async fn should_reject_todo_over_quota() {
+        let quota = Quota {
+            max_todos: Some(1),
+            max_labels: None,
+        };
+        let todo_repo = TodoRepositoryForMemory::new().with_quota(quota);
+        let label_repo = LabelRepositoryForMemory::new();
+        let backup_repo = BackupRepositoryForMemory::new();
+        let maintenance_repo = MaintenanceRepositoryForMemory::new();
+        let access_log_repo = AccessLogRepositoryForMemory::new();
+        TodoFixture::new().text("should_reject_todo_over_quota").insert(&todo_repo).await;
+        let req = build_todo_req_with_json(
+            "/todos",
+            Method::POST,
+            TodoFixture::new().text("over quota").to_json(),
+        );
+        let res = create_app(
+            Config::default(),
+            todo_repo,
+            label_repo,
+            backup_repo,
+            maintenance_repo,
+            access_log_repo,
+            UserRepositoryForMemory::new(),
+        ).oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::TOO_MANY_REQUESTS, res.status());
+        let bytes = hyper::body::to_bytes(res.into_body()).await.unwrap();
+        let body: serde_json::Value = serde_json::from_slice(&bytes).unwrap();
+        assert_eq!("todos", body["resource"]);
+        assert_eq!(1, body["limit"]);
+    }
+
+    async fn add_custom_header<B>(req: Request<B>, next: axum::middleware::Next<B>) -> axum::response::Response {
+        let mut res = next.run(req).await;
+        res.headers_mut().insert("x-custom", header::HeaderValue::from_static("1"));
+        res
+    }
+
+    async fn count_todos(
+        State(TodoRepo(repo)): State<TodoRepo<TodoRepositoryForMemory>>,
+    ) -> String {
+        repo.all(TodoFilter::default(), Include::default()).await.unwrap().len().to_string()
+    }
+
+    #[tokio::test]
+    async fn should_add_custom_routes_and_layers() {
+        let todo_repo = TodoRepositoryForMemory::new();
+        let label_repo = LabelRepositoryForMemory::new();
+        let backup_repo = BackupRepositoryForMemory::new();
+        let maintenance_repo = MaintenanceRepositoryForMemory::new();
+        let access_log_repo = AccessLogRepositoryForMemory::new();
+        TodoFixture::new().text("should_add_custom_routes_and_layers").insert(&todo_repo).await;
+        let app = AppBuilder::new(
+            todo_repo,
+            label_repo,
+            backup_repo,
+            maintenance_repo,
+            access_log_repo,
+            UserRepositoryForMemory::new(),
+        )
+        .with_routes(Router::new().route("/custom/count", get(count_todos)))
+        .with_layer(axum::middleware::from_fn(add_custom_header))
+        .build();
+
+        let req = build_todo_req_with_empty(Method::GET, "/custom/count");
+        let res = app.clone().oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::OK, res.status());
+        assert_eq!("1", res.headers()["x-custom"]);
+        let bytes = hyper::body::to_bytes(res.into_body()).await.unwrap();
+        assert_eq!(&bytes[..], b"1");
+
+        let req = build_todo_req_with_empty(Method::GET, "/todos");
+        let res = app.oneshot(req).await.unwrap();
+        assert_eq!("1", res.headers()["x-custom"]);
+    }
+
+    #[tokio::test]
+    async fn should_map_repository_errors_with_mock() {
+        let mut todo_repo = MockTodoRepository::new();
+        todo_repo
+            .expect_find()
+            .with(eq(42))
+            .times(1)
+            .returning(|id| Err(RepositoryError::NotFound(id).into()));
+        todo_repo
+            .expect_delete()
+            .with(eq(1), eq(Some(3)))
+            .times(1)
+            .returning(|_, _| Err(RepositoryError::PreconditionFailed.into()));
+        let mut label_repo = MockLabelRepository::new();
+        label_repo
+            .expect_create()
+            .times(1)
+            .returning(|_| Err(RepositoryError::Duplicate(7).into()));
+        let backup_repo = BackupRepositoryForMemory::new();
+        let maintenance_repo = MaintenanceRepositoryForMemory::new();
+        let access_log_repo = AccessLogRepositoryForMemory::new();
+        let app = create_app(
+            Config::default(),
+            todo_repo,
+            label_repo,
+            backup_repo,
+            maintenance_repo,
+            access_log_repo,
+            UserRepositoryForMemory::new(),
+        );
+
+        let req = build_todo_req_with_empty(Method::GET, "/todos/42");
+        let res = app.clone().oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::NOT_FOUND, res.status());
+        assert_eq!("TODO_NOT_FOUND", res_to_error_code(res).await);
+
+        let mut req = build_todo_req_with_empty(Method::DELETE, "/todos/1");
+        req.headers_mut().insert(header::IF_MATCH, header::HeaderValue::from_static("\"3\""));
+        let res = app.clone().oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::PRECONDITION_FAILED, res.status());
+        assert_eq!("PRECONDITION_FAILED", res_to_error_code(res).await);
+
+        let req = build_todo_req_with_json("/labels", Method::POST, LabelFixture::new().to_json());
+        let res = app.clone().oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::CONFLICT, res.status());
+        assert_eq!("LABEL_DUPLICATE", res_to_error_code(res).await);
+
+        // ステータスだけで返しているエラーにも汎用の code が付く
+        let req = build_todo_req_with_empty(Method::GET, "/todos?format=xml");
+        let res = app.oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::BAD_REQUEST, res.status());
+        assert_eq!("BAD_REQUEST", res_to_error_code(res).await);
+    }
+
+    #[tokio::test]
+    async fn should_validate_label_ids() {
+        let mut todo_repo = MockTodoRepository::new();
+        todo_repo
+            .expect_create()
+            .times(1)
+            .returning(|_| Err(RepositoryError::LabelsNotFound(vec![404, 405]).into()));
+        let app = create_app(
+            Config::default(),
+            todo_repo,
+            LabelRepositoryForMemory::new(),
+            BackupRepositoryForMemory::new(),
+            MaintenanceRepositoryForMemory::new(),
+            AccessLogRepositoryForMemory::new(),
+            UserRepositoryForMemory::new(),
+        );
+
+        // 形がおかしいものはリポジトリに渡す前に弾く
+        let too_many = format!("{:?}", (1..=21).collect::<Vec<_>>());
+        for labels in ["[1, 1]", "[0]", "[-1]", too_many.as_str()] {
+            let body = format!(r#"{{"text": "should_validate_label_ids", "labels": {}}}"#, labels);
+            let res = app.clone().oneshot(build_todo_req_with_json("/todos", Method::POST, body)).await.unwrap();
+            assert_eq!(StatusCode::UNPROCESSABLE_ENTITY, res.status(), "labels: {}", labels);
+        }
+
+        let body = r#"{"text": "should_validate_label_ids", "labels": [1, 404, 405]}"#.to_string();
+        let res = app.oneshot(build_todo_req_with_json("/todos", Method::POST, body)).await.unwrap();
+        assert_eq!(StatusCode::UNPROCESSABLE_ENTITY, res.status());
+        let bytes = hyper::body::to_bytes(res.into_body()).await.unwrap();
+        let body: serde_json::Value = serde_json::from_slice(&bytes).unwrap();
+        assert_eq!(
+            serde_json::json!({"error": "labels not found", "code": "LABELS_NOT_FOUND", "label_ids": [404, 405]}),
+            body
+        );
+    }
+
+    #[tokio::test]
+    async fn should_report_database_health() {
+        let db_health = DbHealth::new(false);
+        let app = AppBuilder::new(
+            TodoRepositoryForMemory::new(),
+            LabelRepositoryForMemory::new(),
+            BackupRepositoryForMemory::new(),
+            MaintenanceRepositoryForMemory::new(),
+            AccessLogRepositoryForMemory::new(),
+            UserRepositoryForMemory::new(),
+        )
+        .with_db_health(db_health.clone())
+        .with_leadership(Leadership::new(false))
+        .build();
+
+        let req = build_todo_req_with_empty(Method::GET, "/health");
+        let res = app.oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::SERVICE_UNAVAILABLE, res.status());
+        let bytes = hyper::body::to_bytes(res.into_body()).await.unwrap();
+        let body: serde_json::Value = serde_json::from_slice(&bytes).unwrap();
+        assert_eq!("down", body["database"]);
+        assert_eq!(false, body["leader"]);
+    }
+
+    // 件数が増えても GET /todos のステートメント数が変わらないこと (Todo ごとにラベルを引くような N+1 を防ぐ)
+    #[cfg(feature = "database-test")]
+    #[tokio::test]
+    async fn should_list_todos_with_bounded_statements() {
+        use crate::db::test_utils::count_statements;
+        use crate::repositories::{
+            backup::BackupRepositoryForDb,
+            label::{CreateLabel, LabelRepositoryForDb},
+            todo::{CreateTodo, TodoRepositoryForDb},
+        };
+        use sqlx::PgPool;
+
+        dotenv::dotenv().ok();
+        let database_url = std::env::var("DATABASE_URL").expect("undefined [DATABASE_URL]");
+        let pool = PgPool::connect(&database_url).await.expect("failed to connect database");
+        let todo_repo = TodoRepositoryForDb::new(pool.clone());
+        let label_repo = LabelRepositoryForDb::new(pool.clone());
+        let app = create_app(
+            Config::default(),
+            todo_repo.clone(),
+            label_repo.clone(),
+            BackupRepositoryForDb::new(pool.clone()),
+            MaintenanceRepositoryForMemory::new(),
+            AccessLogRepositoryForMemory::new(),
+            UserRepositoryForMemory::new(),
+        );
+        let label = label_repo
+            .create(CreateLabel::new(format!("n+1 {}", uuid::Uuid::new_v4())))
+            .await
+            .unwrap();
+
+        let mut counts = vec![];
+        for round in 0..2 {
+            // 2 回目は 1 回目より Todo もラベルの付いた行も多い
+            for i in 0..(round * 5 + 1) {
+                let todo = CreateTodo::new(format!("[n+1] todo {}", i), vec![label.id]);
+                todo_repo.create(todo).await.unwrap();
+            }
+            for uri in ["/todos", "/todos?page=1&per_page=50"] {
+                let req = build_todo_req_with_empty(Method::GET, uri);
+                let (res, statements) = count_statements(app.clone().oneshot(req)).await;
+                assert_eq!(StatusCode::OK, res.unwrap().status());
+                counts.push((round, uri, statements));
+            }
+        }
+        for (round, uri, statements) in &counts {
+            assert!((1..=3).contains(statements), "{} ran {} statements in round {}", uri, statements, round);
+        }
+        assert_eq!(counts[0].2, counts[2].2, "{:?}", counts);
+        assert_eq!(counts[1].2, counts[3].2, "{:?}", counts);
+    }
+}
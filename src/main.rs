@@ -1,241 +1,178 @@
-mod handlers;
-mod repositories;
-
-use axum::{
-    extract::Extension,
-    routing::{delete, get, post},
-    Router,
-};
-use crate::repositories::{
-    label::{LabelRepository, LabelRepositoryForDb},
-    todo::{TodoRepository, TodoRepositoryForDb},
-};
-use handlers::{
-    label::{all_label, create_label, delete_label},
-    todo::{all_todo, create_todo, delete_todo, find_todo, update_todo},
+use rust_web::{
+    client::Client,
+    config::{Config, LogFormat},
+    db::{self, DbHealth},
+    events::{AuditLogger, BroadcastEventBus, SharedEventBus},
+    leader::{self, LeaderElection, Leadership},
+    middleware,
+    repositories::{
+        access_log::AccessLogRepositoryForDb,
+        backup::BackupRepositoryForDb,
+        label::{self, LabelRepositoryForDb},
+        maintenance::{MaintenanceRepository, MaintenanceRepositoryForDb},
+        todo::{self, TodoRepositoryForDb},
+        user::UserRepositoryForDb,
+    },
+    server, smoke, systemd, AppBuilder,
 };
-use hyper::header::CONTENT_TYPE;
-use std::net::SocketAddr;
-use std::{env, sync::Arc};
-use sqlx::PgPool;
-use tower_http::cors::{Any, CorsLayer, AllowOrigin};
+use std::{env, process, sync::Arc};
 use dotenv::dotenv;
+use tracing_subscriber::EnvFilter;
+
+// rust_web smoke-test --base-url URL [--token TOKEN]
+// デプロイ先に対して CRUD を一通り流し、失敗したら 1 で終わる。設定ファイルや DB は使わない
+async fn smoke_test(args: &[String]) -> i32 {
+    tracing_subscriber::fmt().with_env_filter(EnvFilter::new("info")).init();
+    let mut base_url = None;
+    let mut token = None;
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--base-url" => base_url = args.next().cloned(),
+            "--token" => token = args.next().cloned(),
+            _ => {
+                eprintln!("unknown argument: {}", arg);
+                return 2;
+            }
+        }
+    }
+    let base_url = match base_url {
+        Some(base_url) => base_url,
+        None => {
+            eprintln!("usage: rust_web smoke-test --base-url URL [--token TOKEN]");
+            return 2;
+        }
+    };
+    let mut client = Client::new(base_url);
+    if let Some(token) = token {
+        client = client.with_token(token);
+    }
+    match smoke::run(&client).await {
+        Ok(()) => 0,
+        Err(e) => {
+            tracing::error!("smoke test failed at [{}]: {:#}", e, e.root_cause());
+            1
+        }
+    }
+}
 
 #[tokio::main]
 async fn main() {
-    let log_level = env::var("RUST_LOG").unwrap_or("info".to_string());
-    env::set_var("RUST_LOG", log_level);
-    tracing_subscriber::fmt::init();
     dotenv().ok();
-
-    // let repo = TodoRepositoryForMemory::new();
-    let database_url = env::var("DATABASE_URL").expect("undefined [DATABASE_URL]");
-    tracing::debug!("startconnect database...");
-    let pool = PgPool::connect(database_url.as_str())
-        .await
-        .expect(&format!("cannot connect to database: [{}]", database_url));
-    let app = create_app(
-        TodoRepositoryForDb::new(pool.clone()),
-        LabelRepositoryForDb::new(pool.clone()),
-    );
-    let addr = SocketAddr::from(([127, 0, 0, 1], 3000));
-
-    tracing::debug!("listening on {}", addr);
-
-    axum::Server::bind(&addr)
-        .serve(app.into_make_service())
-        .await
-        .unwrap();
-}
-
-fn  create_app<Todo: TodoRepository, Label: LabelRepository>(
-    todo_repository: Todo,
-    label_repository: Label,
-) -> Router {
-    Router::new()
-        .route("/", get(root))
-        .route("/todos", post(create_todo::<Todo>).get(all_todo::<Todo>))
-        .route(
-            "/todos/:id",
-            get(find_todo::<Todo>)
-                .delete(delete_todo::<Todo>)
-                .patch(update_todo::<Todo>)
-        )
-        .route(
-            "/labels",
-            post(create_label::<Label>).get(all_label::<Label>)
-        )
-        .route("/labels/:id", delete(delete_label::<Label>))
-        .layer(Extension(Arc::new(todo_repository)))
-        .layer(Extension(Arc::new(label_repository)))
-        .layer(
-            CorsLayer::new()
-                .allow_origin(AllowOrigin::exact("http://localhost:3001".parse().unwrap()))
-                .allow_methods(Any)
-                .allow_headers(vec![CONTENT_TYPE])
-        )
-}
-
-async fn root() -> &'static str {
-    "hello world"
-}
-
-#[cfg(test)]
-mod test {
-    use super::*;
-    use crate::repositories::todo::{test_utils::TodoRepositoryForMemory, CreateTodo, TodoEntity};
-    use crate::repositories::label::{test_utils::LabelRepositoryForMemory};
-    use axum::response::Response;
-    use axum::{
-        body::Body,
-        http::{header, Method, Request, StatusCode},
-    };
-    use tower::ServiceExt;
-
-    fn build_todo_req_with_json(path: &str, method: Method, json_body: String) -> Request<Body> {
-        Request::builder()
-            .uri(path)
-            .method(method)
-            .header(header::CONTENT_TYPE, mime::APPLICATION_JSON.to_string())
-            .body(Body::from(json_body))
-            .unwrap()
+    let args: Vec<String> = env::args().skip(1).collect();
+    if args.first().map(String::as_str) == Some("smoke-test") {
+        process::exit(smoke_test(&args[1..]).await);
     }
-
-    fn build_todo_req_with_empty(method: Method, path: &str) -> Request<Body> {
-        Request::builder()
-            .uri(path)
-            .method(method)
-            .body(Body::empty())
-            .unwrap()
-    }
-
-    async fn res_to_todo(res: Response) -> TodoEntity {
-        let bytes = hyper::body::to_bytes(res.into_body()).await.unwrap();
-        let body: String = String::from_utf8(bytes.to_vec()).unwrap();
-        let todo: TodoEntity = serde_json::from_str(&body)
-            .expect(&format!("cannot convert Todo instance. body: {}", body));
-        todo
+    // ログの形式も設定で決まるので、読み込みに失敗したら標準エラーに出して終わる
+    let config = Config::load().unwrap_or_else(|e| {
+        eprintln!("invalid configuration: {}", e);
+        process::exit(1);
+    });
+    let log_level = env::var("RUST_LOG").unwrap_or("info".to_string());
+    env::set_var("RUST_LOG", log_level);
+    let subscriber = tracing_subscriber::fmt().with_env_filter(EnvFilter::from_default_env());
+    match config.log_format {
+        LogFormat::Pretty => subscriber.init(),
+        LogFormat::Json => subscriber.json().init(),
     }
+    tracing::info!("starting with {:?}", config);
+    let _sentry = middleware::error_report::init(config.sentry_dsn(), config.sentry_environment.clone());
 
-    #[tokio::test]
-    async fn should_return_hello_world() {
-        let todo_repo = TodoRepositoryForMemory::new();
-        let label_repo = LabelRepositoryForMemory::new();
-        let req = Request::builder().uri("/").body(Body::empty()).unwrap();
-        let router = create_app(todo_repo, label_repo);
-        let res = router.oneshot(req).await.unwrap();
-        let bytes = hyper::body::to_bytes(res.into_body()).await.unwrap();
-        let body = String::from_utf8(bytes.to_vec()).unwrap();
-        assert_eq!(body, "hello world");
+    // let repo = TodoRepositoryForMemory::new();
+    // DB が落ちていても起動し、復旧すれば自動で繋ぎ直す。状態は /health で確認できる
+    let warmup_connections = config.db_pool_warmup_connections;
+    let pool = db::connect_lazy(config.database_url.as_str(), config.db_max_connections, warmup_connections)
+        .unwrap_or_else(|e| panic!("invalid [DATABASE_URL]: {}", e));
+    // 起動時点で繋がらなければ、最初のチェックでエラーログが出る
+    let db_health = DbHealth::new(true);
+    if let Err(e) = db::wait_until_ready(&pool, &db_health, config.startup_retry()).await {
+        tracing::error!("{}", e);
+        process::exit(1);
     }
-
-    #[tokio::test]
-    async fn should_created_todo() {
-        let expected = TodoEntity::new(1, "should_return_created_todo".to_string());
-
-        let todo_repo = TodoRepositoryForMemory::new();
-        let label_repo = LabelRepositoryForMemory::new();
-        let req = build_todo_req_with_json(
-            "/todos",
-            Method::POST,
-            r#"{
-                "text": "should_return_created_todo",
-                "labels": []
-
-            }"#.to_string(),
-        );
-        let res = create_app(
-                todo_repo,
-                label_repo,
-            )
-            .oneshot(req)
-            .await
-            .expect("failed create todo");
-
-        let todo = res_to_todo(res).await;
-        assert_eq!(expected, todo);
+    if warmup_connections > 0 {
+        // ウォームアップは最適化なので、失敗してもそのまま起動する
+        let statements = [todo::HOT_STATEMENTS, label::HOT_STATEMENTS].concat();
+        if let Err(e) = db::warm_up(&pool, warmup_connections, &statements).await {
+            tracing::warn!("failed to warm up the connection pool: {}", e);
+        }
     }
-
-    #[tokio::test]
-    async fn should_find_todo() {
-        let expected = TodoEntity::new(1, "should_find_todo".to_string());
-
-        let todo_repo = TodoRepositoryForMemory::new();
-        let label_repo = LabelRepositoryForMemory::new();
-        todo_repo.create(CreateTodo::new(
-            "should_find_todo".to_string(),
-            vec![],
-        )).await.expect("cannot create todo");
-        let req = build_todo_req_with_empty(Method::GET, "/todos/1");
-        let res = create_app(
-            todo_repo,
-            label_repo,
-        ).oneshot(req).await.unwrap();
-        let todo = res_to_todo(res).await;
-        assert_eq!(expected, todo);
+    db::spawn_health_check(pool.clone(), db_health.clone(), config.health_check_interval());
+    systemd::spawn_watchdog(pool.clone(), db_health.clone());
+    // 定期ジョブは leader だけが動かす。他のインスタンスは leader が落ちたときに引き継ぐ
+    let leadership = Leadership::new(false);
+    let election = LeaderElection::new(pool.clone(), "scheduler", config.leader_lease());
+    election.clone().spawn(leadership.clone());
+    let maintenance = MaintenanceRepositoryForDb::new(pool.clone());
+    if let Some(interval) = config.purge_interval() {
+        let retention_days = config.purge_retention_days;
+        let maintenance = maintenance.clone();
+        leader::spawn_periodic(leadership.clone(), "purge_expired", interval, move || {
+            let maintenance = maintenance.clone();
+            async move {
+                let report = maintenance.purge_expired(retention_days).await?;
+                tracing::info!("purged {} expired rows", report.purged_rows);
+                Ok(())
+            }
+        });
     }
-
-    #[tokio::test]
-    async fn should_get_all_todos() {
-        let expected = TodoEntity::new(1, "should_get_all_todos".to_string());
-
-        let todo_repo = TodoRepositoryForMemory::new();
-        let label_repo = LabelRepositoryForMemory::new();
-        todo_repo.create(CreateTodo::new(
-            "should_get_all_todos".to_string(),
-            vec![],
-        )).await.expect("cannot create todo");
-        let req = build_todo_req_with_empty(Method::GET, "/todos");
-        let res = create_app(
-            todo_repo,
-            label_repo,
-        ).oneshot(req).await.unwrap();
-        let bytes = hyper::body::to_bytes(res.into_body()).await.unwrap();
-        let body = String::from_utf8(bytes.to_vec()).unwrap();
-        let todo: Vec<TodoEntity> = serde_json::from_str(&body)
-            .expect(&format!("cannot convert Todo instance. body: {:?}", body));
-        assert_eq!(vec![expected], todo);
+    let quota = config.quota();
+    let cache = config.query_cache();
+    let metrics = config.metrics();
+    // 購読者は起動時に登録しておく
+    let events = BroadcastEventBus::default();
+    events.spawn_subscriber(AuditLogger);
+    let events: SharedEventBus = Arc::new(events);
+    let app = AppBuilder::new(
+        TodoRepositoryForDb::new(pool.clone())
+            .with_quota(quota)
+            .with_cache(cache.clone())
+            .with_metrics(metrics.clone())
+            .with_events(events.clone()),
+        LabelRepositoryForDb::new(pool.clone())
+            .with_quota(quota)
+            .with_cache(cache.clone())
+            .with_metrics(metrics.clone())
+            .with_events(events),
+        BackupRepositoryForDb::new(pool.clone()).with_cache(cache.clone()),
+        maintenance,
+        AccessLogRepositoryForDb::new(pool.clone()),
+        UserRepositoryForDb::new(pool.clone()),
+    )
+    .with_db_health(db_health)
+    .with_leadership(leadership)
+    .with_query_cache(cache)
+    .with_metrics(metrics)
+    .with_config(config.clone())
+    .build();
+    let listener = server::listener(config.listen_addr, config.reuse_port).unwrap_or_else(|e| {
+        tracing::error!("failed to listen on {}: {}", config.listen_addr, e);
+        process::exit(1);
+    });
+
+    systemd::notify_ready();
+
+    let served = axum::Server::from_tcp(listener)
+        .unwrap()
+        .serve(app.into_make_service())
+        .with_graceful_shutdown(async {
+            server::shutdown_signal().await;
+            systemd::notify_stopping();
+        })
+        .await;
+    // サーバーがエラーで止まっても、leader の返上とプールの後始末はする
+    match &served {
+        Ok(()) => tracing::info!("all in-flight requests are done"),
+        Err(e) => tracing::error!("server stopped with an error: {}", e),
     }
-
-    #[tokio::test]
-    async fn should_update_todo() {
-        let expected = TodoEntity::new(1, "should_update_todo".to_string());
-
-        let todo_repo = TodoRepositoryForMemory::new();
-        let label_repo = LabelRepositoryForMemory::new();
-        todo_repo.create(CreateTodo::new(
-            "before_update_todo".to_string(),
-            vec![],
-        )).await.expect("cannot create todo");
-        let req = build_todo_req_with_json(
-            "/todos/1",
-            Method::PATCH,
-            r#"{
-                "text": "should_update_todo",
-                "completed": false
-            }"#.to_string(),
-        );
-        let res = create_app(
-            todo_repo,
-            label_repo,
-        ).oneshot(req).await.unwrap();
-        let todo = res_to_todo(res).await;
-        assert_eq!(expected, todo);
+    // 入れ替え先のプロセスがリースの期限切れを待たずに leader になれるようにする
+    if let Err(e) = election.resign().await {
+        tracing::warn!("failed to resign the leadership: {}", e);
     }
-
-    #[tokio::test]
-    async fn should_delete_todo() {
-        let todo_repo = TodoRepositoryForMemory::new();
-        let label_repo = LabelRepositoryForMemory::new();
-        todo_repo.create(CreateTodo::new(
-            "should_delete_todo".to_string(),
-            vec![],
-        )).await.expect("cannot create todo");
-        let req = build_todo_req_with_empty(Method::DELETE, "/todos/1");
-        let res = create_app(
-            todo_repo,
-            label_repo,
-        ).oneshot(req).await.unwrap();
-        assert_eq!(StatusCode::NO_CONTENT, res.status());
+    // DB 側にコネクションを残さないよう、接続を閉じ終わるまで待つ
+    tracing::info!("closing the database pool");
+    pool.close().await;
+    tracing::info!("shutdown complete");
+    if served.is_err() {
+        process::exit(1);
     }
 }
@@ -0,0 +1,88 @@
+use std::collections::BTreeMap;
+
+use crate::repositories::todo::TodoEntity;
+
+// ラベルの付いていない Todo をまとめる見出し
+pub const UNLABELED_HEADING: &str = "No label";
+
+// Wiki やノートアプリに貼り付ける用のチェックリスト。ラベルごとの節に分け、節ごとに 1 つの文字列で返す。
+// 複数のラベルが付いた Todo はそれぞれの節に出す。節の中は作成順 (id 順)
+pub fn checklist_sections(todos: &[TodoEntity]) -> Vec<String> {
+    let mut todos: Vec<&TodoEntity> = todos.iter().collect();
+    todos.sort_by_key(|todo| todo.id);
+    let mut groups: BTreeMap<&str, Vec<&TodoEntity>> = BTreeMap::new();
+    let mut unlabeled = vec![];
+    for todo in todos {
+        if todo.labels.is_empty() {
+            unlabeled.push(todo);
+        }
+        for label in &todo.labels {
+            groups.entry(label.name.as_str()).or_default().push(todo);
+        }
+    }
+
+    let mut sections = vec!["# Todos\n".to_string()];
+    sections.extend(groups.into_iter().map(|(name, todos)| section(name, &todos)));
+    if !unlabeled.is_empty() {
+        sections.push(section(UNLABELED_HEADING, &unlabeled));
+    }
+    sections
+}
+
+fn section(heading: &str, todos: &[&TodoEntity]) -> String {
+    let mut markdown = format!("\n## {}\n\n", escape(heading));
+    for todo in todos {
+        let mark = if todo.completed { 'x' } else { ' ' };
+        markdown.push_str(&format!("- [{}] {}\n", mark, escape(&todo.text)));
+    }
+    markdown
+}
+
+// 本文がリンクや見出しとして解釈されないように、記法に使う記号を escape する。
+// 改行が入るとリストが崩れるので空白にする
+fn escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '\\' | '`' | '*' | '_' | '[' | ']' | '<' | '>' | '#' | '|' | '!' => {
+                escaped.push('\\');
+                escaped.push(c);
+            }
+            '\r' | '\n' => escaped.push(' '),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::repositories::label::Label;
+
+    #[test]
+    fn should_group_checklist_by_label() {
+        let home = Label::new(1, "home".to_string());
+        let work = Label::new(2, "work".to_string());
+        let todos = vec![
+            TodoEntity {
+                completed: true,
+                labels: vec![work.clone(), home.clone()],
+                ..TodoEntity::new(1, "pay *all* the [bills]".to_string())
+            },
+            TodoEntity {
+                labels: vec![work],
+                ..TodoEntity::new(2, "write\nreport".to_string())
+            },
+            TodoEntity::new(3, "# not a heading".to_string()),
+        ];
+        assert_eq!(
+            "# Todos\n\
+             \n## home\n\n- [x] pay \\*all\\* the \\[bills\\]\n\
+             \n## work\n\n- [x] pay \\*all\\* the \\[bills\\]\n- [ ] write report\n\
+             \n## No label\n\n- [ ] \\# not a heading\n",
+            checklist_sections(&todos).concat()
+        );
+        assert_eq!(vec!["# Todos\n"], checklist_sections(&[]));
+    }
+}
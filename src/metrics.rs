@@ -0,0 +1,162 @@
+use axum::http::{Method, StatusCode};
+use prometheus::{Encoder, HistogramOpts, HistogramVec, IntCounterVec, Opts, Registry, TextEncoder};
+use std::{
+    fmt,
+    time::{Duration, Instant},
+};
+
+// ルートに当たらなかったリクエスト (404 など) はパスごとに分けず、まとめて数える
+pub const UNMATCHED_ROUTE: &str = "unmatched";
+
+// Prometheus の text 形式で /admin/metrics から返すメトリクス。clone しても同じものを指す
+#[derive(Clone)]
+pub struct Metrics {
+    registry: Registry,
+    requests: IntCounterVec,
+    request_duration: HistogramVec,
+    query_duration: HistogramVec,
+    // これより遅いクエリはログに出す。None なら出さない
+    slow_query_threshold: Option<Duration>,
+}
+
+impl fmt::Debug for Metrics {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Metrics")
+            .field("slow_query_threshold", &self.slow_query_threshold)
+            .finish_non_exhaustive()
+    }
+}
+
+impl Default for Metrics {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Metrics {
+    pub fn new() -> Self {
+        let registry = Registry::new();
+        // ラベルはルートのパターン (/todos/:id など) にして、ID ごとに系列が増えないようにする
+        let labels = ["route", "method", "status"];
+        let requests = IntCounterVec::new(
+            Opts::new("http_requests_total", "Number of HTTP requests"),
+            &labels,
+        )
+        .unwrap();
+        let request_duration = HistogramVec::new(
+            HistogramOpts::new("http_request_duration_seconds", "HTTP request latency until the response headers"),
+            &labels,
+        )
+        .unwrap();
+        let query_duration = HistogramVec::new(
+            HistogramOpts::new("db_query_duration_seconds", "Repository query latency"),
+            &["query"],
+        )
+        .unwrap();
+        registry.register(Box::new(requests.clone())).unwrap();
+        registry.register(Box::new(request_duration.clone())).unwrap();
+        registry.register(Box::new(query_duration.clone())).unwrap();
+        Self {
+            registry,
+            requests,
+            request_duration,
+            query_duration,
+            slow_query_threshold: None,
+        }
+    }
+
+    pub fn with_slow_query_threshold(mut self, threshold: Option<Duration>) -> Self {
+        self.slow_query_threshold = threshold;
+        self
+    }
+
+    pub fn observe_request(&self, route: &str, method: &Method, status: StatusCode, elapsed: Duration) {
+        let labels = [route, method.as_str(), status_class(status)];
+        self.requests.with_label_values(&labels).inc();
+        self.request_duration
+            .with_label_values(&labels)
+            .observe(elapsed.as_secs_f64());
+    }
+
+    // 返した QueryTimer を drop するまでをクエリの時間として記録する。
+    // params はバインドした値の要約で、遅かったときのログにだけ使う (本文などの中身は入れない)
+    pub fn time_query(&self, name: &'static str, params: String) -> QueryTimer {
+        QueryTimer {
+            metrics: self.clone(),
+            name,
+            params,
+            started: Instant::now(),
+        }
+    }
+
+    pub fn render(&self) -> String {
+        let mut buffer = vec![];
+        TextEncoder::new()
+            .encode(&self.registry.gather(), &mut buffer)
+            .unwrap();
+        String::from_utf8(buffer).unwrap()
+    }
+}
+
+pub struct QueryTimer {
+    metrics: Metrics,
+    name: &'static str,
+    params: String,
+    started: Instant,
+}
+
+impl Drop for QueryTimer {
+    fn drop(&mut self) {
+        let elapsed = self.started.elapsed();
+        self.metrics
+            .query_duration
+            .with_label_values(&[self.name])
+            .observe(elapsed.as_secs_f64());
+        if self.metrics.slow_query_threshold.is_some_and(|threshold| elapsed >= threshold) {
+            tracing::warn!("slow query [{}] took {:?} ({})", self.name, elapsed, self.params);
+        }
+    }
+}
+
+// 200 と 201 などを分けずに 2xx としてまとめる
+fn status_class(status: StatusCode) -> &'static str {
+    match status.as_u16() {
+        100..=199 => "1xx",
+        200..=299 => "2xx",
+        300..=399 => "3xx",
+        400..=499 => "4xx",
+        _ => "5xx",
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn should_label_by_route_method_and_status_class() {
+        let metrics = Metrics::new();
+        metrics.observe_request("/todos/:id", &Method::PATCH, StatusCode::OK, Duration::from_millis(3));
+        metrics.observe_request("/todos/:id", &Method::PATCH, StatusCode::NO_CONTENT, Duration::from_millis(5));
+        metrics.observe_request("/todos/:id", &Method::GET, StatusCode::NOT_FOUND, Duration::from_millis(1));
+
+        let text = metrics.render();
+        assert!(
+            text.contains(r#"http_requests_total{method="PATCH",route="/todos/:id",status="2xx"} 2"#),
+            "{}",
+            text
+        );
+        assert!(text.contains(r#"http_requests_total{method="GET",route="/todos/:id",status="4xx"} 1"#));
+        assert!(text.contains(r#"http_request_duration_seconds_count{method="PATCH",route="/todos/:id",status="2xx"} 2"#));
+    }
+
+    #[test]
+    fn should_time_queries_by_name() {
+        let metrics = Metrics::new().with_slow_query_threshold(Some(Duration::ZERO));
+        drop(metrics.time_query("todos.find", "id=1".to_string()));
+        drop(metrics.time_query("todos.find", "id=2".to_string()));
+
+        let text = metrics.render();
+        assert!(text.contains(r#"db_query_duration_seconds_count{query="todos.find"} 2"#), "{}", text);
+    }
+}
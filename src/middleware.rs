@@ -0,0 +1,9 @@
+pub mod access_log;
+pub mod cache_control;
+pub mod catch_panic;
+pub mod error_body;
+pub mod error_report;
+pub mod load_shed;
+pub mod metrics;
+pub mod request_id;
+pub mod timeout;
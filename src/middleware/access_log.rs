@@ -0,0 +1,87 @@
+use axum::{
+    http::Request,
+    middleware::{self, Next},
+    response::IntoResponse,
+    Router,
+};
+use chrono::Utc;
+use std::time::{Duration, Instant};
+use tokio::sync::mpsc;
+use crate::repositories::access_log::{AccessLogEntry, AccessLogRepository};
+use super::request_id::request_id;
+
+const BATCH_SIZE: usize = 100;
+const FLUSH_INTERVAL: Duration = Duration::from_millis(500);
+const CHANNEL_CAPACITY: usize = 10_000;
+
+// 認証済みのハンドラがレスポンスの extensions に入れておくと、アクセスログに user_id が記録される
+#[derive(Debug, Clone, Copy)]
+pub struct LoggedUser(pub i32);
+
+// ログは channel 経由でバックグラウンドのタスクに渡し、まとめて書き込む
+fn spawn_writer<T: AccessLogRepository>(repo: T) -> mpsc::Sender<AccessLogEntry> {
+    let (tx, mut rx) = mpsc::channel::<AccessLogEntry>(CHANNEL_CAPACITY);
+    tokio::spawn(async move {
+        let mut buffer = Vec::with_capacity(BATCH_SIZE);
+        let mut interval = tokio::time::interval(FLUSH_INTERVAL);
+        loop {
+            tokio::select! {
+                entry = rx.recv() => match entry {
+                    Some(entry) => {
+                        buffer.push(entry);
+                        if buffer.len() < BATCH_SIZE {
+                            continue;
+                        }
+                    }
+                    // 送信側が全て drop されたら、残りを書き込んで終了
+                    None => {
+                        flush(&repo, &mut buffer).await;
+                        break;
+                    }
+                },
+                _ = interval.tick() => {}
+            }
+            flush(&repo, &mut buffer).await;
+        }
+    });
+    tx
+}
+
+async fn flush<T: AccessLogRepository>(repo: &T, buffer: &mut Vec<AccessLogEntry>) {
+    if buffer.is_empty() {
+        return;
+    }
+    if let Err(e) = repo.insert_batch(std::mem::take(buffer)).await {
+        tracing::error!("failed to write access log: {:?}", e);
+    }
+}
+
+pub fn layer<T: AccessLogRepository>(router: Router, repo: T) -> Router {
+    let sender = spawn_writer(repo);
+    router.layer(middleware::from_fn(move |req: Request<_>, next: Next<_>| {
+        let sender = sender.clone();
+        async move {
+            let started = Instant::now();
+            let method = req.method().to_string();
+            let path = req.uri().path().to_string();
+            let request_id = request_id(&req);
+
+            let res = next.run(req).await;
+
+            let entry = AccessLogEntry {
+                request_id,
+                method,
+                path,
+                status: res.status().as_u16() as i32,
+                latency_ms: started.elapsed().as_millis() as i64,
+                user_id: res.extensions().get::<LoggedUser>().map(|user| user.0),
+                created_at: Utc::now(),
+            };
+            // 書き込みが詰まっていてもリクエストは待たせない
+            if sender.try_send(entry).is_err() {
+                tracing::warn!("access log channel is full, dropping entry");
+            }
+            res.into_response()
+        }
+    }))
+}
@@ -0,0 +1,106 @@
+use axum::{
+    http::{
+        header::{self, HeaderValue},
+        Method, Request, StatusCode,
+    },
+    middleware::{self, Next},
+    Router,
+};
+
+const DEFAULT_LIST_MAX_AGE_SECS: u64 = 5;
+// CDN やプロキシにキャッシュさせてよい一覧系のエンドポイント
+const LIST_PATHS: [&str; 3] = ["/todos", "/todos/by-label", "/labels"];
+// 一覧のレスポンスを変えうるリクエストヘッダ (CORS と Accept による出し分け)
+const LIST_VARY: &str = "Accept, Origin";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CacheControl {
+    // 一覧を共有キャッシュに置いてよい秒数。0 なら毎回再検証させる
+    pub list_max_age: u64,
+}
+
+impl Default for CacheControl {
+    fn default() -> Self {
+        Self { list_max_age: DEFAULT_LIST_MAX_AGE_SECS }
+    }
+}
+
+impl CacheControl {
+    // (Cache-Control, Vary)
+    fn directives(&self, method: &Method, path: &str, status: StatusCode) -> (HeaderValue, Option<HeaderValue>) {
+        let readable = method == Method::GET || method == Method::HEAD;
+        // 更新系、エラー、死活監視や管理用 API の結果はどこにも残させない
+        let cacheable = status.is_success() || status == StatusCode::NOT_MODIFIED;
+        if !readable || !cacheable || path == "/health" || path.starts_with("/admin/") {
+            return (HeaderValue::from_static("no-store"), None);
+        }
+        if LIST_PATHS.contains(&path) && self.list_max_age > 0 {
+            let value = format!("public, max-age={}", self.list_max_age);
+            return (
+                HeaderValue::from_str(&value).unwrap(),
+                Some(HeaderValue::from_static(LIST_VARY)),
+            );
+        }
+        // 単体の取得は ETag で再検証させる
+        (HeaderValue::from_static("no-cache"), None)
+    }
+}
+
+// ハンドラが自分で Cache-Control を付けていればそのまま使う
+pub fn layer(router: Router, config: CacheControl) -> Router {
+    router.layer(middleware::from_fn(move |req: Request<_>, next: Next<_>| async move {
+        let method = req.method().clone();
+        let path = req.uri().path().to_string();
+        let mut res = next.run(req).await;
+        if !res.headers().contains_key(header::CACHE_CONTROL) {
+            let (cache_control, vary) = config.directives(&method, &path, res.status());
+            res.headers_mut().insert(header::CACHE_CONTROL, cache_control);
+            if let Some(vary) = vary {
+                res.headers_mut().append(header::VARY, vary);
+            }
+        }
+        res
+    }))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use axum::{body::Body, routing::get};
+    use tower::ServiceExt;
+
+    fn app(config: CacheControl) -> Router {
+        let router = Router::new()
+            .route("/todos", get(|| async { "[]" }).post(|| async { "{}" }))
+            .route("/todos/:id", get(|| async { "{}" }))
+            .route(
+                "/admin/jobs",
+                get(|| async { ([(header::CACHE_CONTROL, "private")], "[]") }),
+            );
+        layer(router, config)
+    }
+
+    async fn headers(config: CacheControl, method: Method, uri: &str) -> (Option<String>, Option<String>) {
+        let req = Request::builder().method(method).uri(uri).body(Body::empty()).unwrap();
+        let res = app(config).oneshot(req).await.unwrap();
+        let get = |name| res.headers().get(name).map(|value: &HeaderValue| value.to_str().unwrap().to_string());
+        (get(header::CACHE_CONTROL), get(header::VARY))
+    }
+
+    #[tokio::test]
+    async fn should_set_cache_headers_by_endpoint() {
+        let config = CacheControl { list_max_age: 10 };
+        assert_eq!(
+            headers(config, Method::GET, "/todos").await,
+            (Some("public, max-age=10".to_string()), Some(LIST_VARY.to_string()))
+        );
+        assert_eq!(headers(config, Method::POST, "/todos").await, (Some("no-store".to_string()), None));
+        assert_eq!(headers(config, Method::GET, "/todos/1").await, (Some("no-cache".to_string()), None));
+        assert_eq!(headers(config, Method::GET, "/missing").await, (Some("no-store".to_string()), None));
+        // ハンドラが付けたものは上書きしない
+        assert_eq!(headers(config, Method::GET, "/admin/jobs").await, (Some("private".to_string()), None));
+
+        let config = CacheControl { list_max_age: 0 };
+        assert_eq!(headers(config, Method::GET, "/todos").await, (Some("no-cache".to_string()), None));
+    }
+}
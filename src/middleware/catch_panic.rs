@@ -0,0 +1,85 @@
+use axum::{
+    body::{boxed, Body, BoxBody},
+    http::{header, Request, Response, StatusCode},
+    middleware::{self, Next},
+    Router,
+};
+use serde_json::json;
+use std::any::Any;
+use tower_http::catch_panic::CatchPanicLayer;
+use crate::error_code::ErrorCode;
+use super::request_id::request_id;
+
+tokio::task_local! {
+    // パニック時のレスポンスには Request を参照できないので、request id をタスクローカルに置いておく
+    static REQUEST_ID: Option<String>;
+}
+
+fn panic_message(err: &(dyn Any + Send)) -> &str {
+    if let Some(message) = err.downcast_ref::<&str>() {
+        message
+    } else if let Some(message) = err.downcast_ref::<String>() {
+        message
+    } else {
+        "unknown panic payload"
+    }
+}
+
+fn handle_panic(err: Box<dyn Any + Send + 'static>) -> Response<BoxBody> {
+    let request_id = REQUEST_ID.try_with(|id| id.clone()).ok().flatten();
+    tracing::error!(
+        request_id = request_id.as_deref().unwrap_or("-"),
+        "handler panicked: {}",
+        panic_message(&*err)
+    );
+
+    let body = json!({
+        "error": "internal server error",
+        "code": ErrorCode::InternalError,
+        "request_id": request_id,
+    });
+    Response::builder()
+        .status(StatusCode::INTERNAL_SERVER_ERROR)
+        .header(header::CONTENT_TYPE, mime::APPLICATION_JSON.to_string())
+        .body(boxed(Body::from(body.to_string())))
+        .unwrap()
+}
+
+// ハンドラがパニックしても接続を切らずに JSON の 500 を返す。
+// request id を参照するので request id の layer より内側に置く
+pub fn layer(router: Router) -> Router {
+    router
+        .layer(CatchPanicLayer::custom(handle_panic))
+        .layer(middleware::from_fn(|req: Request<_>, next: Next<_>| {
+            let id = request_id(&req);
+            REQUEST_ID.scope(id, next.run(req))
+        }))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::middleware::request_id;
+    use axum::routing::get;
+    use tower::ServiceExt;
+
+    async fn panic_handler() -> &'static str {
+        panic!("boom")
+    }
+
+    #[tokio::test]
+    async fn should_return_json_500_on_panic() {
+        let router = Router::new().route("/panic", get(panic_handler));
+        let app = request_id::layer(layer(router));
+        let req = Request::builder()
+            .uri("/panic")
+            .header("x-request-id", "should_return_json_500_on_panic")
+            .body(Body::empty())
+            .unwrap();
+        let res = app.oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::INTERNAL_SERVER_ERROR, res.status());
+        let bytes = hyper::body::to_bytes(res.into_body()).await.unwrap();
+        let body: serde_json::Value = serde_json::from_slice(&bytes).unwrap();
+        assert_eq!("should_return_json_500_on_panic", body["request_id"]);
+    }
+}
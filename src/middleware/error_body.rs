@@ -0,0 +1,81 @@
+use axum::{
+    body::{boxed, Body},
+    http::{header, HeaderValue, Request, Response},
+    middleware::{self, Next},
+    Router,
+};
+use serde_json::json;
+
+use crate::{error_code::ErrorCode, handlers::is_json_content_type};
+
+// ステータスだけ、あるいはテキストで返しているエラーも {"error", "code"} の JSON にそろえる。
+// code はステータスから決める汎用のもの。JSON で返しているエラーはハンドラ側で code を付けている
+pub fn layer(router: Router) -> Router {
+    router.layer(middleware::from_fn(|req: Request<_>, next: Next<_>| async move {
+        let res = next.run(req).await;
+        let is_error = res.status().is_client_error() || res.status().is_server_error();
+        let is_json = res
+            .headers()
+            .get(header::CONTENT_TYPE)
+            .and_then(|value| value.to_str().ok())
+            .is_some_and(is_json_content_type);
+        if !is_error || is_json {
+            return res;
+        }
+
+        let (mut parts, body) = res.into_parts();
+        let code = ErrorCode::from_status(parts.status);
+        // テキストのボディはエラーの説明なので、そのままメッセージにする
+        let message = match hyper::body::to_bytes(body).await {
+            Ok(bytes) if !bytes.is_empty() => String::from_utf8_lossy(&bytes).into_owned(),
+            _ => code.message().to_string(),
+        };
+        parts.headers.remove(header::CONTENT_LENGTH);
+        parts
+            .headers
+            .insert(header::CONTENT_TYPE, HeaderValue::from_static("application/json"));
+        let body = json!({"error": message, "code": code});
+        Response::from_parts(parts, boxed(Body::from(body.to_string())))
+    }))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use axum::{
+        http::{Method, StatusCode},
+        routing::get,
+    };
+    use tower::ServiceExt;
+
+    async fn send(method: Method, uri: &str) -> (StatusCode, serde_json::Value) {
+        let router = Router::new()
+            .route("/empty", get(|| async { StatusCode::NOT_FOUND }))
+            .route("/text", get(|| async { (StatusCode::CONFLICT, "database is not empty") }))
+            .route("/ok", get(|| async { "ok" }));
+        let req = Request::builder().method(method).uri(uri).body(Body::empty()).unwrap();
+        let res = layer(router).oneshot(req).await.unwrap();
+        let status = res.status();
+        let bytes = hyper::body::to_bytes(res.into_body()).await.unwrap();
+        (status, serde_json::from_slice(&bytes).unwrap_or(serde_json::Value::Null))
+    }
+
+    #[tokio::test]
+    async fn should_add_code_to_plain_errors() {
+        assert_eq!(
+            (StatusCode::NOT_FOUND, json!({"error": "not found", "code": "NOT_FOUND"})),
+            send(Method::GET, "/empty").await
+        );
+        assert_eq!(
+            (StatusCode::CONFLICT, json!({"error": "database is not empty", "code": "CONFLICT"})),
+            send(Method::GET, "/text").await
+        );
+        // axum が返す 405 にも付く
+        assert_eq!(
+            (StatusCode::METHOD_NOT_ALLOWED, json!({"error": "method not allowed", "code": "METHOD_NOT_ALLOWED"})),
+            send(Method::DELETE, "/ok").await
+        );
+        // 成功したレスポンスには触らない
+        assert_eq!((StatusCode::OK, serde_json::Value::Null), send(Method::GET, "/ok").await);
+    }
+}
@@ -0,0 +1,89 @@
+use axum::{
+    http::Request,
+    middleware::{self, Next},
+    response::IntoResponse,
+    Router,
+};
+use sentry::{protocol::Level, Hub, SentryFutureExt};
+use std::sync::Arc;
+use crate::repositories::RepositoryError;
+use super::{access_log::LoggedUser, load_shed::Shed, request_id::request_id};
+
+// DSN が設定されているときだけ Sentry に送る。
+// 戻り値の guard を drop すると送信待ちのイベントを flush するので、main の最後まで持っておく
+pub fn init(dsn: Option<&str>, environment: Option<String>) -> Option<sentry::ClientInitGuard> {
+    let dsn = dsn?;
+    Some(sentry::init((
+        dsn,
+        sentry::ClientOptions {
+            release: sentry::release_name!(),
+            environment: environment.map(Into::into),
+            ..Default::default()
+        },
+    )))
+}
+
+// リクエストごとに hub を分けて、パニックやハンドラ内で送ったエラーにもリクエストの情報が付くようにする。
+// 5xx を返したレスポンスもここで送る
+pub fn layer(router: Router) -> Router {
+    router.layer(middleware::from_fn(|req: Request<_>, next: Next<_>| async move {
+        let hub = Arc::new(Hub::new_from_top(Hub::current()));
+        let method = req.method().to_string();
+        let route = req.uri().path().to_string();
+        let request_id = request_id(&req);
+        hub.configure_scope(|scope| {
+            scope.set_tag("method", &method);
+            scope.set_tag("route", &route);
+            if let Some(request_id) = &request_id {
+                scope.set_tag("request_id", request_id);
+            }
+        });
+
+        let res = next.run(req).bind_hub(hub.clone()).await;
+
+        if res.status().is_server_error() && res.extensions().get::<Shed>().is_none() {
+            let user_id = res.extensions().get::<LoggedUser>().map(|user| user.0);
+            hub.with_scope(
+                |scope| {
+                    if let Some(user_id) = user_id {
+                        scope.set_user(Some(sentry::User {
+                            id: Some(user_id.to_string()),
+                            ..Default::default()
+                        }));
+                    }
+                },
+                || {
+                    let message = format!("{} {} responded {}", method, route, res.status());
+                    hub.capture_message(&message, Level::Error)
+                },
+            );
+        }
+        res.into_response()
+    }))
+}
+
+// 想定外のリポジトリエラーだけを送る。NotFound などクライアント起因のものは送らない
+pub fn capture_unexpected(e: &anyhow::Error) {
+    match e.downcast_ref::<RepositoryError>() {
+        None | Some(RepositoryError::Unexpected(_)) => {
+            sentry::integrations::anyhow::capture_anyhow(e);
+        }
+        Some(_) => {}
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn capture_only_unexpected_errors() {
+        let events = sentry::test::with_captured_events(|| {
+            capture_unexpected(&anyhow::anyhow!("connection reset"));
+            capture_unexpected(&RepositoryError::Unexpected("boom".to_string()).into());
+            capture_unexpected(&RepositoryError::NotFound(1).into());
+            capture_unexpected(&RepositoryError::PreconditionFailed.into());
+        });
+        assert_eq!(events.len(), 2);
+    }
+}
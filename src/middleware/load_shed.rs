@@ -0,0 +1,167 @@
+use axum::{
+    extract::MatchedPath,
+    http::{header, HeaderValue, Request, StatusCode},
+    middleware::{self, Next},
+    response::IntoResponse,
+    Json, Router,
+};
+use serde_json::json;
+use std::{collections::BTreeMap, sync::Arc, time::Duration};
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+
+use crate::error_code::ErrorCode;
+
+// 同時に処理するリクエスト数の上限。超えた分は待たせずに 503 で返して、DB のプールを使い切らないようにする
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ConcurrencyLimits {
+    // None なら全体では制限しない
+    pub global: Option<usize>,
+    // ルートのパターン (/todos/:id など) ごとの上限。メソッドは区別しない
+    pub routes: BTreeMap<String, usize>,
+    // 503 の Retry-After
+    pub retry_after: Duration,
+}
+
+impl Default for ConcurrencyLimits {
+    fn default() -> Self {
+        Self {
+            global: None,
+            routes: BTreeMap::new(),
+            retry_after: Duration::from_secs(1),
+        }
+    }
+}
+
+impl ConcurrencyLimits {
+    fn is_unlimited(&self) -> bool {
+        self.global.is_none() && self.routes.is_empty()
+    }
+}
+
+// 弾いたレスポンスの extensions に入れる。混んでいるときにエラー報告まで増やさないよう、Sentry には送らない
+#[derive(Debug, Clone, Copy)]
+pub struct Shed;
+
+fn try_acquire(semaphore: Option<&Arc<Semaphore>>) -> Result<Option<OwnedSemaphorePermit>, ()> {
+    match semaphore {
+        Some(semaphore) => semaphore.clone().try_acquire_owned().map(Some).map_err(|_| ()),
+        None => Ok(None),
+    }
+}
+
+// permit はレスポンスを返すまで持つ (ストリームで返すボディの送信中は数えない)
+pub fn layer(router: Router, limits: ConcurrencyLimits) -> Router {
+    if limits.is_unlimited() {
+        return router;
+    }
+    let global = limits.global.map(|limit| Arc::new(Semaphore::new(limit)));
+    let routes: Arc<BTreeMap<String, Arc<Semaphore>>> = Arc::new(
+        limits
+            .routes
+            .iter()
+            .map(|(route, limit)| (route.clone(), Arc::new(Semaphore::new(*limit))))
+            .collect(),
+    );
+    let retry_after = HeaderValue::from(limits.retry_after.as_secs());
+    router.layer(middleware::from_fn(move |req: Request<_>, next: Next<_>| {
+        let global = global.clone();
+        let routes = routes.clone();
+        let retry_after = retry_after.clone();
+        async move {
+            let route = req.extensions().get::<MatchedPath>().map(|path| path.as_str().to_string());
+            let permits = try_acquire(global.as_ref()).and_then(|global| {
+                let route = route.as_ref().and_then(|route| routes.get(route));
+                Ok((global, try_acquire(route)?))
+            });
+            let _permits = match permits {
+                Ok(permits) => permits,
+                Err(()) => {
+                    tracing::warn!("shedding request to {}", route.as_deref().unwrap_or(req.uri().path()));
+                    let mut res = (
+                        StatusCode::SERVICE_UNAVAILABLE,
+                        [(header::RETRY_AFTER, retry_after)],
+                        Json(json!({"error": "server is busy, retry later", "code": ErrorCode::ServerBusy})),
+                    )
+                        .into_response();
+                    res.extensions_mut().insert(Shed);
+                    return res;
+                }
+            };
+            next.run(req).await.into_response()
+        }
+    }))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use axum::{body::Body, routing::get};
+    use tokio::sync::Notify;
+    use tower::ServiceExt;
+
+    // /slow は release されるまで返らない。entered は /slow に入ったら通知される
+    fn app(limits: ConcurrencyLimits, entered: Arc<Notify>, release: Arc<Notify>) -> Router {
+        let router = Router::new()
+            .route(
+                "/slow/:id",
+                get(move || async move {
+                    entered.notify_one();
+                    release.notified().await;
+                    "done"
+                }),
+            )
+            .route("/fast", get(|| async { "fast" }));
+        layer(router, limits)
+    }
+
+    async fn status(app: Router, uri: &str) -> (StatusCode, Option<HeaderValue>) {
+        let req = Request::builder().uri(uri).body(Body::empty()).unwrap();
+        let res = app.oneshot(req).await.unwrap();
+        (res.status(), res.headers().get(header::RETRY_AFTER).cloned())
+    }
+
+    #[tokio::test]
+    async fn should_shed_requests_over_route_limit() {
+        let (entered, release) = (Arc::new(Notify::new()), Arc::new(Notify::new()));
+        let limits = ConcurrencyLimits {
+            routes: BTreeMap::from([("/slow/:id".to_string(), 1)]),
+            retry_after: Duration::from_secs(3),
+            ..ConcurrencyLimits::default()
+        };
+        let app = app(limits, entered.clone(), release.clone());
+
+        let pending = tokio::spawn(status(app.clone(), "/slow/1"));
+        entered.notified().await;
+        // パスが違っても同じルートなら数える
+        assert_eq!(
+            (StatusCode::SERVICE_UNAVAILABLE, Some(HeaderValue::from(3))),
+            status(app.clone(), "/slow/2").await
+        );
+        assert_eq!(StatusCode::OK, status(app.clone(), "/fast").await.0);
+
+        release.notify_one();
+        assert_eq!(StatusCode::OK, pending.await.unwrap().0);
+        // 終わったら permit は返る
+        let pending = tokio::spawn(status(app.clone(), "/slow/3"));
+        entered.notified().await;
+        release.notify_one();
+        assert_eq!(StatusCode::OK, pending.await.unwrap().0);
+    }
+
+    #[tokio::test]
+    async fn should_shed_requests_over_global_limit() {
+        let (entered, release) = (Arc::new(Notify::new()), Arc::new(Notify::new()));
+        let limits = ConcurrencyLimits {
+            global: Some(1),
+            ..ConcurrencyLimits::default()
+        };
+        let app = app(limits, entered.clone(), release.clone());
+
+        let pending = tokio::spawn(status(app.clone(), "/slow/1"));
+        entered.notified().await;
+        assert_eq!(StatusCode::SERVICE_UNAVAILABLE, status(app.clone(), "/fast").await.0);
+        release.notify_one();
+        assert_eq!(StatusCode::OK, pending.await.unwrap().0);
+        assert_eq!(StatusCode::OK, status(app.clone(), "/fast").await.0);
+    }
+}
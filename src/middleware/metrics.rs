@@ -0,0 +1,28 @@
+use axum::{
+    extract::MatchedPath,
+    http::Request,
+    middleware::{self, Next},
+    Router,
+};
+use std::time::Instant;
+
+use crate::metrics::{Metrics, UNMATCHED_ROUTE};
+
+// レスポンスを返すまでの時間を、ルートのパターン・メソッド・ステータスの系統ごとに記録する
+pub fn layer(router: Router, metrics: Metrics) -> Router {
+    router.layer(middleware::from_fn(move |req: Request<_>, next: Next<_>| {
+        let metrics = metrics.clone();
+        async move {
+            let route = req
+                .extensions()
+                .get::<MatchedPath>()
+                .map(|path| path.as_str().to_string())
+                .unwrap_or_else(|| UNMATCHED_ROUTE.to_string());
+            let method = req.method().clone();
+            let started = Instant::now();
+            let res = next.run(req).await;
+            metrics.observe_request(&route, &method, res.status(), started.elapsed());
+            res
+        }
+    }))
+}
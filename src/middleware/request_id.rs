@@ -0,0 +1,16 @@
+use axum::{http::Request, Router};
+use tower_http::request_id::{MakeRequestUuid, PropagateRequestIdLayer, RequestId, SetRequestIdLayer};
+
+// リクエストに x-request-id が無ければ UUID を振り、レスポンスにも同じ値を返す
+pub fn layer(router: Router) -> Router {
+    router
+        .layer(PropagateRequestIdLayer::x_request_id())
+        .layer(SetRequestIdLayer::x_request_id(MakeRequestUuid))
+}
+
+pub fn request_id<B>(req: &Request<B>) -> Option<String> {
+    req.extensions()
+        .get::<RequestId>()
+        .and_then(|id| id.header_value().to_str().ok())
+        .map(|id| id.to_string())
+}
@@ -0,0 +1,142 @@
+use axum::{
+    extract::MatchedPath,
+    http::{Method, Request, StatusCode},
+    middleware::{self, Next},
+    response::IntoResponse,
+    Json, Router,
+};
+use serde_json::json;
+use std::{collections::BTreeSet, sync::Arc, time::Duration};
+
+use crate::error_code::ErrorCode;
+
+// ルートのグループごとのタイムアウト。読み込みは短く、インポート・エクスポートのような重い処理は長くする
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RouteTimeouts {
+    // GET / HEAD
+    pub read: Duration,
+    // それ以外のメソッド
+    pub write: Duration,
+    // long_routes に入っているルート。メソッドは区別しない
+    pub long: Duration,
+    pub long_routes: BTreeSet<String>,
+}
+
+impl Default for RouteTimeouts {
+    fn default() -> Self {
+        Self {
+            read: Duration::from_secs(5),
+            write: Duration::from_secs(10),
+            long: Duration::from_secs(300),
+            long_routes: BTreeSet::from(["/admin/backup".to_string(), "/admin/restore".to_string()]),
+        }
+    }
+}
+
+impl RouteTimeouts {
+    fn budget(&self, route: Option<&str>, method: &Method) -> Duration {
+        if route.is_some_and(|route| self.long_routes.contains(route)) {
+            self.long
+        } else if method == Method::GET || method == Method::HEAD {
+            self.read
+        } else {
+            self.write
+        }
+    }
+}
+
+// 時間切れになったら handler の future を drop して、実行中のリポジトリの処理ごと打ち切る。
+// 測るのはレスポンスを返すまでで、ストリームで返すボディの送信中は数えない
+pub fn layer(router: Router, timeouts: RouteTimeouts) -> Router {
+    let timeouts = Arc::new(timeouts);
+    router.layer(middleware::from_fn(move |req: Request<_>, next: Next<_>| {
+        let timeouts = timeouts.clone();
+        async move {
+            let route = req.extensions().get::<MatchedPath>().map(|path| path.as_str().to_string());
+            let budget = timeouts.budget(route.as_deref(), req.method());
+            let path = route.unwrap_or_else(|| req.uri().path().to_string());
+            match tokio::time::timeout(budget, next.run(req)).await {
+                Ok(res) => res.into_response(),
+                Err(_) => {
+                    tracing::warn!("request to {} timed out after {:?}", path, budget);
+                    (
+                        StatusCode::GATEWAY_TIMEOUT,
+                        Json(json!({
+                            "error": "request timed out",
+                            "code": ErrorCode::RequestTimeout,
+                            "timeout_ms": budget.as_millis() as u64,
+                        })),
+                    )
+                        .into_response()
+                }
+            }
+        }
+    }))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use axum::{body::Body, routing::get};
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use tower::ServiceExt;
+
+    // 最後まで走りきったら finished を立てる
+    async fn slow(finished: Arc<AtomicBool>) -> &'static str {
+        tokio::time::sleep(Duration::from_millis(200)).await;
+        finished.store(true, Ordering::SeqCst);
+        "done"
+    }
+
+    fn app(finished: Arc<AtomicBool>) -> Router {
+        let timeouts = RouteTimeouts {
+            read: Duration::from_millis(50),
+            write: Duration::from_secs(1),
+            long: Duration::from_secs(1),
+            long_routes: BTreeSet::from(["/export".to_string()]),
+        };
+        let read = finished.clone();
+        let export = finished.clone();
+        let router = Router::new()
+            .route(
+                "/todos/:id",
+                get(move || slow(read.clone())).post(move || slow(finished.clone())),
+            )
+            .route("/export", get(move || slow(export.clone())));
+        layer(router, timeouts)
+    }
+
+    async fn send(app: Router, method: Method, uri: &str) -> (StatusCode, String) {
+        let req = Request::builder().method(method).uri(uri).body(Body::empty()).unwrap();
+        let res = app.oneshot(req).await.unwrap();
+        let status = res.status();
+        let bytes = hyper::body::to_bytes(res.into_body()).await.unwrap();
+        (status, String::from_utf8(bytes.to_vec()).unwrap())
+    }
+
+    #[tokio::test]
+    async fn should_time_out_and_cancel_slow_reads() {
+        let finished = Arc::new(AtomicBool::new(false));
+        let (status, body) = send(app(finished.clone()), Method::GET, "/todos/1").await;
+        assert_eq!(StatusCode::GATEWAY_TIMEOUT, status);
+        let body: serde_json::Value = serde_json::from_str(&body).unwrap();
+        assert_eq!(json!({"error": "request timed out", "code": "REQUEST_TIMEOUT", "timeout_ms": 50}), body);
+        // handler の future は drop されているので、待っても最後まで走らない
+        tokio::time::sleep(Duration::from_millis(300)).await;
+        assert!(!finished.load(Ordering::SeqCst));
+    }
+
+    #[tokio::test]
+    async fn should_give_writes_and_long_routes_their_own_budget() {
+        let finished = Arc::new(AtomicBool::new(false));
+        assert_eq!(
+            (StatusCode::OK, "done".to_string()),
+            send(app(finished.clone()), Method::POST, "/todos/1").await
+        );
+        assert_eq!(
+            (StatusCode::OK, "done".to_string()),
+            send(app(finished.clone()), Method::GET, "/export").await
+        );
+        assert!(finished.load(Ordering::SeqCst));
+    }
+}
@@ -0,0 +1,112 @@
+use axum::async_trait;
+use regex::Regex;
+use std::sync::Arc;
+
+// 保存前に Todo の本文をチェックする。デプロイ先ごとのモデレーション方針はこの trait を実装して差し込む。
+// 外部のモデレーション API を呼べるように async にしておく
+#[async_trait]
+pub trait ContentFilter: Send + Sync + 'static {
+    async fn check(&self, text: &str) -> Result<(), Rejected>;
+}
+
+pub type SharedContentFilter = Arc<dyn ContentFilter>;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Rejected {
+    pub reason: String,
+}
+
+// リクエストのうちモデレーションの対象になる文字列を返す。Normalize と同じくリクエストの型ごとに実装する
+pub trait Moderate {
+    fn texts(&self) -> Vec<&str>;
+}
+
+// 何もチェックしないデフォルトのフィルタ
+#[derive(Debug, Clone, Copy, Default)]
+pub struct NoopFilter;
+
+#[async_trait]
+impl ContentFilter for NoopFilter {
+    async fn check(&self, _text: &str) -> Result<(), Rejected> {
+        Ok(())
+    }
+}
+
+// 禁止語のどれかを含む本文を弾く実装例。大文字小文字は区別しない
+#[derive(Debug, Clone)]
+pub struct DenylistFilter {
+    pattern: Option<Regex>,
+}
+
+// 英数字だけの語は単語単位で比較して、"class" が "ass" に引っかかるようなことを避ける。
+// 日本語には単語境界が無いので、それ以外は部分一致にする
+fn word_pattern(word: &str) -> String {
+    let escaped = regex::escape(word);
+    if word.chars().all(|c| c.is_ascii_alphanumeric()) {
+        format!(r"\b{}\b", escaped)
+    } else {
+        escaped
+    }
+}
+
+impl DenylistFilter {
+    pub fn new<I, S>(words: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: AsRef<str>,
+    {
+        let patterns = words
+            .into_iter()
+            .map(|word| word.as_ref().trim().to_string())
+            .filter(|word| !word.is_empty())
+            .map(|word| word_pattern(&word))
+            .collect::<Vec<_>>();
+        let pattern = if patterns.is_empty() {
+            None
+        } else {
+            Some(Regex::new(&format!("(?i)(?:{})", patterns.join("|"))).unwrap())
+        };
+        Self { pattern }
+    }
+}
+
+#[async_trait]
+impl ContentFilter for DenylistFilter {
+    async fn check(&self, text: &str) -> Result<(), Rejected> {
+        match self.pattern.as_ref().and_then(|pattern| pattern.find(text)) {
+            Some(found) => Err(Rejected {
+                reason: format!("contains denied word: [{}]", found.as_str()),
+            }),
+            None => Ok(()),
+        }
+    }
+}
+
+// 禁止語が設定されていれば DenylistFilter、無ければ NoopFilter
+pub fn from_words(words: &[String]) -> SharedContentFilter {
+    if words.iter().all(|word| word.trim().is_empty()) {
+        Arc::new(NoopFilter)
+    } else {
+        Arc::new(DenylistFilter::new(words))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[tokio::test]
+    async fn denylist_scenario() {
+        let filter = DenylistFilter::new(["spam", "禁止", " "]);
+        assert!(filter.check("buy milk").await.is_ok());
+        assert!(filter.check("classic spammer").await.is_ok());
+        assert_eq!(
+            filter.check("this is SPAM").await,
+            Err(Rejected { reason: "contains denied word: [SPAM]".to_string() })
+        );
+        assert!(filter.check("これは禁止ワード").await.is_err());
+
+        let empty = DenylistFilter::new(Vec::<String>::new());
+        assert!(empty.check("anything").await.is_ok());
+    }
+}
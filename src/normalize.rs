@@ -0,0 +1,36 @@
+use unicode_normalization::UnicodeNormalization;
+
+// バリデーションの前にリクエストの文字列を正規化する。
+// ValidatedJson が validate() の前に呼ぶので、空文字や長さのチェックは正規化後の値に対して行われる
+pub trait Normalize {
+    fn normalize(&mut self);
+}
+
+// NFC に揃え、制御文字を取り除き、前後の空白を削って連続する空白を 1 つにまとめる
+pub fn normalize_text(text: &str) -> String {
+    let text: String = text
+        .nfc()
+        .filter(|c| c.is_whitespace() || !c.is_control())
+        .collect();
+    text.split_whitespace().collect::<Vec<_>>().join(" ")
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn normalize_whitespace_and_control_chars() {
+        assert_eq!(normalize_text("  buy \t\n milk  "), "buy milk");
+        assert_eq!(normalize_text("buy\u{0007} milk\u{0000}"), "buy milk");
+        assert_eq!(normalize_text(" \n\t "), "");
+    }
+
+    #[test]
+    fn normalize_to_nfc() {
+        // "e" + 結合文字のアキュートアクセント -> "é"
+        assert_eq!(normalize_text("cafe\u{0301}"), "caf\u{00e9}");
+        // 半角カナの濁点を合成するのは NFKC なので、NFC では変わらない
+        assert_eq!(normalize_text("ｶﾞ"), "ｶﾞ");
+    }
+}
@@ -0,0 +1,46 @@
+use argon2::{
+    password_hash::{rand_core::OsRng, PasswordHash, PasswordHasher, PasswordVerifier, SaltString},
+    Argon2,
+};
+use std::sync::OnceLock;
+
+// PHC 文字列 ($argon2id$v=19$...) で返す。ソルトとパラメータも含むので、この文字列だけで検証できる
+pub fn hash(password: &str) -> anyhow::Result<String> {
+    let salt = SaltString::generate(&mut OsRng);
+    let hash = Argon2::default()
+        .hash_password(password.as_bytes(), &salt)
+        .map_err(|e| anyhow::anyhow!("failed to hash password: {}", e))?;
+    Ok(hash.to_string())
+}
+
+// 壊れたハッシュも不一致として扱う
+pub fn verify(password: &str, hash: &str) -> bool {
+    match PasswordHash::new(hash) {
+        Ok(parsed) => Argon2::default().verify_password(password.as_bytes(), &parsed).is_ok(),
+        Err(_) => false,
+    }
+}
+
+// 登録されていない email でも同じだけ時間をかけて、応答時間から登録の有無を推測させない。常に false
+pub fn verify_absent(password: &str) -> bool {
+    static DUMMY: OnceLock<String> = OnceLock::new();
+    let dummy = DUMMY.get_or_init(|| hash("dummy password").expect("failed to hash dummy password"));
+    verify(password, dummy);
+    false
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn verify_hashed_password() {
+        let hashed = hash("correct horse").unwrap();
+        assert!(hashed.starts_with("$argon2id$"));
+        assert!(verify("correct horse", &hashed));
+        assert!(!verify("wrong horse", &hashed));
+        // 同じパスワードでもソルトが違う
+        assert_ne!(hashed, hash("correct horse").unwrap());
+        assert!(!verify("correct horse", "not a hash"));
+    }
+}
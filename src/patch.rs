@@ -0,0 +1,111 @@
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+// PATCH のペイロードの 1 項目。Option だと省略と null を区別できないので、3 つに分ける。
+// フィールドには #[serde(default, skip_serializing_if = "Patch::is_absent")] を付ける
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub enum Patch<T> {
+    // キーが無い。変更しない
+    #[default]
+    Absent,
+    // null。消せる項目なら消す
+    Null,
+    Value(T),
+}
+
+impl<T> Patch<T> {
+    pub fn is_absent(&self) -> bool {
+        matches!(self, Patch::Absent)
+    }
+
+    pub fn as_ref(&self) -> Patch<&T> {
+        match self {
+            Patch::Absent => Patch::Absent,
+            Patch::Null => Patch::Null,
+            Patch::Value(value) => Patch::Value(value),
+        }
+    }
+
+    pub fn map<U>(self, f: impl FnOnce(T) -> U) -> Patch<U> {
+        match self {
+            Patch::Absent => Patch::Absent,
+            Patch::Null => Patch::Null,
+            Patch::Value(value) => Patch::Value(f(value)),
+        }
+    }
+
+    // 値だけを取り出す。null を受け付けない項目用 (null は検証で弾いておく)
+    pub fn value(self) -> Option<T> {
+        match self {
+            Patch::Value(value) => Some(value),
+            Patch::Absent | Patch::Null => None,
+        }
+    }
+}
+
+impl<T: Default> Patch<T> {
+    // 変更後の値。省略なら None (変更しない)、null なら空の値にする
+    pub fn into_change(self) -> Option<T> {
+        match self {
+            Patch::Absent => None,
+            Patch::Null => Some(T::default()),
+            Patch::Value(value) => Some(value),
+        }
+    }
+}
+
+// 呼び出し側のコードでは Option のほうが書きやすいので、None を省略として受け取る
+impl<T> From<Option<T>> for Patch<T> {
+    fn from(value: Option<T>) -> Self {
+        value.map_or(Patch::Absent, Patch::Value)
+    }
+}
+
+// キーが無ければ #[serde(default)] で Absent になるので、ここに来るのは null か値のどちらか
+impl<'de, T: Deserialize<'de>> Deserialize<'de> for Patch<T> {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        Ok(Option::<T>::deserialize(deserializer)?.map_or(Patch::Null, Patch::Value))
+    }
+}
+
+impl<T: Serialize> Serialize for Patch<T> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        match self {
+            Patch::Absent | Patch::Null => serializer.serialize_none(),
+            Patch::Value(value) => value.serialize(serializer),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use serde_json::json;
+
+    #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    struct Payload {
+        #[serde(default, skip_serializing_if = "Patch::is_absent")]
+        labels: Patch<Vec<i32>>,
+    }
+
+    #[test]
+    fn should_tell_absent_from_null() {
+        let parse = |value| serde_json::from_value::<Payload>(value).unwrap().labels;
+        assert_eq!(Patch::Absent, parse(json!({})));
+        assert_eq!(Patch::Null, parse(json!({"labels": null})));
+        assert_eq!(Patch::Value(vec![]), parse(json!({"labels": []})));
+
+        assert_eq!(None, Patch::<Vec<i32>>::Absent.into_change());
+        assert_eq!(Some(vec![]), Patch::<Vec<i32>>::Null.into_change());
+        assert_eq!(Some(vec![1]), Patch::Value(vec![1]).into_change());
+    }
+
+    #[test]
+    fn should_round_trip_through_json() {
+        for labels in [Patch::Absent, Patch::Null, Patch::Value(vec![1, 2])] {
+            let payload = Payload { labels };
+            let json = serde_json::to_value(&payload).unwrap();
+            assert_eq!(payload, serde_json::from_value(json).unwrap());
+        }
+        assert_eq!(json!({}), serde_json::to_value(Payload { labels: Patch::Absent }).unwrap());
+    }
+}
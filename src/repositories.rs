@@ -1,14 +1,29 @@
+pub mod access_log;
+pub mod backup;
+pub mod cache;
 pub mod label;
+pub mod maintenance;
+pub mod quota;
+pub mod sync;
 pub mod todo;
+pub mod user;
 
 use thiserror::Error;
 
 #[derive(Debug, Error)]
-enum RepositoryError {
+pub enum RepositoryError {
     #[error("Unexpected Error: [{0}]")]
     Unexpected(String),
     #[error("NotFound, id is {0}")]
     NotFound(i32),
     #[error("Duplicated Error: [{0}]")]
     Duplicate(i32),
+    #[error("Database is not empty")]
+    NotEmpty,
+    #[error("Precondition failed")]
+    PreconditionFailed,
+    #[error("Labels not found: {0:?}")]
+    LabelsNotFound(Vec<i32>),
+    #[error("Quota exceeded: [{resource}] limit is {limit}")]
+    QuotaExceeded { resource: String, limit: i64 },
 }
@@ -0,0 +1,239 @@
+use axum::async_trait;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::{FromRow, PgPool, Postgres, QueryBuilder};
+
+pub const DEFAULT_ACCESS_LOG_LIMIT: i64 = 100;
+pub const MAX_ACCESS_LOG_LIMIT: i64 = 1000;
+
+#[async_trait]
+pub trait AccessLogRepository: Clone + std::marker::Send + std::marker::Sync + 'static {
+    async fn insert_batch(&self, entries: Vec<AccessLogEntry>) -> anyhow::Result<()>;
+    async fn search(&self, filter: AccessLogFilter) -> anyhow::Result<Vec<AccessLogEntry>>;
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq, FromRow)]
+pub struct AccessLogEntry {
+    pub request_id: Option<String>,
+    pub method: String,
+    pub path: String,
+    pub status: i32,
+    pub latency_ms: i64,
+    pub user_id: Option<i32>,
+    pub created_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq, Default)]
+pub struct AccessLogFilter {
+    pub method: Option<String>,
+    // 前方一致
+    pub path: Option<String>,
+    pub status: Option<i32>,
+    pub user_id: Option<i32>,
+    pub request_id: Option<String>,
+    pub since: Option<DateTime<Utc>>,
+    pub until: Option<DateTime<Utc>>,
+    pub limit: Option<i64>,
+}
+
+impl AccessLogFilter {
+    fn limit(&self) -> i64 {
+        self.limit
+            .unwrap_or(DEFAULT_ACCESS_LOG_LIMIT)
+            .clamp(1, MAX_ACCESS_LOG_LIMIT)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct AccessLogRepositoryForDb {
+    pool: PgPool,
+}
+
+impl AccessLogRepositoryForDb {
+    pub fn new(pool: PgPool) -> Self {
+        Self { pool }
+    }
+}
+
+#[async_trait]
+impl AccessLogRepository for AccessLogRepositoryForDb {
+    async fn insert_batch(&self, entries: Vec<AccessLogEntry>) -> anyhow::Result<()> {
+        if entries.is_empty() {
+            return Ok(());
+        }
+        // 1 回の INSERT でまとめて書き込む
+        let mut query = QueryBuilder::<Postgres>::new(
+            "INSERT INTO access_log (request_id, method, path, status, latency_ms, user_id, created_at) ",
+        );
+        query.push_values(entries, |mut row, entry| {
+            row.push_bind(entry.request_id)
+                .push_bind(entry.method)
+                .push_bind(entry.path)
+                .push_bind(entry.status)
+                .push_bind(entry.latency_ms)
+                .push_bind(entry.user_id)
+                .push_bind(entry.created_at);
+        });
+        query.build().execute(&self.pool).await?;
+        Ok(())
+    }
+
+    async fn search(&self, filter: AccessLogFilter) -> anyhow::Result<Vec<AccessLogEntry>> {
+        let mut query = QueryBuilder::<Postgres>::new(
+            "SELECT request_id, method, path, status, latency_ms, user_id, created_at FROM access_log WHERE true",
+        );
+        if let Some(method) = &filter.method {
+            query.push(" AND method = upper(").push_bind(method.clone()).push(")");
+        }
+        if let Some(path) = &filter.path {
+            query.push(" AND starts_with(path, ").push_bind(path.clone()).push(")");
+        }
+        if let Some(status) = filter.status {
+            query.push(" AND status = ").push_bind(status);
+        }
+        if let Some(user_id) = filter.user_id {
+            query.push(" AND user_id = ").push_bind(user_id);
+        }
+        if let Some(request_id) = &filter.request_id {
+            query.push(" AND request_id = ").push_bind(request_id.clone());
+        }
+        if let Some(since) = filter.since {
+            query.push(" AND created_at >= ").push_bind(since);
+        }
+        if let Some(until) = filter.until {
+            query.push(" AND created_at < ").push_bind(until);
+        }
+        query.push(" ORDER BY created_at DESC, id DESC LIMIT ").push_bind(filter.limit());
+
+        let entries = query
+            .build_query_as::<AccessLogEntry>()
+            .fetch_all(&self.pool)
+            .await?;
+        Ok(entries)
+    }
+}
+
+#[cfg(test)]
+#[cfg(feature = "database-test")]
+mod test {
+    use super::*;
+    use chrono::SubsecRound;
+    use dotenv::dotenv;
+    use std::env;
+    use uuid::Uuid;
+
+    #[tokio::test]
+    async fn access_log_scenario() {
+        dotenv().ok();
+        let database_url = env::var("DATABASE_URL").expect("undefined env: [DATABASE_URL]");
+        let pool = PgPool::connect(&database_url)
+            .await
+            .unwrap_or_else(|_| panic!("cannot connect database: [{}]", database_url));
+        let repo = AccessLogRepositoryForDb::new(pool.clone());
+        let request_id = Uuid::new_v4().to_string();
+
+        let entry = |status| AccessLogEntry {
+            request_id: Some(request_id.clone()),
+            method: "GET".to_string(),
+            path: "/todos".to_string(),
+            status,
+            latency_ms: 3,
+            user_id: None,
+            created_at: Utc::now().trunc_subsecs(0),
+        };
+        repo.insert_batch(vec![entry(200), entry(404)])
+            .await
+            .expect("[insert_batch] returned Err");
+
+        let entries = repo
+            .search(AccessLogFilter {
+                method: Some("get".to_string()),
+                path: Some("/todo".to_string()),
+                status: Some(404),
+                request_id: Some(request_id.clone()),
+                ..Default::default()
+            })
+            .await
+            .expect("[search] returned Err");
+        assert_eq!(entries, vec![entry(404)]);
+    }
+}
+
+#[cfg(any(test, feature = "test-support"))]
+pub mod test_utils {
+    use std::sync::{Arc, RwLock};
+
+    use super::*;
+
+    fn matches(filter: &AccessLogFilter, entry: &AccessLogEntry) -> bool {
+        if let Some(method) = &filter.method {
+            if !entry.method.eq_ignore_ascii_case(method) {
+                return false;
+            }
+        }
+        if let Some(path) = &filter.path {
+            if !entry.path.starts_with(path.as_str()) {
+                return false;
+            }
+        }
+        if filter.status.is_some() && filter.status != Some(entry.status) {
+            return false;
+        }
+        if filter.user_id.is_some() && filter.user_id != entry.user_id {
+            return false;
+        }
+        if filter.request_id.is_some() && filter.request_id != entry.request_id {
+            return false;
+        }
+        if let Some(since) = filter.since {
+            if entry.created_at < since {
+                return false;
+            }
+        }
+        if let Some(until) = filter.until {
+            if entry.created_at >= until {
+                return false;
+            }
+        }
+        true
+    }
+
+    #[derive(Debug, Clone)]
+    pub struct AccessLogRepositoryForMemory {
+        store: Arc<RwLock<Vec<AccessLogEntry>>>,
+    }
+
+    impl Default for AccessLogRepositoryForMemory {
+        fn default() -> Self {
+            Self::new()
+        }
+    }
+
+    impl AccessLogRepositoryForMemory {
+        pub fn new() -> Self {
+            AccessLogRepositoryForMemory {
+                store: Arc::default(),
+            }
+        }
+    }
+
+    #[async_trait]
+    impl AccessLogRepository for AccessLogRepositoryForMemory {
+        async fn insert_batch(&self, entries: Vec<AccessLogEntry>) -> anyhow::Result<()> {
+            self.store.write().unwrap().extend(entries);
+            Ok(())
+        }
+
+        async fn search(&self, filter: AccessLogFilter) -> anyhow::Result<Vec<AccessLogEntry>> {
+            let store = self.store.read().unwrap();
+            let entries = store
+                .iter()
+                .rev()
+                .filter(|entry| matches(&filter, entry))
+                .take(filter.limit() as usize)
+                .cloned()
+                .collect();
+            Ok(entries)
+        }
+    }
+}
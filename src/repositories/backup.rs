@@ -0,0 +1,739 @@
+use axum::async_trait;
+use chrono::{DateTime, Utc};
+use futures::stream::{BoxStream, StreamExt, TryStreamExt};
+use serde::{Deserialize, Serialize};
+use sqlx::{FromRow, PgPool};
+use std::collections::HashSet;
+use uuid::Uuid;
+
+use crate::blob::{BlobStore, SharedBlobStore};
+use super::{cache::QueryCache, label::Label, todo::Priority, RepositoryError};
+
+pub const BACKUP_FORMAT: &str = "rust-webapp-backup";
+pub const BACKUP_VERSION: u32 = 1;
+pub const SNAPSHOT_FORMAT: &str = "rust-webapp-snapshot";
+pub const SNAPSHOT_PREFIX: &str = "snapshots";
+// スナップショットに含めるテーブル。アクセスログとリースは含めない
+pub const SNAPSHOT_TABLES: &[&str] = &["labels", "todos", "todo_labels", "sync_mutations", "todo_deletions", "users"];
+
+#[async_trait]
+pub trait BackupRepository: Clone + std::marker::Send + std::marker::Sync + 'static {
+    // 全テーブルの行を 1 件ずつ流す。先頭は必ず Header
+    fn export(&self) -> BoxStream<'static, anyhow::Result<BackupRecord>>;
+    // 空のデータベースに対してのみ復元できる
+    async fn restore(&self, records: Vec<BackupRecord>) -> anyhow::Result<RestoreSummary>;
+    // マイグレーション前の保険。テーブルごとのダンプと manifest.json を store の snapshots/{id}/ に書く
+    async fn snapshot(&self, store: SharedBlobStore) -> anyhow::Result<SnapshotManifest>;
+    // public には触らず、新しく作る schema に復元する。中身を確かめてから手で入れ替える
+    async fn restore_snapshot(
+        &self,
+        store: SharedBlobStore,
+        id: String,
+        schema: String,
+    ) -> anyhow::Result<SnapshotRestore>;
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq, FromRow)]
+pub struct TodoBackup {
+    pub id: i32,
+    pub text: String,
+    pub completed: bool,
+    pub version: i32,
+    pub client_id: Option<Uuid>,
+    pub client_key: Option<String>,
+    // 古いバックアップには無い。その場合、完了済みの Todo には trigger が復元した時刻を入れる
+    pub completed_at: Option<DateTime<Utc>>,
+    // 古いバックアップには無い。その場合は期限なしで復元する
+    pub due_date: Option<DateTime<Utc>>,
+    // 古いバックアップには無い。その場合は medium で復元する
+    #[serde(default)]
+    pub priority: Priority,
+    // ゴミ箱の Todo もそのまま戻す。古いバックアップには無い
+    pub deleted_at: Option<DateTime<Utc>>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq, FromRow)]
+pub struct TodoLabelBackup {
+    pub todo_id: i32,
+    pub label_id: i32,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq, FromRow)]
+pub struct SyncMutationBackup {
+    pub mutation_id: Uuid,
+    // タイムゾーン付きの文字列表現のまま持ち回す
+    pub applied_at: String,
+}
+
+// ndjson の 1 行分。ユーザーと持ち主 (owner_id) は含めないので、復元した Todo とラベルは持ち主なしになる
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum BackupRecord {
+    Header { format: String, version: u32 },
+    Label(Label),
+    Todo(TodoBackup),
+    TodoLabel(TodoLabelBackup),
+    SyncMutation(SyncMutationBackup),
+}
+
+impl BackupRecord {
+    pub fn header() -> Self {
+        BackupRecord::Header {
+            format: BACKUP_FORMAT.to_string(),
+            version: BACKUP_VERSION,
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq, Default)]
+pub struct RestoreSummary {
+    pub labels: usize,
+    pub todos: usize,
+    pub todo_labels: usize,
+    pub sync_mutations: usize,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
+pub struct SnapshotTable {
+    pub name: String,
+    pub rows: usize,
+    pub bytes: usize,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
+pub struct SnapshotManifest {
+    pub id: String,
+    pub format: String,
+    pub created_at: DateTime<Utc>,
+    pub tables: Vec<SnapshotTable>,
+}
+
+impl SnapshotManifest {
+    // id は作成時刻から始めるので、名前順に並べれば古い順になる
+    pub fn new(tables: Vec<SnapshotTable>) -> Self {
+        let created_at = Utc::now();
+        let suffix = Uuid::new_v4().simple().to_string();
+        Self {
+            id: format!("{}-{}", created_at.format("%Y%m%dT%H%M%S%.3fZ"), &suffix[..8]),
+            format: SNAPSHOT_FORMAT.to_string(),
+            created_at,
+            tables,
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
+pub struct SnapshotRestore {
+    pub snapshot_id: String,
+    pub schema: String,
+    pub tables: Vec<SnapshotTable>,
+}
+
+fn snapshot_key(id: &str, name: &str) -> String {
+    format!("{}/{}/{}", SNAPSHOT_PREFIX, id, name)
+}
+
+// SQL に埋め込むので、識別子として安全な名前だけを受け付ける
+pub fn validate_schema_name(schema: &str) -> Result<(), String> {
+    let valid = !schema.is_empty()
+        && schema.len() <= 63
+        && schema.starts_with(|c: char| c.is_ascii_lowercase() || c == '_')
+        && schema.chars().all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '_');
+    if !valid {
+        return Err(format!("invalid schema name: [{}]", schema));
+    }
+    if schema == "public" || schema == "information_schema" || schema.starts_with("pg_") {
+        return Err(format!("reserved schema name: [{}]", schema));
+    }
+    Ok(())
+}
+
+pub(crate) async fn write_manifest(store: &dyn BlobStore, manifest: &SnapshotManifest) -> anyhow::Result<()> {
+    store
+        .put(&snapshot_key(&manifest.id, "manifest.json"), serde_json::to_vec_pretty(manifest)?)
+        .await
+}
+
+// 無い (または書きかけで manifest がまだ無い) スナップショットは None
+pub async fn load_manifest(store: &dyn BlobStore, id: &str) -> anyhow::Result<Option<SnapshotManifest>> {
+    if id.is_empty() || !id.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '.') {
+        return Ok(None);
+    }
+    match store.get(&snapshot_key(id, "manifest.json")).await? {
+        Some(data) => Ok(Some(serde_json::from_slice(&data)?)),
+        None => Ok(None),
+    }
+}
+
+// 古い順
+pub async fn list_snapshots(store: &dyn BlobStore) -> anyhow::Result<Vec<SnapshotManifest>> {
+    let mut manifests = vec![];
+    for id in store.list(SNAPSHOT_PREFIX).await? {
+        if let Some(manifest) = load_manifest(store, &id).await? {
+            manifests.push(manifest);
+        }
+    }
+    Ok(manifests)
+}
+
+// 書き込みを始める前に、ダンプとして整合しているかを確認する
+pub fn validate_backup(records: &[BackupRecord]) -> Result<RestoreSummary, String> {
+    match records.first() {
+        Some(BackupRecord::Header { format, version }) => {
+            if format != BACKUP_FORMAT {
+                return Err(format!("unknown backup format: [{}]", format));
+            }
+            if *version != BACKUP_VERSION {
+                return Err(format!("unsupported backup version: [{}]", version));
+            }
+        }
+        _ => return Err("backup must start with a header record".to_string()),
+    }
+
+    let mut summary = RestoreSummary::default();
+    let mut label_ids = HashSet::new();
+    let mut label_names = HashSet::new();
+    let mut todo_ids = HashSet::new();
+    for record in &records[1..] {
+        match record {
+            BackupRecord::Header { .. } => return Err("duplicated header record".to_string()),
+            BackupRecord::Label(label) => {
+                if !label_ids.insert(label.id) {
+                    return Err(format!("duplicated label id: [{}]", label.id));
+                }
+                if !label_names.insert(label.name.as_str()) {
+                    return Err(format!("duplicated label name: [{}]", label.name));
+                }
+                summary.labels += 1;
+            }
+            BackupRecord::Todo(todo) => {
+                if !todo_ids.insert(todo.id) {
+                    return Err(format!("duplicated todo id: [{}]", todo.id));
+                }
+                summary.todos += 1;
+            }
+            BackupRecord::TodoLabel(_) => summary.todo_labels += 1,
+            BackupRecord::SyncMutation(_) => summary.sync_mutations += 1,
+        }
+    }
+
+    // 関連は全ての行を読んでから検証する (出力順に依存しない)
+    for record in records {
+        if let BackupRecord::TodoLabel(todo_label) = record {
+            if !todo_ids.contains(&todo_label.todo_id) {
+                return Err(format!("todo_label references unknown todo: [{}]", todo_label.todo_id));
+            }
+            if !label_ids.contains(&todo_label.label_id) {
+                return Err(format!("todo_label references unknown label: [{}]", todo_label.label_id));
+            }
+        }
+    }
+
+    Ok(summary)
+}
+
+#[derive(Debug, Clone)]
+pub struct BackupRepositoryForDb {
+    pool: PgPool,
+    cache: QueryCache,
+}
+
+impl BackupRepositoryForDb {
+    pub fn new(pool: PgPool) -> Self {
+        Self { pool, cache: QueryCache::default() }
+    }
+
+    // リストアで Todo とラベルが丸ごと入れ替わるので、終わったらキャッシュを捨てる
+    pub fn with_cache(mut self, cache: QueryCache) -> Self {
+        self.cache = cache;
+        self
+    }
+}
+
+#[async_trait]
+impl BackupRepository for BackupRepositoryForDb {
+    fn export(&self) -> BoxStream<'static, anyhow::Result<BackupRecord>> {
+        let pool = self.pool.clone();
+        // fetch_all せずにカーソルで少しずつ読むので、件数が多くてもメモリは一定
+        let stream = async_stream::try_stream! {
+            yield BackupRecord::header();
+
+            let mut labels = sqlx::query_as::<_, Label>(
+                r#"
+                SELECT id, name FROM labels ORDER BY id
+                "#
+            ).fetch(&pool);
+            while let Some(label) = labels.try_next().await? {
+                yield BackupRecord::Label(label);
+            }
+
+            let mut todos = sqlx::query_as::<_, TodoBackup>(
+                r#"
+                SELECT id, text, completed, version, client_id, client_key, completed_at, due_date, priority, deleted_at FROM todos ORDER BY id
+                "#
+            ).fetch(&pool);
+            while let Some(todo) = todos.try_next().await? {
+                yield BackupRecord::Todo(todo);
+            }
+
+            let mut todo_labels = sqlx::query_as::<_, TodoLabelBackup>(
+                r#"
+                SELECT todo_id, label_id FROM todo_labels ORDER BY id
+                "#
+            ).fetch(&pool);
+            while let Some(todo_label) = todo_labels.try_next().await? {
+                yield BackupRecord::TodoLabel(todo_label);
+            }
+
+            let mut mutations = sqlx::query_as::<_, SyncMutationBackup>(
+                r#"
+                SELECT mutation_id, applied_at::text as applied_at FROM sync_mutations ORDER BY applied_at
+                "#
+            ).fetch(&pool);
+            while let Some(mutation) = mutations.try_next().await? {
+                yield BackupRecord::SyncMutation(mutation);
+            }
+        };
+        stream.boxed()
+    }
+
+    async fn restore(&self, records: Vec<BackupRecord>) -> anyhow::Result<RestoreSummary> {
+        let summary = validate_backup(&records).map_err(RepositoryError::Unexpected)?;
+        let mut tx = self.pool.begin().await?;
+
+        let not_empty = sqlx::query_scalar::<_, bool>(
+            r#"
+            SELECT EXISTS (SELECT 1 FROM labels) OR EXISTS (SELECT 1 FROM todos)
+            "#
+        )
+        .fetch_one(&mut tx)
+        .await?;
+        if not_empty {
+            return Err(RepositoryError::NotEmpty.into());
+        }
+
+        for record in records {
+            match record {
+                BackupRecord::Header { .. } => {}
+                BackupRecord::Label(label) => {
+                    sqlx::query(
+                        r#"
+                        INSERT INTO labels (id, name) VALUES ($1, $2)
+                        "#
+                    )
+                    .bind(label.id)
+                    .bind(label.name)
+                    .execute(&mut tx)
+                    .await?;
+                }
+                BackupRecord::Todo(todo) => {
+                    sqlx::query(
+                        r#"
+                        INSERT INTO todos (id, text, completed, version, client_id, client_key, completed_at, due_date, priority, deleted_at)
+                        VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10)
+                        "#
+                    )
+                    .bind(todo.id)
+                    .bind(todo.text)
+                    .bind(todo.completed)
+                    .bind(todo.version)
+                    .bind(todo.client_id)
+                    .bind(todo.client_key)
+                    .bind(todo.completed_at)
+                    .bind(todo.due_date)
+                    .bind(todo.priority)
+                    .bind(todo.deleted_at)
+                    .execute(&mut tx)
+                    .await?;
+                }
+                BackupRecord::TodoLabel(todo_label) => {
+                    // 外部キー制約は DEFERRABLE なので、todos より先に来てもコミット時に検証される
+                    sqlx::query(
+                        r#"
+                        INSERT INTO todo_labels (todo_id, label_id) VALUES ($1, $2)
+                        "#
+                    )
+                    .bind(todo_label.todo_id)
+                    .bind(todo_label.label_id)
+                    .execute(&mut tx)
+                    .await?;
+                }
+                BackupRecord::SyncMutation(mutation) => {
+                    sqlx::query(
+                        r#"
+                        INSERT INTO sync_mutations (mutation_id, applied_at) VALUES ($1, $2::timestamptz)
+                        "#
+                    )
+                    .bind(mutation.mutation_id)
+                    .bind(mutation.applied_at)
+                    .execute(&mut tx)
+                    .await?;
+                }
+            }
+        }
+
+        // ID を明示して INSERT したので、シーケンスを復元後の最大値に合わせる
+        for table in ["labels", "todos", "todo_labels"] {
+            sqlx::query(&format!(
+                "SELECT setval(pg_get_serial_sequence('{0}', 'id'), COALESCE((SELECT MAX(id) FROM {0}), 0) + 1, false)",
+                table
+            ))
+            .execute(&mut tx)
+            .await?;
+        }
+
+        tx.commit().await?;
+        self.cache.invalidate_all();
+        Ok(summary)
+    }
+
+    async fn snapshot(&self, store: SharedBlobStore) -> anyhow::Result<SnapshotManifest> {
+        // 全テーブルを同じ時点で読むため、1 つのトランザクションで COPY する
+        let mut tx = self.pool.begin().await?;
+        sqlx::query("SET TRANSACTION ISOLATION LEVEL REPEATABLE READ, READ ONLY")
+            .execute(&mut tx)
+            .await?;
+        let mut dumps = vec![];
+        for table in SNAPSHOT_TABLES {
+            let mut data = vec![];
+            let mut chunks = tx.copy_out_raw(&format!("COPY {} TO STDOUT", table)).await?;
+            while let Some(chunk) = chunks.try_next().await? {
+                data.extend_from_slice(&chunk);
+            }
+            dumps.push((table.to_string(), data));
+        }
+        tx.commit().await?;
+
+        // text 形式は値の中の改行をエスケープするので、行数は改行の数になる
+        let tables = dumps
+            .iter()
+            .map(|(name, data)| SnapshotTable {
+                name: name.clone(),
+                rows: data.iter().filter(|b| **b == b'\n').count(),
+                bytes: data.len(),
+            })
+            .collect();
+        let manifest = SnapshotManifest::new(tables);
+        for (name, data) in dumps {
+            store.put(&snapshot_key(&manifest.id, &format!("{}.copy", name)), data).await?;
+        }
+        // manifest を最後に書くので、途中で失敗したスナップショットは一覧に出ない
+        write_manifest(store.as_ref(), &manifest).await?;
+        Ok(manifest)
+    }
+
+    async fn restore_snapshot(
+        &self,
+        store: SharedBlobStore,
+        id: String,
+        schema: String,
+    ) -> anyhow::Result<SnapshotRestore> {
+        validate_schema_name(&schema).map_err(RepositoryError::Unexpected)?;
+        let manifest = load_manifest(store.as_ref(), &id)
+            .await?
+            .ok_or_else(|| RepositoryError::Unexpected(format!("snapshot not found: [{}]", id)))?;
+        // テーブル名も SQL に埋め込むので、manifest の中身をそのまま信用しない
+        if let Some(table) = manifest.tables.iter().find(|table| !SNAPSHOT_TABLES.contains(&table.name.as_str())) {
+            return Err(RepositoryError::Unexpected(format!("unknown table in snapshot: [{}]", table.name)).into());
+        }
+
+        let mut tx = self.pool.begin().await?;
+        let exists = sqlx::query_scalar::<_, bool>(
+            r#"
+            SELECT EXISTS (SELECT 1 FROM information_schema.schemata WHERE schema_name = $1)
+            "#
+        )
+        .bind(&schema)
+        .fetch_one(&mut tx)
+        .await?;
+        if exists {
+            return Err(RepositoryError::Unexpected(format!("schema already exists: [{}]", schema)).into());
+        }
+        sqlx::query(&format!("CREATE SCHEMA {}", schema)).execute(&mut tx).await?;
+        for table in &manifest.tables {
+            let data = store
+                .get(&snapshot_key(&manifest.id, &format!("{}.copy", table.name)))
+                .await?
+                .ok_or_else(|| RepositoryError::Unexpected(format!("snapshot is missing table: [{}]", table.name)))?;
+            // 外部キーとトリガーは複製されないので、テーブルの順番は問わない
+            sqlx::query(&format!("CREATE TABLE {0}.{1} (LIKE public.{1} INCLUDING ALL)", schema, table.name))
+                .execute(&mut tx)
+                .await?;
+            let mut copy = tx.copy_in_raw(&format!("COPY {}.{} FROM STDIN", schema, table.name)).await?;
+            if let Err(e) = copy.send(data).await {
+                copy.abort(e.to_string()).await?;
+                return Err(e.into());
+            }
+            copy.finish().await?;
+        }
+        tx.commit().await?;
+        Ok(SnapshotRestore {
+            snapshot_id: manifest.id,
+            schema,
+            tables: manifest.tables,
+        })
+    }
+}
+
+#[cfg(test)]
+#[cfg(feature = "database-test")]
+mod test {
+    use super::*;
+    use dotenv::dotenv;
+    use std::env;
+
+    #[tokio::test]
+    async fn export_scenario() {
+        dotenv().ok();
+        let database_url = env::var("DATABASE_URL").expect("undefined env: [DATABASE_URL]");
+        let pool = PgPool::connect(&database_url)
+            .await
+            .unwrap_or_else(|_| panic!("cannot connect database: [{}]", database_url));
+        let label = sqlx::query_as::<_, Label>(
+            r#"
+            INSERT INTO labels (name) VALUES ('backup label') RETURNING *
+            "#
+        )
+        .fetch_one(&pool)
+        .await
+        .expect("failed to insert label data.");
+
+        let repo = BackupRepositoryForDb::new(pool.clone());
+        let records: Vec<BackupRecord> = repo.export().try_collect().await.expect("[export] returned Err");
+        assert_eq!(records.first(), Some(&BackupRecord::header()));
+        assert!(records.contains(&BackupRecord::Label(label.clone())));
+
+        // データが残っている DB には復元できない
+        let res = repo.restore(records).await;
+        assert!(matches!(
+            res.unwrap_err().downcast_ref::<RepositoryError>(),
+            Some(RepositoryError::NotEmpty)
+        ));
+
+        sqlx::query("DELETE FROM labels WHERE id = $1")
+            .bind(label.id)
+            .execute(&pool)
+            .await
+            .expect("failed to delete label data.");
+    }
+
+    #[tokio::test]
+    async fn snapshot_scenario() {
+        dotenv().ok();
+        let database_url = env::var("DATABASE_URL").expect("undefined env: [DATABASE_URL]");
+        let pool = PgPool::connect(&database_url)
+            .await
+            .unwrap_or_else(|_| panic!("cannot connect database: [{}]", database_url));
+        let name = format!("snapshot label {}", Uuid::new_v4());
+        let label = sqlx::query_as::<_, Label>(
+            r#"
+            INSERT INTO labels (name) VALUES ($1) RETURNING *
+            "#
+        )
+        .bind(&name)
+        .fetch_one(&pool)
+        .await
+        .expect("failed to insert label data.");
+
+        let root = env::temp_dir().join(format!("snapshot-{}", Uuid::new_v4()));
+        let store: SharedBlobStore = std::sync::Arc::new(crate::blob::FsBlobStore::new(&root));
+        let repo = BackupRepositoryForDb::new(pool.clone());
+        let manifest = repo.snapshot(store.clone()).await.expect("[snapshot] returned Err");
+        assert_eq!(SNAPSHOT_TABLES.len(), manifest.tables.len());
+        assert!(manifest.tables[0].rows >= 1);
+        assert_eq!(vec![manifest.clone()], list_snapshots(store.as_ref()).await.unwrap());
+
+        let schema = format!("snapshot_{}", Uuid::new_v4().simple());
+        let restored = repo
+            .restore_snapshot(store.clone(), manifest.id.clone(), schema.clone())
+            .await
+            .expect("[restore_snapshot] returned Err");
+        assert_eq!(manifest.tables, restored.tables);
+        let count = sqlx::query_scalar::<_, i64>(&format!("SELECT COUNT(*) FROM {}.labels WHERE name = $1", schema))
+            .bind(&name)
+            .fetch_one(&pool)
+            .await
+            .unwrap();
+        assert_eq!(1, count);
+
+        // 既にある schema や public には復元しない
+        assert!(repo.restore_snapshot(store.clone(), manifest.id.clone(), schema.clone()).await.is_err());
+        assert!(repo.restore_snapshot(store.clone(), manifest.id.clone(), "public".to_string()).await.is_err());
+        assert!(repo.restore_snapshot(store, "missing".to_string(), "snapshot_missing".to_string()).await.is_err());
+
+        sqlx::query(&format!("DROP SCHEMA {} CASCADE", schema))
+            .execute(&pool)
+            .await
+            .expect("failed to drop schema.");
+        sqlx::query("DELETE FROM labels WHERE id = $1")
+            .bind(label.id)
+            .execute(&pool)
+            .await
+            .expect("failed to delete label data.");
+        tokio::fs::remove_dir_all(root).await.unwrap();
+    }
+}
+
+#[cfg(any(test, feature = "test-support"))]
+pub mod test_utils {
+    use futures::stream;
+    use std::{
+        collections::HashMap,
+        sync::{Arc, RwLock},
+    };
+
+    use super::*;
+
+    #[derive(Debug, Clone)]
+    pub struct BackupRepositoryForMemory {
+        store: Arc<RwLock<Vec<BackupRecord>>>,
+        schemas: Arc<RwLock<HashMap<String, Vec<BackupRecord>>>>,
+    }
+
+    impl Default for BackupRepositoryForMemory {
+        fn default() -> Self {
+            Self::new()
+        }
+    }
+
+    impl BackupRepositoryForMemory {
+        pub fn new() -> Self {
+            BackupRepositoryForMemory {
+                store: Arc::default(),
+                schemas: Arc::default(),
+            }
+        }
+
+        // restore_snapshot で schema に復元した行
+        pub fn restored(&self, schema: &str) -> Option<Vec<BackupRecord>> {
+            self.schemas.read().unwrap().get(schema).cloned()
+        }
+    }
+
+    #[async_trait]
+    impl BackupRepository for BackupRepositoryForMemory {
+        fn export(&self) -> BoxStream<'static, anyhow::Result<BackupRecord>> {
+            let records: Vec<_> = self.store.read().unwrap().clone();
+            stream::iter(std::iter::once(BackupRecord::header()).chain(records).map(Ok)).boxed()
+        }
+
+        async fn restore(&self, records: Vec<BackupRecord>) -> anyhow::Result<RestoreSummary> {
+            let summary = validate_backup(&records).map_err(RepositoryError::Unexpected)?;
+            let mut store = self.store.write().unwrap();
+            if !store.is_empty() {
+                return Err(RepositoryError::NotEmpty.into());
+            }
+            store.extend(records.into_iter().skip(1));
+            Ok(summary)
+        }
+
+        // テーブルは分けずに、全ての行を ndjson で 1 つにまとめる
+        async fn snapshot(&self, store: SharedBlobStore) -> anyhow::Result<SnapshotManifest> {
+            let records: Vec<_> = self.store.read().unwrap().clone();
+            let mut data = vec![];
+            for record in &records {
+                data.extend(serde_json::to_vec(record)?);
+                data.push(b'\n');
+            }
+            let manifest = SnapshotManifest::new(vec![SnapshotTable {
+                name: "records".to_string(),
+                rows: records.len(),
+                bytes: data.len(),
+            }]);
+            store.put(&snapshot_key(&manifest.id, "records.copy"), data).await?;
+            write_manifest(store.as_ref(), &manifest).await?;
+            Ok(manifest)
+        }
+
+        async fn restore_snapshot(
+            &self,
+            store: SharedBlobStore,
+            id: String,
+            schema: String,
+        ) -> anyhow::Result<SnapshotRestore> {
+            validate_schema_name(&schema).map_err(RepositoryError::Unexpected)?;
+            let manifest = load_manifest(store.as_ref(), &id)
+                .await?
+                .ok_or_else(|| RepositoryError::Unexpected(format!("snapshot not found: [{}]", id)))?;
+            let data = store.get(&snapshot_key(&manifest.id, "records.copy")).await?.unwrap_or_default();
+            let records = data
+                .split(|b| *b == b'\n')
+                .filter(|line| !line.is_empty())
+                .map(serde_json::from_slice)
+                .collect::<Result<Vec<BackupRecord>, _>>()?;
+            let mut schemas = self.schemas.write().unwrap();
+            if schemas.contains_key(&schema) {
+                return Err(RepositoryError::Unexpected(format!("schema already exists: [{}]", schema)).into());
+            }
+            schemas.insert(schema.clone(), records);
+            Ok(SnapshotRestore {
+                snapshot_id: manifest.id,
+                schema,
+                tables: manifest.tables,
+            })
+        }
+    }
+
+    #[cfg(test)]
+    mod test {
+        use super::*;
+
+        fn records() -> Vec<BackupRecord> {
+            vec![
+                BackupRecord::header(),
+                BackupRecord::Label(Label::new(1, "label 1".to_string())),
+                BackupRecord::Todo(TodoBackup {
+                    id: 1,
+                    text: "todo 1".to_string(),
+                    completed: false,
+                    version: 1,
+                    client_id: None,
+                    client_key: None,
+                    completed_at: None,
+                    due_date: None,
+                    priority: Priority::Medium,
+                    deleted_at: None,
+                }),
+                BackupRecord::TodoLabel(TodoLabelBackup { todo_id: 1, label_id: 1 }),
+            ]
+        }
+
+        #[test]
+        fn validate_backup_test() {
+            let summary = validate_backup(&records()).unwrap();
+            assert_eq!(summary.labels, 1);
+            assert_eq!(summary.todos, 1);
+            assert_eq!(summary.todo_labels, 1);
+
+            // header が無い
+            assert!(validate_backup(&records()[1..]).is_err());
+
+            // 存在しない label を参照している
+            let mut dangling = records();
+            dangling.push(BackupRecord::TodoLabel(TodoLabelBackup { todo_id: 1, label_id: 2 }));
+            assert!(validate_backup(&dangling).is_err());
+        }
+
+        #[test]
+        fn validate_schema_name_test() {
+            assert!(validate_schema_name("before_20221218").is_ok());
+            assert!(validate_schema_name("_restore").is_ok());
+            for schema in ["", "public", "pg_temp", "information_schema", "1st", "Upper", "a; DROP TABLE todos", "a.b"] {
+                assert!(validate_schema_name(schema).is_err(), "{}", schema);
+            }
+            assert!(validate_schema_name(&"a".repeat(64)).is_err());
+        }
+
+        #[tokio::test]
+        async fn backup_restore_scenario() {
+            let repo = BackupRepositoryForMemory::new();
+            repo.restore(records()).await.expect("failed restore");
+
+            let exported: Vec<BackupRecord> = repo.export().try_collect().await.unwrap();
+            assert_eq!(exported, records());
+
+            // 空でないので復元できない
+            assert!(repo.restore(records()).await.is_err());
+        }
+    }
+}
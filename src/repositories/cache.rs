@@ -0,0 +1,229 @@
+use moka::future::Cache;
+use serde::{Deserialize, Serialize};
+use std::{
+    future::Future,
+    hash::Hash,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+
+use super::{label::Label, todo::TodoEntity};
+
+// find / all の結果のキャッシュ設定
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CacheConfig {
+    // id ごとにキャッシュする Todo の最大件数
+    pub max_capacity: u64,
+    pub ttl: Duration,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Default)]
+pub struct CacheStats {
+    pub enabled: bool,
+    pub hits: u64,
+    pub misses: u64,
+    pub hit_rate: f64,
+    pub entries: u64,
+}
+
+struct Inner {
+    todo: Cache<i32, TodoEntity>,
+    todos: Cache<(), Vec<TodoEntity>>,
+    labels: Cache<(), Vec<Label>>,
+    // 書き込みのたびに進める。読み込み中に書き込みがあったら、読んだ値はキャッシュしない
+    generation: AtomicU64,
+    hits: AtomicU64,
+    misses: AtomicU64,
+}
+
+// リポジトリ間で共有するクエリ結果のキャッシュ。Default は無効 (常に DB を読む)。
+// 書き込み側は自分が変えたものを invalidate する
+#[derive(Clone, Default)]
+pub struct QueryCache(Option<Arc<Inner>>);
+
+impl std::fmt::Debug for QueryCache {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_tuple("QueryCache").field(&self.0.is_some()).finish()
+    }
+}
+
+impl QueryCache {
+    pub fn new(config: CacheConfig) -> Self {
+        // 一覧は 1 件しか持たない
+        Self(Some(Arc::new(Inner {
+            todo: Cache::builder().max_capacity(config.max_capacity).time_to_live(config.ttl).build(),
+            todos: Cache::builder().max_capacity(1).time_to_live(config.ttl).build(),
+            labels: Cache::builder().max_capacity(1).time_to_live(config.ttl).build(),
+            generation: AtomicU64::new(0),
+            hits: AtomicU64::new(0),
+            misses: AtomicU64::new(0),
+        })))
+    }
+
+    pub fn stats(&self) -> CacheStats {
+        let inner = match &self.0 {
+            Some(inner) => inner,
+            None => return CacheStats::default(),
+        };
+        let hits = inner.hits.load(Ordering::Relaxed);
+        let misses = inner.misses.load(Ordering::Relaxed);
+        CacheStats {
+            enabled: true,
+            hits,
+            misses,
+            hit_rate: if hits + misses == 0 { 0.0 } else { hits as f64 / (hits + misses) as f64 },
+            entries: inner.todo.entry_count() + inner.todos.entry_count() + inner.labels.entry_count(),
+        }
+    }
+
+    pub async fn todo<F>(&self, id: i32, load: F) -> anyhow::Result<TodoEntity>
+    where
+        F: Future<Output = anyhow::Result<TodoEntity>>,
+    {
+        self.get_or_load(|inner| &inner.todo, id, load).await
+    }
+
+    pub async fn todos<F>(&self, load: F) -> anyhow::Result<Vec<TodoEntity>>
+    where
+        F: Future<Output = anyhow::Result<Vec<TodoEntity>>>,
+    {
+        self.get_or_load(|inner| &inner.todos, (), load).await
+    }
+
+    pub async fn labels<F>(&self, load: F) -> anyhow::Result<Vec<Label>>
+    where
+        F: Future<Output = anyhow::Result<Vec<Label>>>,
+    {
+        self.get_or_load(|inner| &inner.labels, (), load).await
+    }
+
+    // エラーはキャッシュしない (NotFound などの downcast をそのまま返すため)
+    async fn get_or_load<K, V, F>(&self, cache: impl Fn(&Inner) -> &Cache<K, V>, key: K, load: F) -> anyhow::Result<V>
+    where
+        K: Hash + Eq + Clone + Send + Sync + 'static,
+        V: Clone + Send + Sync + 'static,
+        F: Future<Output = anyhow::Result<V>>,
+    {
+        let inner = match &self.0 {
+            Some(inner) => inner,
+            None => return load.await,
+        };
+        let cache = cache(inner);
+        let generation = inner.generation.load(Ordering::SeqCst);
+        if let Some(value) = cache.get(&key).await {
+            inner.hits.fetch_add(1, Ordering::Relaxed);
+            return Ok(value);
+        }
+        inner.misses.fetch_add(1, Ordering::Relaxed);
+        let value = load.await?;
+        // 書き込み側は generation を進めてから invalidate するので、
+        // 入れた後に generation が変わっていたら自分で消せば、読み込み中の書き込みで古くなった値は残らない
+        cache.insert(key.clone(), value.clone()).await;
+        if inner.generation.load(Ordering::SeqCst) != generation {
+            cache.invalidate(&key).await;
+        }
+        Ok(value)
+    }
+
+    pub async fn invalidate_todo(&self, id: i32) {
+        if let Some(inner) = &self.0 {
+            inner.generation.fetch_add(1, Ordering::SeqCst);
+            inner.todos.invalidate_all();
+            inner.todo.invalidate(&id).await;
+        }
+    }
+
+    pub fn invalidate_todos(&self) {
+        if let Some(inner) = &self.0 {
+            inner.generation.fetch_add(1, Ordering::SeqCst);
+            inner.todo.invalidate_all();
+            inner.todos.invalidate_all();
+        }
+    }
+
+    pub fn invalidate_labels(&self) {
+        if let Some(inner) = &self.0 {
+            inner.generation.fetch_add(1, Ordering::SeqCst);
+            inner.labels.invalidate_all();
+        }
+    }
+
+    pub fn invalidate_all(&self) {
+        self.invalidate_todos();
+        self.invalidate_labels();
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::repositories::todo::Priority;
+
+    fn todo(id: i32, text: &str) -> TodoEntity {
+        TodoEntity {
+            id,
+            text: text.to_string(),
+            completed: false,
+            version: 1,
+            labels: vec![],
+            completed_at: None,
+            owner_id: None,
+            due_date: None,
+            priority: Priority::Medium,
+            deleted_at: None,
+        }
+    }
+
+    fn cache() -> QueryCache {
+        QueryCache::new(CacheConfig {
+            max_capacity: 10,
+            ttl: Duration::from_secs(60),
+        })
+    }
+
+    #[tokio::test]
+    async fn should_hit_until_invalidated() {
+        let cache = cache();
+        let first = cache.todo(1, async { Ok(todo(1, "first")) }).await.unwrap();
+        let cached = cache.todo(1, async { Ok(todo(1, "second")) }).await.unwrap();
+        assert_eq!(first, cached);
+
+        cache.invalidate_todo(1).await;
+        let reloaded = cache.todo(1, async { Ok(todo(1, "second")) }).await.unwrap();
+        assert_eq!(reloaded.text, "second");
+
+        let stats = cache.stats();
+        assert!(stats.enabled);
+        assert_eq!((stats.hits, stats.misses), (1, 2));
+    }
+
+    #[tokio::test]
+    async fn should_not_keep_value_loaded_during_write() {
+        let cache = cache();
+        let stale = cache
+            .todos(async {
+                // 読み込み中に別のリクエストが書き込んだ
+                cache.invalidate_todo(1).await;
+                Ok(vec![todo(1, "stale")])
+            })
+            .await
+            .unwrap();
+        assert_eq!(stale[0].text, "stale");
+        let fresh = cache.todos(async { Ok(vec![todo(1, "fresh")]) }).await.unwrap();
+        assert_eq!(fresh[0].text, "fresh");
+    }
+
+    #[tokio::test]
+    async fn should_not_cache_errors_or_when_disabled() {
+        let cache = cache();
+        assert!(cache.labels(async { Err(anyhow::anyhow!("boom")) }).await.is_err());
+        assert!(cache.labels(async { Ok(vec![]) }).await.unwrap().is_empty());
+
+        let disabled = QueryCache::default();
+        disabled.labels(async { Ok(vec![]) }).await.unwrap();
+        assert_eq!(disabled.stats(), CacheStats::default());
+    }
+}
@@ -1,14 +1,37 @@
 use axum::async_trait;
 use serde::{Deserialize, Serialize};
 use sqlx::PgPool;
-use super::RepositoryError;
+use crate::events::{self, DomainEvent, SharedEventBus};
+use crate::metrics::Metrics;
+use crate::normalize::{normalize_text, Normalize};
+use super::{
+    cache::QueryCache,
+    quota::{self, Quota},
+    todo::LabelAssignment,
+    RepositoryError,
+};
 use validator::Validate;
 
+// 起動時のウォームアップで prepare しておくクエリ
+const ALL_SQL: &str = r#"
+    SELECT id, name FROM labels
+    ORDER BY id ASC;
+"#;
+
+pub const HOT_STATEMENTS: &[&str] = &[ALL_SQL];
+pub const CANONICAL_QUERIES: &[(&str, &str)] = &[("labels.all", ALL_SQL)];
+
 #[async_trait]
 pub trait LabelRepository: Clone + std::marker::Send + std::marker::Sync + 'static {
     async fn create(&self, payload: CreateLabel) -> anyhow::Result<Label>;
-    async fn all(&self) -> anyhow::Result<Vec<Label>>;
-    async fn delete(&self, id: i32) -> anyhow::Result<()>;
+    // 他のユーザーのラベルは無いものとして NotFound
+    async fn find(&self, id: i32, owner_id: Option<i32>) -> anyhow::Result<LabelDetail>;
+    // owner_id を渡すと、そのユーザーのラベルだけ。None なら絞り込まない
+    async fn all(&self, owner_id: Option<i32>) -> anyhow::Result<Vec<Label>>;
+    // 名前を変える。他のユーザーのラベルは無いものとして NotFound、同じ持ち主に同名のラベルがあれば Duplicate
+    async fn update(&self, id: i32, payload: UpdateLabel, owner_id: Option<i32>) -> anyhow::Result<Label>;
+    // 付いている Todo からは外してから消す。他のユーザーのラベルは無いものとして NotFound
+    async fn delete(&self, id: i32, owner_id: Option<i32>) -> anyhow::Result<()>;
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq, sqlx::FromRow)]
@@ -17,83 +40,319 @@ pub struct Label {
     pub name: String,
 }
 
+// 消す前に使われているかを確かめる用。todo_count はゴミ箱に入っていない Todo のうち、このラベルが付いたものの数
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq, sqlx::FromRow)]
+pub struct LabelDetail {
+    pub id: i32,
+    pub name: String,
+    pub todo_count: i64,
+}
+
 #[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq, Validate)]
 pub struct CreateLabel {
     #[validate(length(min = 1, message = "Can not be empty"))]
     #[validate(length(max = 100, message = "Over name length"))]
     name: String,
+    // リクエストのボディからは受け取らず、ハンドラが認証したユーザーを入れる
+    #[serde(skip)]
+    owner_id: Option<i32>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq, Validate)]
+pub struct UpdateLabel {
+    #[validate(length(min = 1, message = "Can not be empty"))]
+    #[validate(length(max = 100, message = "Over name length"))]
+    name: String,
+}
+
+impl Label {
+    pub fn new(id: i32, name: String) -> Self {
+        Self { id, name }
+    }
+}
+
+impl CreateLabel {
+    pub fn new(name: String) -> Self {
+        Self { name, owner_id: None }
+    }
 
+    pub fn with_owner(mut self, owner_id: i32) -> Self {
+        self.owner_id = Some(owner_id);
+        self
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn owner_id(&self) -> Option<i32> {
+        self.owner_id
+    }
+}
+
+impl Normalize for CreateLabel {
+    fn normalize(&mut self) {
+        self.name = normalize_text(&self.name);
+    }
+}
+
+impl UpdateLabel {
+    pub fn new(name: String) -> Self {
+        Self { name }
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+}
+
+impl Normalize for UpdateLabel {
+    fn normalize(&mut self) {
+        self.name = normalize_text(&self.name);
+    }
 }
 
 #[derive(Debug, Clone)]
 pub struct LabelRepositoryForDb {
     pool: PgPool,
+    quota: Quota,
+    cache: QueryCache,
+    metrics: Metrics,
+    events: SharedEventBus,
 }
 
 impl LabelRepositoryForDb {
     pub fn new(pool: PgPool) -> Self {
-        Self { pool }
+        Self {
+            pool,
+            quota: Quota::default(),
+            cache: QueryCache::default(),
+            metrics: Metrics::default(),
+            events: events::noop(),
+        }
+    }
+
+    pub fn with_quota(mut self, quota: Quota) -> Self {
+        self.quota = quota;
+        self
+    }
+
+    // all の結果をキャッシュする。Todo のリポジトリと同じものを渡せば統計もまとめて見られる
+    pub fn with_cache(mut self, cache: QueryCache) -> Self {
+        self.cache = cache;
+        self
+    }
+
+    pub fn with_metrics(mut self, metrics: Metrics) -> Self {
+        self.metrics = metrics;
+        self
+    }
+
+    pub fn with_events(mut self, events: SharedEventBus) -> Self {
+        self.events = events;
+        self
+    }
+
+    #[tracing::instrument(skip(self), fields(rows = tracing::field::Empty))]
+    async fn load_all(&self) -> anyhow::Result<Vec<Label>> {
+        let _timer = self.metrics.time_query("labels.all", String::new());
+        let labels = sqlx::query_as::<_, Label>(
+            ALL_SQL
+        )
+        .fetch_all(&self.pool)
+        .await?;
+
+        tracing::Span::current().record("rows", labels.len());
+        Ok(labels)
+    }
+
+    #[tracing::instrument(skip(self), fields(rows = tracing::field::Empty))]
+    async fn load_owned(&self, owner_id: i32) -> anyhow::Result<Vec<Label>> {
+        let _timer = self.metrics.time_query("labels.all", format!("owner_id={}", owner_id));
+        let labels = sqlx::query_as::<_, Label>(
+            r#"
+            SELECT id, name FROM labels
+            WHERE owner_id = $1
+            ORDER BY id ASC
+            "#
+        )
+        .bind(owner_id)
+        .fetch_all(&self.pool)
+        .await?;
+
+        tracing::Span::current().record("rows", labels.len());
+        Ok(labels)
     }
 }
 
 #[async_trait]
 impl LabelRepository for LabelRepositoryForDb {
+    #[tracing::instrument(skip_all)]
     async fn create(&self, payload: CreateLabel) -> anyhow::Result<Label> {
-        let optional_label = sqlx::query_as::<_, Label>(
+        let _timer = self.metrics.time_query("labels.create", format!("name_len={}", payload.name.len()));
+        let mut tx = self.pool.begin().await?;
+        quota::check_in_tx(&mut tx, "labels", payload.owner_id, self.quota.max_labels).await?;
+
+        // 同名のラベルが同時に作られても、片方は必ず ON CONFLICT 側に倒れる。
+        // 名前が一意なのは持ち主ごと (labels_owner_name_key)
+        let created = sqlx::query_as::<_, Label>(
             r#"
-            select id, name from labels where name = $1
+            INSERT INTO labels (name, owner_id)
+            VALUES ( $1, $2 )
+            ON CONFLICT ((COALESCE(owner_id, 0)), name) DO NOTHING
+            RETURNING id, name
             "#
         ).bind(payload.name.clone())
-        .fetch_optional(&self.pool)
+        .bind(payload.owner_id)
+        .fetch_optional(&mut tx)
         .await?;
 
-        if let Some(label) = optional_label {
-            // アプリケーションでバリデーションするなら、
-            // どうして DB 側に制約を入れないのだろうか？？？
-            return Err(RepositoryError::Duplicate(label.id).into());
-            // return Ok(label);
-        }
+        let label = match created {
+            Some(label) => label,
+            None => {
+                let id = sqlx::query_scalar::<_, i32>(
+                    r#"
+                    SELECT id FROM labels WHERE name = $1 AND owner_id IS NOT DISTINCT FROM $2
+                    "#
+                ).bind(payload.name)
+                .bind(payload.owner_id)
+                .fetch_one(&mut tx)
+                .await?;
+                return Err(RepositoryError::Duplicate(id).into());
+            }
+        };
 
-        let label = sqlx::query_as::<_, Label>(
+        tx.commit().await?;
+        self.cache.invalidate_labels();
+        self.events.publish(DomainEvent::LabelCreated { label: label.clone() });
+        Ok(label)
+    }
+
+    #[tracing::instrument(skip(self))]
+    async fn find(&self, id: i32, owner_id: Option<i32>) -> anyhow::Result<LabelDetail> {
+        let _timer = self.metrics.time_query("labels.find", format!("id={}, owner_id={:?}", id, owner_id));
+        let label = sqlx::query_as::<_, LabelDetail>(
             r#"
-            INSERT INTO labels (name)
-            VALUES ( $1 )
-            RETURNING *
+            SELECT labels.id, labels.name, COUNT(todos.id) AS todo_count
+            FROM labels
+            LEFT JOIN todo_labels ON todo_labels.label_id = labels.id
+            LEFT JOIN todos ON todos.id = todo_labels.todo_id AND todos.deleted_at IS NULL
+            WHERE labels.id = $1 AND ($2::INTEGER IS NULL OR labels.owner_id = $2)
+            GROUP BY labels.id, labels.name
             "#
-        ).bind(payload.name)
-
-        .fetch_one(&self.pool)
-        .await?;
+        )
+        .bind(id)
+        .bind(owner_id)
+        .fetch_optional(&self.pool)
+        .await?
+        .ok_or(RepositoryError::NotFound(id))?;
 
         Ok(label)
     }
 
-    async fn all(&self) -> anyhow::Result<Vec<Label>> {
-        let labels = sqlx::query_as::<_, Label>(
+    #[tracing::instrument(skip(self))]
+    async fn all(&self, owner_id: Option<i32>) -> anyhow::Result<Vec<Label>> {
+        // キャッシュするのは全体の一覧だけ
+        match owner_id {
+            Some(owner_id) => self.load_owned(owner_id).await,
+            None => self.cache.labels(self.load_all()).await,
+        }
+    }
+
+    #[tracing::instrument(skip(self, payload))]
+    async fn update(&self, id: i32, payload: UpdateLabel, owner_id: Option<i32>) -> anyhow::Result<Label> {
+        let _timer = self.metrics.time_query("labels.update", format!("id={}, owner_id={:?}", id, owner_id));
+        let updated = sqlx::query_as::<_, Label>(
             r#"
-            SELECT id, name FROM labels
-            ORDER BY id ASC;
+            UPDATE labels SET name = $2
+            WHERE id = $1 AND ($3::INTEGER IS NULL OR owner_id = $3)
+            RETURNING id, name
             "#
         )
-        .fetch_all(&self.pool)
-        .await?;
+        .bind(id)
+        .bind(payload.name.clone())
+        .bind(owner_id)
+        .fetch_optional(&self.pool)
+        .await;
+
+        let label = match updated {
+            Ok(Some(label)) => label,
+            Ok(None) => return Err(RepositoryError::NotFound(id).into()),
+            // 同じ持ち主に同名のラベルがあると labels_owner_name_key に引っかかる
+            Err(sqlx::Error::Database(e)) if e.constraint() == Some("labels_owner_name_key") => {
+                let existing = sqlx::query_scalar::<_, i32>(
+                    r#"
+                    SELECT other.id FROM labels other
+                    JOIN labels target ON target.id = $1 AND other.owner_id IS NOT DISTINCT FROM target.owner_id
+                    WHERE other.name = $2
+                    "#
+                ).bind(id)
+                .bind(payload.name)
+                .fetch_one(&self.pool)
+                .await?;
+                return Err(RepositoryError::Duplicate(existing).into());
+            }
+            Err(e) => return Err(e.into()),
+        };
 
-        Ok(labels)
+        // Todo もラベルの名前を持っているので、一緒に捨てる
+        self.cache.invalidate_all();
+        self.events.publish(DomainEvent::LabelUpdated { label: label.clone() });
+        Ok(label)
     }
 
-    async fn delete(&self, id: i32) -> anyhow::Result<()> {
+    #[tracing::instrument(skip(self))]
+    async fn delete(&self, id: i32, owner_id: Option<i32>) -> anyhow::Result<()> {
+        let _timer = self.metrics.time_query("labels.delete", format!("id={}, owner_id={:?}", id, owner_id));
+        let mut tx = self.pool.begin().await?;
+        // 消すまでに他のトランザクションから付けられないように、先に行ロックを取る
+        sqlx::query(
+            r#"
+            SELECT id FROM labels WHERE id = $1 AND ($2::INTEGER IS NULL OR owner_id = $2) FOR UPDATE
+            "#
+        )
+        .bind(id)
+        .bind(owner_id)
+        .fetch_optional(&mut tx)
+        .await?
+        .ok_or(RepositoryError::NotFound(id))?;
+
+        // ゴミ箱の Todo も含めて外す。ラベルの付け外しと同じく、外れた Todo の version を進める
+        let mut detached = sqlx::query_scalar::<_, i32>(
+            r#"
+            WITH detached AS (
+                DELETE FROM todo_labels WHERE label_id = $1
+                RETURNING todo_id
+            )
+            UPDATE todos SET version = version + 1
+            WHERE id IN (SELECT todo_id FROM detached) AND deleted_at IS NULL
+            RETURNING id
+            "#
+        )
+        .bind(id)
+        .fetch_all(&mut tx)
+        .await?;
         sqlx::query(
             r#"
             DELETE FROM labels WHERE id = $1
             "#
         )
         .bind(id)
-        .execute(&self.pool)
-        .await
-        .map_err(|e| match e {
-            sqlx::Error::RowNotFound => RepositoryError::NotFound(id),
-            _ => RepositoryError::Unexpected(e.to_string()),
-        })?;
+        .execute(&mut tx)
+        .await?;
+        tx.commit().await?;
+
+        // Todo もラベルを持っているので、一緒に捨てる
+        self.cache.invalidate_all();
+        if !detached.is_empty() {
+            detached.sort_unstable();
+            self.events.publish(DomainEvent::LabelAssigned {
+                label_id: id,
+                action: LabelAssignment::Detach,
+                todo_ids: detached,
+            });
+        }
+        self.events.publish(DomainEvent::LabelDeleted { id });
 
         Ok(())
     }
@@ -103,6 +362,7 @@ impl LabelRepository for LabelRepositoryForDb {
 #[cfg(feature = "database-test")]
 mod test {
     use super::*;
+    use crate::repositories::todo::{CreateTodo, TodoRepository, TodoRepositoryForDb};
     use dotenv::dotenv;
     use sqlx::PgPool;
     use std::env;
@@ -113,20 +373,68 @@ mod test {
         let database_url = env::var("DATABASE_URL").expect("undefined env: [DATABASE_URL]");
         let pool = PgPool::connect(&database_url)
             .await
-            .expect(&format!("cannot connect database: [{}]", database_url));
+            .unwrap_or_else(|_| panic!("cannot connect database: [{}]", database_url));
         let repo = LabelRepositoryForDb::new(pool.clone());
         let label_text = "test_label";
 
         // create
         // name が unique 制約である場合、DB クリアを毎回やらないと成立しない
         let label = repo
-            .create(CreateLabel {
-                name:label_text.to_string(),
-            })
+            .create(CreateLabel::new(label_text.to_string()))
             .await
             .expect("[create] returned Err");
         assert_eq!(label.name, label_text);
 
+        // find
+        let todo_repo = TodoRepositoryForDb::new(pool.clone());
+        let found = repo.find(label.id, None).await.expect("[find] returned Err");
+        assert_eq!(LabelDetail { id: label.id, name: label_text.to_string(), todo_count: 0 }, found);
+        let todo = todo_repo
+            .create(CreateTodo::new("[label crud_scenario] text".to_string(), vec![label.id]))
+            .await
+            .expect("[create todo] returned Err");
+        assert_eq!(1, repo.find(label.id, None).await.expect("[find] returned Err").todo_count);
+        // ゴミ箱の Todo は数えない
+        todo_repo.delete(todo.id, None).await.expect("[delete todo] returned Err");
+        assert_eq!(0, repo.find(label.id, None).await.expect("[find] returned Err").todo_count);
+        let err = repo.find(-1, None).await.expect_err("[find] missing label returned Ok");
+        assert!(matches!(err.downcast_ref::<RepositoryError>(), Some(RepositoryError::NotFound(-1))));
+
+        // duplicate
+        let err = repo
+            .create(CreateLabel::new(label_text.to_string()))
+            .await
+            .expect_err("[create] duplicated name returned Ok");
+        assert!(matches!(
+            err.downcast_ref::<RepositoryError>(),
+            Some(RepositoryError::Duplicate(id)) if *id == label.id
+        ));
+
+        // update
+        let other = repo
+            .create(CreateLabel::new("test_label_other".to_string()))
+            .await
+            .expect("[create] returned Err");
+        let renamed = repo
+            .update(label.id, UpdateLabel::new("test_label_renamed".to_string()), None)
+            .await
+            .expect("[update] returned Err");
+        assert_eq!(Label::new(label.id, "test_label_renamed".to_string()), renamed);
+        let err = repo
+            .update(label.id, UpdateLabel::new("test_label_other".to_string()), None)
+            .await
+            .expect_err("[update] duplicated name returned Ok");
+        assert!(matches!(
+            err.downcast_ref::<RepositoryError>(),
+            Some(RepositoryError::Duplicate(id)) if *id == other.id
+        ));
+        let err = repo
+            .update(-1, UpdateLabel::new("test_label_missing".to_string()), None)
+            .await
+            .expect_err("[update] missing label returned Ok");
+        assert!(matches!(err.downcast_ref::<RepositoryError>(), Some(RepositoryError::NotFound(-1))));
+        repo.delete(other.id, None).await.expect("[delete] returned Err");
+
         // all
         // let labels = repo.all()
         //     .await
@@ -136,41 +444,115 @@ mod test {
         // // assert!(labels.len() == 1); // DB クリアする前提がないので今はこれが安定して成立しない
         // assert_eq!(label.name, label_text);
 
+        // quota
+        let limited = LabelRepositoryForDb::new(pool.clone()).with_quota(Quota {
+            max_todos: None,
+            max_labels: Some(0),
+        });
+        let err = limited
+            .create(CreateLabel::new("over_quota_label".to_string()))
+            .await
+            .expect_err("[create] over quota returned Ok");
+        assert!(matches!(
+            err.downcast_ref::<RepositoryError>(),
+            Some(RepositoryError::QuotaExceeded { .. })
+        ));
+
         // delete
-        let _ = repo.delete(label.id)
+        let err = repo.delete(label.id, Some(i32::MAX)).await.expect_err("[delete] other owner's label returned Ok");
+        assert!(matches!(err.downcast_ref::<RepositoryError>(), Some(RepositoryError::NotFound(id)) if *id == label.id));
+        // 付いている Todo からは外してから消す
+        let live = todo_repo
+            .create(CreateTodo::new("[label crud_scenario] live".to_string(), vec![label.id]))
+            .await
+            .expect("[create todo] returned Err");
+        repo.delete(label.id, None)
             .await
             .expect("[delete] returned Err");
+        let detached = todo_repo.find(live.id).await.expect("[find todo] returned Err");
+        assert!(detached.labels.is_empty());
+        assert_eq!(live.version + 1, detached.version);
+        let err = repo.delete(label.id, None).await.expect_err("[delete] missing label returned Ok");
+        assert!(matches!(err.downcast_ref::<RepositoryError>(), Some(RepositoryError::NotFound(id)) if *id == label.id));
+        todo_repo.delete(live.id, None).await.expect("[delete todo] returned Err");
         // let labels = repo.all().await.expect("[all] returned Err");
         // 他 (Todo) のテストが途中で失敗するなど、Label が残っている初期状態で
         // このテストが起動してしまうと、次のアサーションは失敗する
         // assert_eq!(labels.len(), 0);
     }
+
+    // 当時のテーブルだけを別のスキーマに作り、同名のラベルを寄せるマイグレーションを流す
+    #[tokio::test]
+    async fn unique_name_migration_merges_colliding_associations() {
+        use sqlx::Executor;
+        use uuid::Uuid;
+
+        dotenv().ok();
+        let database_url = env::var("DATABASE_URL").expect("undefined env: [DATABASE_URL]");
+        let pool = PgPool::connect(&database_url).await.expect("failed to connect database");
+        // ロールバックすればスキーマごと消える
+        let mut tx = pool.begin().await.unwrap();
+        let schema = format!("label_unique_name_{}", Uuid::new_v4().simple());
+        tx.execute(
+            format!(
+                r#"
+                CREATE SCHEMA {schema};
+                SET LOCAL search_path TO {schema};
+                CREATE TABLE labels (id SERIAL PRIMARY KEY, name TEXT NOT NULL);
+                CREATE TABLE todo_labels (id SERIAL PRIMARY KEY, todo_id INTEGER NOT NULL, label_id INTEGER NOT NULL);
+                INSERT INTO labels (id, name) VALUES (1, 'a'), (2, 'a'), (3, 'a'), (4, 'b');
+                -- 10: 寄せた先にも付いている。11: 寄せられる側どうしで重なる。12, 13: 重ならない
+                INSERT INTO todo_labels (todo_id, label_id) VALUES (10, 2), (10, 1), (11, 2), (11, 3), (12, 3), (13, 4);
+                "#,
+                schema = schema
+            )
+            .as_str(),
+        )
+        .await
+        .unwrap();
+        tx.execute(include_str!("../../migrations/20221214120000_label_unique_name.sql")).await.unwrap();
+
+        let labels = sqlx::query_as::<_, (i32, String)>("SELECT id, name FROM labels ORDER BY id")
+            .fetch_all(&mut tx)
+            .await
+            .unwrap();
+        assert_eq!(vec![(1, "a".to_string()), (4, "b".to_string())], labels);
+        let todo_labels = sqlx::query_as::<_, (i32, i32)>("SELECT todo_id, label_id FROM todo_labels ORDER BY todo_id")
+            .fetch_all(&mut tx)
+            .await
+            .unwrap();
+        assert_eq!(vec![(10, 1), (11, 1), (12, 1), (13, 4)], todo_labels);
+        tx.rollback().await.unwrap();
+    }
 }
 
-#[cfg(test)]
+#[cfg(any(test, feature = "test-support"))]
 pub mod test_utils {
     use axum::async_trait;
     use std::{
         collections::HashMap,
         sync::{Arc, RwLock, RwLockReadGuard, RwLockWriteGuard}
     };
-    use crate::repositories::label::CreateLabel;
+    use crate::repositories::label::{CreateLabel, UpdateLabel};
+    use crate::repositories::todo::{test_utils::TodoRepositoryForMemory, AssignLabel, Include, TodoFilter, TodoRepository};
 
     use super::*;
 
-    impl Label {
-        pub fn new(id: i32, name: String) -> Self {
-            Self {
-                id,
-                name,
-            }
+    // ハンドラのテストで、リポジトリの呼ばれ方や特定のエラーを返したときの挙動を確かめる用
+    mockall::mock! {
+        pub LabelRepository {}
+
+        impl Clone for LabelRepository {
+            fn clone(&self) -> Self;
         }
-    }
 
-    #[cfg(test)]
-    impl CreateLabel {
-        pub fn new(name: String) -> Self {
-            Self { name: name }
+        #[async_trait]
+        impl LabelRepository for LabelRepository {
+            async fn create(&self, payload: CreateLabel) -> anyhow::Result<Label>;
+            async fn find(&self, id: i32, owner_id: Option<i32>) -> anyhow::Result<LabelDetail>;
+            async fn all(&self, owner_id: Option<i32>) -> anyhow::Result<Vec<Label>>;
+            async fn update(&self, id: i32, payload: UpdateLabel, owner_id: Option<i32>) -> anyhow::Result<Label>;
+            async fn delete(&self, id: i32, owner_id: Option<i32>) -> anyhow::Result<()>;
         }
     }
 
@@ -179,20 +561,44 @@ pub mod test_utils {
     #[derive(Debug, Clone)]
     pub struct LabelRepositoryForMemory {
         store: Arc<RwLock<LabelDatas>>,
+        // Label は持ち主を持たないので、名前の重複を確かめる用に別に持つ
+        owners: Arc<RwLock<HashMap<i32, i32>>>,
+        quota: Quota,
+        // find の todo_count を数える先。渡さなければ 0
+        todos: Option<TodoRepositoryForMemory>,
+    }
+
+    impl Default for LabelRepositoryForMemory {
+        fn default() -> Self {
+            Self::new()
+        }
     }
 
     impl LabelRepositoryForMemory {
         pub fn new() -> Self {
             LabelRepositoryForMemory {
                 store: Arc::default(),
+                owners: Arc::default(),
+                quota: Quota::default(),
+                todos: None,
             }
         }
 
-        fn write_store_ref(&self) -> RwLockWriteGuard<LabelDatas> {
+        pub fn with_quota(mut self, quota: Quota) -> Self {
+            self.quota = quota;
+            self
+        }
+
+        pub fn with_todos(mut self, todos: TodoRepositoryForMemory) -> Self {
+            self.todos = Some(todos);
+            self
+        }
+
+        fn write_store_ref(&self) -> RwLockWriteGuard<'_, LabelDatas> {
             self.store.write().unwrap()
         }
 
-        fn read_store_ref(&self) -> RwLockReadGuard<LabelDatas> {
+        fn read_store_ref(&self) -> RwLockReadGuard<'_, LabelDatas> {
             self.store.read().unwrap()
         }
     }
@@ -201,24 +607,89 @@ pub mod test_utils {
     impl LabelRepository for LabelRepositoryForMemory {
         async fn create(&self, payload: CreateLabel) -> anyhow::Result<Label> {
             let mut store = self.write_store_ref();
+            let mut owners = self.owners.write().unwrap();
+            if let Some(label) = store
+                .values()
+                .find(|label| label.name == payload.name && owners.get(&label.id).copied() == payload.owner_id)
+            {
+                return Err(RepositoryError::Duplicate(label.id).into());
+            }
+            let used = store.keys().filter(|id| owners.get(id).copied() == payload.owner_id).count();
+            quota::check("labels", self.quota.max_labels, used as i64)?;
             let id = (store.len() + 1) as i32;
             let label = Label::new(id, payload.name.clone());
             store.insert(id, label.clone());
+            if let Some(owner_id) = payload.owner_id {
+                owners.insert(id, owner_id);
+            }
             Ok(label)
         }
 
-        async fn all(&self) -> anyhow::Result<Vec<Label>> {
+        async fn find(&self, id: i32, owner_id: Option<i32>) -> anyhow::Result<LabelDetail> {
+            let label = {
+                let store = self.read_store_ref();
+                let owners = self.owners.read().unwrap();
+                store
+                    .get(&id)
+                    .filter(|_| owner_id.is_none_or(|owner_id| owners.get(&id) == Some(&owner_id)))
+                    .cloned()
+                    .ok_or(RepositoryError::NotFound(id))?
+            };
+            let todo_count = match &self.todos {
+                Some(todos) => {
+                    let filter = TodoFilter { label_id: Some(id), ..TodoFilter::default() };
+                    todos.all(filter, Include::NOTHING).await?.len() as i64
+                }
+                None => 0,
+            };
+            Ok(LabelDetail { id: label.id, name: label.name, todo_count })
+        }
+
+        async fn all(&self, owner_id: Option<i32>) -> anyhow::Result<Vec<Label>> {
             let store = self.read_store_ref();
+            let owners = self.owners.read().unwrap();
             let labels = Vec::from_iter(
-                store.values()
-                    .map(|label| label.clone())
+                store
+                    .values()
+                    .filter(|label| owner_id.is_none_or(|owner_id| owners.get(&label.id) == Some(&owner_id)))
+                    .cloned()
             );
             Ok(labels)
         }
 
-        async fn delete(&self, id: i32) -> anyhow::Result<()> {
+        async fn update(&self, id: i32, payload: UpdateLabel, owner_id: Option<i32>) -> anyhow::Result<Label> {
             let mut store = self.write_store_ref();
-            store.remove(&id).ok_or(RepositoryError::NotFound(id))?;
+            let owners = self.owners.read().unwrap();
+            let owner = owners.get(&id).copied();
+            if !store.contains_key(&id) || owner_id.is_some_and(|owner_id| owner != Some(owner_id)) {
+                return Err(RepositoryError::NotFound(id).into());
+            }
+            if let Some(label) = store
+                .values()
+                .find(|label| label.id != id && label.name == payload.name && owners.get(&label.id).copied() == owner)
+            {
+                return Err(RepositoryError::Duplicate(label.id).into());
+            }
+            let label = Label::new(id, payload.name);
+            store.insert(id, label.clone());
+            Ok(label)
+        }
+
+        async fn delete(&self, id: i32, owner_id: Option<i32>) -> anyhow::Result<()> {
+            {
+                let mut store = self.write_store_ref();
+                let mut owners = self.owners.write().unwrap();
+                if !store.contains_key(&id) || owner_id.is_some_and(|owner_id| owners.get(&id) != Some(&owner_id)) {
+                    return Err(RepositoryError::NotFound(id).into());
+                }
+                store.remove(&id);
+                owners.remove(&id);
+            }
+            if let Some(todos) = &self.todos {
+                let filter = TodoFilter { label_id: Some(id), ..TodoFilter::default() };
+                let ids = todos.all(filter, Include::NOTHING).await?.iter().map(|todo| todo.id).collect();
+                todos.assign_label(id, AssignLabel::new(ids, LabelAssignment::Detach)).await?;
+            }
             Ok(())
         }
     }
@@ -226,6 +697,7 @@ pub mod test_utils {
     #[cfg(test)]
     mod test {
         use super::*;
+        use crate::repositories::todo::CreateTodo;
 
         #[tokio::test]
         async fn label_crud_scenario() {
@@ -237,19 +709,55 @@ pub mod test_utils {
 
             // create
             let label = repo
-                .create(CreateLabel { name: name })
+                .create(CreateLabel::new(name.clone()))
                 .await
                 .expect("failed create label");
             assert_eq!(expected, label);
 
+            // duplicate
+            assert!(repo.create(CreateLabel::new(name.clone())).await.is_err());
+
             // all
-            let labels = repo.all().await.expect("failed get all labels");
+            let labels = repo.all(None).await.expect("failed get all labels");
             assert_eq!(vec![label], labels);
 
+            // find
+            let todos = TodoRepositoryForMemory::new();
+            let repo = repo.with_todos(todos.clone());
+            let found = repo.find(id, None).await.expect("failed find label");
+            assert_eq!(LabelDetail { id, name: name.clone(), todo_count: 0 }, found);
+            todos.create(CreateTodo::new("labeled".to_string(), vec![id])).await.expect("failed create todo");
+            assert_eq!(1, repo.find(id, None).await.expect("failed find label").todo_count);
+            assert!(repo.find(id, Some(1)).await.is_err());
+
+            // quota
+            let limited = LabelRepositoryForMemory::new().with_quota(Quota {
+                max_todos: None,
+                max_labels: Some(0),
+            });
+            assert!(limited.create(CreateLabel::new("over quota".to_string())).await.is_err());
+
+            // update
+            let other = repo.create(CreateLabel::new("other".to_string())).await.expect("failed create label");
+            let renamed = repo
+                .update(id, UpdateLabel::new("renamed".to_string()), None)
+                .await
+                .expect("failed update label");
+            assert_eq!(Label::new(id, "renamed".to_string()), renamed);
+            let err = repo.update(id, UpdateLabel::new("other".to_string()), None).await.unwrap_err();
+            assert!(matches!(err.downcast_ref::<RepositoryError>(), Some(RepositoryError::Duplicate(dup)) if *dup == other.id));
+            assert!(repo.update(id, UpdateLabel::new("renamed".to_string()), Some(1)).await.is_err());
+            repo.delete(other.id, None).await.expect("failed delete label");
+
             // delete
-            repo.delete(id).await.expect("failed delete label");
-            let labels = repo.all().await.expect("failed get all labels");
+            assert!(repo.delete(id, Some(1)).await.is_err());
+            repo.delete(id, None).await.expect("failed delete label");
+            let labels = repo.all(None).await.expect("failed get all labels");
             assert_eq!(labels.len(), 0);
+            let todo = todos.all(TodoFilter::default(), Include::default()).await.expect("failed get all todos").remove(0);
+            assert!(todo.labels.is_empty());
+            let err = repo.delete(id, None).await.unwrap_err();
+            assert!(matches!(err.downcast_ref::<RepositoryError>(), Some(RepositoryError::NotFound(missing)) if *missing == id));
         }
     }
 }
\ No newline at end of file
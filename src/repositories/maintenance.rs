@@ -0,0 +1,274 @@
+use axum::async_trait;
+use serde::{Deserialize, Serialize};
+use sqlx::PgPool;
+
+use crate::db::DistributedLock;
+use super::{label, todo};
+
+#[async_trait]
+pub trait MaintenanceRepository: Clone + std::marker::Send + std::marker::Sync + 'static {
+    async fn rebuild_search_index(&self) -> anyhow::Result<MaintenanceReport>;
+    async fn refresh_stats(&self) -> anyhow::Result<MaintenanceReport>;
+    async fn purge_expired(&self, retention_days: i32) -> anyhow::Result<MaintenanceReport>;
+    async fn explain_queries(&self) -> anyhow::Result<Vec<QueryPlan>>;
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq, Default)]
+pub struct MaintenanceReport {
+    pub tables: Vec<String>,
+    pub purged_rows: u64,
+    // 他のインスタンスが同じジョブを実行中だったので何もしなかった
+    #[serde(default)]
+    pub skipped: bool,
+}
+
+impl MaintenanceReport {
+    fn skipped() -> Self {
+        Self {
+            skipped: true,
+            ..Self::default()
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct QueryPlan {
+    pub name: String,
+    // EXPLAIN (FORMAT JSON) の結果そのまま
+    pub plan: serde_json::Value,
+}
+
+// 検索で使う todos 周りのテーブル
+const SEARCH_TABLES: [&str; 2] = ["todos", "todo_labels"];
+const STATS_TABLES: [&str; 4] = ["todos", "labels", "todo_labels", "sync_mutations"];
+
+#[derive(Debug, Clone)]
+pub struct MaintenanceRepositoryForDb {
+    pool: PgPool,
+    // 複数インスタンスから同時に頼まれても、各ジョブは 1 つのインスタンスでしか実行しない
+    lock: DistributedLock,
+}
+
+impl MaintenanceRepositoryForDb {
+    pub fn new(pool: PgPool) -> Self {
+        Self {
+            lock: DistributedLock::new(pool.clone()),
+            pool,
+        }
+    }
+}
+
+#[async_trait]
+impl MaintenanceRepository for MaintenanceRepositoryForDb {
+    async fn rebuild_search_index(&self) -> anyhow::Result<MaintenanceReport> {
+        let report = self.lock.run_exclusive("job:rebuild_search_index", async {
+            for table in SEARCH_TABLES {
+                // テーブル名は定数なので format! で埋め込んでも問題ない
+                sqlx::query(&format!("REINDEX TABLE {}", table))
+                    .execute(&self.pool)
+                    .await?;
+            }
+            Ok(MaintenanceReport {
+                tables: SEARCH_TABLES.iter().map(|table| table.to_string()).collect(),
+                ..MaintenanceReport::default()
+            })
+        })
+        .await?;
+        Ok(report.unwrap_or_else(MaintenanceReport::skipped))
+    }
+
+    async fn refresh_stats(&self) -> anyhow::Result<MaintenanceReport> {
+        let report = self.lock.run_exclusive("job:refresh_stats", async {
+            sqlx::query(&format!("ANALYZE {}", STATS_TABLES.join(", ")))
+                .execute(&self.pool)
+                .await?;
+            Ok(MaintenanceReport {
+                tables: STATS_TABLES.iter().map(|table| table.to_string()).collect(),
+                ..MaintenanceReport::default()
+            })
+        })
+        .await?;
+        Ok(report.unwrap_or_else(MaintenanceReport::skipped))
+    }
+
+    async fn purge_expired(&self, retention_days: i32) -> anyhow::Result<MaintenanceReport> {
+        let report = self.lock.run_exclusive("job:purge_expired", async {
+            // 同期ミューテーションの記録は再送の判定にしか使わないので、古いものは消してよい
+            let purged = sqlx::query(
+                r#"
+                DELETE FROM sync_mutations
+                WHERE applied_at < now() - make_interval(days => $1)
+                "#
+            )
+            .bind(retention_days)
+            .execute(&self.pool)
+            .await?;
+            // ログインなどの記録も、保持期間を過ぎたら見せない
+            let purged_events = sqlx::query(
+                r#"
+                DELETE FROM auth_events
+                WHERE created_at < now() - make_interval(days => $1)
+                "#
+            )
+            .bind(retention_days)
+            .execute(&self.pool)
+            .await?;
+            // 期限の切れていないトークンが残っている系列は、使用済みのトークンも再利用の検知に使うので消さない
+            let purged_families = sqlx::query(
+                r#"
+                DELETE FROM refresh_token_families f
+                WHERE NOT EXISTS (
+                    SELECT 1 FROM refresh_tokens t WHERE t.family_id = f.id AND t.expires_at > now()
+                )
+                "#
+            )
+            .execute(&self.pool)
+            .await?;
+            // 期限の過ぎたアクセストークンは署名の検証で弾ける
+            let purged_revocations = sqlx::query(
+                r#"
+                DELETE FROM revoked_tokens WHERE expires_at < now()
+                "#
+            )
+            .execute(&self.pool)
+            .await?;
+            Ok(MaintenanceReport {
+                tables: vec![
+                    "sync_mutations".to_string(),
+                    "auth_events".to_string(),
+                    "refresh_token_families".to_string(),
+                    "revoked_tokens".to_string(),
+                ],
+                purged_rows: purged.rows_affected()
+                    + purged_events.rows_affected()
+                    + purged_families.rows_affected()
+                    + purged_revocations.rows_affected(),
+                ..MaintenanceReport::default()
+            })
+        })
+        .await?;
+        Ok(report.unwrap_or_else(MaintenanceReport::skipped))
+    }
+
+    // ANALYZE は実際にクエリを流すので、読み込みだけのクエリでもトランザクションの中で流して捨てる。
+    // $1 を取るクエリには id として 1 を渡す
+    async fn explain_queries(&self) -> anyhow::Result<Vec<QueryPlan>> {
+        let mut tx = self.pool.begin().await?;
+        let mut plans = vec![];
+        for (name, sql) in todo::CANONICAL_QUERIES.iter().chain(label::CANONICAL_QUERIES) {
+            let explain = format!("EXPLAIN (ANALYZE, FORMAT JSON) {}", sql);
+            let query = sqlx::query_scalar::<_, sqlx::types::Json<serde_json::Value>>(&explain);
+            let query = if sql.contains("$1") { query.bind(1) } else { query };
+            let plan = query.fetch_one(&mut tx).await?;
+            plans.push(QueryPlan {
+                name: name.to_string(),
+                plan: plan.0,
+            });
+        }
+        tx.rollback().await?;
+        Ok(plans)
+    }
+}
+
+#[cfg(test)]
+#[cfg(feature = "database-test")]
+mod test {
+    use super::*;
+    use dotenv::dotenv;
+    use std::env;
+    use uuid::Uuid;
+
+    #[tokio::test]
+    async fn maintenance_scenario() {
+        dotenv().ok();
+        let database_url = env::var("DATABASE_URL").expect("undefined env: [DATABASE_URL]");
+        let pool = PgPool::connect(&database_url)
+            .await
+            .unwrap_or_else(|_| panic!("cannot connect database: [{}]", database_url));
+        let repo = MaintenanceRepositoryForDb::new(pool.clone());
+
+        repo.rebuild_search_index().await.expect("[rebuild_search_index] returned Err");
+        repo.refresh_stats().await.expect("[refresh_stats] returned Err");
+
+        let expired = Uuid::new_v4();
+        sqlx::query(
+            r#"
+            INSERT INTO sync_mutations (mutation_id, applied_at) VALUES ($1, now() - interval '40 days')
+            "#
+        )
+        .bind(expired)
+        .execute(&pool)
+        .await
+        .expect("failed to insert sync mutation.");
+        let report = repo.purge_expired(30).await.expect("[purge_expired] returned Err");
+        assert!(report.purged_rows >= 1);
+
+        // 他のインスタンスが実行中なら何もしない
+        let other = MaintenanceRepositoryForDb::new(pool.clone());
+        let report = repo
+            .lock
+            .run_exclusive("job:purge_expired", other.purge_expired(30))
+            .await
+            .unwrap()
+            .unwrap();
+        assert!(report.skipped);
+    }
+
+    #[tokio::test]
+    async fn explain_returns_plans_for_canonical_queries() {
+        dotenv().ok();
+        let database_url = env::var("DATABASE_URL").expect("undefined env: [DATABASE_URL]");
+        let pool = PgPool::connect(&database_url)
+            .await
+            .unwrap_or_else(|_| panic!("cannot connect database: [{}]", database_url));
+        let repo = MaintenanceRepositoryForDb::new(pool);
+
+        let plans = repo.explain_queries().await.expect("[explain_queries] returned Err");
+        let names: Vec<_> = plans.iter().map(|plan| plan.name.as_str()).collect();
+        assert_eq!(vec!["todos.find", "todos.all", "labels.all"], names);
+        for plan in plans {
+            assert!(plan.plan[0]["Plan"]["Node Type"].is_string(), "{}", plan.plan);
+            assert!(plan.plan[0]["Execution Time"].is_number(), "{}", plan.plan);
+        }
+    }
+}
+
+#[cfg(any(test, feature = "test-support"))]
+pub mod test_utils {
+    use super::*;
+
+    // DB を持たないので、何もせずに成功だけ返す
+    #[derive(Debug, Clone)]
+    pub struct MaintenanceRepositoryForMemory;
+
+    impl Default for MaintenanceRepositoryForMemory {
+        fn default() -> Self {
+            Self::new()
+        }
+    }
+
+    impl MaintenanceRepositoryForMemory {
+        pub fn new() -> Self {
+            MaintenanceRepositoryForMemory
+        }
+    }
+
+    #[async_trait]
+    impl MaintenanceRepository for MaintenanceRepositoryForMemory {
+        async fn rebuild_search_index(&self) -> anyhow::Result<MaintenanceReport> {
+            Ok(MaintenanceReport::default())
+        }
+
+        async fn refresh_stats(&self) -> anyhow::Result<MaintenanceReport> {
+            Ok(MaintenanceReport::default())
+        }
+
+        async fn purge_expired(&self, _retention_days: i32) -> anyhow::Result<MaintenanceReport> {
+            Ok(MaintenanceReport::default())
+        }
+
+        async fn explain_queries(&self) -> anyhow::Result<Vec<QueryPlan>> {
+            Ok(vec![])
+        }
+    }
+}
@@ -0,0 +1,127 @@
+use sqlx::{Postgres, Transaction};
+use std::collections::BTreeMap;
+
+use super::RepositoryError;
+
+// リソースごとの作成数の上限。None なら無制限。
+// 持ち主 (ユーザー) ごとの上限で、持ち主の無いもの (認証しないとき) はそれだけでまとめて数える。
+// ゴミ箱の Todo は数えない
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Quota {
+    pub max_todos: Option<i64>,
+    pub max_labels: Option<i64>,
+}
+
+// あと 1 件作ると上限を超えるなら QuotaExceeded を返す
+pub fn check(resource: &str, limit: Option<i64>, used: i64) -> Result<(), RepositoryError> {
+    match limit {
+        Some(limit) if used >= limit => Err(RepositoryError::QuotaExceeded {
+            resource: resource.to_string(),
+            limit,
+        }),
+        _ => Ok(()),
+    }
+}
+
+// 件数の確認と INSERT の間に同じ持ち主の他のトランザクションが割り込まないよう、
+// テーブルと持ち主ごとの advisory lock をトランザクション終了まで取ってから数える
+pub async fn check_in_tx(
+    tx: &mut Transaction<'_, Postgres>,
+    table: &str,
+    owner_id: Option<i32>,
+    limit: Option<i64>,
+) -> anyhow::Result<()> {
+    check_many_in_tx(tx, table, owner_id, limit, 1).await
+}
+
+// count 件まとめて作る場合。1 件でも上限を超えるなら全体を QuotaExceeded にする
+pub async fn check_many_in_tx(
+    tx: &mut Transaction<'_, Postgres>,
+    table: &str,
+    owner_id: Option<i32>,
+    limit: Option<i64>,
+    count: i64,
+) -> anyhow::Result<()> {
+    if limit.is_none() || count == 0 {
+        return Ok(());
+    }
+    sqlx::query("SELECT pg_advisory_xact_lock(hashtext($1), COALESCE($2::INTEGER, 0))")
+        .bind(format!("quota:{}", table))
+        .bind(owner_id)
+        .execute(&mut *tx)
+        .await?;
+    let used = used_in_tx(tx, table, owner_id).await?;
+    check(table, limit, used + count - 1)?;
+    Ok(())
+}
+
+// 持ち主の混ざったものをまとめて作る場合 (インポートなど)。
+// 持ち主ごとに数え、デッドロックしないようロックは持ち主の順に取る
+pub async fn check_owners_in_tx(
+    tx: &mut Transaction<'_, Postgres>,
+    table: &str,
+    limit: Option<i64>,
+    owner_ids: impl IntoIterator<Item = Option<i32>>,
+) -> anyhow::Result<()> {
+    let mut counts = BTreeMap::<Option<i32>, i64>::new();
+    for owner_id in owner_ids {
+        *counts.entry(owner_id).or_default() += 1;
+    }
+    for (owner_id, count) in counts {
+        check_many_in_tx(tx, table, owner_id, limit, count).await?;
+    }
+    Ok(())
+}
+
+// Todo は trigger が持っている件数を読む (ゴミ箱の分は入っていない)。
+// 持ち主の無い Todo の件数は、全体の件数から持ち主のいる分を引く
+async fn used_in_tx(tx: &mut Transaction<'_, Postgres>, table: &str, owner_id: Option<i32>) -> anyhow::Result<i64> {
+    let used = match (table, owner_id) {
+        ("todos", Some(owner_id)) => {
+            sqlx::query_scalar::<_, i64>(
+                "SELECT COALESCE((SELECT open + completed FROM todo_owner_counters WHERE owner_id = $1), 0)",
+            )
+            .bind(owner_id)
+            .fetch_one(&mut *tx)
+            .await?
+        }
+        ("todos", None) => {
+            sqlx::query_scalar::<_, i64>(
+                r#"
+                SELECT (SELECT open + completed FROM todo_counters)
+                    - (SELECT COALESCE(SUM(open + completed), 0)::BIGINT FROM todo_owner_counters)
+                "#
+            )
+            .fetch_one(&mut *tx)
+            .await?
+        }
+        _ => {
+            sqlx::query_scalar::<_, i64>(&format!(
+                "SELECT count(*) FROM {} WHERE COALESCE(owner_id, 0) = COALESCE($1::INTEGER, 0)",
+                table
+            ))
+            .bind(owner_id)
+            .fetch_one(&mut *tx)
+            .await?
+        }
+    };
+    Ok(used)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn check_limit() {
+        assert!(check("todos", None, 1000).is_ok());
+        assert!(check("todos", Some(2), 1).is_ok());
+        match check("todos", Some(2), 2) {
+            Err(RepositoryError::QuotaExceeded { resource, limit }) => {
+                assert_eq!(resource, "todos");
+                assert_eq!(limit, 2);
+            }
+            other => panic!("unexpected result: {:?}", other),
+        }
+    }
+}
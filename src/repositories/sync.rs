@@ -0,0 +1,468 @@
+use serde::{Deserialize, Serialize};
+use std::str::FromStr;
+use uuid::Uuid;
+use validator::{Validate, ValidationErrors};
+
+use crate::{
+    moderation::Moderate,
+    normalize::{normalize_text, Normalize},
+};
+use super::todo::{validate_label_ids, CreateTodo, TodoEntity};
+
+// サーバー側の version とクライアントの base_version が食い違ったときの解決方針
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, Default)]
+#[serde(rename_all = "kebab-case")]
+pub enum ConflictPolicy {
+    #[default]
+    ServerWins,
+    ClientWins,
+    Merge,
+}
+
+impl FromStr for ConflictPolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "server-wins" => Ok(ConflictPolicy::ServerWins),
+            "client-wins" => Ok(ConflictPolicy::ClientWins),
+            "merge" => Ok(ConflictPolicy::Merge),
+            _ => Err(format!("unknown conflict policy: [{}]", s)),
+        }
+    }
+}
+
+// クライアントが base_version の時点で見ていた Todo の値。
+// merge ポリシーで「サーバー側で何が変わったか」を判定するのに使う
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
+pub struct SyncBase {
+    pub text: String,
+    pub completed: bool,
+    pub labels: Vec<i32>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq, Validate)]
+pub struct SyncTodo {
+    pub id: i32,
+    pub base_version: i32,
+    #[validate(length(min = 1, message = "Can not be empty"))]
+    #[validate(length(max = 100, message = "Over text length"))]
+    pub text: Option<String>,
+    pub completed: Option<bool>,
+    #[validate(custom = "validate_label_ids")]
+    pub labels: Option<Vec<i32>>,
+    pub base: Option<SyncBase>,
+}
+
+impl SyncTodo {
+    // 削除は変えるフィールドを持たないので、version の食い違いだけを見る
+    pub fn deletion(id: i32, base_version: i32) -> Self {
+        Self {
+            id,
+            base_version,
+            text: None,
+            completed: None,
+            labels: None,
+            base: None,
+        }
+    }
+}
+
+// クライアント側で積まれた変更 1 件分。
+// mutation_id はクライアントが採番する UUID で、再送時に二重適用しないために使う
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum SyncMutation {
+    Create {
+        mutation_id: Uuid,
+        client_id: Uuid,
+        #[serde(flatten)]
+        todo: CreateTodo,
+    },
+    Update {
+        mutation_id: Uuid,
+        #[serde(flatten)]
+        change: SyncTodo,
+    },
+    // 古い version を見て消そうとしていたら、更新と同じく conflict policy で決める
+    Delete {
+        mutation_id: Uuid,
+        id: i32,
+        base_version: i32,
+    },
+}
+
+impl SyncMutation {
+    pub fn mutation_id(&self) -> Uuid {
+        match self {
+            SyncMutation::Create { mutation_id, .. }
+            | SyncMutation::Update { mutation_id, .. }
+            | SyncMutation::Delete { mutation_id, .. } => *mutation_id,
+        }
+    }
+}
+
+impl Normalize for SyncMutation {
+    fn normalize(&mut self) {
+        match self {
+            SyncMutation::Create { todo, .. } => todo.normalize(),
+            SyncMutation::Update { change, .. } => {
+                if let Some(text) = &change.text {
+                    change.text = Some(normalize_text(text));
+                }
+            }
+            SyncMutation::Delete { .. } => {}
+        }
+    }
+}
+
+impl Normalize for SyncRequest {
+    fn normalize(&mut self) {
+        self.mutations.iter_mut().for_each(Normalize::normalize);
+    }
+}
+
+impl Moderate for SyncRequest {
+    fn texts(&self) -> Vec<&str> {
+        self.mutations
+            .iter()
+            .flat_map(|mutation| match mutation {
+                SyncMutation::Create { todo, .. } => todo.texts(),
+                SyncMutation::Update { change, .. } => change.text.as_deref().into_iter().collect(),
+                SyncMutation::Delete { .. } => vec![],
+            })
+            .collect()
+    }
+}
+
+// validator の derive は enum に対応していないので手で実装する
+impl Validate for SyncMutation {
+    fn validate(&self) -> Result<(), ValidationErrors> {
+        match self {
+            SyncMutation::Create { todo, .. } => todo.validate(),
+            SyncMutation::Update { change, .. } => change.validate(),
+            SyncMutation::Delete { .. } => Ok(()),
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq, Validate)]
+pub struct SyncRequest {
+    // 一つのトランザクションで行ロックを取りながら流すので、一度に送れる数を抑える
+    #[validate(length(max = 100, message = "Over 100 mutations"))]
+    #[validate]
+    pub mutations: Vec<SyncMutation>,
+    pub policy: Option<ConflictPolicy>,
+    // リクエストのボディからは受け取らず、ハンドラが認証したユーザーを入れる
+    #[serde(skip)]
+    owner_id: Option<i32>,
+}
+
+impl SyncRequest {
+    // 作る Todo の持ち主にもなる。更新と削除はこのユーザーの Todo だけが対象
+    pub fn with_owner(mut self, owner_id: i32) -> Self {
+        self.owner_id = Some(owner_id);
+        self.mutations = self
+            .mutations
+            .into_iter()
+            .map(|mutation| match mutation {
+                SyncMutation::Create { mutation_id, client_id, todo } => SyncMutation::Create {
+                    mutation_id,
+                    client_id,
+                    todo: todo.with_owner(owner_id),
+                },
+                mutation => mutation,
+            })
+            .collect();
+        self
+    }
+
+    pub fn owner_id(&self) -> Option<i32> {
+        self.owner_id
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Resolution {
+    ServerWins,
+    ClientWins,
+    Merged,
+    // 同じフィールドが両側で変更されていて、クライアント側で解決が必要
+    Unresolved,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SyncConflict {
+    pub id: i32,
+    pub base_version: i32,
+    pub resolution: Resolution,
+    pub fields: Vec<String>,
+    // 解決後のサーバー側の状態
+    pub server: TodoEntity,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
+pub struct SyncIdMapping {
+    pub client_id: Uuid,
+    pub id: i32,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct SyncResult {
+    pub applied: Vec<TodoEntity>,
+    pub conflicts: Vec<SyncConflict>,
+    pub deleted: Vec<i32>,
+    // クライアント側 ID -> サーバー側 ID の対応
+    pub id_map: Vec<SyncIdMapping>,
+    // 適用済みだったのでスキップしたミューテーション
+    pub skipped: Vec<Uuid>,
+    // サーバー側で削除されていて更新できなかった Todo。ミューテーションは適用済みとして扱う
+    pub missing: Vec<i32>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Decision {
+    Apply(SyncTodo),
+    Conflict {
+        resolution: Resolution,
+        apply: Option<SyncTodo>,
+        fields: Vec<String>,
+    },
+}
+
+fn sorted(labels: &[i32]) -> Vec<i32> {
+    let mut labels = labels.to_vec();
+    labels.sort_unstable();
+    labels
+}
+
+// クライアントが変更しようとしているフィールド名
+fn touched_fields(change: &SyncTodo) -> Vec<String> {
+    let mut fields = vec![];
+    if change.text.is_some() {
+        fields.push("text".to_string());
+    }
+    if change.completed.is_some() {
+        fields.push("completed".to_string());
+    }
+    if change.labels.is_some() {
+        fields.push("labels".to_string());
+    }
+    fields
+}
+
+// base から見て、サーバー側とクライアント側の両方が別の値に変更したフィールド名
+fn overlapping_fields(server: &TodoEntity, base: &SyncBase, change: &SyncTodo) -> Vec<String> {
+    let mut fields = vec![];
+    if let Some(text) = &change.text {
+        if server.text != base.text && *text != server.text {
+            fields.push("text".to_string());
+        }
+    }
+    if let Some(completed) = change.completed {
+        if server.completed != base.completed && completed != server.completed {
+            fields.push("completed".to_string());
+        }
+    }
+    if let Some(labels) = &change.labels {
+        let server_labels: Vec<i32> = sorted(&server.labels.iter().map(|label| label.id).collect::<Vec<_>>());
+        if server_labels != sorted(&base.labels) && sorted(labels) != server_labels {
+            fields.push("labels".to_string());
+        }
+    }
+    fields
+}
+
+pub fn resolve(policy: ConflictPolicy, server: &TodoEntity, change: &SyncTodo) -> Decision {
+    if server.version == change.base_version {
+        return Decision::Apply(change.clone());
+    }
+
+    let fields = match &change.base {
+        Some(base) => overlapping_fields(server, base, change),
+        None => touched_fields(change),
+    };
+
+    match policy {
+        ConflictPolicy::ServerWins => Decision::Conflict {
+            resolution: Resolution::ServerWins,
+            apply: None,
+            fields,
+        },
+        ConflictPolicy::ClientWins => Decision::Conflict {
+            resolution: Resolution::ClientWins,
+            apply: Some(change.clone()),
+            fields,
+        },
+        // base が無いとサーバー側の変更箇所が分からないので、マージは諦める
+        ConflictPolicy::Merge if change.base.is_some() && fields.is_empty() => Decision::Conflict {
+            resolution: Resolution::Merged,
+            apply: Some(change.clone()),
+            fields,
+        },
+        ConflictPolicy::Merge => Decision::Conflict {
+            resolution: Resolution::Unresolved,
+            apply: None,
+            fields,
+        },
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::repositories::{label::Label, todo::Priority};
+
+    fn server() -> TodoEntity {
+        TodoEntity {
+            id: 1,
+            text: "server text".to_string(),
+            completed: false,
+            labels: vec![Label::new(1, "label 1".to_string())],
+            version: 3,
+            completed_at: None,
+            owner_id: None,
+            due_date: None,
+            priority: Priority::Medium,
+            deleted_at: None,
+        }
+    }
+
+    fn change(base_version: i32) -> SyncTodo {
+        SyncTodo {
+            id: 1,
+            base_version,
+            text: None,
+            completed: Some(true),
+            labels: None,
+            base: Some(SyncBase {
+                text: "base text".to_string(),
+                completed: false,
+                labels: vec![1],
+            }),
+        }
+    }
+
+    #[test]
+    fn apply_when_version_matches() {
+        let change = change(3);
+        for policy in [ConflictPolicy::ServerWins, ConflictPolicy::ClientWins, ConflictPolicy::Merge] {
+            assert_eq!(resolve(policy, &server(), &change), Decision::Apply(change.clone()));
+        }
+    }
+
+    #[test]
+    fn server_and_client_wins() {
+        let change = change(2);
+        assert_eq!(
+            resolve(ConflictPolicy::ServerWins, &server(), &change),
+            Decision::Conflict {
+                resolution: Resolution::ServerWins,
+                apply: None,
+                fields: vec![],
+            }
+        );
+        assert_eq!(
+            resolve(ConflictPolicy::ClientWins, &server(), &change),
+            Decision::Conflict {
+                resolution: Resolution::ClientWins,
+                apply: Some(change.clone()),
+                fields: vec![],
+            }
+        );
+    }
+
+    #[test]
+    fn merge_disjoint_fields() {
+        // サーバー側は text だけ、クライアント側は completed だけを変更している
+        let change = change(2);
+        assert_eq!(
+            resolve(ConflictPolicy::Merge, &server(), &change),
+            Decision::Conflict {
+                resolution: Resolution::Merged,
+                apply: Some(change.clone()),
+                fields: vec![],
+            }
+        );
+    }
+
+    #[test]
+    fn merge_overlapping_fields_is_unresolved() {
+        let mut change = change(2);
+        change.text = Some("client text".to_string());
+        assert_eq!(
+            resolve(ConflictPolicy::Merge, &server(), &change),
+            Decision::Conflict {
+                resolution: Resolution::Unresolved,
+                apply: None,
+                fields: vec!["text".to_string()],
+            }
+        );
+
+        // base が無い場合はマージできない
+        let mut change = self::change(2);
+        change.base = None;
+        assert_eq!(
+            resolve(ConflictPolicy::Merge, &server(), &change),
+            Decision::Conflict {
+                resolution: Resolution::Unresolved,
+                apply: None,
+                fields: vec!["completed".to_string()],
+            }
+        );
+    }
+
+    #[test]
+    fn deserialize_mutations() {
+        let request: SyncRequest = serde_json::from_str(
+            r#"{
+                "mutations": [
+                    {
+                        "type": "create",
+                        "mutation_id": "6f1a3c1e-8a52-4c47-9e0a-0d7d4b4c9a11",
+                        "client_id": "0b8e3f0c-2f1d-4b8a-9d53-8c2a7a1d5e22",
+                        "text": "offline todo",
+                        "labels": []
+                    },
+                    {
+                        "type": "delete",
+                        "mutation_id": "a3d5e7f9-1b2c-4d6e-8f0a-1b2c3d4e5f60",
+                        "id": 3,
+                        "base_version": 2
+                    }
+                ]
+            }"#,
+        )
+        .unwrap();
+        assert_eq!(request.mutations.len(), 2);
+        assert_eq!(request.policy, None);
+        assert!(request.validate().is_ok());
+
+        let invalid: SyncRequest = serde_json::from_str(
+            r#"{
+                "mutations": [{
+                    "type": "create",
+                    "mutation_id": "6f1a3c1e-8a52-4c47-9e0a-0d7d4b4c9a11",
+                    "client_id": "0b8e3f0c-2f1d-4b8a-9d53-8c2a7a1d5e22",
+                    "text": "",
+                    "labels": []
+                }]
+            }"#,
+        )
+        .unwrap();
+        assert!(invalid.validate().is_err());
+
+        let mutations: Vec<_> = (0..101)
+            .map(|id| serde_json::json!({"type": "delete", "mutation_id": Uuid::new_v4(), "id": id, "base_version": 1}))
+            .collect();
+        let too_many: SyncRequest = serde_json::from_value(serde_json::json!({ "mutations": mutations })).unwrap();
+        assert!(too_many.validate().is_err());
+    }
+
+    #[test]
+    fn parse_policy() {
+        assert_eq!("merge".parse::<ConflictPolicy>(), Ok(ConflictPolicy::Merge));
+        assert!("unknown".parse::<ConflictPolicy>().is_err());
+    }
+}
@@ -0,0 +1,1028 @@
+use axum::async_trait;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::{FromRow, PgPool, Postgres, Transaction};
+use uuid::Uuid;
+use validator::Validate;
+
+use crate::normalize::Normalize;
+use super::RepositoryError;
+
+#[async_trait]
+pub trait UserRepository: Clone + std::marker::Send + std::marker::Sync + 'static {
+    // パスワードはハッシュにしてから渡す。email が使われていれば Duplicate
+    async fn create(&self, email: String, password_hash: String) -> anyhow::Result<User>;
+    async fn find(&self, id: i32) -> anyhow::Result<User>;
+    // ログイン用。無ければ None
+    async fn find_by_email(&self, email: &str) -> anyhow::Result<Option<User>>;
+    // パスワードはハッシュにしてから渡す
+    async fn update_password(&self, id: i32, password_hash: String) -> anyhow::Result<()>;
+    async fn record_event(&self, event: NewAuthEvent) -> anyhow::Result<()>;
+    // 新しい順に limit 件
+    async fn events(&self, user_id: i32, limit: i64) -> anyhow::Result<Vec<AuthEvent>>;
+    // since より後で、最後にログインできた (または解除された) 後のログインの失敗
+    async fn failed_logins(&self, email: &str, since: DateTime<Utc>) -> anyhow::Result<FailedLogins>;
+    // ログインしたときに refresh トークンの系列 (セッション) を作り、最初のトークンを登録する
+    async fn create_refresh_family(&self, token: NewRefreshToken, user_agent: Option<String>) -> anyhow::Result<()>;
+    // token_id を使用済みにして、同じ系列に next を登録する
+    async fn rotate_refresh_token(&self, token_id: Uuid, next: NewRefreshToken) -> anyhow::Result<RefreshRotation>;
+    // user_id の有効な系列を無効にできたら true
+    async fn revoke_refresh_family(&self, family_id: Uuid, user_id: i32) -> anyhow::Result<bool>;
+    // user_id の系列を全部無効にして、その数を返す
+    async fn revoke_all_refresh_families(&self, user_id: i32) -> anyhow::Result<u64>;
+    // 無効にしておらず、まだ引き換えられるトークンのある系列。最後に使った順
+    async fn sessions(&self, user_id: i32) -> anyhow::Result<Vec<Session>>;
+    // アクセストークンの jti を expires_at まで失効させる
+    async fn revoke_token(&self, token_id: Uuid, expires_at: DateTime<Utc>) -> anyhow::Result<()>;
+    // jti を失効させたか、session_id の系列を無効にしたら true
+    async fn is_token_revoked(&self, token_id: Uuid, session_id: Option<Uuid>) -> anyhow::Result<bool>;
+}
+
+// password_hash をレスポンスに出さないよう、Serialize は derive しない
+#[derive(Debug, Clone, PartialEq, Eq, FromRow)]
+pub struct User {
+    pub id: i32,
+    pub email: String,
+    pub password_hash: String,
+    pub created_at: DateTime<Utc>,
+}
+
+// DB では SMALLINT で持つ。一度使った値は変えない
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Hash, sqlx::Type)]
+#[serde(rename_all = "snake_case")]
+#[repr(i16)]
+pub enum AuthEventKind {
+    Login = 0,
+    LoginFailed = 1,
+    TokenIssued = 2,
+    PasswordChanged = 3,
+    // 管理者がロックを解除した
+    AccountUnlocked = 4,
+    // 使用済みの refresh トークンが使われたので、その系列を無効にした
+    RefreshTokenReused = 5,
+    // POST /auth/revoke
+    TokenRevoked = 6,
+}
+
+// 登録されていない email へのログインの失敗は user_id が無い
+#[derive(Debug, Clone, PartialEq, Eq, FromRow)]
+pub struct AuthEvent {
+    pub id: i64,
+    pub user_id: Option<i32>,
+    pub email: String,
+    pub kind: AuthEventKind,
+    pub user_agent: Option<String>,
+    pub created_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, FromRow)]
+pub struct FailedLogins {
+    pub count: i64,
+    pub last_failed_at: Option<DateTime<Utc>>,
+}
+
+// refresh トークンの jti と、そのトークンの系列
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct NewRefreshToken {
+    pub id: Uuid,
+    pub family_id: Uuid,
+    pub user_id: i32,
+    pub expires_at: DateTime<Utc>,
+}
+
+// ログインごとの refresh トークンの系列。id は系列の id
+#[derive(Debug, Clone, PartialEq, Eq, FromRow)]
+pub struct Session {
+    pub id: Uuid,
+    pub user_agent: Option<String>,
+    pub created_at: DateTime<Utc>,
+    // ログインか、最後に refresh トークンを引き換えたとき
+    pub last_seen_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RefreshRotation {
+    Rotated,
+    // 使用済みのトークンだった。系列ごと無効にしたので、その系列のトークンはもう使えない
+    Reused,
+    // 知らない、期限切れ、無効にした系列のトークン
+    Invalid,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct NewAuthEvent {
+    pub user_id: Option<i32>,
+    pub email: String,
+    pub kind: AuthEventKind,
+    pub user_agent: Option<String>,
+}
+
+impl NewAuthEvent {
+    pub fn new(kind: AuthEventKind, email: String) -> Self {
+        Self {
+            user_id: None,
+            email,
+            kind,
+            user_agent: None,
+        }
+    }
+
+    pub fn user(mut self, user_id: Option<i32>) -> Self {
+        self.user_id = user_id;
+        self
+    }
+
+    pub fn user_agent(mut self, user_agent: Option<String>) -> Self {
+        self.user_agent = user_agent;
+        self
+    }
+}
+
+#[derive(Clone, PartialEq, Eq, Deserialize, Validate)]
+pub struct RegisterUser {
+    #[validate(email(message = "Invalid email"))]
+    #[validate(length(max = 254, message = "Over email length"))]
+    email: String,
+    #[validate(length(min = 8, message = "Too short password"))]
+    #[validate(length(max = 128, message = "Over password length"))]
+    password: String,
+}
+
+// ログインでは形を検証しない。登録できない値なら単に一致しない
+#[derive(Clone, PartialEq, Eq, Deserialize, Validate)]
+pub struct LoginUser {
+    email: String,
+    password: String,
+}
+
+// POST /me/password 用。今のパスワードが合っていなければ変えない
+#[derive(Clone, PartialEq, Eq, Deserialize, Validate)]
+pub struct ChangePassword {
+    current_password: String,
+    #[validate(length(min = 8, message = "Too short password"))]
+    #[validate(length(max = 128, message = "Over password length"))]
+    new_password: String,
+}
+
+// POST /auth/refresh 用
+#[derive(Clone, PartialEq, Eq, Deserialize, Validate)]
+pub struct RefreshToken {
+    refresh_token: String,
+}
+
+// POST /auth/revoke 用。アクセストークンと refresh トークンのどちらでもよい
+#[derive(Clone, PartialEq, Eq, Deserialize, Validate)]
+pub struct RevokeToken {
+    token: String,
+}
+
+impl RegisterUser {
+    pub fn new(email: String, password: String) -> Self {
+        Self { email, password }
+    }
+
+    pub fn email(&self) -> &str {
+        &self.email
+    }
+
+    pub fn password(&self) -> &str {
+        &self.password
+    }
+}
+
+impl LoginUser {
+    pub fn new(email: String, password: String) -> Self {
+        Self { email, password }
+    }
+
+    pub fn email(&self) -> &str {
+        &self.email
+    }
+
+    pub fn password(&self) -> &str {
+        &self.password
+    }
+}
+
+impl ChangePassword {
+    pub fn new(current_password: String, new_password: String) -> Self {
+        Self {
+            current_password,
+            new_password,
+        }
+    }
+
+    pub fn current_password(&self) -> &str {
+        &self.current_password
+    }
+
+    pub fn new_password(&self) -> &str {
+        &self.new_password
+    }
+}
+
+impl RefreshToken {
+    pub fn new(refresh_token: String) -> Self {
+        Self { refresh_token }
+    }
+
+    pub fn refresh_token(&self) -> &str {
+        &self.refresh_token
+    }
+}
+
+impl RevokeToken {
+    pub fn new(token: String) -> Self {
+        Self { token }
+    }
+
+    pub fn token(&self) -> &str {
+        &self.token
+    }
+}
+
+// トレースやパニックのメッセージにパスワードが出ないようにする
+impl std::fmt::Debug for RegisterUser {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("RegisterUser").field("email", &self.email).finish_non_exhaustive()
+    }
+}
+
+impl std::fmt::Debug for LoginUser {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("LoginUser").field("email", &self.email).finish_non_exhaustive()
+    }
+}
+
+impl std::fmt::Debug for ChangePassword {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("ChangePassword").finish_non_exhaustive()
+    }
+}
+
+impl std::fmt::Debug for RefreshToken {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("RefreshToken").finish_non_exhaustive()
+    }
+}
+
+impl std::fmt::Debug for RevokeToken {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("RevokeToken").finish_non_exhaustive()
+    }
+}
+
+// email は大文字小文字を区別せずに扱うので小文字にそろえる。パスワードは触らない
+fn normalize_email(email: &str) -> String {
+    email.trim().to_lowercase()
+}
+
+impl Normalize for RegisterUser {
+    fn normalize(&mut self) {
+        self.email = normalize_email(&self.email);
+    }
+}
+
+impl Normalize for LoginUser {
+    fn normalize(&mut self) {
+        self.email = normalize_email(&self.email);
+    }
+}
+
+// パスワードは触らない
+impl Normalize for ChangePassword {
+    fn normalize(&mut self) {}
+}
+
+impl Normalize for RefreshToken {
+    fn normalize(&mut self) {
+        self.refresh_token = self.refresh_token.trim().to_string();
+    }
+}
+
+impl Normalize for RevokeToken {
+    fn normalize(&mut self) {
+        self.token = self.token.trim().to_string();
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct UserRepositoryForDb {
+    pool: PgPool,
+}
+
+impl UserRepositoryForDb {
+    pub fn new(pool: PgPool) -> Self {
+        Self { pool }
+    }
+}
+
+#[async_trait]
+impl UserRepository for UserRepositoryForDb {
+    #[tracing::instrument(skip(self, password_hash))]
+    async fn create(&self, email: String, password_hash: String) -> anyhow::Result<User> {
+        // 同じ email で同時に登録されても、片方は必ず ON CONFLICT 側に倒れる
+        let created = sqlx::query_as::<_, User>(
+            r#"
+            INSERT INTO users (email, password_hash)
+            VALUES ($1, $2)
+            ON CONFLICT (email) DO NOTHING
+            RETURNING *
+            "#
+        )
+        .bind(&email)
+        .bind(password_hash)
+        .fetch_optional(&self.pool)
+        .await?;
+
+        match created {
+            Some(user) => Ok(user),
+            None => {
+                let user = self
+                    .find_by_email(&email)
+                    .await?
+                    .ok_or_else(|| RepositoryError::Unexpected(format!("user disappeared: [{}]", email)))?;
+                Err(RepositoryError::Duplicate(user.id).into())
+            }
+        }
+    }
+
+    #[tracing::instrument(skip(self))]
+    async fn find(&self, id: i32) -> anyhow::Result<User> {
+        let user = sqlx::query_as::<_, User>(
+            r#"
+            SELECT * FROM users WHERE id = $1
+            "#
+        )
+        .bind(id)
+        .fetch_optional(&self.pool)
+        .await?
+        .ok_or(RepositoryError::NotFound(id))?;
+        Ok(user)
+    }
+
+    #[tracing::instrument(skip(self))]
+    async fn find_by_email(&self, email: &str) -> anyhow::Result<Option<User>> {
+        let user = sqlx::query_as::<_, User>(
+            r#"
+            SELECT * FROM users WHERE email = $1
+            "#
+        )
+        .bind(email)
+        .fetch_optional(&self.pool)
+        .await?;
+        Ok(user)
+    }
+
+    #[tracing::instrument(skip(self, password_hash))]
+    async fn update_password(&self, id: i32, password_hash: String) -> anyhow::Result<()> {
+        let updated = sqlx::query(
+            r#"
+            UPDATE users SET password_hash = $2 WHERE id = $1
+            "#
+        )
+        .bind(id)
+        .bind(password_hash)
+        .execute(&self.pool)
+        .await?;
+        if updated.rows_affected() == 0 {
+            return Err(RepositoryError::NotFound(id).into());
+        }
+        Ok(())
+    }
+
+    #[tracing::instrument(skip(self))]
+    async fn record_event(&self, event: NewAuthEvent) -> anyhow::Result<()> {
+        sqlx::query(
+            r#"
+            INSERT INTO auth_events (user_id, email, kind, user_agent)
+            VALUES ($1, $2, $3, $4)
+            "#
+        )
+        .bind(event.user_id)
+        .bind(event.email)
+        .bind(event.kind)
+        .bind(event.user_agent)
+        .execute(&self.pool)
+        .await?;
+        Ok(())
+    }
+
+    #[tracing::instrument(skip(self))]
+    async fn events(&self, user_id: i32, limit: i64) -> anyhow::Result<Vec<AuthEvent>> {
+        let events = sqlx::query_as::<_, AuthEvent>(
+            r#"
+            SELECT * FROM auth_events
+            WHERE user_id = $1
+            ORDER BY created_at DESC, id DESC
+            LIMIT $2
+            "#
+        )
+        .bind(user_id)
+        .bind(limit)
+        .fetch_all(&self.pool)
+        .await?;
+        Ok(events)
+    }
+
+    #[tracing::instrument(skip(self))]
+    async fn failed_logins(&self, email: &str, since: DateTime<Utc>) -> anyhow::Result<FailedLogins> {
+        let failed = sqlx::query_as::<_, FailedLogins>(
+            r#"
+            SELECT COUNT(*) AS count, MAX(created_at) AS last_failed_at
+            FROM auth_events
+            WHERE email = $1 AND kind = $3 AND created_at > $2
+                AND created_at > COALESCE(
+                    (SELECT MAX(created_at) FROM auth_events WHERE email = $1 AND kind IN ($4, $5)),
+                    '-infinity'
+                )
+            "#
+        )
+        .bind(email)
+        .bind(since)
+        .bind(AuthEventKind::LoginFailed)
+        .bind(AuthEventKind::Login)
+        .bind(AuthEventKind::AccountUnlocked)
+        .fetch_one(&self.pool)
+        .await?;
+        Ok(failed)
+    }
+
+    #[tracing::instrument(skip(self))]
+    async fn create_refresh_family(&self, token: NewRefreshToken, user_agent: Option<String>) -> anyhow::Result<()> {
+        let mut tx = self.pool.begin().await?;
+        sqlx::query(
+            r#"
+            INSERT INTO refresh_token_families (id, user_id, user_agent) VALUES ($1, $2, $3)
+            "#
+        )
+        .bind(token.family_id)
+        .bind(token.user_id)
+        .bind(user_agent)
+        .execute(&mut tx)
+        .await?;
+        insert_refresh_token(&mut tx, token).await?;
+        tx.commit().await?;
+        Ok(())
+    }
+
+    #[tracing::instrument(skip(self))]
+    async fn rotate_refresh_token(&self, token_id: Uuid, next: NewRefreshToken) -> anyhow::Result<RefreshRotation> {
+        let mut tx = self.pool.begin().await?;
+        // 同じトークンで同時に来ても、後のほうは使用済みのトークンとして扱われる
+        let current = sqlx::query_as::<_, (Option<DateTime<Utc>>, DateTime<Utc>, Option<DateTime<Utc>>)>(
+            r#"
+            SELECT t.used_at, t.expires_at, f.revoked_at
+            FROM refresh_tokens t
+            JOIN refresh_token_families f ON f.id = t.family_id
+            WHERE t.id = $1 AND t.family_id = $2 AND f.user_id = $3
+            FOR UPDATE OF t, f
+            "#
+        )
+        .bind(token_id)
+        .bind(next.family_id)
+        .bind(next.user_id)
+        .fetch_optional(&mut tx)
+        .await?;
+        let (used_at, expires_at) = match current {
+            Some((used_at, expires_at, None)) => (used_at, expires_at),
+            _ => return Ok(RefreshRotation::Invalid),
+        };
+        if used_at.is_some() {
+            sqlx::query(
+                r#"
+                UPDATE refresh_token_families SET revoked_at = now() WHERE id = $1
+                "#
+            )
+            .bind(next.family_id)
+            .execute(&mut tx)
+            .await?;
+            tx.commit().await?;
+            return Ok(RefreshRotation::Reused);
+        }
+        if expires_at <= Utc::now() {
+            return Ok(RefreshRotation::Invalid);
+        }
+        sqlx::query(
+            r#"
+            UPDATE refresh_tokens SET used_at = now() WHERE id = $1
+            "#
+        )
+        .bind(token_id)
+        .execute(&mut tx)
+        .await?;
+        sqlx::query(
+            r#"
+            UPDATE refresh_token_families SET last_seen_at = now() WHERE id = $1
+            "#
+        )
+        .bind(next.family_id)
+        .execute(&mut tx)
+        .await?;
+        insert_refresh_token(&mut tx, next).await?;
+        tx.commit().await?;
+        Ok(RefreshRotation::Rotated)
+    }
+
+    #[tracing::instrument(skip(self))]
+    async fn revoke_refresh_family(&self, family_id: Uuid, user_id: i32) -> anyhow::Result<bool> {
+        let revoked = sqlx::query(
+            r#"
+            UPDATE refresh_token_families SET revoked_at = now()
+            WHERE id = $1 AND user_id = $2 AND revoked_at IS NULL
+            "#
+        )
+        .bind(family_id)
+        .bind(user_id)
+        .execute(&self.pool)
+        .await?;
+        Ok(revoked.rows_affected() > 0)
+    }
+
+    #[tracing::instrument(skip(self))]
+    async fn revoke_all_refresh_families(&self, user_id: i32) -> anyhow::Result<u64> {
+        let revoked = sqlx::query(
+            r#"
+            UPDATE refresh_token_families SET revoked_at = now()
+            WHERE user_id = $1 AND revoked_at IS NULL
+            "#
+        )
+        .bind(user_id)
+        .execute(&self.pool)
+        .await?;
+        Ok(revoked.rows_affected())
+    }
+
+    #[tracing::instrument(skip(self))]
+    async fn sessions(&self, user_id: i32) -> anyhow::Result<Vec<Session>> {
+        let sessions = sqlx::query_as::<_, Session>(
+            r#"
+            SELECT f.id, f.user_agent, f.created_at, f.last_seen_at
+            FROM refresh_token_families f
+            WHERE f.user_id = $1 AND f.revoked_at IS NULL
+                AND EXISTS (
+                    SELECT 1 FROM refresh_tokens t
+                    WHERE t.family_id = f.id AND t.used_at IS NULL AND t.expires_at > now()
+                )
+            ORDER BY f.last_seen_at DESC, f.created_at DESC
+            "#
+        )
+        .bind(user_id)
+        .fetch_all(&self.pool)
+        .await?;
+        Ok(sessions)
+    }
+
+    #[tracing::instrument(skip(self))]
+    async fn revoke_token(&self, token_id: Uuid, expires_at: DateTime<Utc>) -> anyhow::Result<()> {
+        sqlx::query(
+            r#"
+            INSERT INTO revoked_tokens (id, expires_at) VALUES ($1, $2)
+            ON CONFLICT (id) DO NOTHING
+            "#
+        )
+        .bind(token_id)
+        .bind(expires_at)
+        .execute(&self.pool)
+        .await?;
+        Ok(())
+    }
+
+    #[tracing::instrument(skip(self))]
+    async fn is_token_revoked(&self, token_id: Uuid, session_id: Option<Uuid>) -> anyhow::Result<bool> {
+        let revoked = sqlx::query_scalar::<_, bool>(
+            r#"
+            SELECT EXISTS (SELECT 1 FROM revoked_tokens WHERE id = $1)
+                OR EXISTS (SELECT 1 FROM refresh_token_families WHERE id = $2 AND revoked_at IS NOT NULL)
+            "#
+        )
+        .bind(token_id)
+        .bind(session_id)
+        .fetch_one(&self.pool)
+        .await?;
+        Ok(revoked)
+    }
+}
+
+async fn insert_refresh_token(tx: &mut Transaction<'_, Postgres>, token: NewRefreshToken) -> anyhow::Result<()> {
+    sqlx::query(
+        r#"
+        INSERT INTO refresh_tokens (id, family_id, expires_at) VALUES ($1, $2, $3)
+        "#
+    )
+    .bind(token.id)
+    .bind(token.family_id)
+    .bind(token.expires_at)
+    .execute(tx)
+    .await?;
+    Ok(())
+}
+
+#[cfg(test)]
+#[cfg(feature = "database-test")]
+mod test {
+    use super::*;
+    use dotenv::dotenv;
+    use std::env;
+    use uuid::Uuid;
+
+    #[tokio::test]
+    async fn crud_scenario() {
+        dotenv().ok();
+        let database_url = env::var("DATABASE_URL").expect("undefined env: [DATABASE_URL]");
+        let pool = PgPool::connect(&database_url)
+            .await
+            .unwrap_or_else(|_| panic!("cannot connect database: [{}]", database_url));
+        let repo = UserRepositoryForDb::new(pool.clone());
+        let email = format!("{}@example.com", Uuid::new_v4().simple());
+
+        // create
+        let user = repo
+            .create(email.clone(), "hash".to_string())
+            .await
+            .expect("[create] returned Err");
+        assert_eq!(user.email, email);
+
+        // duplicate
+        let err = repo
+            .create(email.clone(), "other hash".to_string())
+            .await
+            .expect_err("[create] duplicated email returned Ok");
+        assert!(matches!(
+            err.downcast_ref::<RepositoryError>(),
+            Some(RepositoryError::Duplicate(id)) if *id == user.id
+        ));
+
+        // find
+        assert_eq!(user, repo.find(user.id).await.expect("[find] returned Err"));
+        assert_eq!(Some(user.clone()), repo.find_by_email(&email).await.expect("[find_by_email] returned Err"));
+        assert_eq!(None, repo.find_by_email("nobody@example.com").await.expect("[find_by_email] returned Err"));
+
+        // update_password
+        repo.update_password(user.id, "new hash".to_string())
+            .await
+            .expect("[update_password] returned Err");
+        assert_eq!("new hash", repo.find(user.id).await.unwrap().password_hash);
+
+        // events
+        for kind in [AuthEventKind::LoginFailed, AuthEventKind::Login, AuthEventKind::PasswordChanged] {
+            let event = NewAuthEvent::new(kind, email.clone())
+                .user(Some(user.id))
+                .user_agent(Some("curl/7.88".to_string()));
+            repo.record_event(event).await.expect("[record_event] returned Err");
+        }
+        let events = repo.events(user.id, 2).await.expect("[events] returned Err");
+        assert_eq!(
+            vec![AuthEventKind::PasswordChanged, AuthEventKind::Login],
+            events.iter().map(|event| event.kind).collect::<Vec<_>>()
+        );
+        assert_eq!(Some("curl/7.88"), events[0].user_agent.as_deref());
+
+        // failed_logins はログインできた後の失敗だけ数える
+        let since = Utc::now() - chrono::Duration::hours(1);
+        assert_eq!(0, repo.failed_logins(&email, since).await.expect("[failed_logins] returned Err").count);
+        for kind in [AuthEventKind::LoginFailed, AuthEventKind::LoginFailed] {
+            repo.record_event(NewAuthEvent::new(kind, email.clone())).await.unwrap();
+        }
+        let failed = repo.failed_logins(&email, since).await.expect("[failed_logins] returned Err");
+        assert_eq!(2, failed.count);
+        assert!(failed.last_failed_at.is_some());
+        assert_eq!(0, repo.failed_logins(&email, Utc::now()).await.unwrap().count);
+        let event = NewAuthEvent::new(AuthEventKind::AccountUnlocked, email.clone()).user(Some(user.id));
+        repo.record_event(event).await.unwrap();
+        assert_eq!(0, repo.failed_logins(&email, since).await.unwrap().count);
+
+        // refresh トークン
+        let token = |family_id| NewRefreshToken {
+            id: Uuid::new_v4(),
+            family_id,
+            user_id: user.id,
+            expires_at: Utc::now() + chrono::Duration::hours(1),
+        };
+        let first = token(Uuid::new_v4());
+        repo.create_refresh_family(first, Some("curl/7.88".to_string()))
+            .await
+            .expect("[create_refresh_family] returned Err");
+        let second = token(first.family_id);
+        assert_eq!(RefreshRotation::Rotated, repo.rotate_refresh_token(first.id, second).await.unwrap());
+        // 系列の違うトークンとしては回せない
+        let other = token(Uuid::new_v4());
+        assert_eq!(RefreshRotation::Invalid, repo.rotate_refresh_token(second.id, other).await.unwrap());
+        // 使用済みのトークンがまた来たら、系列ごと使えなくなる
+        assert_eq!(RefreshRotation::Reused, repo.rotate_refresh_token(first.id, token(first.family_id)).await.unwrap());
+        assert_eq!(RefreshRotation::Invalid, repo.rotate_refresh_token(second.id, token(first.family_id)).await.unwrap());
+
+        // 他のユーザーの系列は無効にできない
+        let family = token(Uuid::new_v4());
+        repo.create_refresh_family(family, None).await.unwrap();
+        assert!(!repo
+            .revoke_refresh_family(family.family_id, user.id + 1)
+            .await
+            .expect("[revoke_refresh_family] returned Err"));
+        let next = token(family.family_id);
+        assert_eq!(RefreshRotation::Rotated, repo.rotate_refresh_token(family.id, next).await.unwrap());
+        // 無効にした系列は一覧に出さない
+        let sessions = repo.sessions(user.id).await.expect("[sessions] returned Err");
+        assert_eq!(vec![family.family_id], sessions.iter().map(|session| session.id).collect::<Vec<_>>());
+        assert!(!repo.is_token_revoked(Uuid::new_v4(), Some(family.family_id)).await.unwrap());
+        assert!(repo.revoke_refresh_family(family.family_id, user.id).await.unwrap());
+        assert!(!repo.revoke_refresh_family(family.family_id, user.id).await.unwrap());
+        assert_eq!(RefreshRotation::Invalid, repo.rotate_refresh_token(next.id, token(family.family_id)).await.unwrap());
+        assert!(repo.is_token_revoked(Uuid::new_v4(), Some(family.family_id)).await.unwrap());
+
+        for _ in 0..2 {
+            repo.create_refresh_family(token(Uuid::new_v4()), None).await.unwrap();
+        }
+        assert_eq!(2, repo.sessions(user.id).await.unwrap().len());
+        assert_eq!(2, repo.revoke_all_refresh_families(user.id).await.expect("[revoke_all_refresh_families] returned Err"));
+        assert!(repo.sessions(user.id).await.unwrap().is_empty());
+
+        // アクセストークンの失効
+        let token_id = Uuid::new_v4();
+        assert!(!repo.is_token_revoked(token_id, None).await.expect("[is_token_revoked] returned Err"));
+        let expires_at = Utc::now() + chrono::Duration::hours(1);
+        repo.revoke_token(token_id, expires_at).await.expect("[revoke_token] returned Err");
+        repo.revoke_token(token_id, expires_at).await.expect("[revoke_token] twice returned Err");
+        assert!(repo.is_token_revoked(token_id, None).await.unwrap());
+        sqlx::query("DELETE FROM revoked_tokens WHERE id = $1")
+            .bind(token_id)
+            .execute(&pool)
+            .await
+            .unwrap();
+
+        sqlx::query("DELETE FROM users WHERE id = $1")
+            .bind(user.id)
+            .execute(&pool)
+            .await
+            .unwrap();
+    }
+}
+
+#[cfg(any(test, feature = "test-support"))]
+pub mod test_utils {
+    use axum::async_trait;
+    use chrono::Utc;
+    use std::{
+        collections::{HashMap, HashSet},
+        sync::{Arc, RwLock, RwLockReadGuard, RwLockWriteGuard}
+    };
+
+    use super::*;
+
+    // ハンドラのテストで、リポジトリの呼ばれ方や特定のエラーを返したときの挙動を確かめる用
+    mockall::mock! {
+        pub UserRepository {}
+
+        impl Clone for UserRepository {
+            fn clone(&self) -> Self;
+        }
+
+        #[async_trait]
+        impl UserRepository for UserRepository {
+            async fn create(&self, email: String, password_hash: String) -> anyhow::Result<User>;
+            async fn find(&self, id: i32) -> anyhow::Result<User>;
+            async fn find_by_email(&self, email: &str) -> anyhow::Result<Option<User>>;
+            async fn update_password(&self, id: i32, password_hash: String) -> anyhow::Result<()>;
+            async fn record_event(&self, event: NewAuthEvent) -> anyhow::Result<()>;
+            async fn events(&self, user_id: i32, limit: i64) -> anyhow::Result<Vec<AuthEvent>>;
+            async fn failed_logins(&self, email: &str, since: DateTime<Utc>) -> anyhow::Result<FailedLogins>;
+            async fn create_refresh_family(&self, token: NewRefreshToken, user_agent: Option<String>) -> anyhow::Result<()>;
+            async fn rotate_refresh_token(&self, token_id: Uuid, next: NewRefreshToken) -> anyhow::Result<RefreshRotation>;
+            async fn revoke_refresh_family(&self, family_id: Uuid, user_id: i32) -> anyhow::Result<bool>;
+            async fn revoke_all_refresh_families(&self, user_id: i32) -> anyhow::Result<u64>;
+            async fn sessions(&self, user_id: i32) -> anyhow::Result<Vec<Session>>;
+            async fn revoke_token(&self, token_id: Uuid, expires_at: DateTime<Utc>) -> anyhow::Result<()>;
+            async fn is_token_revoked(&self, token_id: Uuid, session_id: Option<Uuid>) -> anyhow::Result<bool>;
+        }
+    }
+
+    type UserDatas = HashMap<i32, User>;
+
+    #[derive(Debug, Clone, Copy)]
+    struct RefreshTokenState {
+        token: NewRefreshToken,
+        used: bool,
+    }
+
+    #[derive(Debug, Clone)]
+    struct SessionState {
+        session: Session,
+        user_id: i32,
+        revoked: bool,
+    }
+
+    #[derive(Debug, Default)]
+    struct RefreshTokens {
+        sessions: HashMap<Uuid, SessionState>,
+        tokens: HashMap<Uuid, RefreshTokenState>,
+    }
+
+    #[derive(Debug, Clone)]
+    pub struct UserRepositoryForMemory {
+        store: Arc<RwLock<UserDatas>>,
+        // 記録した順
+        events: Arc<RwLock<Vec<AuthEvent>>>,
+        refresh_tokens: Arc<RwLock<RefreshTokens>>,
+        // 期限が過ぎても消さない
+        revoked_tokens: Arc<RwLock<HashSet<Uuid>>>,
+    }
+
+    impl Default for UserRepositoryForMemory {
+        fn default() -> Self {
+            Self::new()
+        }
+    }
+
+    impl UserRepositoryForMemory {
+        pub fn new() -> Self {
+            UserRepositoryForMemory {
+                store: Arc::default(),
+                events: Arc::default(),
+                refresh_tokens: Arc::default(),
+                revoked_tokens: Arc::default(),
+            }
+        }
+
+        fn write_store_ref(&self) -> RwLockWriteGuard<'_, UserDatas> {
+            self.store.write().unwrap()
+        }
+
+        fn read_store_ref(&self) -> RwLockReadGuard<'_, UserDatas> {
+            self.store.read().unwrap()
+        }
+    }
+
+    #[async_trait]
+    impl UserRepository for UserRepositoryForMemory {
+        async fn create(&self, email: String, password_hash: String) -> anyhow::Result<User> {
+            let mut store = self.write_store_ref();
+            if let Some(user) = store.values().find(|user| user.email == email) {
+                return Err(RepositoryError::Duplicate(user.id).into());
+            }
+            let id = (store.len() + 1) as i32;
+            let user = User {
+                id,
+                email,
+                password_hash,
+                created_at: Utc::now(),
+            };
+            store.insert(id, user.clone());
+            Ok(user)
+        }
+
+        async fn find(&self, id: i32) -> anyhow::Result<User> {
+            let store = self.read_store_ref();
+            let user = store.get(&id).cloned().ok_or(RepositoryError::NotFound(id))?;
+            Ok(user)
+        }
+
+        async fn find_by_email(&self, email: &str) -> anyhow::Result<Option<User>> {
+            let store = self.read_store_ref();
+            Ok(store.values().find(|user| user.email == email).cloned())
+        }
+
+        async fn update_password(&self, id: i32, password_hash: String) -> anyhow::Result<()> {
+            let mut store = self.write_store_ref();
+            let user = store.get_mut(&id).ok_or(RepositoryError::NotFound(id))?;
+            user.password_hash = password_hash;
+            Ok(())
+        }
+
+        async fn record_event(&self, event: NewAuthEvent) -> anyhow::Result<()> {
+            let mut events = self.events.write().unwrap();
+            let id = (events.len() + 1) as i64;
+            events.push(AuthEvent {
+                id,
+                user_id: event.user_id,
+                email: event.email,
+                kind: event.kind,
+                user_agent: event.user_agent,
+                created_at: Utc::now(),
+            });
+            Ok(())
+        }
+
+        async fn events(&self, user_id: i32, limit: i64) -> anyhow::Result<Vec<AuthEvent>> {
+            let events = self.events.read().unwrap();
+            Ok(events
+                .iter()
+                .rev()
+                .filter(|event| event.user_id == Some(user_id))
+                .take(limit as usize)
+                .cloned()
+                .collect())
+        }
+
+        async fn failed_logins(&self, email: &str, since: DateTime<Utc>) -> anyhow::Result<FailedLogins> {
+            let events = self.events.read().unwrap();
+            let failures = events
+                .iter()
+                .filter(|event| event.email == email)
+                .rev()
+                .take_while(|event| !matches!(event.kind, AuthEventKind::Login | AuthEventKind::AccountUnlocked))
+                .filter(|event| event.kind == AuthEventKind::LoginFailed && event.created_at > since);
+            Ok(failures.fold(FailedLogins::default(), |failed, event| FailedLogins {
+                count: failed.count + 1,
+                last_failed_at: failed.last_failed_at.max(Some(event.created_at)),
+            }))
+        }
+
+        async fn create_refresh_family(&self, token: NewRefreshToken, user_agent: Option<String>) -> anyhow::Result<()> {
+            let mut refresh_tokens = self.refresh_tokens.write().unwrap();
+            let now = Utc::now();
+            let session = Session {
+                id: token.family_id,
+                user_agent,
+                created_at: now,
+                last_seen_at: now,
+            };
+            refresh_tokens.sessions.insert(
+                token.family_id,
+                SessionState {
+                    session,
+                    user_id: token.user_id,
+                    revoked: false,
+                },
+            );
+            refresh_tokens.tokens.insert(token.id, RefreshTokenState { token, used: false });
+            Ok(())
+        }
+
+        async fn rotate_refresh_token(&self, token_id: Uuid, next: NewRefreshToken) -> anyhow::Result<RefreshRotation> {
+            let mut refresh_tokens = self.refresh_tokens.write().unwrap();
+            let current = match refresh_tokens.tokens.get(&token_id).copied() {
+                Some(current) if current.token.family_id == next.family_id && current.token.user_id == next.user_id => current,
+                _ => return Ok(RefreshRotation::Invalid),
+            };
+            let session = match refresh_tokens.sessions.get_mut(&next.family_id) {
+                Some(session) if !session.revoked => session,
+                _ => return Ok(RefreshRotation::Invalid),
+            };
+            if current.used {
+                session.revoked = true;
+                return Ok(RefreshRotation::Reused);
+            }
+            if current.token.expires_at <= Utc::now() {
+                return Ok(RefreshRotation::Invalid);
+            }
+            session.session.last_seen_at = Utc::now();
+            refresh_tokens.tokens.insert(token_id, RefreshTokenState { used: true, ..current });
+            refresh_tokens.tokens.insert(next.id, RefreshTokenState { token: next, used: false });
+            Ok(RefreshRotation::Rotated)
+        }
+
+        async fn revoke_refresh_family(&self, family_id: Uuid, user_id: i32) -> anyhow::Result<bool> {
+            let mut refresh_tokens = self.refresh_tokens.write().unwrap();
+            match refresh_tokens.sessions.get_mut(&family_id) {
+                Some(session) if session.user_id == user_id && !session.revoked => {
+                    session.revoked = true;
+                    Ok(true)
+                }
+                _ => Ok(false),
+            }
+        }
+
+        async fn revoke_all_refresh_families(&self, user_id: i32) -> anyhow::Result<u64> {
+            let mut refresh_tokens = self.refresh_tokens.write().unwrap();
+            let mut revoked = 0;
+            for session in refresh_tokens.sessions.values_mut() {
+                if session.user_id == user_id && !session.revoked {
+                    session.revoked = true;
+                    revoked += 1;
+                }
+            }
+            Ok(revoked)
+        }
+
+        async fn sessions(&self, user_id: i32) -> anyhow::Result<Vec<Session>> {
+            let refresh_tokens = self.refresh_tokens.read().unwrap();
+            let now = Utc::now();
+            let mut sessions: Vec<Session> = refresh_tokens
+                .sessions
+                .values()
+                .filter(|session| session.user_id == user_id && !session.revoked)
+                .filter(|session| {
+                    refresh_tokens.tokens.values().any(|state| {
+                        state.token.family_id == session.session.id && !state.used && state.token.expires_at > now
+                    })
+                })
+                .map(|session| session.session.clone())
+                .collect();
+            sessions.sort_by(|a, b| b.last_seen_at.cmp(&a.last_seen_at).then(b.created_at.cmp(&a.created_at)));
+            Ok(sessions)
+        }
+
+        async fn revoke_token(&self, token_id: Uuid, _expires_at: DateTime<Utc>) -> anyhow::Result<()> {
+            self.revoked_tokens.write().unwrap().insert(token_id);
+            Ok(())
+        }
+
+        async fn is_token_revoked(&self, token_id: Uuid, session_id: Option<Uuid>) -> anyhow::Result<bool> {
+            let session_revoked = session_id.is_some_and(|session_id| {
+                let refresh_tokens = self.refresh_tokens.read().unwrap();
+                refresh_tokens.sessions.get(&session_id).is_some_and(|session| session.revoked)
+            });
+            Ok(session_revoked || self.revoked_tokens.read().unwrap().contains(&token_id))
+        }
+    }
+}
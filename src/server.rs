@@ -0,0 +1,101 @@
+use std::{env, net::SocketAddr, net::TcpListener, process};
+use tokio::net::TcpSocket;
+
+const LISTEN_BACKLOG: u32 = 1024;
+
+// systemd のソケットアクティベーションで渡される最初の fd
+#[cfg(unix)]
+const SD_LISTEN_FDS_START: i32 = 3;
+
+// systemd から listen 済みのソケットを渡されていればそれを使う。
+// 無ければ addr に bind する。reuse_port なら SO_REUSEPORT を付けて、
+// 古いプロセスが listen したままでも新しいプロセスが同じポートで待ち受けられるようにする
+pub fn listener(addr: SocketAddr, reuse_port: bool) -> anyhow::Result<TcpListener> {
+    if let Some(listener) = inherited_listener()? {
+        tracing::info!("listening on the socket passed by systemd");
+        return Ok(listener);
+    }
+    let socket = if addr.is_ipv4() { TcpSocket::new_v4()? } else { TcpSocket::new_v6()? };
+    socket.set_reuseaddr(true)?;
+    if reuse_port {
+        set_reuseport(&socket)?;
+    }
+    socket.bind(addr)?;
+    let listener = socket.listen(LISTEN_BACKLOG)?.into_std()?;
+    tracing::info!("listening on {}", addr);
+    Ok(listener)
+}
+
+#[cfg(unix)]
+fn set_reuseport(socket: &TcpSocket) -> anyhow::Result<()> {
+    socket.set_reuseport(true)?;
+    Ok(())
+}
+
+#[cfg(not(unix))]
+fn set_reuseport(_socket: &TcpSocket) -> anyhow::Result<()> {
+    anyhow::bail!("SO_REUSEPORT is not supported on this platform")
+}
+
+// LISTEN_PID が自分で LISTEN_FDS が 1 のときだけ受け取る (sd_listen_fds と同じ判定)
+#[cfg(unix)]
+fn inherited_listener() -> anyhow::Result<Option<TcpListener>> {
+    use std::os::unix::io::FromRawFd;
+
+    let for_us = env::var("LISTEN_PID").ok().and_then(|pid| pid.parse::<u32>().ok()) == Some(process::id());
+    let fds = env::var("LISTEN_FDS").ok().and_then(|fds| fds.parse::<i32>().ok());
+    match (for_us, fds) {
+        (true, Some(1)) => {
+            // 子プロセスに引き継がせない
+            env::remove_var("LISTEN_PID");
+            env::remove_var("LISTEN_FDS");
+            // fd 3 は systemd が listen 済みの TCP ソケットとして渡したもの
+            let listener = unsafe { TcpListener::from_raw_fd(SD_LISTEN_FDS_START) };
+            listener.set_nonblocking(true)?;
+            Ok(Some(listener))
+        }
+        (true, Some(fds)) => anyhow::bail!("expected 1 socket from systemd, got {}", fds),
+        _ => Ok(None),
+    }
+}
+
+#[cfg(not(unix))]
+fn inherited_listener() -> anyhow::Result<Option<TcpListener>> {
+    Ok(None)
+}
+
+// SIGTERM か Ctrl-C で返る。with_graceful_shutdown に渡すと、新しい接続の受け付けをやめて、
+// 処理中のリクエストが終わるのを待ってから止まる
+pub async fn shutdown_signal() {
+    let ctrl_c = async {
+        tokio::signal::ctrl_c().await.expect("failed to listen for Ctrl-C");
+    };
+    #[cfg(unix)]
+    let terminate = async {
+        tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate())
+            .expect("failed to listen for SIGTERM")
+            .recv()
+            .await;
+    };
+    #[cfg(not(unix))]
+    let terminate = std::future::pending::<()>();
+    tokio::select! {
+        _ = ctrl_c => {},
+        _ = terminate => {},
+    }
+    tracing::info!("shutting down, draining in-flight requests");
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[tokio::test]
+    async fn should_share_port_with_reuse_port() {
+        let first = listener("127.0.0.1:0".parse().unwrap(), true).unwrap();
+        let addr = first.local_addr().unwrap();
+        // 古いプロセスが listen したままでも、新しいプロセスが同じポートに bind できる
+        let second = listener(addr, true).unwrap();
+        assert_eq!(addr, second.local_addr().unwrap());
+    }
+}
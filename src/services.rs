@@ -0,0 +1,17 @@
+pub mod import;
+pub mod todo;
+
+use thiserror::Error;
+
+use crate::moderation::Rejected;
+
+// ハンドラはこのエラーを HTTP のステータスに読み替えるだけにする
+#[derive(Debug, Error)]
+pub enum ServiceError {
+    #[error("content rejected: {}", .0.reason)]
+    Rejected(Rejected),
+    #[error("invalid input: {0}")]
+    InvalidInput(&'static str),
+    #[error(transparent)]
+    Repository(#[from] anyhow::Error),
+}
@@ -0,0 +1,165 @@
+use std::{collections::HashMap, sync::Arc};
+use validator::Validate;
+
+use crate::{
+    import::{ImportPlan, ImportReport, ImportSkip},
+    normalize::Normalize,
+    repositories::{
+        label::{CreateLabel, LabelRepository},
+        todo::{TodoRepository, UpsertTodo},
+    },
+};
+use super::{todo::TodoService, ServiceError};
+
+// ImportPlan をこのアプリに書き込む。API から作るときと同じ検証とモデレーションを通し、
+// 通らなかったものは飛ばして ImportReport に理由を残す (1 件の失敗で全体を止めない)。
+// 通ったものは COPY でまとめて書き込む
+pub struct ImportService<T: TodoRepository, L: LabelRepository> {
+    todos: TodoService<T>,
+    labels: Arc<L>,
+    // 取り込んだ Todo とラベルの持ち主。同じ名前のラベルもこのユーザーのものから探す
+    owner_id: Option<i32>,
+}
+
+impl<T: TodoRepository, L: LabelRepository> ImportService<T, L> {
+    pub fn new(todos: TodoService<T>, labels: Arc<L>) -> Self {
+        Self { todos, labels, owner_id: None }
+    }
+
+    pub fn with_owner(mut self, owner_id: i32) -> Self {
+        self.owner_id = Some(owner_id);
+        self
+    }
+
+    pub async fn run(&self, plan: ImportPlan) -> anyhow::Result<ImportReport> {
+        let mut report = ImportReport::default();
+        let label_ids = self.import_labels(&plan.labels, &mut report).await?;
+
+        // 取り込み元での順番で理由を並べたいので、添字と一緒に持っておく
+        let mut skipped = vec![];
+        let mut payloads = vec![];
+        let mut texts = vec![];
+        for (index, todo) in plan.todos.into_iter().enumerate() {
+            let labels = todo.labels.iter().filter_map(|name| label_ids.get(name).copied()).collect();
+            let mut payload = UpsertTodo::new(todo.text.clone(), todo.completed, labels);
+            if let Some(owner_id) = self.owner_id {
+                payload = payload.with_owner(owner_id);
+            }
+            payload.normalize();
+            if let Err(e) = payload.validate() {
+                skipped.push((index, skip("todo", &todo.text, e.to_string())));
+                continue;
+            }
+            payloads.push(payload);
+            texts.push((index, todo.text));
+        }
+
+        let inserted = self.todos.bulk_insert(payloads).await?;
+        for (position, rejected) in inserted.rejected {
+            let (index, text) = &texts[position];
+            skipped.push((*index, skip("todo", text, ServiceError::Rejected(rejected).to_string())));
+        }
+        skipped.sort_by_key(|(index, _)| *index);
+        report.skipped.extend(skipped.into_iter().map(|(_, skip)| skip));
+        report.todos_created = inserted.ids.len();
+        Ok(report)
+    }
+
+    // 取り込み元のラベル名 -> ラベルの ID。同じ名前のラベルがあればそれを使う
+    async fn import_labels(&self, names: &[String], report: &mut ImportReport) -> anyhow::Result<HashMap<String, i32>> {
+        let existing: HashMap<String, i32> = self
+            .labels
+            .all(self.owner_id)
+            .await?
+            .into_iter()
+            .map(|label| (label.name, label.id))
+            .collect();
+        let mut ids = HashMap::new();
+        for name in names {
+            let mut payload = CreateLabel::new(name.clone());
+            if let Some(owner_id) = self.owner_id {
+                payload = payload.with_owner(owner_id);
+            }
+            payload.normalize();
+            if let Some(id) = existing.get(payload.name()) {
+                report.labels_reused += 1;
+                ids.insert(name.clone(), *id);
+                continue;
+            }
+            if let Err(e) = payload.validate() {
+                report.skipped.push(skip("label", name, e.to_string()));
+                continue;
+            }
+            match self.labels.create(payload).await {
+                Ok(label) => {
+                    report.labels_created += 1;
+                    ids.insert(name.clone(), label.id);
+                }
+                Err(e) => report.skipped.push(skip("label", name, e.to_string())),
+            }
+        }
+        Ok(ids)
+    }
+}
+
+fn skip(kind: &str, name: &str, reason: String) -> ImportSkip {
+    ImportSkip {
+        kind: kind.to_string(),
+        name: name.to_string(),
+        reason,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{
+        import::ImportedTodo,
+        moderation::DenylistFilter,
+        repositories::{
+            label::test_utils::LabelRepositoryForMemory,
+            todo::{test_utils::TodoRepositoryForMemory, Include, TodoFilter},
+        },
+    };
+
+    #[tokio::test]
+    async fn should_import_and_report_skipped_items() {
+        let todo_repo = Arc::new(TodoRepositoryForMemory::new());
+        let label_repo = Arc::new(LabelRepositoryForMemory::new());
+        let home = label_repo.create(CreateLabel::new("home".to_string())).await.unwrap();
+        let service = ImportService::new(
+            TodoService::new(todo_repo.clone(), Arc::new(DenylistFilter::new(["spam"]))),
+            label_repo.clone(),
+        );
+        let todo = |text: &str, completed, labels: &[&str]| ImportedTodo {
+            text: text.to_string(),
+            completed,
+            labels: labels.iter().map(|label| label.to_string()).collect(),
+        };
+        let plan = ImportPlan {
+            labels: vec!["home".to_string(), "work".to_string(), "x".repeat(101)],
+            todos: vec![
+                todo("buy milk", true, &["home", "work"]),
+                todo("buy spam", false, &[]),
+                todo("", false, &[]),
+            ],
+        };
+
+        let report = service.run(plan).await.unwrap();
+        assert_eq!((1, 1, 1), (report.labels_created, report.labels_reused, report.todos_created));
+        assert_eq!(
+            vec![("label", "x".repeat(101)), ("todo", "buy spam".to_string()), ("todo", String::new())],
+            report
+                .skipped
+                .iter()
+                .map(|skip| (skip.kind.as_str(), skip.name.clone()))
+                .collect::<Vec<_>>()
+        );
+
+        let todos = todo_repo.all(TodoFilter::default(), Include::default()).await.unwrap();
+        assert_eq!(1, todos.len());
+        assert!(todos[0].completed);
+        assert_eq!(home.id, todos[0].labels[0].id);
+        assert_eq!(2, todos[0].labels.len());
+    }
+}
@@ -0,0 +1,157 @@
+use std::sync::Arc;
+
+use crate::{
+    moderation::{Moderate, Rejected, SharedContentFilter},
+    repositories::{
+        sync::{ConflictPolicy, SyncRequest, SyncResult},
+        todo::{BulkCreateTodo, CreateTodo, TodoEntity, TodoRepository, UpdateTodo, Upserted, UpsertTodo},
+    },
+};
+use super::ServiceError;
+
+// 連携先のキーはそれなりの長さまでに制限しておく
+const MAX_CLIENT_KEY_LENGTH: usize = 255;
+
+// bulk_insert の結果。rejected はモデレーションで弾いたもの (渡した todos の添字と理由)
+#[derive(Debug)]
+pub struct BulkInserted {
+    pub ids: Vec<i32>,
+    pub rejected: Vec<(usize, Rejected)>,
+}
+
+// Todo の書き込みにかかる業務ルール (モデレーションや入力の制限) をまとめる。
+// axum に依存しないので、HTTP を通さずにテストできる
+#[derive(Clone)]
+pub struct TodoService<T: TodoRepository> {
+    repo: Arc<T>,
+    filter: SharedContentFilter,
+}
+
+impl<T: TodoRepository> TodoService<T> {
+    pub fn new(repo: Arc<T>, filter: SharedContentFilter) -> Self {
+        Self { repo, filter }
+    }
+
+    // 読み出しには業務ルールが無いので、リポジトリをそのまま使う
+    pub fn repo(&self) -> &T {
+        &self.repo
+    }
+
+    // 1 つでも弾かれたら全体を保存しない
+    async fn moderate<P: Moderate>(&self, payload: &P) -> Result<(), ServiceError> {
+        for text in payload.texts() {
+            self.filter.check(text).await.map_err(ServiceError::Rejected)?;
+        }
+        Ok(())
+    }
+
+    pub async fn create(&self, payload: CreateTodo) -> Result<TodoEntity, ServiceError> {
+        self.moderate(&payload).await?;
+        Ok(self.repo.create(payload).await?)
+    }
+
+    // create と同じく、1 件でも弾かれたら全体を保存しない
+    pub async fn bulk_create(&self, payload: BulkCreateTodo) -> Result<Vec<TodoEntity>, ServiceError> {
+        self.moderate(&payload).await?;
+        Ok(self.repo.bulk_create(payload.into_todos()).await?)
+    }
+
+    pub async fn update(
+        &self,
+        id: i32,
+        payload: UpdateTodo,
+        expected_version: Option<i32>,
+    ) -> Result<TodoEntity, ServiceError> {
+        self.moderate(&payload).await?;
+        Ok(self.repo.update(id, payload, expected_version).await?)
+    }
+
+    pub async fn upsert_by_key(
+        &self,
+        client_key: String,
+        payload: UpsertTodo,
+        expected_version: Option<i32>,
+    ) -> Result<Upserted, ServiceError> {
+        if client_key.len() > MAX_CLIENT_KEY_LENGTH {
+            return Err(ServiceError::InvalidInput("client key is too long"));
+        }
+        self.moderate(&payload).await?;
+        Ok(self.repo.upsert_by_key(client_key, payload, expected_version).await?)
+    }
+
+    // インポート用。create と違い、弾かれたものだけを除いて残りをまとめて保存する
+    pub async fn bulk_insert(&self, todos: Vec<UpsertTodo>) -> Result<BulkInserted, ServiceError> {
+        let mut accepted = vec![];
+        let mut rejected = vec![];
+        for (index, todo) in todos.into_iter().enumerate() {
+            match self.moderate(&todo).await {
+                Ok(()) => accepted.push(todo),
+                Err(ServiceError::Rejected(reason)) => rejected.push((index, reason)),
+                Err(e) => return Err(e),
+            }
+        }
+        let ids = self.repo.bulk_insert(accepted).await?;
+        Ok(BulkInserted { ids, rejected })
+    }
+
+    // リクエストでポリシーが指定されていなければ、サーバーの設定値 (default_policy) を使う
+    pub async fn sync(&self, request: SyncRequest, default_policy: ConflictPolicy) -> Result<SyncResult, ServiceError> {
+        self.moderate(&request).await?;
+        let policy = request.policy.unwrap_or(default_policy);
+        let owner_id = request.owner_id();
+        Ok(self.repo.sync(request.mutations, policy, owner_id).await?)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{
+        moderation::{DenylistFilter, NoopFilter},
+        repositories::todo::{test_utils::TodoRepositoryForMemory, Include, TodoFilter},
+    };
+
+    fn service(filter: SharedContentFilter) -> TodoService<TodoRepositoryForMemory> {
+        TodoService::new(Arc::new(TodoRepositoryForMemory::new()), filter)
+    }
+
+    #[tokio::test]
+    async fn should_not_save_rejected_content() {
+        let service = service(Arc::new(DenylistFilter::new(["spam"])));
+        let rejected = service.create(CreateTodo::new("buy spam".to_string(), vec![])).await;
+        assert!(matches!(rejected, Err(ServiceError::Rejected(_))));
+        assert!(service.repo.all(TodoFilter::default(), Include::default()).await.unwrap().is_empty());
+
+        let created = service.create(CreateTodo::new("buy milk".to_string(), vec![])).await.unwrap();
+        let rejected = service
+            .update(created.id, UpdateTodo::new(Some("spam".to_string()), None, None), None)
+            .await;
+        assert!(matches!(rejected, Err(ServiceError::Rejected(_))));
+        assert_eq!("buy milk", service.repo.find(created.id).await.unwrap().text);
+    }
+
+    #[tokio::test]
+    async fn should_bulk_insert_all_but_rejected_content() {
+        let service = service(Arc::new(DenylistFilter::new(["spam"])));
+        let todos = vec![
+            UpsertTodo::new("buy milk".to_string(), true, vec![]),
+            UpsertTodo::new("buy spam".to_string(), false, vec![]),
+            UpsertTodo::new("buy eggs".to_string(), false, vec![]),
+        ];
+        let inserted = service.bulk_insert(todos).await.unwrap();
+        assert_eq!(vec![1], inserted.rejected.iter().map(|(index, _)| *index).collect::<Vec<_>>());
+        assert_eq!(2, inserted.ids.len());
+        let milk = service.repo.find(inserted.ids[0]).await.unwrap();
+        assert_eq!(("buy milk", true), (milk.text.as_str(), milk.completed));
+    }
+
+    #[tokio::test]
+    async fn should_reject_long_client_keys() {
+        let service = service(Arc::new(NoopFilter));
+        let payload = UpsertTodo::new("text".to_string(), false, vec![]);
+        let result = service.upsert_by_key("k".repeat(MAX_CLIENT_KEY_LENGTH + 1), payload.clone(), None).await;
+        assert!(matches!(result, Err(ServiceError::InvalidInput(_))));
+        let result = service.upsert_by_key("k".repeat(MAX_CLIENT_KEY_LENGTH), payload, None).await;
+        assert!(matches!(result, Ok(Upserted::Created(_))));
+    }
+}
@@ -0,0 +1,82 @@
+use anyhow::{bail, Context};
+use uuid::Uuid;
+
+use crate::{
+    client::{Client, ClientError},
+    error_code::ErrorCode,
+    repositories::{
+        label::CreateLabel,
+        todo::{CreateTodo, UpdateTodo},
+    },
+};
+
+// デプロイ直後に、本番と同じ経路で CRUD が一通り通るかを確かめる。
+//   rust_web smoke-test --base-url https://api.example.com
+// 作ったデータは最後に消す。途中で失敗したら、どの手順で落ちたかをエラーにして返す
+pub async fn run(client: &Client) -> anyhow::Result<()> {
+    // 同時に走らせても、既存のラベルとぶつからない名前にする
+    let name = format!("smoke-test {}", Uuid::new_v4());
+    let label = client
+        .labels()
+        .create(CreateLabel::new(name.clone()))
+        .await
+        .context("create label")?;
+    tracing::info!("created label {}", label.id);
+
+    let todo = client
+        .todos()
+        .create(CreateTodo::new(name.clone(), vec![label.id]))
+        .await
+        .context("create todo")?;
+    if todo.text != name || todo.labels.iter().all(|attached| attached.id != label.id) {
+        bail!("create todo: unexpected response {:?}", todo);
+    }
+    tracing::info!("created todo {}", todo.id);
+
+    let updated = client
+        .todos()
+        .update(todo.id, UpdateTodo::new(None, Some(true), None))
+        .await
+        .context("update todo")?;
+    if !updated.completed || updated.version <= todo.version {
+        bail!("update todo: unexpected response {:?}", updated);
+    }
+    let found = client.todos().find(todo.id).await.context("find todo")?;
+    if found != updated {
+        bail!("find todo: expected {:?}, got {:?}", updated, found);
+    }
+
+    client.todos().delete(todo.id).await.context("delete todo")?;
+    match client.todos().find(todo.id).await {
+        Err(ClientError::Status { code: Some(ErrorCode::TodoNotFound), .. }) => {}
+        other => bail!("find deleted todo: expected TODO_NOT_FOUND, got {:?}", other),
+    }
+    client.labels().delete(label.id).await.context("delete label")?;
+    tracing::info!("smoke test passed");
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{
+        config::Config,
+        test_support::{memory_app, LabelRepositoryForMemory, TodoRepositoryForMemory},
+    };
+    use std::net::TcpListener;
+
+    #[tokio::test]
+    async fn should_pass_against_memory_app() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let app = memory_app(Config::default(), TodoRepositoryForMemory::new(), LabelRepositoryForMemory::new());
+        tokio::spawn(axum::Server::from_tcp(listener).unwrap().serve(app.into_make_service()));
+
+        run(&Client::new(format!("http://{}", addr))).await.unwrap();
+        // 後片付けまでしている
+        assert!(Client::new(format!("http://{}", addr)).labels().list().await.unwrap().is_empty());
+
+        let e = run(&Client::new("http://127.0.0.1:1")).await.unwrap_err();
+        assert_eq!("create label", e.to_string());
+    }
+}
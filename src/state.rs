@@ -0,0 +1,142 @@
+use axum::extract::FromRef;
+use std::sync::Arc;
+
+use crate::{
+    db::DbHealth,
+    handlers::{
+        admin::{AdminConfig, SnapshotConfig},
+        auth::AuthConfig,
+        feed::FeedConfig,
+        pagination::PublicBaseUrl,
+        StrictJson,
+    },
+    jobs::JobRegistry,
+    leader::Leadership,
+    metrics::Metrics,
+    moderation::SharedContentFilter,
+    repositories::{cache::QueryCache, sync::ConflictPolicy},
+};
+
+// リポジトリは型引数が同じになりうる (テストで同じモックを使うなど) ので、
+// State<Arc<T>> では取り出す先を区別できない。種類ごとに包んで取り出す
+#[derive(Debug)]
+pub struct TodoRepo<T>(pub Arc<T>);
+
+#[derive(Debug)]
+pub struct LabelRepo<T>(pub Arc<T>);
+
+#[derive(Debug)]
+pub struct BackupRepo<T>(pub Arc<T>);
+
+#[derive(Debug)]
+pub struct MaintenanceRepo<T>(pub Arc<T>);
+
+#[derive(Debug)]
+pub struct AccessLogRepo<T>(pub Arc<T>);
+
+#[derive(Debug)]
+pub struct UserRepo<T>(pub Arc<T>);
+
+// ハンドラと extractor が参照するもの。AppBuilder::build で組み立てて Router の State にする。
+// 足りないものがあればルートを組み立てるところでコンパイルエラーになる
+pub struct AppState<Todo, Label, Backup, Maintenance, AccessLog, User> {
+    pub todos: TodoRepo<Todo>,
+    pub labels: LabelRepo<Label>,
+    pub backups: BackupRepo<Backup>,
+    pub maintenance: MaintenanceRepo<Maintenance>,
+    pub access_log: AccessLogRepo<AccessLog>,
+    pub users: UserRepo<User>,
+    pub jobs: JobRegistry,
+    pub conflict_policy: ConflictPolicy,
+    pub admin: AdminConfig,
+    pub auth: AuthConfig,
+    pub feed: FeedConfig,
+    pub snapshots: SnapshotConfig,
+    pub strict_json: StrictJson,
+    pub public_base_url: PublicBaseUrl,
+    pub content_filter: SharedContentFilter,
+    pub db_health: DbHealth,
+    pub leadership: Leadership,
+    pub query_cache: QueryCache,
+    pub metrics: Metrics,
+}
+
+// derive だと型引数のリポジトリにも Clone を要求してしまうので、中の Arc だけ clone する
+macro_rules! impl_repo {
+    ($($repo:ident),*) => {
+        $(
+            impl<T> Clone for $repo<T> {
+                fn clone(&self) -> Self {
+                    Self(self.0.clone())
+                }
+            }
+        )*
+    };
+}
+
+impl_repo!(TodoRepo, LabelRepo, BackupRepo, MaintenanceRepo, AccessLogRepo, UserRepo);
+
+impl<Todo, Label, Backup, Maintenance, AccessLog, User> Clone
+    for AppState<Todo, Label, Backup, Maintenance, AccessLog, User>
+{
+    fn clone(&self) -> Self {
+        Self {
+            todos: self.todos.clone(),
+            labels: self.labels.clone(),
+            backups: self.backups.clone(),
+            maintenance: self.maintenance.clone(),
+            access_log: self.access_log.clone(),
+            users: self.users.clone(),
+            jobs: self.jobs.clone(),
+            conflict_policy: self.conflict_policy,
+            admin: self.admin.clone(),
+            auth: self.auth.clone(),
+            feed: self.feed.clone(),
+            snapshots: self.snapshots.clone(),
+            strict_json: self.strict_json,
+            public_base_url: self.public_base_url.clone(),
+            content_filter: self.content_filter.clone(),
+            db_health: self.db_health.clone(),
+            leadership: self.leadership.clone(),
+            query_cache: self.query_cache.clone(),
+            metrics: self.metrics.clone(),
+        }
+    }
+}
+
+// axum の derive(FromRef) は型引数のある struct に使えないので、フィールドごとに書き出す
+macro_rules! impl_from_ref {
+    ($($field:ident: $ty:ty),* $(,)?) => {
+        $(
+            impl<Todo, Label, Backup, Maintenance, AccessLog, User>
+                FromRef<AppState<Todo, Label, Backup, Maintenance, AccessLog, User>> for $ty
+            {
+                fn from_ref(state: &AppState<Todo, Label, Backup, Maintenance, AccessLog, User>) -> Self {
+                    state.$field.clone()
+                }
+            }
+        )*
+    };
+}
+
+impl_from_ref!(
+    todos: TodoRepo<Todo>,
+    labels: LabelRepo<Label>,
+    backups: BackupRepo<Backup>,
+    maintenance: MaintenanceRepo<Maintenance>,
+    access_log: AccessLogRepo<AccessLog>,
+    users: UserRepo<User>,
+    jobs: JobRegistry,
+    conflict_policy: ConflictPolicy,
+    admin: AdminConfig,
+    auth: AuthConfig,
+    feed: FeedConfig,
+    snapshots: SnapshotConfig,
+    strict_json: StrictJson,
+    public_base_url: PublicBaseUrl,
+    content_filter: SharedContentFilter,
+    db_health: DbHealth,
+    leadership: Leadership,
+    query_cache: QueryCache,
+    metrics: Metrics,
+);
@@ -0,0 +1,111 @@
+use sd_notify::NotifyState;
+use sqlx::PgPool;
+use std::time::{Duration, Instant};
+use tokio::task::JoinHandle;
+
+use crate::db::{self, DbHealth};
+
+// systemd (Type=notify) に状態を知らせる。NOTIFY_SOCKET が無ければ (systemd 以外から起動したら) 何もしない
+fn notify(state: &[NotifyState]) {
+    if let Err(e) = sd_notify::notify(false, state) {
+        tracing::warn!("failed to notify systemd: {}", e);
+    }
+}
+
+// listen を始めて、リクエストを受け付けられるようになったら呼ぶ
+pub fn notify_ready() {
+    notify(&[NotifyState::Ready, NotifyState::Status("accepting requests")]);
+}
+
+pub fn notify_stopping() {
+    notify(&[NotifyState::Stopping, NotifyState::Status("draining in-flight requests")]);
+}
+
+// DB のチェックがこれより長く終わらなければ固まったとみなす。
+// acquire のタイムアウトは 5 秒なので、DB が落ちているだけならこれより十分早くエラーで返ってくる
+const DB_STALL_TIMEOUT: Duration = Duration::from_secs(30);
+
+// interval ごとに ping する。DB のチェックは ping のあいだに 1 回ずつ流し、
+// チェックが stall_after を超えて終わらないあいだは ping を止める。
+// イベントループが固まれば、そもそも ping が送られない
+async fn watchdog_loop<F: FnMut()>(
+    pool: PgPool,
+    health: DbHealth,
+    interval: Duration,
+    stall_after: Duration,
+    mut ping: F,
+) {
+    let mut ticker = tokio::time::interval(interval);
+    loop {
+        ticker.tick().await;
+        ping();
+        let started = Instant::now();
+        let check = db::check(&pool, &health);
+        tokio::pin!(check);
+        loop {
+            tokio::select! {
+                _ = &mut check => break,
+                _ = ticker.tick() => {
+                    if started.elapsed() < stall_after {
+                        ping();
+                    } else {
+                        tracing::error!(
+                            "database check has not finished in {:?}, skipping the watchdog ping",
+                            stall_after
+                        );
+                    }
+                }
+            }
+        }
+    }
+}
+
+// WatchdogSec が設定されていれば、その半分の間隔で WATCHDOG=1 を送る。
+// イベントループや DB へのアクセスが固まれば ping が止まり、systemd が再起動する。
+// DB が落ちているだけなら再起動しても直らないので、ping は止めない
+pub fn spawn_watchdog(pool: PgPool, health: DbHealth) -> Option<JoinHandle<()>> {
+    let mut usec = 0;
+    if !sd_notify::watchdog_enabled(false, &mut usec) {
+        return None;
+    }
+    let interval = Duration::from_micros(usec) / 2;
+    tracing::info!("systemd watchdog is enabled, pinging every {:?}", interval);
+    Some(tokio::spawn(watchdog_loop(pool, health, interval, DB_STALL_TIMEOUT, || {
+        notify(&[NotifyState::Watchdog])
+    })))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    };
+
+    async fn count_pings(stall_after: Duration) -> Arc<AtomicUsize> {
+        // 誰も listen していないポートなので、チェックは acquire のタイムアウト (5 秒) まで終わらない
+        let pool = db::connect_lazy("postgres://admin@127.0.0.1:1/todos", 1, 0).unwrap();
+        let pings = Arc::new(AtomicUsize::new(0));
+        let counter = pings.clone();
+        let ping = move || {
+            counter.fetch_add(1, Ordering::SeqCst);
+        };
+        let interval = Duration::from_millis(10);
+        let task = tokio::spawn(watchdog_loop(pool, DbHealth::default(), interval, stall_after, ping));
+        tokio::time::sleep(Duration::from_millis(300)).await;
+        task.abort();
+        pings
+    }
+
+    #[tokio::test]
+    async fn watchdog_pings_while_database_check_is_pending() {
+        assert!(count_pings(Duration::from_secs(30)).await.load(Ordering::SeqCst) >= 10);
+    }
+
+    #[tokio::test]
+    async fn watchdog_stops_pinging_when_database_check_stalls() {
+        let pings = count_pings(Duration::from_millis(50)).await;
+        assert!(pings.load(Ordering::SeqCst) <= 10);
+    }
+}
@@ -0,0 +1,54 @@
+use axum::Router;
+
+use crate::{config::Config, create_app};
+
+// ルーターを組み込む側のクレートが、DB を立てずにテストを書くための入り口。
+// test-support feature を有効にすると使える
+pub use crate::fixtures::{LabelFixture, TodoFixture};
+pub use crate::repositories::{
+    access_log::test_utils::AccessLogRepositoryForMemory,
+    backup::test_utils::BackupRepositoryForMemory,
+    label::test_utils::{LabelRepositoryForMemory, MockLabelRepository},
+    maintenance::test_utils::MaintenanceRepositoryForMemory,
+    todo::test_utils::{MockTodoRepository, TodoRepositoryForMemory},
+    user::test_utils::{MockUserRepository, UserRepositoryForMemory},
+};
+
+// Todo とラベルのリポジトリは、テストデータを入れられるよう呼び出し側で作って渡す。
+// それ以外はテストで中身を見ることがほぼ無いので、空のものを使う
+pub fn memory_app(
+    config: Config,
+    todo_repository: TodoRepositoryForMemory,
+    label_repository: LabelRepositoryForMemory,
+) -> Router {
+    create_app(
+        config,
+        todo_repository,
+        label_repository,
+        BackupRepositoryForMemory::new(),
+        MaintenanceRepositoryForMemory::new(),
+        AccessLogRepositoryForMemory::new(),
+        UserRepositoryForMemory::new(),
+    )
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use axum::{
+        body::Body,
+        http::{Request, StatusCode},
+    };
+    use tower::ServiceExt;
+
+    #[tokio::test]
+    async fn should_serve_seeded_todos() {
+        let todo_repo = TodoRepositoryForMemory::new();
+        let todo = TodoFixture::new().text("seeded").insert(&todo_repo).await;
+        let app = memory_app(Config::default(), todo_repo, LabelRepositoryForMemory::new());
+
+        let req = Request::builder().uri(format!("/todos/{}", todo.id)).body(Body::empty()).unwrap();
+        let res = app.oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::OK, res.status());
+    }
+}